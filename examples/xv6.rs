@@ -1,10 +1,14 @@
 use std::collections::HashMap;
+use std::path::Path;
 use std::sync::atomic::AtomicBool;
 use std::sync::Arc;
 
 use elfloader::{ElfBinary, VAddr};
+use rriscv::chardev;
 use rriscv::cpu::{PrivMode, TrapCause};
+use rriscv::disk::{self, DiskMode};
 use rriscv::elf;
+use rriscv::virtio::block::VirtioBlockDisk;
 use rriscv::{
     cpu::{self},
     mmu::MMU,
@@ -17,18 +21,37 @@ fn main() {
 
     let vbase: u64 = 0x8000_0000;
 
-    let mmu = MMU::create();
+    let mmu = &mut MMU::create();
+
+    // Usage: xv6 [--aia] [--serial <backend>] [--read-only | --overlay <file>], writes go
+    // to fs.img by default and the console is on the terminal, see `chardev::open`. With
+    // --aia the APLIC and IMSIC replace the PLIC
+    let usage = "Usage: xv6 [--aia] [--serial <backend>] [--read-only | --overlay <file>]";
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+    if let Some(index) = args.iter().position(|arg| arg == "--aia") {
+        args.remove(index);
+        mmu.select_aia();
+    }
     println!("DTB Device Table:");
     mmu.dump_device_table();
 
-    let mmu = &mut MMU::create();
+    let serial = match args.iter().position(|arg| arg == "--serial") {
+        Some(index) if index + 1 < args.len() => args.drain(index..index + 2).nth(1).unwrap(),
+        Some(_) => panic!("{}", usage),
+        None => String::from("stdio"),
+    };
+    let mode = match args.iter().map(String::as_str).collect::<Vec<_>>()[..] {
+        [] => DiskMode::Writable,
+        ["--read-only"] => DiskMode::ReadOnly,
+        ["--overlay", overlay] => DiskMode::CopyOnWrite(Path::new(overlay)),
+        _ => panic!("{}", usage),
+    };
+    let disk = disk::open(Path::new("examples/xv6/fs.img"), mode).expect("Can't open xv6 fs image");
+    println!("Virtio filesystem initialized ({} bytes)", disk.len());
+    mmu.attach_virtio(Box::new(VirtioBlockDisk::new(disk)));
 
-    let fs_contents = fs::read("examples/xv6/fs.img").expect("Can't read xv6 fs images");
-    println!(
-        "Virtio filesystem initialized ({} bytes)",
-        fs_contents.len()
-    );
-    mmu.virtio_mut().load_fs(fs_contents);
+    mmu.uart_mut()
+        .connect(chardev::open(&serial).expect("Can't open the serial backend"));
 
     let binary_blob = fs::read("examples/xv6/kernel").expect("Can't read xv6 kernel binary");
     let binary = ElfBinary::new(binary_blob.as_slice()).expect("Got proper ELF file");
@@ -0,0 +1,783 @@
+use elfloader::VAddr;
+
+use crate::{
+    cpu::{MipMask, PrivMode, RegisterValue, TrapCause, Xlen},
+    memory::MemoryOperations,
+};
+
+/// Where the AIA lives when it is selected in place of the PLIC, as on QEMU's virt machine
+pub const APLIC_M_BASE: u64 = 0x0c00_0000;
+pub const APLIC_S_BASE: u64 = 0x0d00_0000;
+pub const APLIC_SIZE: u64 = 0x8000;
+pub const IMSIC_M_BASE: u64 = 0x2400_0000;
+pub const IMSIC_S_BASE: u64 = 0x2800_0000;
+/// Each hart has a page in the M-level and in the S-level IMSIC region
+pub const IMSIC_PAGE_SIZE: u64 = 0x1000;
+
+/// Interrupt identities of an interrupt file go from 1 to `IMSIC_IDS - 1`
+pub const IMSIC_IDS: u32 = 256;
+const IMSIC_WORDS: usize = (IMSIC_IDS / 64) as usize;
+
+/// Registers of an interrupt file, selected by `miselect`/`siselect`
+const EIDELIVERY: u64 = 0x70;
+const EITHRESHOLD: u64 = 0x72;
+const EIP0: u64 = 0x80;
+const EIP63: u64 = 0xbf;
+const EIE0: u64 = 0xc0;
+const EIE63: u64 = 0xff;
+/// The major interrupt priority arrays, read-only zero here
+const IPRIO0: u64 = 0x30;
+const IPRIO15: u64 = 0x3f;
+
+/// An IMSIC interrupt file: the M-level or S-level one of a hart
+#[derive(Default)]
+pub struct InterruptFile {
+    eidelivery: u32,
+    eithreshold: u32,
+    eip: [u64; IMSIC_WORDS],
+    eie: [u64; IMSIC_WORDS],
+}
+
+impl InterruptFile {
+    /// An MSI arrives with the interrupt identity `id`
+    pub fn set_pending(&mut self, id: u32) {
+        if id != 0 && id < IMSIC_IDS {
+            self.eip[(id / 64) as usize] |= 1 << (id % 64);
+        }
+    }
+
+    /// The pending and enabled identity with the highest priority, which is the lowest
+    /// number, if it is below `eithreshold`. Formatted as `*topei`, 0 if there is none.
+    pub fn topei(&self) -> u32 {
+        for word in 0..IMSIC_WORDS {
+            let candidates = self.eip[word] & self.eie[word];
+            if candidates != 0 {
+                let id = (word * 64) as u32 + candidates.trailing_zeros();
+                return match self.eithreshold == 0 || id < self.eithreshold {
+                    true => id << 16 | id,
+                    false => 0,
+                };
+            }
+        }
+        0
+    }
+
+    /// A write to `*topei` clears the pending bit of the identity it reports
+    pub fn claim(&mut self) {
+        let id = self.topei() >> 16;
+        self.eip[(id / 64) as usize] &= !(1 << (id % 64));
+    }
+
+    /// Indicates whether the file raises MEIP or SEIP of its hart
+    pub fn is_interrupting(&self) -> bool {
+        self.eidelivery == 1 && self.topei() != 0
+    }
+
+    /// Indicates whether `*ireg` can access the register selected by `select`. On RV64
+    /// the odd numbered `eipN`, `eieN` and `iprioN` registers do not exist.
+    pub fn has_register(select: u64, xlen: Xlen) -> bool {
+        match select {
+            EIDELIVERY | EITHRESHOLD => true,
+            IPRIO0..=IPRIO15 | EIP0..=EIE63 => xlen == Xlen::Bits32 || select & 1 == 0,
+            _ => false,
+        }
+    }
+
+    pub fn read_register(&self, select: u64, xlen: Xlen) -> RegisterValue {
+        match select {
+            EIDELIVERY => self.eidelivery as RegisterValue,
+            EITHRESHOLD => self.eithreshold as RegisterValue,
+            EIP0..=EIP63 => read_bits(&self.eip, select - EIP0, xlen),
+            EIE0..=EIE63 => read_bits(&self.eie, select - EIE0, xlen),
+            _ => 0,
+        }
+    }
+
+    pub fn write_register(&mut self, select: u64, value: RegisterValue, xlen: Xlen) {
+        match select {
+            EIDELIVERY => self.eidelivery = (value & 1) as u32,
+            EITHRESHOLD => self.eithreshold = (value as u32) & (IMSIC_IDS - 1),
+            EIP0..=EIP63 => write_bits(&mut self.eip, select - EIP0, value, xlen),
+            EIE0..=EIE63 => write_bits(&mut self.eie, select - EIE0, value, xlen),
+            _ => {}
+        }
+        // Identity 0 does not exist
+        self.eip[0] &= !1;
+        self.eie[0] &= !1;
+    }
+}
+
+/// The 32 bit register `index` of a bit array, or the 64 bit register on RV64
+fn read_bits(bits: &[u64; IMSIC_WORDS], index: u64, xlen: Xlen) -> RegisterValue {
+    let word = match bits.get((index / 2) as usize) {
+        Some(word) => *word,
+        None => return 0,
+    };
+    match xlen {
+        Xlen::Bits32 => (word >> (32 * (index % 2))) & 0xffff_ffff,
+        _ => word,
+    }
+}
+
+fn write_bits(bits: &mut [u64; IMSIC_WORDS], index: u64, value: RegisterValue, xlen: Xlen) {
+    let word = match bits.get_mut((index / 2) as usize) {
+        Some(word) => word,
+        None => return,
+    };
+    match xlen {
+        Xlen::Bits32 => {
+            let shift = 32 * (index % 2);
+            *word = (*word & !(0xffff_ffff << shift)) | (value & 0xffff_ffff) << shift;
+        }
+        _ => *word = value,
+    }
+}
+
+/// The memory-mapped side of the IMSICs. MSIs written to the page of an interrupt file
+/// wait here until its hart picks them up.
+pub struct IMSIC {
+    harts: usize,
+    /// `(hart, level of the interrupt file, identity)`
+    msis: Vec<(usize, PrivMode, u32)>,
+}
+
+const SETEIPNUM_LE: u64 = 0x0;
+const SETEIPNUM_BE: u64 = 0x4;
+
+impl IMSIC {
+    pub fn create(harts: usize) -> IMSIC {
+        IMSIC {
+            harts,
+            msis: Vec::new(),
+        }
+    }
+
+    /// The hart and level of the interrupt file whose page holds `addr`
+    fn file(&self, addr: VAddr) -> Option<(usize, PrivMode)> {
+        let size = self.harts as u64 * IMSIC_PAGE_SIZE;
+        if (IMSIC_M_BASE..IMSIC_M_BASE + size).contains(&addr) {
+            Some((
+                ((addr - IMSIC_M_BASE) / IMSIC_PAGE_SIZE) as usize,
+                PrivMode::Machine,
+            ))
+        } else if (IMSIC_S_BASE..IMSIC_S_BASE + size).contains(&addr) {
+            Some((
+                ((addr - IMSIC_S_BASE) / IMSIC_PAGE_SIZE) as usize,
+                PrivMode::Supervisor,
+            ))
+        } else {
+            None
+        }
+    }
+
+    pub fn includes(&self, addr: VAddr) -> bool {
+        self.file(addr).is_some()
+    }
+
+    /// Takes the MSIs sent to the interrupt files of `hart`
+    pub fn take(&mut self, hart: usize) -> Vec<(PrivMode, u32)> {
+        let mut msis = vec![];
+        self.msis
+            .retain(|(target, level, id)| match *target == hart {
+                true => {
+                    msis.push((*level, *id));
+                    false
+                }
+                false => true,
+            });
+        msis
+    }
+}
+
+impl MemoryOperations<IMSIC, u8> for IMSIC {
+    fn read32(&mut self, _addr: VAddr) -> Result<u32, TrapCause> {
+        Ok(0)
+    }
+
+    fn write32(&mut self, addr: VAddr, value: u32) -> Option<TrapCause> {
+        let (hart, level) = self.file(addr)?;
+        match addr % IMSIC_PAGE_SIZE {
+            SETEIPNUM_LE => self.msis.push((hart, level, value)),
+            SETEIPNUM_BE => self.msis.push((hart, level, value.swap_bytes())),
+            _ => {}
+        }
+        None
+    }
+}
+
+/// Number of interrupt sources, including the reserved source 0
+const NSOURCES: usize = 1024;
+const NWORDS: usize = NSOURCES / 32;
+
+const DOMAINCFG_READ_ONLY: u32 = 0x8000_0000;
+const DOMAINCFG_IE: u32 = 1 << 8;
+const DOMAINCFG_DM: u32 = 1 << 2;
+
+/// Delegates the source to the child domain
+const SOURCECFG_D: u32 = 1 << 10;
+/// Source modes
+const SM_INACTIVE: u32 = 0;
+const SM_DETACHED: u32 = 1;
+const SM_EDGE1: u32 = 4;
+const SM_EDGE0: u32 = 5;
+const SM_LEVEL1: u32 = 6;
+const SM_LEVEL0: u32 = 7;
+
+const TARGET_HART_SHIFT: u32 = 18;
+const TARGET_EIID: u32 = 0x7ff;
+const TARGET_IPRIO: u32 = 0xff;
+
+/// Register map of a domain, as offsets from its base
+const DOMAINCFG: u64 = 0x0000;
+const SOURCECFG: u64 = 0x0000;
+const MMSIADDRCFG: u64 = 0x1bc0;
+const MMSIADDRCFGH: u64 = 0x1bc4;
+const SMSIADDRCFG: u64 = 0x1bc8;
+const SMSIADDRCFGH: u64 = 0x1bcc;
+const SETIP: u64 = 0x1c00;
+const SETIPNUM: u64 = 0x1cdc;
+const IN_CLRIP: u64 = 0x1d00;
+const CLRIPNUM: u64 = 0x1ddc;
+const SETIE: u64 = 0x1e00;
+const SETIENUM: u64 = 0x1edc;
+const CLRIE: u64 = 0x1f00;
+const CLRIENUM: u64 = 0x1fdc;
+const SETIPNUM_LE: u64 = 0x2000;
+const SETIPNUM_BE: u64 = 0x2004;
+const GENMSI: u64 = 0x3000;
+const TARGET: u64 = 0x3000;
+const IDC: u64 = 0x4000;
+const IDC_SIZE: u64 = 0x20;
+
+/// Registers of an interrupt delivery control structure, one per hart
+const IDELIVERY: u64 = 0x00;
+const IFORCE: u64 = 0x04;
+const ITHRESHOLD: u64 = 0x08;
+const TOPI: u64 = 0x18;
+const CLAIMI: u64 = 0x1c;
+
+/// The interrupt delivery control of a hart, for direct delivery mode
+#[derive(Default, Clone)]
+struct Idc {
+    idelivery: u32,
+    iforce: u32,
+    ithreshold: u32,
+    /// MEIP or SEIP of the hart, depending on the domain
+    asserted: bool,
+}
+
+/// An interrupt domain, the M-level root domain or its S-level child
+struct Domain {
+    domaincfg: u32,
+    sourcecfg: [u32; NSOURCES],
+    target: [u32; NSOURCES],
+    pending: [u32; NWORDS],
+    enabled: [u32; NWORDS],
+    genmsi: u32,
+    idcs: Vec<Idc>,
+}
+
+impl Domain {
+    fn create(harts: usize) -> Domain {
+        Domain {
+            domaincfg: 0,
+            sourcecfg: [0; NSOURCES],
+            target: [0; NSOURCES],
+            pending: [0; NWORDS],
+            enabled: [0; NWORDS],
+            genmsi: 0,
+            idcs: vec![Idc::default(); harts],
+        }
+    }
+
+    fn msi_mode(&self) -> bool {
+        (self.domaincfg & DOMAINCFG_DM) != 0
+    }
+
+    /// The highest priority pending and enabled source directed at `hart`, formatted as
+    /// `topi`. Lower priority numbers go first, ties to the lowest source number.
+    fn topi(&self, hart: usize) -> u32 {
+        let threshold = self.idcs[hart].ithreshold;
+        let mut best = 0;
+        let mut best_priority = u32::MAX;
+        for word in 0..NWORDS {
+            let mut candidates = self.pending[word] & self.enabled[word];
+            while candidates != 0 {
+                let irq = word * 32 + candidates.trailing_zeros() as usize;
+                candidates &= candidates - 1;
+                let target = self.target[irq];
+                let priority = target & TARGET_IPRIO;
+                if (target >> TARGET_HART_SHIFT) as usize == hart
+                    && (threshold == 0 || priority < threshold)
+                    && priority < best_priority
+                {
+                    best = (irq as u32) << 16 | priority;
+                    best_priority = priority;
+                }
+            }
+        }
+        best
+    }
+}
+
+/// An advanced platform-level interrupt controller with an M-level root domain and an
+/// S-level child domain. Each domain delivers interrupts directly to the harts, or as
+/// MSIs to their IMSICs, as selected by `domaincfg.DM`.
+pub struct APLIC {
+    /// The root domain, then its child
+    domains: [Domain; 2],
+    /// Input levels of the sources, as seen by the last tick
+    inputs: [u32; NWORDS],
+    mmsiaddrcfg: u64,
+    smsiaddrcfg: u64,
+    /// MSIs to be written, as `(address, data)`
+    msis: Vec<(u64, u32)>,
+    /// Interrupt lines as last seen by `update_mip`, per hart
+    mip: Vec<RegisterValue>,
+    needs_update_irq: bool,
+}
+
+impl APLIC {
+    /// Creates an APLIC for `harts`, sending MSIs to the IMSICs at their usual place
+    pub fn create(harts: usize) -> APLIC {
+        // Low hart index width, the interrupt files of the harts are on consecutive pages
+        let lhxw = (usize::BITS - harts.saturating_sub(1).leading_zeros()) as u64;
+        APLIC {
+            domains: [Domain::create(harts), Domain::create(harts)],
+            inputs: [0; NWORDS],
+            mmsiaddrcfg: (IMSIC_M_BASE >> 12) | lhxw << 44,
+            smsiaddrcfg: IMSIC_S_BASE >> 12,
+            msis: Vec::new(),
+            mip: vec![0; harts],
+            needs_update_irq: false,
+        }
+    }
+
+    pub fn includes(&self, addr: VAddr) -> bool {
+        (APLIC_M_BASE..APLIC_M_BASE + APLIC_SIZE).contains(&addr)
+            || (APLIC_S_BASE..APLIC_S_BASE + APLIC_SIZE).contains(&addr)
+    }
+
+    /// The domain whose registers hold `addr`, and the offset within them
+    fn domain_offset(addr: VAddr) -> (usize, u64) {
+        match addr >= APLIC_S_BASE {
+            true => (1, addr - APLIC_S_BASE),
+            false => (0, addr - APLIC_M_BASE),
+        }
+    }
+
+    /// The mode of `irq` in `domain`, inactive when the source belongs to another domain
+    fn source_mode(&self, domain: usize, irq: usize) -> u32 {
+        let delegated = (self.domains[0].sourcecfg[irq] & SOURCECFG_D) != 0;
+        match (domain, delegated) {
+            (0, false) | (1, true) => self.domains[domain].sourcecfg[irq] & 7,
+            _ => SM_INACTIVE,
+        }
+    }
+
+    /// The input of `irq`, inverted for the sources active when low
+    fn rectified_input(&self, domain: usize, irq: usize) -> bool {
+        let input = is_set(&self.inputs, irq);
+        match self.source_mode(domain, irq) {
+            SM_EDGE1 | SM_LEVEL1 => input,
+            SM_EDGE0 | SM_LEVEL0 => !input,
+            _ => false,
+        }
+    }
+
+    /// Samples the `(source, level)` interrupt lines of the devices
+    pub fn tick(&mut self, irqs: &[(u32, bool)]) {
+        for (irq, level) in irqs.iter().copied() {
+            let irq = irq as usize;
+            if irq == 0 || irq >= NSOURCES || is_set(&self.inputs, irq) == level {
+                continue;
+            }
+            set(&mut self.inputs, irq, level);
+            let domain = match (self.domains[0].sourcecfg[irq] & SOURCECFG_D) != 0 {
+                true => 1,
+                false => 0,
+            };
+            let rectified = self.rectified_input(domain, irq);
+            match self.source_mode(domain, irq) {
+                SM_EDGE1 | SM_EDGE0 if rectified => {
+                    set(&mut self.domains[domain].pending, irq, true)
+                }
+                SM_LEVEL1 | SM_LEVEL0 => set(&mut self.domains[domain].pending, irq, rectified),
+                _ => continue,
+            }
+            self.needs_update_irq = true;
+        }
+
+        if self.needs_update_irq {
+            self.needs_update_irq = false;
+            for domain in 0..self.domains.len() {
+                self.deliver(domain);
+            }
+        }
+    }
+
+    /// Forwards the pending and enabled sources of `domain` as MSIs, or updates the
+    /// interrupt lines of its IDCs
+    fn deliver(&mut self, domain: usize) {
+        let enabled = (self.domains[domain].domaincfg & DOMAINCFG_IE) != 0;
+        if self.domains[domain].msi_mode() {
+            if !enabled {
+                return;
+            }
+            for word in 0..NWORDS {
+                let d = &mut self.domains[domain];
+                let mut forward = d.pending[word] & d.enabled[word];
+                d.pending[word] &= !forward;
+                while forward != 0 {
+                    let irq = word * 32 + forward.trailing_zeros() as usize;
+                    forward &= forward - 1;
+                    let target = self.domains[domain].target[irq];
+                    self.send_msi(domain, target);
+                }
+            }
+        } else {
+            for hart in 0..self.domains[domain].idcs.len() {
+                let d = &self.domains[domain];
+                let idc = &d.idcs[hart];
+                let asserted =
+                    enabled && idc.idelivery == 1 && (idc.iforce == 1 || d.topi(hart) != 0);
+                self.domains[domain].idcs[hart].asserted = asserted;
+            }
+        }
+    }
+
+    /// Queues an MSI to the interrupt file of `domain`'s level of the hart in `target`
+    fn send_msi(&mut self, domain: usize, target: u32) {
+        let hart = (target >> TARGET_HART_SHIFT) as u64;
+        let eiid = target & TARGET_EIID;
+        // The geometry of the hart index comes from mmsiaddrcfgh, for both levels
+        let config = self.mmsiaddrcfg >> 32;
+        let lhxw = (config >> 12) & 0xf;
+        let hhxw = (config >> 16) & 0x7;
+        let hhxs = (config >> 24) & 0x1f;
+        let (ppn, lhxs) = match domain {
+            0 => (self.mmsiaddrcfg, (config >> 20) & 0x7),
+            _ => (self.smsiaddrcfg, (self.smsiaddrcfg >> 52) & 0x7),
+        };
+        let ppn = (ppn & 0xffff_ffff) | ((ppn >> 32) & 0xfff) << 32;
+        let group = (hart >> lhxw) & ((1 << hhxw) - 1);
+        let hart = hart & ((1 << lhxw) - 1);
+        let address = (ppn | group << (hhxs + 12) | hart << lhxs) << 12;
+        self.msis.push((address, eiid));
+    }
+
+    /// Takes the MSIs to be written to memory, as `(address, data)`
+    pub fn take_msis(&mut self) -> Vec<(u64, u32)> {
+        std::mem::take(&mut self.msis)
+    }
+
+    /// Returns the `mip` register value of `hart` with MEIP and SEIP updated from the
+    /// IDCs of the root and child domain. As with the PLIC, the bits are only touched
+    /// when the interrupt lines change.
+    pub fn update_mip(&mut self, hart: usize, mip: RegisterValue) -> RegisterValue {
+        let mut lines = 0;
+        for (domain, mask) in [(0, MipMask::MEIP), (1, MipMask::SEIP)] {
+            if self.domains[domain].idcs[hart].asserted {
+                lines |= mask as RegisterValue;
+            }
+        }
+        let changed = lines ^ self.mip[hart];
+        self.mip[hart] = lines;
+        (mip & !changed) | (lines & changed)
+    }
+
+    /// Sets the pending bit of `irq` as done by `setip` and `setipnum`
+    fn set_pending(&mut self, domain: usize, irq: usize) {
+        let level_sensitive = match self.source_mode(domain, irq) {
+            SM_DETACHED | SM_EDGE1 | SM_EDGE0 => false,
+            SM_LEVEL1 | SM_LEVEL0 => true,
+            _ => return,
+        };
+        // The pending bit of a level-sensitive source follows its input in direct mode
+        if !level_sensitive
+            || (self.domains[domain].msi_mode() && self.rectified_input(domain, irq))
+        {
+            set(&mut self.domains[domain].pending, irq, true);
+        }
+    }
+
+    /// Clears the pending bit of `irq` as done by `in_clrip` and `clripnum`
+    fn clear_pending(&mut self, domain: usize, irq: usize) {
+        let level_sensitive = match self.source_mode(domain, irq) {
+            SM_DETACHED | SM_EDGE1 | SM_EDGE0 => false,
+            SM_LEVEL1 | SM_LEVEL0 => true,
+            _ => return,
+        };
+        if !level_sensitive || self.domains[domain].msi_mode() {
+            set(&mut self.domains[domain].pending, irq, false);
+        }
+    }
+
+    fn set_enabled(&mut self, domain: usize, irq: usize, enabled: bool) {
+        if self.source_mode(domain, irq) != SM_INACTIVE {
+            set(&mut self.domains[domain].enabled, irq, enabled);
+        }
+    }
+
+    fn claim(&mut self, domain: usize, hart: usize) -> u32 {
+        let topi = self.domains[domain].topi(hart);
+        let irq = (topi >> 16) as usize;
+        if irq == 0 {
+            self.domains[domain].idcs[hart].iforce = 0;
+        } else {
+            // Level-sensitive sources stay pending while their input is active
+            let still_active = match self.source_mode(domain, irq) {
+                SM_LEVEL1 | SM_LEVEL0 => self.rectified_input(domain, irq),
+                _ => false,
+            };
+            set(&mut self.domains[domain].pending, irq, still_active);
+        }
+        self.needs_update_irq = true;
+        topi
+    }
+
+    fn write_sourcecfg(&mut self, domain: usize, irq: usize, value: u32) {
+        if domain == 1 && self.source_mode(0, irq) != SM_INACTIVE {
+            return;
+        }
+        if domain == 1 && (self.domains[0].sourcecfg[irq] & SOURCECFG_D) == 0 {
+            return;
+        }
+        let value = match value & 7 {
+            // The root domain has a single child, and the child none
+            _ if domain == 0 && (value & SOURCECFG_D) != 0 => SOURCECFG_D,
+            SM_DETACHED | SM_EDGE1 | SM_EDGE0 | SM_LEVEL1 | SM_LEVEL0 => value & 7,
+            _ => SM_INACTIVE,
+        };
+        if domain == 0 && ((self.domains[0].sourcecfg[irq] ^ value) & SOURCECFG_D) != 0 {
+            // The source moves between the domains, starting over in the child
+            let child = &mut self.domains[1];
+            child.sourcecfg[irq] = SM_INACTIVE;
+            set(&mut child.pending, irq, false);
+            set(&mut child.enabled, irq, false);
+        }
+        self.domains[domain].sourcecfg[irq] = value;
+        match self.source_mode(domain, irq) {
+            SM_INACTIVE => {
+                set(&mut self.domains[domain].pending, irq, false);
+                set(&mut self.domains[domain].enabled, irq, false);
+            }
+            SM_LEVEL1 | SM_LEVEL0 => {
+                let active = self.rectified_input(domain, irq);
+                set(&mut self.domains[domain].pending, irq, active);
+            }
+            _ => {}
+        }
+    }
+
+    fn write_target(&mut self, domain: usize, irq: usize, value: u32) {
+        if self.source_mode(domain, irq) == SM_INACTIVE {
+            return;
+        }
+        let mut hart = value >> TARGET_HART_SHIFT;
+        if hart as usize >= self.mip.len() {
+            hart = 0;
+        }
+        // There are no guest interrupt files, the guest index is always 0
+        self.domains[domain].target[irq] = match self.domains[domain].msi_mode() {
+            true => hart << TARGET_HART_SHIFT | (value & TARGET_EIID),
+            false => hart << TARGET_HART_SHIFT | (value & TARGET_IPRIO).max(1),
+        };
+    }
+
+    fn read_register(&mut self, domain: usize, offset: u64) -> u32 {
+        let d = &self.domains[domain];
+        let active = |irq: usize| self.source_mode(domain, irq) != SM_INACTIVE;
+        match offset {
+            DOMAINCFG => DOMAINCFG_READ_ONLY | d.domaincfg,
+            0x0004..=0x0ffc => {
+                let irq = (offset - SOURCECFG) as usize / 4;
+                match domain == 0 || (self.domains[0].sourcecfg[irq] & SOURCECFG_D) != 0 {
+                    true => d.sourcecfg[irq],
+                    false => 0,
+                }
+            }
+            // The MSI addresses are configured in the root domain only
+            MMSIADDRCFG if domain == 0 => self.mmsiaddrcfg as u32,
+            MMSIADDRCFGH if domain == 0 => (self.mmsiaddrcfg >> 32) as u32,
+            SMSIADDRCFG if domain == 0 => self.smsiaddrcfg as u32,
+            SMSIADDRCFGH if domain == 0 => (self.smsiaddrcfg >> 32) as u32,
+            SETIP..=0x1c7c => d.pending[(offset - SETIP) as usize / 4],
+            IN_CLRIP..=0x1d7c => {
+                let word = (offset - IN_CLRIP) as usize / 4;
+                (0..32)
+                    .filter(|bit| {
+                        let irq = word * 32 + bit;
+                        irq != 0 && active(irq) && self.rectified_input(domain, irq)
+                    })
+                    .fold(0, |inputs, bit| inputs | 1 << bit)
+            }
+            SETIE..=0x1e7c => d.enabled[(offset - SETIE) as usize / 4],
+            GENMSI => d.genmsi,
+            0x3004..=0x3ffc => {
+                let irq = (offset - TARGET) as usize / 4;
+                match active(irq) {
+                    true => d.target[irq],
+                    false => 0,
+                }
+            }
+            IDC.. => {
+                let hart = ((offset - IDC) / IDC_SIZE) as usize;
+                if hart >= d.idcs.len() {
+                    return 0;
+                }
+                let idc = &d.idcs[hart];
+                match (offset - IDC) % IDC_SIZE {
+                    IDELIVERY => idc.idelivery,
+                    IFORCE => idc.iforce,
+                    ITHRESHOLD => idc.ithreshold,
+                    TOPI => d.topi(hart),
+                    CLAIMI => self.claim(domain, hart),
+                    _ => 0,
+                }
+            }
+            _ => 0,
+        }
+    }
+
+    fn write_register(&mut self, domain: usize, offset: u64, value: u32) {
+        let irq = value as usize;
+        match offset {
+            DOMAINCFG => self.domains[domain].domaincfg = value & (DOMAINCFG_IE | DOMAINCFG_DM),
+            0x0004..=0x0ffc => {
+                self.write_sourcecfg(domain, (offset - SOURCECFG) as usize / 4, value)
+            }
+            MMSIADDRCFG if domain == 0 => {
+                self.mmsiaddrcfg = (self.mmsiaddrcfg & !0xffff_ffff) | value as u64
+            }
+            MMSIADDRCFGH if domain == 0 => {
+                self.mmsiaddrcfg = (self.mmsiaddrcfg & 0xffff_ffff) | (value as u64) << 32
+            }
+            SMSIADDRCFG if domain == 0 => {
+                self.smsiaddrcfg = (self.smsiaddrcfg & !0xffff_ffff) | value as u64
+            }
+            SMSIADDRCFGH if domain == 0 => {
+                self.smsiaddrcfg = (self.smsiaddrcfg & 0xffff_ffff) | (value as u64) << 32
+            }
+            SETIP..=0x1c7c | IN_CLRIP..=0x1d7c | SETIE..=0x1e7c | CLRIE..=0x1f7c => {
+                let word = ((offset & 0xff) / 4) as usize;
+                for bit in (0..32).filter(|bit| (value >> bit) & 1 == 1) {
+                    let irq = word * 32 + bit;
+                    match offset & !0xff {
+                        SETIP => self.set_pending(domain, irq),
+                        IN_CLRIP => self.clear_pending(domain, irq),
+                        SETIE => self.set_enabled(domain, irq, true),
+                        _ => self.set_enabled(domain, irq, false),
+                    }
+                }
+            }
+            SETIPNUM | SETIPNUM_LE if irq < NSOURCES => self.set_pending(domain, irq),
+            SETIPNUM_BE => {
+                let irq = value.swap_bytes() as usize;
+                if irq < NSOURCES {
+                    self.set_pending(domain, irq);
+                }
+            }
+            CLRIPNUM if irq < NSOURCES => self.clear_pending(domain, irq),
+            SETIENUM if irq < NSOURCES => self.set_enabled(domain, irq, true),
+            CLRIENUM if irq < NSOURCES => self.set_enabled(domain, irq, false),
+            GENMSI => {
+                let hart = (value >> TARGET_HART_SHIFT) as usize;
+                // MSIs are sent at once, the Busy bit always reads as 0
+                let target = match hart < self.mip.len() {
+                    true => value & (!0 << TARGET_HART_SHIFT | TARGET_EIID),
+                    false => value & TARGET_EIID,
+                };
+                self.domains[domain].genmsi = target;
+                if self.domains[domain].msi_mode() {
+                    self.send_msi(domain, target);
+                }
+            }
+            0x3004..=0x3ffc => self.write_target(domain, (offset - TARGET) as usize / 4, value),
+            IDC.. => {
+                let hart = ((offset - IDC) / IDC_SIZE) as usize;
+                if let Some(idc) = self.domains[domain].idcs.get_mut(hart) {
+                    match (offset - IDC) % IDC_SIZE {
+                        IDELIVERY => idc.idelivery = value & 1,
+                        IFORCE => idc.iforce = value & 1,
+                        ITHRESHOLD => idc.ithreshold = value & TARGET_IPRIO,
+                        _ => {}
+                    }
+                }
+            }
+            _ => {}
+        }
+        self.needs_update_irq = true;
+    }
+}
+
+fn is_set(bits: &[u32; NWORDS], irq: usize) -> bool {
+    ((bits[irq >> 5] >> (irq & 31)) & 1) == 1
+}
+
+fn set(bits: &mut [u32; NWORDS], irq: usize, value: bool) {
+    let word = &mut bits[irq >> 5];
+    match value {
+        true => *word |= 1 << (irq & 31),
+        false => *word &= !(1 << (irq & 31)),
+    }
+}
+
+/// Only naturally aligned 32 bit accesses are supported
+impl MemoryOperations<APLIC, u8> for APLIC {
+    fn read32(&mut self, address: VAddr) -> Result<u32, TrapCause> {
+        let (domain, offset) = APLIC::domain_offset(address);
+        match address & 3 {
+            0 => Ok(self.read_register(domain, offset)),
+            _ => Err(TrapCause::LoadAccessFault(address)),
+        }
+    }
+
+    fn write32(&mut self, address: VAddr, value: u32) -> Option<TrapCause> {
+        let (domain, offset) = APLIC::domain_offset(address);
+        match address & 3 {
+            0 => {
+                self.write_register(domain, offset, value);
+                None
+            }
+            _ => Some(TrapCause::StoreAccessFault(address)),
+        }
+    }
+}
+
+/// The Advanced Interrupt Architecture: an APLIC, and an IMSIC for each hart. The
+/// interrupt files of the IMSICs are kept by the harts, which reach them through
+/// their CSRs.
+pub struct AIA {
+    pub aplic: APLIC,
+    pub imsic: IMSIC,
+}
+
+impl AIA {
+    pub fn create(harts: usize) -> AIA {
+        AIA {
+            aplic: APLIC::create(harts),
+            imsic: IMSIC::create(harts),
+        }
+    }
+
+    pub fn includes(&self, addr: VAddr) -> bool {
+        self.aplic.includes(addr) || self.imsic.includes(addr)
+    }
+}
+
+impl MemoryOperations<AIA, u8> for AIA {
+    fn read32(&mut self, address: VAddr) -> Result<u32, TrapCause> {
+        match self.aplic.includes(address) {
+            true => self.aplic.read32(address),
+            false => self.imsic.read32(address),
+        }
+    }
+
+    fn write32(&mut self, address: VAddr, value: u32) -> Option<TrapCause> {
+        match self.aplic.includes(address) {
+            true => self.aplic.write32(address, value),
+            false => self.imsic.write32(address, value),
+        }
+    }
+}
@@ -0,0 +1,349 @@
+use std::{
+    ffi::CStr,
+    fs::{File, OpenOptions},
+    io::{self, Read, Write},
+    os::unix::{
+        io::FromRawFd,
+        net::{UnixListener, UnixStream},
+    },
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Receiver},
+        Mutex, MutexGuard, OnceLock,
+    },
+    thread,
+    time::Duration,
+};
+
+/// A host byte stream behind a serial or console device
+pub trait CharDevice {
+    /// Moves pending input into `buf` without blocking, returning the number of bytes read
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize>;
+
+    /// Writes all of `bytes` to the host
+    fn write(&mut self, bytes: &[u8]) -> io::Result<()>;
+}
+
+/// Opens the host side of a serial line from a command line style `spec`:
+/// * `stdio` the emulator's terminal, in raw mode
+/// * `pty` a new pseudo-terminal, its path is printed
+/// * `file:<path>` output appended to `path`
+/// * `unix:<path>` a Unix socket listening on `path`
+pub fn open(spec: &str) -> io::Result<Box<dyn CharDevice>> {
+    match spec.split_once(':') {
+        None if spec == "stdio" => Ok(Box::new(Stdio::raw()?)),
+        None if spec == "pty" => {
+            let pty = Pty::create()?;
+            println!("Serial line on {}", pty.path().display());
+            Ok(Box::new(pty))
+        }
+        Some(("file", path)) => Ok(Box::new(LogFile::create(Path::new(path))?)),
+        Some(("unix", path)) => Ok(Box::new(UnixSocket::create(Path::new(path))?)),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("unknown serial backend {:?}", spec),
+        )),
+    }
+}
+
+/// Host stdin, shared by every `Stdio` device. A thread does the blocking reads.
+static STDIN: OnceLock<Mutex<Receiver<u8>>> = OnceLock::new();
+
+/// Set while `pause_stdin` hands stdin to someone else
+static PAUSED: AtomicBool = AtomicBool::new(false);
+/// Held by the reader thread while it waits for or reads input
+static READER: Mutex<()> = Mutex::new(());
+
+fn stdin() -> &'static Mutex<Receiver<u8>> {
+    STDIN.get_or_init(|| {
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            let mut buf = [0u8; 64];
+            loop {
+                if PAUSED.load(Ordering::Acquire) {
+                    thread::sleep(Duration::from_millis(50));
+                    continue;
+                }
+                let _reading = READER.lock().unwrap();
+                if PAUSED.load(Ordering::Acquire) {
+                    continue;
+                }
+                // Wait briefly rather than block in read, so pausing does not lose a byte
+                let mut fd = libc::pollfd {
+                    fd: libc::STDIN_FILENO,
+                    events: libc::POLLIN,
+                    revents: 0,
+                };
+                if unsafe { libc::poll(&mut fd, 1, 50) } <= 0 {
+                    continue;
+                }
+                let count =
+                    unsafe { libc::read(libc::STDIN_FILENO, buf.as_mut_ptr().cast(), buf.len()) };
+                match count {
+                    0 => return,
+                    count if count < 0 => match io::Error::last_os_error().kind() {
+                        io::ErrorKind::Interrupted => continue,
+                        _ => return,
+                    },
+                    count => {
+                        for byte in &buf[..count as usize] {
+                            if tx.send(*byte).is_err() {
+                                return;
+                            }
+                        }
+                    }
+                }
+            }
+        });
+        Mutex::new(rx)
+    })
+}
+
+/// Terminal settings of stdin before `Stdio::raw` changed them
+static TERMINAL: OnceLock<libc::termios> = OnceLock::new();
+/// Terminal settings `Stdio::raw` applied
+static RAW_TERMINAL: OnceLock<libc::termios> = OnceLock::new();
+
+/// Gives stdin back to the emulator, see `pause_stdin`
+pub struct StdinPause {
+    _reader: MutexGuard<'static, ()>,
+}
+
+/// Stops the guest reading stdin until the returned value is dropped, putting the
+/// terminal back the way it was meanwhile. For the debugger, which prompts on stdin.
+pub fn pause_stdin() -> StdinPause {
+    PAUSED.store(true, Ordering::Release);
+    let reader = READER.lock().unwrap_or_else(|e| e.into_inner());
+    restore_terminal();
+    StdinPause { _reader: reader }
+}
+
+impl Drop for StdinPause {
+    fn drop(&mut self) {
+        if let Some(termios) = RAW_TERMINAL.get() {
+            unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, termios) };
+        }
+        PAUSED.store(false, Ordering::Release);
+    }
+}
+
+extern "C" fn restore_terminal() {
+    if let Some(termios) = TERMINAL.get() {
+        unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, termios) };
+    }
+}
+
+/// The emulator's own stdin and stdout
+pub struct Stdio {}
+
+impl Stdio {
+    pub fn create() -> Stdio {
+        stdin();
+        Stdio {}
+    }
+
+    /// Like `create`, but puts the host terminal in raw mode until the emulator exits, so
+    /// keys reach the guest as they are typed, without echo or line editing. Ctrl-C still
+    /// interrupts the emulator.
+    pub fn raw() -> io::Result<Stdio> {
+        if unsafe { libc::isatty(libc::STDIN_FILENO) } == 1 && TERMINAL.get().is_none() {
+            let mut termios: libc::termios = unsafe { std::mem::zeroed() };
+            if unsafe { libc::tcgetattr(libc::STDIN_FILENO, &mut termios) } != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let _ = TERMINAL.set(termios);
+            unsafe { libc::atexit(restore_terminal) };
+
+            unsafe { libc::cfmakeraw(&mut termios) };
+            // Keep Ctrl-C for the emulator and "\n" starting a new line on output
+            termios.c_lflag |= libc::ISIG;
+            termios.c_oflag |= libc::OPOST;
+            if unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &termios) } != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let _ = RAW_TERMINAL.set(termios);
+        }
+        Ok(Stdio::create())
+    }
+}
+
+impl CharDevice for Stdio {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let input = stdin().lock().unwrap();
+        let mut count = 0;
+        while count < buf.len() {
+            match input.try_recv() {
+                Ok(byte) => buf[count] = byte,
+                Err(_) => break,
+            }
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> io::Result<()> {
+        let mut stdout = io::stdout().lock();
+        stdout.write_all(bytes)?;
+        stdout.flush()
+    }
+}
+
+/// Output appended to a host file, there is never any input
+pub struct LogFile {
+    file: File,
+}
+
+impl LogFile {
+    pub fn create(path: &Path) -> io::Result<LogFile> {
+        Ok(LogFile {
+            file: OpenOptions::new().create(true).append(true).open(path)?,
+        })
+    }
+}
+
+impl CharDevice for LogFile {
+    fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+        Ok(0)
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.file.write_all(bytes)
+    }
+}
+
+/// A listening Unix socket, connected to one client at a time.
+///
+/// Output written while no client is connected is dropped, as on a serial line with
+/// nothing plugged in.
+pub struct UnixSocket {
+    listener: UnixListener,
+    client: Option<UnixStream>,
+}
+
+impl UnixSocket {
+    /// Listens on `path`, which must not exist yet
+    pub fn create(path: &Path) -> io::Result<UnixSocket> {
+        let listener = UnixListener::bind(path)?;
+        listener.set_nonblocking(true)?;
+        Ok(UnixSocket {
+            listener,
+            client: None,
+        })
+    }
+
+    /// The connected client, accepting a waiting one if there is none
+    fn client(&mut self) -> Option<&mut UnixStream> {
+        if self.client.is_none() {
+            if let Ok((stream, _)) = self.listener.accept() {
+                if stream.set_nonblocking(true).is_ok() {
+                    self.client = Some(stream);
+                }
+            }
+        }
+        self.client.as_mut()
+    }
+}
+
+impl CharDevice for UnixSocket {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let client = match self.client() {
+            Some(client) => client,
+            None => return Ok(0),
+        };
+        match client.read(buf) {
+            // The client hung up, wait for the next one
+            Ok(0) => {
+                self.client = None;
+                Ok(0)
+            }
+            Ok(count) => Ok(count),
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => Ok(0),
+            Err(e) => {
+                self.client = None;
+                Err(e)
+            }
+        }
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> io::Result<()> {
+        let client = match self.client() {
+            Some(client) => client,
+            None => return Ok(()),
+        };
+        // The stream is non-blocking for reads, let writes wait for the client
+        client.set_nonblocking(false)?;
+        let result = client.write_all(bytes);
+        client.set_nonblocking(true)?;
+        if result.is_err() {
+            self.client = None;
+        }
+        result
+    }
+}
+
+/// The master side of a host pseudo-terminal. Attach to the guest with a terminal
+/// program on the slave side given by `path`, e.g. `screen /dev/pts/3`.
+///
+/// Output written while nothing has the slave open is dropped once the kernel buffer
+/// fills up.
+pub struct Pty {
+    master: File,
+    path: PathBuf,
+}
+
+impl Pty {
+    pub fn create() -> io::Result<Pty> {
+        let fd = unsafe { libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY | libc::O_NONBLOCK) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let master = unsafe { File::from_raw_fd(fd) };
+        if unsafe { libc::grantpt(fd) } != 0 || unsafe { libc::unlockpt(fd) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut name = [0 as libc::c_char; 64];
+        if unsafe { libc::ptsname_r(fd, name.as_mut_ptr(), name.len()) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let path = unsafe { CStr::from_ptr(name.as_ptr()) };
+
+        // A serial line passes bytes through untouched, without echo or line editing
+        let mut termios: libc::termios = unsafe { std::mem::zeroed() };
+        if unsafe { libc::tcgetattr(fd, &mut termios) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        unsafe { libc::cfmakeraw(&mut termios) };
+        if unsafe { libc::tcsetattr(fd, libc::TCSANOW, &termios) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Pty {
+            master,
+            path: PathBuf::from(path.to_string_lossy().into_owned()),
+        })
+    }
+
+    /// Path of the slave side, like `/dev/pts/3`
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl CharDevice for Pty {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.master.read(buf) {
+            Ok(count) => Ok(count),
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => Ok(0),
+            // Nothing has the slave side open
+            Err(e) if e.raw_os_error() == Some(libc::EIO) => Ok(0),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> io::Result<()> {
+        match self.master.write_all(bytes) {
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => Ok(()),
+            result => result,
+        }
+    }
+}
@@ -4,15 +4,19 @@ use std::usize;
 
 use elfloader::VAddr;
 
+use crate::aia::InterruptFile;
 use crate::debugger::{Debugger, DebuggerResult};
+use crate::fpu::{RoundingMode, RM_DYNAMIC};
 use crate::instructions::decoder::InstructionDecoder;
 use crate::mmu::MMU;
 use crate::pipeline::{PipelineStages, Stage};
+use crate::pmp::PMP;
 
 pub type Register = u8;
 pub type RegisterValue = u64;
 
 type Registers = [RegisterValue; 32];
+type FRegisters = [u64; 32];
 type CSRRegisters = [RegisterValue; 4096];
 
 macro_rules! cpu_trace {
@@ -37,7 +41,7 @@ pub enum TrapCause {
 
     InstructionAddressMisaligned = 0,
     InstructionAccessFault(VAddr) = 1,
-    IllegalInstruction(VAddr) = 2,
+    IllegalInstruction(u64) = 2,
     Breakpoint = 3,
     LoadAddressMisaligned = 4,
     LoadAccessFault(VAddr) = 5,
@@ -46,9 +50,9 @@ pub enum TrapCause {
     EnvCallFromUMode = 8,
     EnvCallFromSMode = 9,
     EnvCallFromMMode = 11,
-    InstructionPageFault = 12,
-    LoadPageFault = 13,
-    StorePageFault = 15,
+    InstructionPageFault(VAddr) = 12,
+    LoadPageFault(VAddr) = 13,
+    StorePageFault(VAddr) = 15,
 }
 
 impl Display for TrapCause {
@@ -77,9 +81,9 @@ impl TrapCause {
             | TrapCause::EnvCallFromUMode
             | TrapCause::EnvCallFromSMode
             | TrapCause::EnvCallFromMMode
-            | TrapCause::InstructionPageFault
-            | TrapCause::LoadPageFault
-            | TrapCause::StorePageFault => u16::from(*self) as u64,
+            | TrapCause::InstructionPageFault(_)
+            | TrapCause::LoadPageFault(_)
+            | TrapCause::StorePageFault(_) => u16::from(*self) as u64,
 
             TrapCause::UserSoftwareIrq
             | TrapCause::SupervisorSoftIrq
@@ -92,6 +96,21 @@ impl TrapCause {
             | TrapCause::MachineExternalIrq => (u16::from(*self) - 0x100) as u64 + interrupt_bit,
         }
     }
+
+    /// Value written to `mtval`/`stval`: the faulting address for access and page faults,
+    /// and the instruction bits for illegal instructions
+    pub fn tval(&self) -> u64 {
+        match *self {
+            TrapCause::InstructionAccessFault(addr)
+            | TrapCause::IllegalInstruction(addr)
+            | TrapCause::LoadAccessFault(addr)
+            | TrapCause::StoreAccessFault(addr)
+            | TrapCause::InstructionPageFault(addr)
+            | TrapCause::LoadPageFault(addr)
+            | TrapCause::StorePageFault(addr) => addr,
+            _ => 0,
+        }
+    }
 }
 
 impl From<TrapCause> for u16 {
@@ -108,9 +127,9 @@ impl From<TrapCause> for u16 {
             TrapCause::EnvCallFromUMode => 8,
             TrapCause::EnvCallFromSMode => 9,
             TrapCause::EnvCallFromMMode => 11,
-            TrapCause::InstructionPageFault => 12,
-            TrapCause::LoadPageFault => 13,
-            TrapCause::StorePageFault => 15,
+            TrapCause::InstructionPageFault(_) => 12,
+            TrapCause::LoadPageFault(_) => 13,
+            TrapCause::StorePageFault(_) => 15,
             TrapCause::UserSoftwareIrq => 0x100,
             TrapCause::SupervisorSoftIrq => 0x101,
             TrapCause::MachineSoftIrq => 0x103,
@@ -153,7 +172,7 @@ impl From<PrivMode> for u64 {
 }
 
 #[allow(non_camel_case_types)]
-#[derive(Debug, Copy, Clone, PartialEq, FromPrimitive)]
+#[derive(Debug, Copy, Clone, PartialEq, PartialOrd, FromPrimitive)]
 #[repr(u16)]
 pub enum CSRRegister {
     ustatus = 0,
@@ -172,12 +191,18 @@ pub enum CSRRegister {
     sideleg = 0x103,
     sie = 0x104,
     stvec = 0x105,
+    scounteren = 0x106,
 
     sscratch = 0x140,
     sepc = 0x141,
     scause = 0x142,
     stval = 0x143,
     sip = 0x144,
+    stimecmp = 0x14d,
+    siselect = 0x150,
+    sireg = 0x151,
+    stimecmph = 0x15d, // RV32 only
+    stopei = 0x15c,
 
     satp = 0x180,
 
@@ -189,15 +214,99 @@ pub enum CSRRegister {
     mtvec = 0x305,
     mcounteren = 0x306,
     mstatush = 0x307,
+    menvcfg = 0x30a,
+    menvcfgh = 0x31a, // RV32 only
 
     mscratch = 0x340,
     mepc = 0x341,
     mcause = 0x342,
     mtval = 0x343,
     mip = 0x344,
+    miselect = 0x350,
+    mireg = 0x351,
+    mtopei = 0x35c,
 
     pmpcfg0 = 0x3a0,
+    pmpcfg1 = 0x3a1,
+    pmpcfg2 = 0x3a2,
+    pmpcfg3 = 0x3a3,
+    pmpcfg4 = 0x3a4,
+    pmpcfg5 = 0x3a5,
+    pmpcfg6 = 0x3a6,
+    pmpcfg7 = 0x3a7,
+    pmpcfg8 = 0x3a8,
+    pmpcfg9 = 0x3a9,
+    pmpcfg10 = 0x3aa,
+    pmpcfg11 = 0x3ab,
+    pmpcfg12 = 0x3ac,
+    pmpcfg13 = 0x3ad,
+    pmpcfg14 = 0x3ae,
+    pmpcfg15 = 0x3af,
+
     pmpaddr0 = 0x3b0,
+    pmpaddr1 = 0x3b1,
+    pmpaddr2 = 0x3b2,
+    pmpaddr3 = 0x3b3,
+    pmpaddr4 = 0x3b4,
+    pmpaddr5 = 0x3b5,
+    pmpaddr6 = 0x3b6,
+    pmpaddr7 = 0x3b7,
+    pmpaddr8 = 0x3b8,
+    pmpaddr9 = 0x3b9,
+    pmpaddr10 = 0x3ba,
+    pmpaddr11 = 0x3bb,
+    pmpaddr12 = 0x3bc,
+    pmpaddr13 = 0x3bd,
+    pmpaddr14 = 0x3be,
+    pmpaddr15 = 0x3bf,
+    pmpaddr16 = 0x3c0,
+    pmpaddr17 = 0x3c1,
+    pmpaddr18 = 0x3c2,
+    pmpaddr19 = 0x3c3,
+    pmpaddr20 = 0x3c4,
+    pmpaddr21 = 0x3c5,
+    pmpaddr22 = 0x3c6,
+    pmpaddr23 = 0x3c7,
+    pmpaddr24 = 0x3c8,
+    pmpaddr25 = 0x3c9,
+    pmpaddr26 = 0x3ca,
+    pmpaddr27 = 0x3cb,
+    pmpaddr28 = 0x3cc,
+    pmpaddr29 = 0x3cd,
+    pmpaddr30 = 0x3ce,
+    pmpaddr31 = 0x3cf,
+    pmpaddr32 = 0x3d0,
+    pmpaddr33 = 0x3d1,
+    pmpaddr34 = 0x3d2,
+    pmpaddr35 = 0x3d3,
+    pmpaddr36 = 0x3d4,
+    pmpaddr37 = 0x3d5,
+    pmpaddr38 = 0x3d6,
+    pmpaddr39 = 0x3d7,
+    pmpaddr40 = 0x3d8,
+    pmpaddr41 = 0x3d9,
+    pmpaddr42 = 0x3da,
+    pmpaddr43 = 0x3db,
+    pmpaddr44 = 0x3dc,
+    pmpaddr45 = 0x3dd,
+    pmpaddr46 = 0x3de,
+    pmpaddr47 = 0x3df,
+    pmpaddr48 = 0x3e0,
+    pmpaddr49 = 0x3e1,
+    pmpaddr50 = 0x3e2,
+    pmpaddr51 = 0x3e3,
+    pmpaddr52 = 0x3e4,
+    pmpaddr53 = 0x3e5,
+    pmpaddr54 = 0x3e6,
+    pmpaddr55 = 0x3e7,
+    pmpaddr56 = 0x3e8,
+    pmpaddr57 = 0x3e9,
+    pmpaddr58 = 0x3ea,
+    pmpaddr59 = 0x3eb,
+    pmpaddr60 = 0x3ec,
+    pmpaddr61 = 0x3ed,
+    pmpaddr62 = 0x3ee,
+    pmpaddr63 = 0x3ef,
 
     debug0 = 0x7a0,
     debug1 = 0x7a1,
@@ -222,11 +331,15 @@ pub enum CSRRegister {
     timeh = 0xc81,
     instreth = 0xc82,
 
+    stopi = 0xdb0,
+
     mvendorid = 0xf11,
     marchid = 0xf12,
     mimpid = 0xf13,
     mhartid = 0xf14,
     mconfigptr = 0xf15,
+
+    mtopi = 0xfb0,
 }
 
 #[allow(non_snake_case)]
@@ -240,7 +353,9 @@ impl CpuExtensions {
     const RVM: u64 = 0x0000000000001000;
     // Atomic Instructions
     const RVA: u64 = 0x0000000000000001;
-    //    const RVF: u64 = 0x0000000000000020;
+    // Single-Precision Floating-Point
+    const RVF: u64 = 0x0000000000000020;
+    // Double-Precision Floating-Point
     const RVD: u64 = 0x0000000000000008;
     // Standard Extension for Vector Operations
     const RVV: u64 = 0x0000000000200000;
@@ -258,16 +373,31 @@ impl CpuExtensions {
 
 pub const CYCLES_PER_INSTRUCTION: usize = 6;
 
+/// Size in bytes of the naturally aligned reservation set registered by LR.W/LR.D
+pub const RESERVATION_SET_SIZE: u64 = 8;
+
 pub struct Core {
     pub id: u64,
     pub xlen: Xlen,
     registers: Registers,
+    fregisters: FRegisters,
     csrs: CSRRegisters,
+    // Backs the pmpcfg/pmpaddr CSRs, copied to the MMU when it changes
+    pmp: PMP,
+    pmp_dirty: bool,
+    // The M-level and S-level IMSIC interrupt files, once the MMU has the AIA selected
+    imsic: Option<[InterruptFile; 2]>,
+    // MEIP and SEIP as last driven by the interrupt files
+    imsic_lines: RegisterValue,
     pmode: PrivMode,
     pc: u64,
     wfi: bool,
     pub prev_pc: u64,
+    // Bits of the instruction in flight, reported in tval when it turns out to be illegal
+    pub instruction_bits: u32,
     pub stage: Stage,
+    // Load reservation held by LR.W/LR.D, consumed by SC.W/SC.D
+    reservation: Option<VAddr>,
     pub cycles: u64,
     // Debug usage:
     step_cycles: usize,
@@ -287,15 +417,22 @@ impl Core {
             id,
             xlen: Xlen::Bits64,
             registers,
+            fregisters: [0; 32],
             csrs,
+            pmp: PMP::create(),
+            pmp_dirty: true,
+            imsic: None,
+            imsic_lines: 0,
             pmode: PrivMode::Machine,
             pc: 0,
             prev_pc: 0,
+            instruction_bits: 0,
             cycles: 0,
             step_cycles: 0,
             breakpoint_address: None, //Some(0x8000076a),
             wfi: false,
             stage: Stage::FETCH,
+            reservation: None,
             symbols: HashMap::new(),
             symboltrace: VecDeque::<(VAddr, String)>::new(),
             instruction_decoder: InstructionDecoder::create(),
@@ -318,6 +455,7 @@ impl Core {
     pub fn reset(&mut self, pc: u64) {
         self.pc = pc;
         self.stage = Stage::FETCH;
+        self.reservation = None;
         let (mxl, bits) = match self.xlen {
             Xlen::Bits32 => (1, 32),
             Xlen::Bits64 => (2, 64),
@@ -327,9 +465,10 @@ impl Core {
         let unsupported = CpuExtensions::RVH // hypervisor
             | CpuExtensions::RVE // embedded
             | CpuExtensions::RVV // vector
-            | CpuExtensions::RVJ // dynlang
-            | CpuExtensions::RVD; // double floats;
+            | CpuExtensions::RVJ; // dynlang
         let supported = CpuExtensions::RVA
+            | CpuExtensions::RVF // single floats
+            | CpuExtensions::RVD // double floats
             | CpuExtensions::RVI
             | CpuExtensions::RVC
             | CpuExtensions::RVM // int mul/div
@@ -338,8 +477,13 @@ impl Core {
             | (bits - 2);
         self.write_csr(CSRRegister::misa, supported & (!unsupported));
 
-        // Set mxl
-        self.csrs[CSRRegister::mstatus as usize] = self.read_csr(CSRRegister::mstatus) | (2 << 31)
+        // UXL is derived from xlen when mstatus is read. FS starts out Initial, so that
+        // bare-metal programs which never touch mstatus can use the FPU.
+        self.csrs[CSRRegister::mstatus as usize] = MstatusMask::FS_INITIAL;
+
+        // Resetting is the only way to clear locked PMP entries
+        self.pmp = PMP::create();
+        self.pmp_dirty = true;
     }
 
     #[inline]
@@ -434,6 +578,181 @@ impl Core {
         res
     }
 
+    /// Sign-extends a 32-bit memory word into a register value, as done for
+    /// LW, LR.W and the .W AMOs.
+    #[inline]
+    pub fn extend_word(&self, value: u32) -> RegisterValue {
+        match self.xlen {
+            Xlen::Bits32 => value as u64,
+            Xlen::Bits64 => value as i32 as i64 as u64,
+            Xlen::Bits128 => panic!("128bit not supported"),
+        }
+    }
+
+    /// Registers a reservation on `addr`, as done by LR.W/LR.D
+    pub fn set_reservation(&mut self, addr: VAddr) {
+        self.reservation = Some(addr);
+    }
+
+    /// Consumes the reservation, returning true if it was held on `addr` (SC.W/SC.D)
+    pub fn take_reservation(&mut self, addr: VAddr) -> bool {
+        self.reservation.take() == Some(addr)
+    }
+
+    /// Drops the reservation if a store of `size` bytes at `addr` touches its reservation set
+    pub fn invalidate_reservation(&mut self, addr: VAddr, size: u64) {
+        if let Some(reserved) = self.reservation {
+            let set_start = reserved & !(RESERVATION_SET_SIZE - 1);
+            let set_end = set_start + RESERVATION_SET_SIZE;
+            if addr < set_end && addr.wrapping_add(size) > set_start {
+                self.reservation = None;
+            }
+        }
+    }
+
+    /// The exception raised by the instruction in flight when its encoding is reserved,
+    /// not implemented, or not allowed in the current privilege mode
+    pub fn illegal_instruction(&self) -> TrapCause {
+        TrapCause::IllegalInstruction(self.instruction_bits as u64)
+    }
+
+    /// Resolves the CSR addressed by a Zicsr instruction, failing if it does not exist or
+    /// may not be accessed from the current privilege mode
+    pub fn csr_register(&self, csr: u16, write: bool) -> Result<CSRRegister, TrapCause> {
+        let reg: CSRRegister = match num::FromPrimitive::from_u16(csr) {
+            Some(reg) => reg,
+            None => return Err(self.illegal_instruction()),
+        };
+        // Bits 9:8 hold the lowest privilege level allowed, and 11:10 = 0b11 marks read-only
+        let privileged = (csr >> 8) & 3 > self.pmode as u16;
+        let read_only = write && (csr >> 10) & 3 == 3;
+        let unavailable = match reg {
+            CSRRegister::satp => {
+                self.pmode == PrivMode::Supervisor
+                    && (self.read_csr(CSRRegister::mstatus) >> 20) & 1 == 1
+            }
+            CSRRegister::fflags | CSRRegister::frm | CSRRegister::fcsr => !self.fp_enabled(),
+            CSRRegister::cycle | CSRRegister::time | CSRRegister::instret => {
+                !self.counter_enabled(csr)
+            }
+            CSRRegister::cycleh | CSRRegister::timeh | CSRRegister::instreth => {
+                self.xlen != Xlen::Bits32 || !self.counter_enabled(csr)
+            }
+            CSRRegister::stimecmp => !self.stimecmp_enabled(),
+            CSRRegister::miselect
+            | CSRRegister::mtopei
+            | CSRRegister::mtopi
+            | CSRRegister::siselect
+            | CSRRegister::stopei
+            | CSRRegister::stopi => self.imsic.is_none(),
+            CSRRegister::mireg => !self.ireg_available(CSRRegister::miselect),
+            CSRRegister::sireg => !self.ireg_available(CSRRegister::siselect),
+            CSRRegister::stimecmph => self.xlen != Xlen::Bits32 || !self.stimecmp_enabled(),
+            CSRRegister::mstatush | CSRRegister::minstreth | CSRRegister::menvcfgh => {
+                self.xlen != Xlen::Bits32
+            }
+            _ if (CSRRegister::pmpcfg0..=CSRRegister::pmpcfg15).contains(&reg) => {
+                self.xlen != Xlen::Bits32 && csr & 1 == 1
+            }
+            _ => false,
+        };
+        match privileged || read_only || unavailable {
+            true => Err(self.illegal_instruction()),
+            false => Ok(reg),
+        }
+    }
+
+    /// Checks the bit of `mcounteren`, and `scounteren` in U-mode, that lets lower
+    /// privilege modes read the unprivileged counter `csr`
+    fn counter_enabled(&self, csr: u16) -> bool {
+        let bit = 1 << (csr & 0x1f);
+        let enabled = |counteren: CSRRegister| self.csrs[counteren as usize] & bit != 0;
+        match self.pmode {
+            PrivMode::Machine => true,
+            PrivMode::Supervisor => enabled(CSRRegister::mcounteren),
+            _ => enabled(CSRRegister::mcounteren) && enabled(CSRRegister::scounteren),
+        }
+    }
+
+    /// Below M-mode, `stimecmp` needs both menvcfg.STCE and mcounteren.TM
+    fn stimecmp_enabled(&self) -> bool {
+        self.pmode == PrivMode::Machine
+            || (self.csrs[CSRRegister::menvcfg as usize] & MenvcfgMask::STCE != 0
+                && self.csrs[CSRRegister::mcounteren as usize] & 2 != 0)
+    }
+
+    /// With Sstc enabled, STIP is read-only and tells whether `time` reached `stimecmp`
+    fn update_stip(&mut self) {
+        if self.csrs[CSRRegister::menvcfg as usize] & MenvcfgMask::STCE == 0 {
+            return;
+        }
+        let pending =
+            self.csrs[CSRRegister::time as usize] >= self.csrs[CSRRegister::stimecmp as usize];
+        let mip = &mut self.csrs[CSRRegister::mip as usize];
+        match pending {
+            true => *mip |= MipMask::STIP as RegisterValue,
+            false => *mip &= !(MipMask::STIP as RegisterValue),
+        }
+    }
+
+    /// `*ireg` accesses the interrupt file register selected by `*iselect`, if it exists
+    fn ireg_available(&self, iselect: CSRRegister) -> bool {
+        self.imsic.is_some() && InterruptFile::has_register(self.csrs[iselect as usize], self.xlen)
+    }
+
+    /// The interrupt file of the M-level or S-level, for the `*iselect`, `*ireg` and
+    /// `*topei` CSRs of that level
+    fn interrupt_file(&self, level: PrivMode) -> Option<&InterruptFile> {
+        let index = (level != PrivMode::Machine) as usize;
+        self.imsic.as_ref().map(|files| &files[index])
+    }
+
+    fn interrupt_file_mut(&mut self, level: PrivMode) -> Option<&mut InterruptFile> {
+        let index = (level != PrivMode::Machine) as usize;
+        self.imsic.as_mut().map(|files| &mut files[index])
+    }
+
+    /// With the IMSIC, MEIP and SEIP follow the interrupt files. As with the PLIC, the
+    /// bits are only touched when the lines change.
+    fn update_eip(&mut self) {
+        let files = match &self.imsic {
+            Some(files) => files,
+            None => return,
+        };
+        let mut lines = 0;
+        for (file, mask) in files.iter().zip([MipMask::MEIP, MipMask::SEIP]) {
+            if file.is_interrupting() {
+                lines |= mask as RegisterValue;
+            }
+        }
+        let changed = lines ^ self.imsic_lines;
+        self.imsic_lines = lines;
+        let mip = &mut self.csrs[CSRRegister::mip as usize];
+        *mip = (*mip & !changed) | (lines & changed);
+    }
+
+    /// Formats the highest priority interrupt of `pending` for `mtopi`/`stopi`. All
+    /// the `iprio` priorities are zero, so interrupts go in their default order and
+    /// report a priority of 1.
+    fn topi(pending: RegisterValue) -> RegisterValue {
+        [
+            MipMask::MEIP,
+            MipMask::MSIP,
+            MipMask::MTIP,
+            MipMask::SEIP,
+            MipMask::SSIP,
+            MipMask::STIP,
+        ]
+        .iter()
+        .map(|mask| *mask as RegisterValue)
+        .find(|mask| pending & mask != 0)
+        .map_or(0, |mask| (mask.trailing_zeros() as RegisterValue) << 16 | 1)
+    }
+
+    pub fn clear_reservation(&mut self) {
+        self.reservation = None;
+    }
+
     pub fn read_csr(&self, reg: CSRRegister) -> RegisterValue {
         // SSTATUS, SIE, and SIP are subsets of MSTATUS, MIE, and MIP
         let value = match reg {
@@ -441,13 +760,51 @@ impl Core {
             CSRRegister::frm => (self.csrs[CSRRegister::fcsr as usize] >> 5) & 0x7,
             //                               UXL
             // 10000000000000000000000000000011 00000000 00001101 11100000 00000000
-            CSRRegister::sstatus => self.csrs[CSRRegister::mstatus as usize] & 0x80000003000de162,
+            CSRRegister::sstatus => {
+                (self.csrs[CSRRegister::mstatus as usize] & 0x80000003000de162) | self.sd_bit()
+            }
             CSRRegister::sie => self.csrs[CSRRegister::mie as usize] & 0x222,
             CSRRegister::sip => self.csrs[CSRRegister::mip as usize] & 0x222,
-            CSRRegister::time => todo!(),
+            CSRRegister::timeh => self.csrs[CSRRegister::time as usize] >> 32,
+            CSRRegister::stimecmph => self.csrs[CSRRegister::stimecmp as usize] >> 32,
+            CSRRegister::menvcfgh => self.csrs[CSRRegister::menvcfg as usize] >> 32,
+            CSRRegister::mireg | CSRRegister::sireg => {
+                let (level, iselect) = match reg {
+                    CSRRegister::mireg => (PrivMode::Machine, CSRRegister::miselect),
+                    _ => (PrivMode::Supervisor, CSRRegister::siselect),
+                };
+                self.interrupt_file(level).map_or(0, |file| {
+                    file.read_register(self.csrs[iselect as usize], self.xlen)
+                })
+            }
+            CSRRegister::mtopei => self
+                .interrupt_file(PrivMode::Machine)
+                .map_or(0, |file| file.topei() as RegisterValue),
+            CSRRegister::stopei => self
+                .interrupt_file(PrivMode::Supervisor)
+                .map_or(0, |file| file.topei() as RegisterValue),
+            CSRRegister::mtopi => {
+                let pending = self.csrs[CSRRegister::mip as usize]
+                    & self.csrs[CSRRegister::mie as usize]
+                    & !self.csrs[CSRRegister::mideleg as usize];
+                Core::topi(pending)
+            }
+            CSRRegister::stopi => {
+                let pending = self.csrs[CSRRegister::mip as usize]
+                    & self.csrs[CSRRegister::mie as usize]
+                    & self.csrs[CSRRegister::mideleg as usize]
+                    & 0x222;
+                Core::topi(pending)
+            }
+            _ if (CSRRegister::pmpcfg0..=CSRRegister::pmpcfg15).contains(&reg) => self
+                .pmp
+                .read_cfg(reg as usize - CSRRegister::pmpcfg0 as usize, self.xlen),
+            _ if (CSRRegister::pmpaddr0..=CSRRegister::pmpaddr63).contains(&reg) => self
+                .pmp
+                .read_addr(reg as usize - CSRRegister::pmpaddr0 as usize),
             CSRRegister::mstatus => {
                 let uxl = ((self.xlen as u64) / 32) << 32;
-                self.csrs[CSRRegister::sstatus as usize] | uxl
+                self.csrs[CSRRegister::mstatus as usize] | uxl | self.sd_bit()
             }
             _ => self.csrs[reg as usize],
         };
@@ -462,15 +819,23 @@ impl Core {
             CSRRegister::fflags => {
                 self.csrs[CSRRegister::fcsr as usize] &= !0x1f;
                 self.csrs[CSRRegister::fcsr as usize] |= value & 0x1f;
+                self.set_fs_dirty();
             }
             CSRRegister::frm => {
                 self.csrs[CSRRegister::fcsr as usize] &= !0xe0;
                 self.csrs[CSRRegister::fcsr as usize] |= (value << 5) & 0xe0;
+                self.set_fs_dirty();
+            }
+            CSRRegister::fcsr => {
+                self.csrs[CSRRegister::fcsr as usize] = value & 0xff;
+                self.set_fs_dirty();
             }
             CSRRegister::sstatus => {
                 //1000000000000000000000000000001100000000000011011110000000000000
+                // SD is read-only, it is derived from FS
                 self.csrs[CSRRegister::mstatus as usize] &= !0x80000003000de162;
-                self.csrs[CSRRegister::mstatus as usize] |= value & 0x80000003000de162;
+                self.csrs[CSRRegister::mstatus as usize] |=
+                    value & 0x80000003000de162 & !MstatusMask::SD;
             }
             CSRRegister::sie => {
                 // sie is subset of mie
@@ -480,16 +845,100 @@ impl Core {
             CSRRegister::sip => {
                 self.csrs[CSRRegister::mip as usize] &= !0x222;
                 self.csrs[CSRRegister::mip as usize] |= value & 0x222;
+                self.update_stip();
+            }
+            CSRRegister::mip => {
+                self.csrs[reg as usize] = value;
+                self.update_stip();
             }
             CSRRegister::mideleg => {
                 self.csrs[reg as usize] = value & 0x666; // from qemu
             }
             CSRRegister::mstatus => {
-                self.csrs[CSRRegister::mstatus as usize] &= !0x80000003000de162;
-                self.csrs[CSRRegister::mstatus as usize] |= value & 0x80000003000de162;
+                // MPP is WARL, the reserved mode 2 leaves the previous mode in place
+                let value = match (value >> 11) & 3 {
+                    2 => (value & !(3 << 11)) | (old & (3 << 11)),
+                    _ => value,
+                };
+                self.csrs[CSRRegister::mstatus as usize] &= !MstatusMask::WRITABLE;
+                self.csrs[CSRRegister::mstatus as usize] |= value & MstatusMask::WRITABLE;
                 //     //                self.csrs[CSRRegister::mstatus as usize] = value;
             }
-            CSRRegister::time => todo!(),
+            CSRRegister::satp => {
+                // Writes selecting an unsupported translation scheme have no effect
+                if self.xlen == Xlen::Bits64 && !matches!(value >> 60, 0 | 8 | 9 | 10) {
+                    return;
+                }
+                self.csrs[reg as usize] = value;
+            }
+            CSRRegister::menvcfg => {
+                let value = match self.xlen {
+                    Xlen::Bits32 => (old & !0xffff_ffff) | (value & 0xffff_ffff),
+                    _ => value,
+                };
+                self.csrs[reg as usize] = value & MenvcfgMask::STCE;
+                self.update_stip();
+            }
+            CSRRegister::menvcfgh => {
+                let menvcfg = self.csrs[CSRRegister::menvcfg as usize];
+                self.csrs[CSRRegister::menvcfg as usize] =
+                    ((menvcfg & 0xffff_ffff) | value << 32) & MenvcfgMask::STCE;
+                self.update_stip();
+            }
+            CSRRegister::stimecmp => {
+                self.csrs[reg as usize] = match self.xlen {
+                    Xlen::Bits32 => (old & !0xffff_ffff) | (value & 0xffff_ffff),
+                    _ => value,
+                };
+                self.update_stip();
+            }
+            CSRRegister::stimecmph => {
+                let stimecmp = self.csrs[CSRRegister::stimecmp as usize];
+                self.csrs[CSRRegister::stimecmp as usize] =
+                    (stimecmp & 0xffff_ffff) | (value & 0xffff_ffff) << 32;
+                self.update_stip();
+            }
+            CSRRegister::miselect | CSRRegister::siselect => {
+                self.csrs[reg as usize] = value & 0xfff;
+            }
+            CSRRegister::mireg | CSRRegister::sireg => {
+                let (level, iselect) = match reg {
+                    CSRRegister::mireg => (PrivMode::Machine, CSRRegister::miselect),
+                    _ => (PrivMode::Supervisor, CSRRegister::siselect),
+                };
+                let (select, xlen) = (self.csrs[iselect as usize], self.xlen);
+                if let Some(file) = self.interrupt_file_mut(level) {
+                    file.write_register(select, value, xlen);
+                }
+                self.update_eip();
+            }
+            // Writing `*topei` claims the interrupt it reports, whatever the value
+            CSRRegister::mtopei | CSRRegister::stopei => {
+                let level = match reg {
+                    CSRRegister::mtopei => PrivMode::Machine,
+                    _ => PrivMode::Supervisor,
+                };
+                if let Some(file) = self.interrupt_file_mut(level) {
+                    file.claim();
+                }
+                self.update_eip();
+            }
+            CSRRegister::mcounteren | CSRRegister::scounteren => {
+                // Only the CY, TM and IR bits, there are no hardware performance counters
+                self.csrs[reg as usize] = value & 0x7;
+            }
+            _ if (CSRRegister::pmpcfg0..=CSRRegister::pmpcfg15).contains(&reg) => {
+                let xlen = self.xlen;
+                self.pmp
+                    .write_cfg(reg as usize - CSRRegister::pmpcfg0 as usize, value, xlen);
+                self.pmp_dirty = true;
+            }
+            _ if (CSRRegister::pmpaddr0..=CSRRegister::pmpaddr63).contains(&reg) => {
+                let xlen = self.xlen;
+                self.pmp
+                    .write_addr(reg as usize - CSRRegister::pmpaddr0 as usize, value, xlen);
+                self.pmp_dirty = true;
+            }
 
             _ => {
                 self.csrs[reg as usize] = value;
@@ -529,8 +978,66 @@ impl Core {
         }
     }
 
+    #[inline]
+    pub fn read_fregister(&self, reg: Register) -> u64 {
+        self.fregisters[reg as usize]
+    }
+
+    /// Writes an FP register, marking the FP state dirty
+    #[inline]
+    pub fn write_fregister(&mut self, reg: Register, value: u64) {
+        self.fregisters[reg as usize] = value;
+        self.set_fs_dirty();
+    }
+
+    /// FP instructions and CSRs are only accessible while mstatus.FS is not Off
+    #[inline]
+    pub fn fp_enabled(&self) -> bool {
+        self.csrs[CSRRegister::mstatus as usize] & MstatusMask::FS != 0
+    }
+
+    fn set_fs_dirty(&mut self) {
+        self.csrs[CSRRegister::mstatus as usize] |= MstatusMask::FS_DIRTY;
+    }
+
+    /// The SD bit summarises whether FS is Dirty, it lives in the top bit of mstatus/sstatus
+    fn sd_bit(&self) -> RegisterValue {
+        match self.csrs[CSRRegister::mstatus as usize] & MstatusMask::FS {
+            MstatusMask::FS_DIRTY => 1 << (self.xlen as u64 - 1),
+            _ => 0,
+        }
+    }
+
+    /// Resolves the `rm` field of an FP instruction, returning None for reserved encodings
+    pub fn rounding_mode(&self, rm: u8) -> Option<RoundingMode> {
+        let rm = match rm {
+            RM_DYNAMIC => self.read_csr(CSRRegister::frm) as u8,
+            _ => rm,
+        };
+        num::FromPrimitive::from_u8(rm)
+    }
+
+    /// Accumulates exception flags raised by an FP instruction into fflags
+    pub fn accrue_fflags(&mut self, flags: u8) {
+        if flags != 0 {
+            self.csrs[CSRRegister::fcsr as usize] |= flags as RegisterValue;
+            self.set_fs_dirty();
+        }
+    }
+
     pub fn cycle(&mut self, mmu: &mut MMU) {
         self.cycles = self.cycles + 1;
+        // The time CSR is a read-only shadow of the CLINT's mtime
+        self.csrs[CSRRegister::time as usize] = mmu.mtime();
+        self.update_stip();
+        // MSIs written to the IMSIC pages of this hart land in its interrupt files
+        if mmu.has_aia() {
+            let files = self.imsic.get_or_insert_with(Default::default);
+            for (level, id) in mmu.take_msis(self.id as usize) {
+                files[(level != PrivMode::Machine) as usize].set_pending(id);
+            }
+            self.update_eip();
+        }
         if self.step_cycles > 0 {
             self.step_cycles = self.step_cycles - 1;
             if self.step_cycles == 0 {
@@ -566,6 +1073,10 @@ impl Core {
                 mmu.update_privilege_mode(self.pmode);
                 mmu.update_satp(self.read_csr(CSRRegister::satp), self.xlen);
                 mmu.update_mstatus(self.read_csr(CSRRegister::mstatus));
+                if self.pmp_dirty {
+                    mmu.update_pmp(self.pmp);
+                    self.pmp_dirty = false;
+                }
             }
             _ => {}
         }
@@ -634,6 +1145,26 @@ impl MipMask {
     pub const MEIP: u16 = 0x800;
 }
 
+// Masks for the `mstatus` CSR register
+#[non_exhaustive]
+pub struct MstatusMask {}
+impl MstatusMask {
+    pub const FS: u64 = 0x6000;
+    pub const FS_INITIAL: u64 = 0x2000;
+    pub const FS_DIRTY: u64 = 0x6000;
+    pub const SD: u64 = 0x8000000000000000;
+    /// SIE, MIE, SPIE, MPIE, SPP, MPP, FS, MPRV, SUM, MXR, TVM, TW and TSR
+    pub const WRITABLE: u64 = 0x7e79aa;
+}
+
+// Masks for the `menvcfg` CSR register
+#[non_exhaustive]
+pub struct MenvcfgMask {}
+impl MenvcfgMask {
+    /// Sstc: `stimecmp` is accessible below M-mode and drives STIP
+    pub const STCE: u64 = 1 << 63;
+}
+
 impl From<MipMask> for u64 {
     fn from(value: MipMask) -> Self {
         value.into()
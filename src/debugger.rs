@@ -2,6 +2,7 @@ use elfloader::VAddr;
 use rustyline::error::ReadlineError;
 use rustyline::Result;
 
+use crate::chardev;
 use crate::cpu::{CSRRegister, Core, Register, TrapCause, CYCLES_PER_INSTRUCTION};
 use crate::disassembler::Disassembler;
 use crate::memory::MemoryOperations;
@@ -27,6 +28,8 @@ impl Debugger {
 
         Debugger::dump_status(core, mmu);
 
+        // The prompt reads the terminal, keep the guest's serial line off it meanwhile
+        let _stdin = chardev::pause_stdin();
         match self.main(core, mmu) {
             Ok(result) => result,
             Err(er) => DebuggerResult::Quit(er.to_string()),
@@ -74,6 +77,20 @@ impl Debugger {
                                 }
                                 None
                             }
+                            "tlb" => {
+                                let tlb = mmu.tlb();
+                                let lookups = tlb.hits() + tlb.misses();
+                                println!(
+                                    "TLB: {} hits, {} misses ({:.2}% hit rate)",
+                                    tlb.hits(),
+                                    tlb.misses(),
+                                    match lookups {
+                                        0 => 0.0,
+                                        _ => 100.0 * tlb.hits() as f64 / lookups as f64,
+                                    }
+                                );
+                                None
+                            }
                             "d" => {
                                 let addr = match split.len() > 0 {
                                     true => Debugger::parse_addr(core, split[1]),
@@ -13,20 +13,22 @@ impl Disassembler {
         let raw = RawInstruction::from_word(word, 0);
         let decoded = instruction_decoder.decode_instruction(raw);
         let s = match decoded {
-            DecodedInstruction::I(inst) => inst.select(xlen).to_string(),
-            DecodedInstruction::U(inst) => inst.select(xlen).to_string(),
-            DecodedInstruction::CI(param) => param.select(xlen).to_string(),
-            DecodedInstruction::J(param) => param.select(xlen).to_string(),
-            DecodedInstruction::CR(param) => param.select(xlen).to_string(),
-            DecodedInstruction::B(param) => param.select(xlen).to_string(),
-            DecodedInstruction::S(param) => param.select(xlen).to_string(),
-            DecodedInstruction::R(param) => param.select(xlen).to_string(),
-            DecodedInstruction::CSS(param) => param.select(xlen).to_string(),
-            DecodedInstruction::CIW(param) => param.select(xlen).to_string(),
-            DecodedInstruction::CL(param) => param.select(xlen).to_string(),
-            DecodedInstruction::CS(param) => param.select(xlen).to_string(),
-            DecodedInstruction::CB(param) => param.select(xlen).to_string(),
-            DecodedInstruction::CJ(param) => param.select(xlen).to_string(),
+            Ok(DecodedInstruction::I(inst)) => inst.select(xlen).to_string(),
+            Ok(DecodedInstruction::U(inst)) => inst.select(xlen).to_string(),
+            Ok(DecodedInstruction::CI(param)) => param.select(xlen).to_string(),
+            Ok(DecodedInstruction::J(param)) => param.select(xlen).to_string(),
+            Ok(DecodedInstruction::CR(param)) => param.select(xlen).to_string(),
+            Ok(DecodedInstruction::B(param)) => param.select(xlen).to_string(),
+            Ok(DecodedInstruction::S(param)) => param.select(xlen).to_string(),
+            Ok(DecodedInstruction::R(param)) => param.select(xlen).to_string(),
+            Ok(DecodedInstruction::R4(param)) => param.select(xlen).to_string(),
+            Ok(DecodedInstruction::CSS(param)) => param.select(xlen).to_string(),
+            Ok(DecodedInstruction::CIW(param)) => param.select(xlen).to_string(),
+            Ok(DecodedInstruction::CL(param)) => param.select(xlen).to_string(),
+            Ok(DecodedInstruction::CS(param)) => param.select(xlen).to_string(),
+            Ok(DecodedInstruction::CB(param)) => param.select(xlen).to_string(),
+            Ok(DecodedInstruction::CJ(param)) => param.select(xlen).to_string(),
+            Err(_) => String::from("ILLEGAL"),
         };
         let sp = s.split(" ").collect::<Vec<&str>>();
         if sp.len() > 0 && sp[0].len() < 4 {
@@ -0,0 +1,268 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::Path,
+};
+
+/// Granularity at which an overlay tracks what has been written
+const OVERLAY_SECTOR_SIZE: u64 = 512;
+
+/// Storage behind a block device
+pub trait DiskImage {
+    /// Size of the image in bytes
+    fn len(&self) -> u64;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Read-only images fail every write
+    fn read_only(&self) -> bool {
+        false
+    }
+
+    /// Fills `buf` from the image, starting at byte `offset`
+    fn read(&mut self, offset: u64, buf: &mut [u8]) -> io::Result<()>;
+
+    /// Writes `buf` to the image, starting at byte `offset`
+    fn write(&mut self, offset: u64, buf: &[u8]) -> io::Result<()>;
+
+    /// Makes previous writes durable
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// How a host image file backs a disk
+pub enum DiskMode<'a> {
+    /// Guest writes go straight to the image
+    Writable,
+    /// The image is only read, guest writes land in the given overlay file which is
+    /// created if missing, and reused by later runs if not
+    CopyOnWrite(&'a Path),
+    /// Guest writes fail
+    ReadOnly,
+}
+
+/// Opens the host file at `path` as a disk image
+pub fn open(path: &Path, mode: DiskMode) -> io::Result<Box<dyn DiskImage>> {
+    match mode {
+        DiskMode::Writable => Ok(Box::new(FileImage::open(
+            OpenOptions::new().read(true).write(true).open(path)?,
+            false,
+        )?)),
+        DiskMode::ReadOnly => Ok(Box::new(FileImage::open(File::open(path)?, true)?)),
+        DiskMode::CopyOnWrite(overlay) => Ok(Box::new(OverlayImage::open(
+            File::open(path)?,
+            OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .open(overlay)?,
+        )?)),
+    }
+}
+
+fn out_of_range() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::UnexpectedEof,
+        "access beyond the end of the disk",
+    )
+}
+
+fn check_range(offset: u64, len: usize, size: u64) -> io::Result<()> {
+    match offset.checked_add(len as u64) {
+        Some(end) if end <= size => Ok(()),
+        _ => Err(out_of_range()),
+    }
+}
+
+fn read_at(file: &mut File, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+    file.seek(SeekFrom::Start(offset))?;
+    file.read_exact(buf)
+}
+
+fn write_at(file: &mut File, offset: u64, buf: &[u8]) -> io::Result<()> {
+    file.seek(SeekFrom::Start(offset))?;
+    file.write_all(buf)
+}
+
+/// An image held in host memory, lost when the emulator exits
+impl DiskImage for Vec<u8> {
+    fn len(&self) -> u64 {
+        self.as_slice().len() as u64
+    }
+
+    fn read(&mut self, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+        check_range(offset, buf.len(), DiskImage::len(self))?;
+        let start = offset as usize;
+        buf.copy_from_slice(&self[start..start + buf.len()]);
+        Ok(())
+    }
+
+    fn write(&mut self, offset: u64, buf: &[u8]) -> io::Result<()> {
+        check_range(offset, buf.len(), DiskImage::len(self))?;
+        let start = offset as usize;
+        self[start..start + buf.len()].copy_from_slice(buf);
+        Ok(())
+    }
+}
+
+/// A host file accessed in place
+pub struct FileImage {
+    file: File,
+    len: u64,
+    read_only: bool,
+}
+
+impl FileImage {
+    pub fn open(file: File, read_only: bool) -> io::Result<FileImage> {
+        let len = file.metadata()?.len();
+        Ok(FileImage {
+            file,
+            len,
+            read_only,
+        })
+    }
+}
+
+impl DiskImage for FileImage {
+    fn len(&self) -> u64 {
+        self.len
+    }
+
+    fn read_only(&self) -> bool {
+        self.read_only
+    }
+
+    fn read(&mut self, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+        check_range(offset, buf.len(), self.len)?;
+        read_at(&mut self.file, offset, buf)
+    }
+
+    fn write(&mut self, offset: u64, buf: &[u8]) -> io::Result<()> {
+        if self.read_only {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "read-only disk",
+            ));
+        }
+        check_range(offset, buf.len(), self.len)?;
+        write_at(&mut self.file, offset, buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.sync_data()
+    }
+}
+
+/// A read-only base image with writes redirected to an overlay file.
+///
+/// The overlay holds written sectors at their offset in the image, followed by a bitmap
+/// with one bit per sector telling whether the overlay or the base holds its contents.
+pub struct OverlayImage {
+    base: File,
+    overlay: File,
+    len: u64,
+    written: Vec<u8>,
+}
+
+impl OverlayImage {
+    pub fn open(base: File, mut overlay: File) -> io::Result<OverlayImage> {
+        let len = base.metadata()?.len();
+        let sectors = (len + OVERLAY_SECTOR_SIZE - 1) / OVERLAY_SECTOR_SIZE;
+        let mut written = vec![0; ((sectors + 7) / 8) as usize];
+        let overlay_len = overlay.metadata()?.len();
+        if overlay_len == 0 {
+            // Fresh overlay, sparse where nothing has been written yet
+            overlay.set_len(len + written.len() as u64)?;
+        } else if overlay_len == len + written.len() as u64 {
+            read_at(&mut overlay, len, &mut written)?;
+        } else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "overlay does not match the size of its base image",
+            ));
+        }
+        Ok(OverlayImage {
+            base,
+            overlay,
+            len,
+            written,
+        })
+    }
+
+    fn is_written(&self, sector: u64) -> bool {
+        (self.written[(sector / 8) as usize] >> (sector % 8)) & 1 == 1
+    }
+
+    /// Splits `[offset, offset + len)` at sector boundaries, into `(sector, offset, len)`
+    fn segments(offset: u64, len: usize) -> impl Iterator<Item = (u64, u64, usize)> {
+        let end = offset + len as u64;
+        let mut pos = offset;
+        std::iter::from_fn(move || {
+            if pos >= end {
+                return None;
+            }
+            let sector = pos / OVERLAY_SECTOR_SIZE;
+            let next = ((sector + 1) * OVERLAY_SECTOR_SIZE).min(end);
+            let segment = (sector, pos, (next - pos) as usize);
+            pos = next;
+            Some(segment)
+        })
+    }
+}
+
+impl DiskImage for OverlayImage {
+    fn len(&self) -> u64 {
+        self.len
+    }
+
+    fn read(&mut self, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+        check_range(offset, buf.len(), self.len)?;
+        let mut done = 0;
+        for (sector, pos, len) in OverlayImage::segments(offset, buf.len()) {
+            let file = match self.is_written(sector) {
+                true => &mut self.overlay,
+                false => &mut self.base,
+            };
+            read_at(file, pos, &mut buf[done..done + len])?;
+            done += len;
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, offset: u64, buf: &[u8]) -> io::Result<()> {
+        check_range(offset, buf.len(), self.len)?;
+        let mut done = 0;
+        for (sector, pos, len) in OverlayImage::segments(offset, buf.len()) {
+            if !self.is_written(sector) {
+                // Partially written sectors start out as a copy of the base
+                let start = sector * OVERLAY_SECTOR_SIZE;
+                let end = (start + OVERLAY_SECTOR_SIZE).min(self.len);
+                if pos != start || pos + len as u64 != end {
+                    let mut copy = vec![0; (end - start) as usize];
+                    read_at(&mut self.base, start, &mut copy)?;
+                    write_at(&mut self.overlay, start, &copy)?;
+                }
+            }
+            write_at(&mut self.overlay, pos, &buf[done..done + len])?;
+            done += len;
+
+            if !self.is_written(sector) {
+                let index = (sector / 8) as usize;
+                self.written[index] |= 1 << (sector % 8);
+                write_at(
+                    &mut self.overlay,
+                    self.len + index as u64,
+                    &self.written[index..index + 1],
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.overlay.sync_data()
+    }
+}
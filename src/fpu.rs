@@ -0,0 +1,651 @@
+//! Software IEEE-754 arithmetic for the F and D extensions.
+//!
+//! Values are handled as raw bit patterns so that single and double precision share one
+//! implementation, parameterised by `FloatFormat`. Results are computed exactly (or with a
+//! sticky bit) in a wide integer significand and rounded once, which gives correct results
+//! for every rounding mode along with the exception flags accrued in `fflags`.
+
+use std::cmp::Ordering;
+
+/// Rounding modes, as encoded in the `rm` field of FP instructions and in `frm`
+#[derive(Debug, Copy, Clone, PartialEq, FromPrimitive)]
+#[repr(u8)]
+pub enum RoundingMode {
+    RNE = 0b000, // Round to Nearest, ties to Even
+    RTZ = 0b001, // Round towards Zero
+    RDN = 0b010, // Round Down (towards -inf)
+    RUP = 0b011, // Round Up (towards +inf)
+    RMM = 0b100, // Round to Nearest, ties to Max Magnitude
+}
+
+/// The `rm` field value selecting the dynamic rounding mode in `frm`
+pub const RM_DYNAMIC: u8 = 0b111;
+
+// Masks for the accrued exception flags in `fflags`
+#[non_exhaustive]
+pub struct FFlags {}
+impl FFlags {
+    pub const NX: u8 = 0x01; // Inexact
+    pub const UF: u8 = 0x02; // Underflow
+    pub const OF: u8 = 0x04; // Overflow
+    pub const DZ: u8 = 0x08; // Divide by Zero
+    pub const NV: u8 = 0x10; // Invalid Operation
+}
+
+/// Floating-point formats, as encoded in the `fmt` field of OP-FP and R4-type instructions
+#[derive(Debug, Copy, Clone, PartialEq, FromPrimitive)]
+#[repr(u8)]
+pub enum FloatFormat {
+    S = 0b00,
+    D = 0b01,
+}
+
+/// A finite value `(-1)^sign * sig * 2^exp`
+#[derive(Debug, Copy, Clone)]
+struct Unpacked {
+    sign: bool,
+    exp: i32,
+    sig: u128,
+}
+
+impl Unpacked {
+    /// Shifts the significand so that its most significant bit is at bit 125, leaving
+    /// room for the carry of an addition
+    fn normalize(self) -> Unpacked {
+        let shift = 125 - msb(self.sig);
+        Unpacked {
+            sign: self.sign,
+            exp: self.exp - shift,
+            sig: self.sig << shift,
+        }
+    }
+}
+
+#[inline]
+fn msb(value: u128) -> i32 {
+    127 - value.leading_zeros() as i32
+}
+
+/// Shifts right, OR-ing any bits shifted out into the least significant bit
+fn shift_right_jam(value: u128, shift: i32) -> u128 {
+    if shift <= 0 {
+        value
+    } else if shift >= 128 {
+        (value != 0) as u128
+    } else {
+        (value >> shift) | ((value & ((1 << shift) - 1)) != 0) as u128
+    }
+}
+
+/// Shifts `sig` right by `shift` bits, rounding according to `rm`. Returns the rounded
+/// value and whether any non-zero bits were discarded.
+fn round_shift(sig: u128, shift: i32, sign: bool, rm: RoundingMode) -> (u128, bool) {
+    if shift <= 0 {
+        return (sig << -shift, false);
+    }
+    let (q, inexact, half) = match shift.cmp(&128) {
+        Ordering::Greater => (0, sig != 0, Ordering::Less),
+        Ordering::Equal => (0, sig != 0, sig.cmp(&(1 << 127))),
+        Ordering::Less => {
+            let rem = sig & ((1 << shift) - 1);
+            (sig >> shift, rem != 0, rem.cmp(&(1 << (shift - 1))))
+        }
+    };
+    let increment = inexact
+        && match rm {
+            RoundingMode::RNE => {
+                half == Ordering::Greater || (half == Ordering::Equal && q & 1 == 1)
+            }
+            RoundingMode::RMM => half != Ordering::Less,
+            RoundingMode::RTZ => false,
+            RoundingMode::RDN => sign,
+            RoundingMode::RUP => !sign,
+        };
+    (q + increment as u128, inexact)
+}
+
+/// Integer square root, rounded down
+fn isqrt(value: u128) -> u128 {
+    let mut op = value;
+    let mut res = 0;
+    let mut one = 1 << 126;
+    while one > op {
+        one >>= 2;
+    }
+    while one != 0 {
+        if op >= res + one {
+            op -= res + one;
+            res = (res >> 1) + one;
+        } else {
+            res >>= 1;
+        }
+        one >>= 2;
+    }
+    res
+}
+
+impl FloatFormat {
+    #[inline]
+    fn width(self) -> u32 {
+        match self {
+            FloatFormat::S => 32,
+            FloatFormat::D => 64,
+        }
+    }
+
+    #[inline]
+    fn frac_bits(self) -> u32 {
+        match self {
+            FloatFormat::S => 23,
+            FloatFormat::D => 52,
+        }
+    }
+
+    #[inline]
+    fn exp_bits(self) -> u32 {
+        self.width() - self.frac_bits() - 1
+    }
+
+    #[inline]
+    fn bias(self) -> i32 {
+        (1 << (self.exp_bits() - 1)) - 1
+    }
+
+    #[inline]
+    fn max_exp(self) -> u64 {
+        (1 << self.exp_bits()) - 1
+    }
+
+    #[inline]
+    fn frac_mask(self) -> u64 {
+        (1 << self.frac_bits()) - 1
+    }
+
+    #[inline]
+    pub fn sign_mask(self) -> u64 {
+        1 << (self.width() - 1)
+    }
+
+    #[inline]
+    fn biased_exp(self, a: u64) -> u64 {
+        (a >> self.frac_bits()) & self.max_exp()
+    }
+
+    #[inline]
+    fn sign(self, a: u64) -> bool {
+        a & self.sign_mask() != 0
+    }
+
+    #[inline]
+    fn is_nan(self, a: u64) -> bool {
+        self.biased_exp(a) == self.max_exp() && a & self.frac_mask() != 0
+    }
+
+    #[inline]
+    fn is_snan(self, a: u64) -> bool {
+        self.is_nan(a) && a & (1 << (self.frac_bits() - 1)) == 0
+    }
+
+    #[inline]
+    fn is_inf(self, a: u64) -> bool {
+        self.biased_exp(a) == self.max_exp() && a & self.frac_mask() == 0
+    }
+
+    #[inline]
+    fn is_zero(self, a: u64) -> bool {
+        a & !self.sign_mask() == 0
+    }
+
+    pub fn canonical_nan(self) -> u64 {
+        match self {
+            FloatFormat::S => 0x7fc00000,
+            FloatFormat::D => 0x7ff8000000000000,
+        }
+    }
+
+    fn zero(self, sign: bool) -> u64 {
+        if sign {
+            self.sign_mask()
+        } else {
+            0
+        }
+    }
+
+    fn infinity(self, sign: bool) -> u64 {
+        self.zero(sign) | (self.max_exp() << self.frac_bits())
+    }
+
+    fn max_finite(self, sign: bool) -> u64 {
+        self.zero(sign) | ((self.max_exp() - 1) << self.frac_bits()) | self.frac_mask()
+    }
+
+    /// NaN-boxes a value for storage in the 64-bit FP register file
+    pub fn box_value(self, a: u64) -> u64 {
+        match self {
+            FloatFormat::S => 0xffffffff00000000 | (a & 0xffffffff),
+            FloatFormat::D => a,
+        }
+    }
+
+    /// Reads a value from an FP register. Improperly NaN-boxed singles read as the canonical NaN.
+    pub fn unbox(self, register: u64) -> u64 {
+        match self {
+            FloatFormat::S if register >> 32 != 0xffffffff => self.canonical_nan(),
+            FloatFormat::S => register & 0xffffffff,
+            FloatFormat::D => register,
+        }
+    }
+
+    /// Unpacks a finite value; zeroes unpack to a zero significand
+    fn unpack(self, a: u64) -> Unpacked {
+        let biased = self.biased_exp(a);
+        let frac = a & self.frac_mask();
+        let (exp, sig) = match biased {
+            0 => (1 - self.bias() - self.frac_bits() as i32, frac),
+            _ => (
+                biased as i32 - self.bias() - self.frac_bits() as i32,
+                frac | (1 << self.frac_bits()),
+            ),
+        };
+        Unpacked {
+            sign: self.sign(a),
+            exp,
+            sig: sig as u128,
+        }
+    }
+
+    /// Rounds `(-1)^sign * sig * 2^exp` to this format. Tininess is detected after rounding.
+    fn round_pack(self, sign: bool, exp: i32, sig: u128, rm: RoundingMode) -> (u64, u8) {
+        if sig == 0 {
+            return (self.zero(sign), 0);
+        }
+        let precision = self.frac_bits() as i32 + 1;
+        let emin = 1 - self.bias();
+        let leading = exp + msb(sig);
+
+        let mut lsb = leading.max(emin) - (precision - 1);
+        let (mut q, inexact) = round_shift(sig, lsb - exp, sign, rm);
+        if q >> precision != 0 {
+            q >>= 1;
+            lsb += 1;
+        }
+
+        let mut flags = 0;
+        if inexact {
+            flags |= FFlags::NX;
+            if leading < emin {
+                // Only a value just below 2^emin can round up out of the subnormal range
+                let (unbounded, _) = round_shift(sig, leading - (precision - 1) - exp, sign, rm);
+                if leading < emin - 1 || unbounded >> precision == 0 {
+                    flags |= FFlags::UF;
+                }
+            }
+        }
+
+        let biased = match q >> (precision - 1) {
+            0 => 0,
+            _ => lsb + precision - 1 + self.bias(),
+        };
+        if biased >= self.max_exp() as i32 {
+            let to_infinity = match rm {
+                RoundingMode::RNE | RoundingMode::RMM => true,
+                RoundingMode::RTZ => false,
+                RoundingMode::RDN => sign,
+                RoundingMode::RUP => !sign,
+            };
+            let value = match to_infinity {
+                true => self.infinity(sign),
+                false => self.max_finite(sign),
+            };
+            return (value, FFlags::OF | FFlags::NX);
+        }
+
+        (
+            self.zero(sign) | ((biased as u64) << self.frac_bits()) | (q as u64 & self.frac_mask()),
+            flags,
+        )
+    }
+
+    /// Any operation with a NaN input returns the canonical NaN, signalling NaNs also raise NV
+    fn propagate_nan(self, operands: &[u64]) -> (u64, u8) {
+        let flags = match operands.iter().any(|a| self.is_snan(*a)) {
+            true => FFlags::NV,
+            false => 0,
+        };
+        (self.canonical_nan(), flags)
+    }
+
+    fn add_unpacked(self, a: Unpacked, b: Unpacked, rm: RoundingMode) -> (u64, u8) {
+        if a.sig == 0 && b.sig == 0 {
+            let sign = match rm {
+                RoundingMode::RDN => a.sign || b.sign,
+                _ => a.sign && b.sign,
+            };
+            return (self.zero(sign), 0);
+        } else if a.sig == 0 {
+            return self.round_pack(b.sign, b.exp, b.sig, rm);
+        } else if b.sig == 0 {
+            return self.round_pack(a.sign, a.exp, a.sig, rm);
+        }
+
+        let (a, b) = (a.normalize(), b.normalize());
+        let (big, small) = match a.exp >= b.exp {
+            true => (a, b),
+            false => (b, a),
+        };
+        let small_sig = shift_right_jam(small.sig, big.exp - small.exp);
+        if big.sign == small.sign {
+            return self.round_pack(big.sign, big.exp, big.sig + small_sig, rm);
+        }
+        match big.sig.cmp(&small_sig) {
+            Ordering::Greater => self.round_pack(big.sign, big.exp, big.sig - small_sig, rm),
+            Ordering::Less => self.round_pack(small.sign, big.exp, small_sig - big.sig, rm),
+            // "x - x" is +0 in all rounding modes except round down
+            Ordering::Equal => (self.zero(rm == RoundingMode::RDN), 0),
+        }
+    }
+
+    pub fn add(self, a: u64, b: u64, rm: RoundingMode) -> (u64, u8) {
+        if self.is_nan(a) || self.is_nan(b) {
+            return self.propagate_nan(&[a, b]);
+        }
+        match (self.is_inf(a), self.is_inf(b)) {
+            (true, true) if self.sign(a) != self.sign(b) => (self.canonical_nan(), FFlags::NV),
+            (true, _) => (a, 0),
+            (false, true) => (b, 0),
+            (false, false) => self.add_unpacked(self.unpack(a), self.unpack(b), rm),
+        }
+    }
+
+    pub fn sub(self, a: u64, b: u64, rm: RoundingMode) -> (u64, u8) {
+        self.add(a, b ^ self.sign_mask(), rm)
+    }
+
+    pub fn mul(self, a: u64, b: u64, rm: RoundingMode) -> (u64, u8) {
+        if self.is_nan(a) || self.is_nan(b) {
+            return self.propagate_nan(&[a, b]);
+        }
+        let sign = self.sign(a) != self.sign(b);
+        if self.is_inf(a) || self.is_inf(b) {
+            return match self.is_zero(a) || self.is_zero(b) {
+                true => (self.canonical_nan(), FFlags::NV),
+                false => (self.infinity(sign), 0),
+            };
+        }
+        let (ua, ub) = (self.unpack(a), self.unpack(b));
+        self.round_pack(sign, ua.exp + ub.exp, ua.sig * ub.sig, rm)
+    }
+
+    /// Computes `a * b + c` with a single rounding
+    pub fn mul_add(self, a: u64, b: u64, c: u64, rm: RoundingMode) -> (u64, u8) {
+        // The invalid flag is raised for inf * 0 even when the addend is a quiet NaN
+        let invalid_product =
+            (self.is_inf(a) && self.is_zero(b)) || (self.is_zero(a) && self.is_inf(b));
+        if self.is_nan(a) || self.is_nan(b) || self.is_nan(c) {
+            let (value, flags) = self.propagate_nan(&[a, b, c]);
+            return match invalid_product {
+                true => (value, FFlags::NV),
+                false => (value, flags),
+            };
+        }
+        if invalid_product {
+            return (self.canonical_nan(), FFlags::NV);
+        }
+
+        let product_sign = self.sign(a) != self.sign(b);
+        if self.is_inf(a) || self.is_inf(b) {
+            return match self.is_inf(c) && self.sign(c) != product_sign {
+                true => (self.canonical_nan(), FFlags::NV),
+                false => (self.infinity(product_sign), 0),
+            };
+        }
+        if self.is_inf(c) {
+            return (c, 0);
+        }
+
+        let (ua, ub) = (self.unpack(a), self.unpack(b));
+        let product = Unpacked {
+            sign: product_sign,
+            exp: ua.exp + ub.exp,
+            sig: ua.sig * ub.sig,
+        };
+        self.add_unpacked(product, self.unpack(c), rm)
+    }
+
+    pub fn div(self, a: u64, b: u64, rm: RoundingMode) -> (u64, u8) {
+        if self.is_nan(a) || self.is_nan(b) {
+            return self.propagate_nan(&[a, b]);
+        }
+        let sign = self.sign(a) != self.sign(b);
+        if self.is_inf(a) {
+            return match self.is_inf(b) {
+                true => (self.canonical_nan(), FFlags::NV),
+                false => (self.infinity(sign), 0),
+            };
+        }
+        if self.is_inf(b) {
+            return (self.zero(sign), 0);
+        }
+        if self.is_zero(b) {
+            return match self.is_zero(a) {
+                true => (self.canonical_nan(), FFlags::NV),
+                false => (self.infinity(sign), FFlags::DZ),
+            };
+        }
+        if self.is_zero(a) {
+            return (self.zero(sign), 0);
+        }
+
+        // Leave at least 63 quotient bits, the remainder is folded into a sticky bit
+        let (ua, ub) = (self.unpack(a), self.unpack(b));
+        let (shift_a, shift_b) = (126 - msb(ua.sig), 63 - msb(ub.sig));
+        let dividend = ua.sig << shift_a;
+        let divisor = ub.sig << shift_b;
+        let quotient = dividend / divisor;
+        let sticky = (dividend % divisor != 0) as u128;
+        let exp = (ua.exp - shift_a) - (ub.exp - shift_b) - 1;
+        self.round_pack(sign, exp, (quotient << 1) | sticky, rm)
+    }
+
+    pub fn sqrt(self, a: u64, rm: RoundingMode) -> (u64, u8) {
+        if self.is_nan(a) {
+            return self.propagate_nan(&[a]);
+        }
+        if self.is_zero(a) {
+            return (a, 0);
+        }
+        if self.sign(a) {
+            return (self.canonical_nan(), FFlags::NV);
+        }
+        if self.is_inf(a) {
+            return (a, 0);
+        }
+
+        let ua = self.unpack(a);
+        let mut shift = 124 - msb(ua.sig);
+        if (ua.exp - shift) % 2 != 0 {
+            shift += 1;
+        }
+        let sig = ua.sig << shift;
+        let root = isqrt(sig);
+        let sticky = (root * root != sig) as u128;
+        let exp = (ua.exp - shift) / 2 - 1;
+        self.round_pack(false, exp, (root << 1) | sticky, rm)
+    }
+
+    pub fn sgnj(self, a: u64, b: u64) -> u64 {
+        (a & !self.sign_mask()) | (b & self.sign_mask())
+    }
+
+    pub fn sgnjn(self, a: u64, b: u64) -> u64 {
+        (a & !self.sign_mask()) | (!b & self.sign_mask())
+    }
+
+    pub fn sgnjx(self, a: u64, b: u64) -> u64 {
+        a ^ (b & self.sign_mask())
+    }
+
+    /// Ordering of two non-NaN values, with -0 == +0
+    fn lt_ordered(self, a: u64, b: u64) -> bool {
+        if self.is_zero(a) && self.is_zero(b) {
+            return false;
+        }
+        let (magnitude_a, magnitude_b) = (a & !self.sign_mask(), b & !self.sign_mask());
+        match (self.sign(a), self.sign(b)) {
+            (true, false) => true,
+            (false, true) => false,
+            (true, true) => magnitude_a > magnitude_b,
+            (false, false) => magnitude_a < magnitude_b,
+        }
+    }
+
+    fn min_max(self, a: u64, b: u64, max: bool) -> (u64, u8) {
+        let flags = match self.is_snan(a) || self.is_snan(b) {
+            true => FFlags::NV,
+            false => 0,
+        };
+        let value = match (self.is_nan(a), self.is_nan(b)) {
+            (true, true) => self.canonical_nan(),
+            (true, false) => b,
+            (false, true) => a,
+            // -0 is considered less than +0
+            _ if self.is_zero(a) && self.is_zero(b) => match self.sign(a) != max {
+                true => a,
+                false => b,
+            },
+            _ => match self.lt_ordered(a, b) != max {
+                true => a,
+                false => b,
+            },
+        };
+        (value, flags)
+    }
+
+    pub fn min(self, a: u64, b: u64) -> (u64, u8) {
+        self.min_max(a, b, false)
+    }
+
+    pub fn max(self, a: u64, b: u64) -> (u64, u8) {
+        self.min_max(a, b, true)
+    }
+
+    /// Quiet comparison, only signalling NaNs raise NV
+    pub fn eq(self, a: u64, b: u64) -> (bool, u8) {
+        if self.is_nan(a) || self.is_nan(b) {
+            let (_, flags) = self.propagate_nan(&[a, b]);
+            return (false, flags);
+        }
+        (a == b || (self.is_zero(a) && self.is_zero(b)), 0)
+    }
+
+    /// Signalling comparison, any NaN raises NV
+    pub fn lt(self, a: u64, b: u64) -> (bool, u8) {
+        if self.is_nan(a) || self.is_nan(b) {
+            return (false, FFlags::NV);
+        }
+        (self.lt_ordered(a, b), 0)
+    }
+
+    /// Signalling comparison, any NaN raises NV
+    pub fn le(self, a: u64, b: u64) -> (bool, u8) {
+        if self.is_nan(a) || self.is_nan(b) {
+            return (false, FFlags::NV);
+        }
+        let (equal, _) = self.eq(a, b);
+        (equal || self.lt_ordered(a, b), 0)
+    }
+
+    /// The FCLASS bit mask for `a`
+    pub fn classify(self, a: u64) -> u64 {
+        let sign = self.sign(a);
+        let bit = if self.is_inf(a) {
+            match sign {
+                true => 0,
+                false => 7,
+            }
+        } else if self.is_nan(a) {
+            match self.is_snan(a) {
+                true => 8,
+                false => 9,
+            }
+        } else if self.is_zero(a) {
+            match sign {
+                true => 3,
+                false => 4,
+            }
+        } else if self.biased_exp(a) == 0 {
+            match sign {
+                true => 2,
+                false => 5,
+            }
+        } else {
+            match sign {
+                true => 1,
+                false => 6,
+            }
+        };
+        1 << bit
+    }
+
+    /// Converts to a `width`-bit integer. Out-of-range inputs and NaNs saturate and raise NV.
+    /// 32-bit results are sign-extended, as FCVT.W[U] writes them to XLEN registers.
+    pub fn to_integer(self, a: u64, signed: bool, width: u32, rm: RoundingMode) -> (u64, u8) {
+        let (min, max): (i128, i128) = match signed {
+            true => (-(1 << (width - 1)), (1 << (width - 1)) - 1),
+            false => (0, (1 << width) - 1),
+        };
+        let extend = |value: i128| match width {
+            32 => value as i32 as u64,
+            _ => value as u64,
+        };
+        let sign = self.sign(a);
+        let saturated = match sign && !self.is_nan(a) {
+            true => extend(min),
+            false => extend(max),
+        };
+        if self.is_nan(a) || self.is_inf(a) {
+            return (saturated, FFlags::NV);
+        }
+
+        let ua = self.unpack(a);
+        let (magnitude, inexact) = match ua.exp >= 0 {
+            true if ua.sig != 0 && msb(ua.sig) + ua.exp >= 64 => return (saturated, FFlags::NV),
+            true => (ua.sig << ua.exp, false),
+            false => round_shift(ua.sig, -ua.exp, sign, rm),
+        };
+        let value = match sign {
+            true => -(magnitude as i128),
+            false => magnitude as i128,
+        };
+        if value < min || value > max {
+            return (saturated, FFlags::NV);
+        }
+        let flags = match inexact {
+            true => FFlags::NX,
+            false => 0,
+        };
+        (extend(value), flags)
+    }
+
+    /// Converts a 64-bit integer, callers extend 32-bit sources according to signedness
+    pub fn from_integer(self, value: u64, signed: bool, rm: RoundingMode) -> (u64, u8) {
+        let (sign, magnitude) = match signed && (value as i64) < 0 {
+            true => (true, (value as i64).unsigned_abs()),
+            false => (false, value),
+        };
+        self.round_pack(sign, 0, magnitude as u128, rm)
+    }
+
+    /// Converts `a` from the `from` format into this one
+    pub fn convert(self, from: FloatFormat, a: u64, rm: RoundingMode) -> (u64, u8) {
+        if from.is_nan(a) {
+            let (_, flags) = from.propagate_nan(&[a]);
+            return (self.canonical_nan(), flags);
+        }
+        if from.is_inf(a) {
+            return (self.infinity(from.sign(a)), 0);
+        }
+        let ua = from.unpack(a);
+        self.round_pack(ua.sign, ua.exp, ua.sig, rm)
+    }
+}
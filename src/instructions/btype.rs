@@ -189,15 +189,16 @@ impl Display for Instruction<Btype> {
 impl InstructionSelector<Btype> for Btype {
     fn select(&self, _xlen: crate::cpu::Xlen) -> Instruction<Btype> {
         match self.opcode {
-            MajorOpcode::BRANCH => match num::FromPrimitive::from_u8(self.funct3).unwrap() {
-                BRANCH_Funct3::BNE => Instruction::BNE(self),
-                BRANCH_Funct3::BEQ => Instruction::BEQ(self),
-                BRANCH_Funct3::BGE => Instruction::BGE(self),
-                BRANCH_Funct3::BLT => Instruction::BLT(self),
-                BRANCH_Funct3::BLTU => Instruction::BLTU(self),
-                BRANCH_Funct3::BGEU => Instruction::BGEU(self),
+            MajorOpcode::BRANCH => match num::FromPrimitive::from_u8(self.funct3) {
+                Some(BRANCH_Funct3::BNE) => Instruction::BNE(self),
+                Some(BRANCH_Funct3::BEQ) => Instruction::BEQ(self),
+                Some(BRANCH_Funct3::BGE) => Instruction::BGE(self),
+                Some(BRANCH_Funct3::BLT) => Instruction::BLT(self),
+                Some(BRANCH_Funct3::BLTU) => Instruction::BLTU(self),
+                Some(BRANCH_Funct3::BGEU) => Instruction::BGEU(self),
+                None => Instruction::illegal(),
             },
-            _ => panic!("No such opcode for Btype instruction: {:#?}", self.opcode),
+            _ => Instruction::illegal(),
         }
     }
 }
@@ -174,11 +174,11 @@ impl InstructionSelector<CBtype> for CBtype {
                     0b00 => Instruction::C_SRLI(self),
                     0b01 => Instruction::C_SRAI(self),
                     0b10 => Instruction::C_ANDI(self),
-                    _ => panic!(),
+                    _ => Instruction::illegal(),
                 },
-                _ => todo!(),
+                _ => Instruction::illegal(),
             },
-            _ => panic!(),
+            _ => Instruction::illegal(),
         }
     }
 }
@@ -48,6 +48,12 @@ impl ImmediateDecoder<u16, u16> for CItype {
     }
 }
 
+/// The decoded immediate uses the C.LUI layout; doubleword loads from the stack take
+/// offset[5] from [12], offset[4:3] from [6:5] and offset[8:6] from [4:2]
+fn doubleword_offset(imm: u16) -> u16 {
+    (imm & 0x38) | ((imm & 0x7) << 6)
+}
+
 #[allow(non_snake_case)]
 impl Instruction<CItype> {
     pub fn C_ADDI16SP(args: &CItype) -> Instruction<CItype> {
@@ -123,7 +129,7 @@ impl Instruction<CItype> {
             mnemonic: &"C.LDSP",
             args: Some(*args),
             funct: |core, args| {
-                let ze_imm = args.imm as u64;
+                let ze_imm = doubleword_offset(args.imm) as u64;
                 let sp = core.read_register(2);
                 let addr = sp + (ze_imm);
                 // instruction_trace!(println!(
@@ -139,6 +145,21 @@ impl Instruction<CItype> {
         }
     }
 
+    pub fn C_FLDSP(args: &CItype) -> Instruction<CItype> {
+        Instruction {
+            mnemonic: "C.FLDSP",
+            args: Some(*args),
+            funct: |core, args| {
+                if !core.fp_enabled() {
+                    return Stage::TRAP(core.illegal_instruction());
+                }
+                let sp = core.read_register(2);
+                let addr = sp + doubleword_offset(args.imm) as u64;
+                Stage::MEMORY(crate::pipeline::MemoryAccess::FREAD64(addr, args.rs1_rd))
+            },
+        }
+    }
+
     pub fn C_LWSP(args: &CItype) -> Instruction<CItype> {
         Instruction {
             mnemonic: &"C.LWSP",
@@ -204,22 +225,27 @@ impl InstructionSelector<CItype> for CItype {
     fn select(&self, _xlen: Xlen) -> Instruction<CItype> {
         match self.opcode {
             CompressedOpcode::C1 => match num::FromPrimitive::from_u8(self.funct3 as u8).unwrap() {
+                // Both need a non-zero immediate
+                C1_Funct3::C_LUI if self.imm == 0 => Instruction::illegal(),
                 C1_Funct3::C_LUI => match self.rs1_rd != 0 && self.rs1_rd != 2 {
                     true => Instruction::C_LUI(self),
                     false => Instruction::C_ADDI16SP(self),
                 },
                 C1_Funct3::C_ADDI => Instruction::C_ADDI(self),
                 C1_Funct3::C_LI => Instruction::C_LI(self),
+                C1_Funct3::C_ADDIW if self.rs1_rd == 0 => Instruction::illegal(),
                 C1_Funct3::C_ADDIW => Instruction::C_ADDIW(self),
-                _ => panic!(),
+                _ => Instruction::illegal(),
             },
             CompressedOpcode::C2 => match num::FromPrimitive::from_u8(self.funct3 as u8).unwrap() {
                 C2_Funct3::C_SLLI => Instruction::C_SLLI(self),
+                C2_Funct3::C_LDSP | C2_Funct3::C_LWSP if self.rs1_rd == 0 => Instruction::illegal(),
                 C2_Funct3::C_LDSP => Instruction::C_LDSP(self),
                 C2_Funct3::C_LWSP => Instruction::C_LWSP(self),
-                _ => panic!(),
+                C2_Funct3::C_FLDSP => Instruction::C_FLDSP(self),
+                _ => Instruction::illegal(),
             },
-            _ => panic!("opcode {:#x?} unknown for CIType", self.opcode),
+            _ => Instruction::illegal(),
         }
     }
 }
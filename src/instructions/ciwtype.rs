@@ -73,9 +73,9 @@ impl Instruction<CIWtype> {
 
 impl InstructionSelector<CIWtype> for CIWtype {
     fn select(&self, _xlen: crate::cpu::Xlen) -> Instruction<CIWtype> {
-        match num::FromPrimitive::from_u8(self.funct3 as u8).unwrap() {
-            C0_Funct3::C_ADDI4SPN => Instruction::C_ADDI4SPN(self),
-            _ => panic!(),
+        match num::FromPrimitive::from_u8(self.funct3 as u8) {
+            Some(C0_Funct3::C_ADDI4SPN) if self.nzuimm != 0 => Instruction::C_ADDI4SPN(self),
+            _ => Instruction::illegal(),
         }
     }
 }
@@ -84,9 +84,9 @@ impl InstructionSelector<CJtype> for CJtype {
         match self.opcode {
             CompressedOpcode::C1 => match num::FromPrimitive::from_u8(self.funct3).unwrap() {
                 C1_Funct3::C_J => Instruction::C_J(self),
-                _ => todo!(),
+                _ => Instruction::illegal(),
             },
-            _ => panic!(),
+            _ => Instruction::illegal(),
         }
     }
 }
@@ -41,6 +41,11 @@ impl ImmediateDecoder<u16, u16> for CLtype {
     }
 }
 
+/// The decoded immediate uses the C.LW layout; doubleword loads place offset[7:6] in [6:5]
+fn doubleword_offset(imm: u16) -> u16 {
+    (imm & 0x78) | ((imm & 0x4) << 5)
+}
+
 #[allow(non_snake_case)]
 impl Instruction<CLtype> {
     pub fn C_LW(args: &CLtype) -> Instruction<CLtype> {
@@ -64,7 +69,7 @@ impl Instruction<CLtype> {
             args: Some(*args),
             funct: |core, args| {
                 let rs1v = core.read_register(args.rs1) as i64;
-                let addr = rs1v.wrapping_add(args.imm as i64) as u64;
+                let addr = rs1v.wrapping_add(doubleword_offset(args.imm) as i64) as u64;
                 // instruction_trace!(println!(
                 //     "C.LW: rs1v={:#x?} imm={:#x?} addr={:#x?}",
                 //     rs1v, args.imm, addr
@@ -73,6 +78,20 @@ impl Instruction<CLtype> {
             },
         }
     }
+    pub fn C_FLD(args: &CLtype) -> Instruction<CLtype> {
+        Instruction {
+            mnemonic: "C.FLD",
+            args: Some(*args),
+            funct: |core, args| {
+                if !core.fp_enabled() {
+                    return Stage::TRAP(core.illegal_instruction());
+                }
+                let rs1v = core.read_register(args.rs1) as i64;
+                let addr = rs1v.wrapping_add(doubleword_offset(args.imm) as i64) as u64;
+                Stage::MEMORY(crate::pipeline::MemoryAccess::FREAD64(addr, args.rd))
+            },
+        }
+    }
 }
 
 impl Display for Instruction<CLtype> {
@@ -93,12 +112,13 @@ impl Display for Instruction<CLtype> {
 impl InstructionSelector<CLtype> for CLtype {
     fn select(&self, _xlen: crate::cpu::Xlen) -> Instruction<CLtype> {
         match self.opcode {
-            CompressedOpcode::C0 => match num::FromPrimitive::from_u8(self.funct3 as u8).unwrap() {
-                C0_Funct3::C_LW => Instruction::C_LW(self),
-                C0_Funct3::C_LD => Instruction::C_LD(self),
-                _ => todo!(),
+            CompressedOpcode::C0 => match num::FromPrimitive::from_u8(self.funct3 as u8) {
+                Some(C0_Funct3::C_LW) => Instruction::C_LW(self),
+                Some(C0_Funct3::C_LD) => Instruction::C_LD(self),
+                Some(C0_Funct3::C_FLD) => Instruction::C_FLD(self),
+                _ => Instruction::illegal(),
             },
-            _ => panic!(),
+            _ => Instruction::illegal(),
         }
     }
 }
@@ -111,22 +111,23 @@ impl InstructionSelector<CRtype> for CRtype {
             CompressedOpcode::C2 => {
                 match self.funct1 {
                     // C.JR / C.MV
-                    0 => match self.rs2 {
-                        0 => Instruction::C_JR(self),
+                    0 => match (self.rs2, self.rs1_rd) {
+                        (0, 0) => Instruction::illegal(),
+                        (0, _) => Instruction::C_JR(self),
                         _ => Instruction::C_MV(self),
                     },
                     // C.EBREAK / C.JALR / C.ADD
                     1 => match self.rs2 {
                         0 => match self.rs1_rd {
-                            0 => Instruction::C_EBREAK(self), //todo!("C.EBREAK"),
+                            0 => Instruction::C_EBREAK(self),
                             _ => Instruction::C_JALR(self),
                         },
                         _ => Instruction::C_ADD(self),
                     },
-                    _ => panic!(),
+                    _ => Instruction::illegal(),
                 }
             }
-            _ => panic!(),
+            _ => Instruction::illegal(),
         }
     }
 }
@@ -43,6 +43,12 @@ impl ImmediateDecoder<u16, u16> for CSStype {
     }
 }
 
+/// The decoded immediate uses the C.SWSP layout; doubleword stores take offset[5:3] from [12:10]
+/// and offset[8:6] from [9:7]
+fn doubleword_offset(uimm: u16) -> u16 {
+    (uimm & 0xf8) | ((uimm & 0x4) << 6)
+}
+
 impl Display for Instruction<CSStype> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         if !self.args.is_some() {
@@ -65,7 +71,7 @@ impl Instruction<CSStype> {
             mnemonic: "C.SDSP",
             funct: |core, args| {
                 let sp = core.read_register(2);
-                let addr = sp + (args.uimm as u64);
+                let addr = sp + (doubleword_offset(args.uimm) as u64);
                 let rs2v = core.read_register(args.rs2);
                 instruction_trace!(println!(
                     "C.SDSP: args.uimm={:#x?}  rs2v: {:#x?}  sp={:#x?}  addr={:#x?}",
@@ -96,11 +102,28 @@ impl Instruction<CSStype> {
         }
     }
 
+    pub fn C_FSDSP(args: &CSStype) -> Instruction<CSStype> {
+        Instruction {
+            args: Some(*args),
+            mnemonic: "C.FSDSP",
+            funct: |core, args| {
+                if !core.fp_enabled() {
+                    return Stage::TRAP(core.illegal_instruction());
+                }
+                let sp = core.read_register(2);
+                let addr = sp + (doubleword_offset(args.uimm) as u64);
+                let rs2v = core.read_fregister(args.rs2);
+                Stage::MEMORY(MemoryAccess::WRITE64(addr, rs2v))
+            },
+        }
+    }
+
     pub fn C_FSWSP(args: &CSStype) -> Instruction<CSStype> {
         Instruction {
             args: Some(*args),
             mnemonic: "C.FSWSP",
-            funct: |_core, _args| todo!(),
+            // F is only supported on RV64
+            funct: |core, _args| Stage::TRAP(core.illegal_instruction()),
         }
     }
 }
@@ -108,13 +131,14 @@ impl Instruction<CSStype> {
 impl InstructionSelector<CSStype> for CSStype {
     fn select(&self, xlen: Xlen) -> Instruction<CSStype> {
         match self.funct3 {
+            Funct3::B101 => Instruction::C_FSDSP(self),
             Funct3::B110 => Instruction::C_SWSP(self),
             // C.FSWSP or C.SDSP
             Funct3::B111 => match xlen {
                 Xlen::Bits32 => Instruction::C_FSWSP(self),
                 _ => Instruction::C_SDSP(self),
             },
-            _ => panic!(),
+            _ => Instruction::illegal(),
         }
     }
 }
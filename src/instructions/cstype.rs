@@ -39,6 +39,11 @@ impl ImmediateDecoder<u16, u8> for CStype {
     }
 }
 
+/// The decoded offset uses the C.SW layout; doubleword stores place offset[7:6] in [6:5]
+fn doubleword_offset(offset: u8) -> u8 {
+    (offset & 0x78) | ((offset & 0x4) << 5)
+}
+
 impl CompressedFormatDecoder<CStype> for CStype {
     fn decode(word: u16) -> CStype {
         CStype {
@@ -143,7 +148,17 @@ impl Instruction<CStype> {
     cs_instruction!(C_SD, "C.SD", |core, args| {
         let rs1v = core.read_register(args.rs1_rd);
         let rs2v = core.read_register(args.rs2);
-        let addr = rs1v + args.offset as VAddr;
+        let addr = rs1v + doubleword_offset(args.offset) as VAddr;
+        Stage::MEMORY(MemoryAccess::WRITE64(addr, rs2v))
+    });
+
+    cs_instruction!(C_FSD, "C.FSD", |core, args| {
+        if !core.fp_enabled() {
+            return Stage::TRAP(core.illegal_instruction());
+        }
+        let rs1v = core.read_register(args.rs1_rd);
+        let rs2v = core.read_fregister(args.rs2);
+        let addr = rs1v + doubleword_offset(args.offset) as VAddr;
         Stage::MEMORY(MemoryAccess::WRITE64(addr, rs2v))
     });
 
@@ -158,10 +173,11 @@ impl Instruction<CStype> {
 impl InstructionSelector<CStype> for CStype {
     fn select(&self, _xlen: Xlen) -> Instruction<CStype> {
         match self.opcode {
-            CompressedOpcode::C0 => match num::FromPrimitive::from_u8(self.funct3 as u8).unwrap() {
-                C0_Funct3::C_SD => Instruction::C_SD(self),
-                C0_Funct3::C_SW => Instruction::C_SW(self),
-                _ => panic!(),
+            CompressedOpcode::C0 => match num::FromPrimitive::from_u8(self.funct3 as u8) {
+                Some(C0_Funct3::C_SD) => Instruction::C_SD(self),
+                Some(C0_Funct3::C_SW) => Instruction::C_SW(self),
+                Some(C0_Funct3::C_FSD) => Instruction::C_FSD(self),
+                _ => Instruction::illegal(),
             },
             CompressedOpcode::C1 => match self.funct6 {
                 0b100011 => match self.funct2 {
@@ -169,16 +185,16 @@ impl InstructionSelector<CStype> for CStype {
                     0b01 => Instruction::C_XOR(self),
                     0b10 => Instruction::C_OR(self),
                     0b11 => Instruction::C_AND(self),
-                    _ => panic!(),
+                    _ => Instruction::illegal(),
                 },
                 0b100111 => match self.funct2 {
                     0b00 => Instruction::C_SUBW(self),
                     0b01 => Instruction::C_ADDW(self),
-                    _ => panic!("reserved instruction?"),
+                    _ => Instruction::illegal(),
                 },
-                _ => panic!("{:#x?}", self.funct6),
+                _ => Instruction::illegal(),
             },
-            _ => panic!(),
+            _ => Instruction::illegal(),
         }
     }
 }
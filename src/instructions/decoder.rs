@@ -7,7 +7,13 @@
 //! takes one or several more extra match arms.
 
 use crate::{
-    instructions::{functions::C0_Funct3, map::FORMAT_MAP, CompressedFormat, InstructionFormat},
+    cpu::TrapCause,
+    instructions::{
+        functions::{C0_Funct3, Funct5, Funct7},
+        map::FORMAT_MAP,
+        opcodes::MajorOpcode,
+        CompressedFormat, InstructionFormat,
+    },
     pipeline::RawInstruction,
 };
 
@@ -24,6 +30,7 @@ use super::{
     functions::{C1_Funct3, C2_Funct3},
     itype::Itype,
     jtype::Jtype,
+    r4type::R4type,
     rtype::Rtype,
     stype::Stype,
     utype::Utype,
@@ -38,6 +45,7 @@ pub enum DecodedInstruction {
     S(Stype),
     U(Utype),
     R(Rtype),
+    R4(R4type),
     CR(CRtype),
     CI(CItype),
     CSS(CSStype),
@@ -57,39 +65,61 @@ impl InstructionDecoder {
         InstructionDecoder {}
     }
 
-    pub fn decode_instruction(&self, instruction: RawInstruction) -> DecodedInstruction {
+    /// Fails with an illegal instruction exception for encodings that do not map
+    /// onto any format, or whose function fields have no meaning
+    pub fn decode_instruction(
+        &self,
+        instruction: RawInstruction,
+    ) -> Result<DecodedInstruction, TrapCause> {
         let word = instruction.word;
-        match instruction.compressed {
+        let illegal = TrapCause::IllegalInstruction(word as u64);
+        Ok(match instruction.compressed {
             false => {
                 let opcode_idx = (word & 0x7f) as usize;
                 //debug_trace!(println!("opcode_idx: {}", opcode_idx));
                 match FORMAT_MAP[opcode_idx] {
-                    InstructionFormat::R => DecodedInstruction::R(Rtype::decode(word)),
+                    InstructionFormat::R => {
+                        // Rtype falls back to defaults for unknown function fields, so
+                        // they have to be rejected here
+                        let known = match num::FromPrimitive::from_u8(opcode_idx as u8) {
+                            Some(MajorOpcode::AMO) => {
+                                num::FromPrimitive::from_u32(word >> 27).map(|_: Funct5| ())
+                            }
+                            _ => num::FromPrimitive::from_u32(word >> 25).map(|_: Funct7| ()),
+                        };
+                        if known.is_none() {
+                            return Err(illegal);
+                        }
+                        DecodedInstruction::R(Rtype::decode(word))
+                    }
                     InstructionFormat::U => DecodedInstruction::U(Utype::decode(word)),
                     InstructionFormat::S => DecodedInstruction::S(Stype::decode(word)),
                     InstructionFormat::B => DecodedInstruction::B(Btype::decode(word)),
                     InstructionFormat::I => DecodedInstruction::I(Itype::decode(word)),
                     InstructionFormat::J => DecodedInstruction::J(Jtype::decode(word)),
-                    _ => panic!(
-                        "invalid format {:?} ({})",
-                        FORMAT_MAP[opcode_idx], opcode_idx
-                    ),
+                    InstructionFormat::R4 => DecodedInstruction::R4(R4type::decode(word)),
+                    InstructionFormat::Unknown => return Err(illegal),
                 }
             }
 
             true => {
+                // The all-zero halfword is defined to be illegal
+                if word == 0 {
+                    return Err(illegal);
+                }
                 let funct3 = ((word >> 13) & (0x7)) as u8; // bits 2,3,4
                 let format = match word & 3 {
                     0 => {
                         // Quadrant 0
-                        match num::FromPrimitive::from_u8(funct3).unwrap() {
-                            C0_Funct3::C_ADDI4SPN => CompressedFormat::CIW,
-                            C0_Funct3::C_LQ => CompressedFormat::CL,
-                            C0_Funct3::C_LW => CompressedFormat::CL,
-                            C0_Funct3::C_LD => CompressedFormat::CL,
-                            C0_Funct3::C_SQ => CompressedFormat::CS,
-                            C0_Funct3::C_SW => CompressedFormat::CS,
-                            C0_Funct3::C_SD => CompressedFormat::CS,
+                        match num::FromPrimitive::from_u8(funct3) {
+                            Some(C0_Funct3::C_ADDI4SPN) => CompressedFormat::CIW,
+                            Some(C0_Funct3::C_FLD) => CompressedFormat::CL,
+                            Some(C0_Funct3::C_LW) => CompressedFormat::CL,
+                            Some(C0_Funct3::C_LD) => CompressedFormat::CL,
+                            Some(C0_Funct3::C_FSD) => CompressedFormat::CS,
+                            Some(C0_Funct3::C_SW) => CompressedFormat::CS,
+                            Some(C0_Funct3::C_SD) => CompressedFormat::CS,
+                            None => return Err(illegal),
                         }
                     }
                     1 => match num::FromPrimitive::from_u8(funct3).unwrap() {
@@ -112,9 +142,11 @@ impl InstructionDecoder {
                     2 => match num::FromPrimitive::from_u8(funct3).unwrap() {
                         C2_Funct3::C_LDSP => CompressedFormat::CI,
                         C2_Funct3::C_LWSP => CompressedFormat::CI,
+                        C2_Funct3::C_FLDSP => CompressedFormat::CI,
                         C2_Funct3::C_SLLI => CompressedFormat::CI,
                         C2_Funct3::C_SDSP => CompressedFormat::CSS,
                         C2_Funct3::C_SWSP => CompressedFormat::CSS,
+                        C2_Funct3::C_FSDSP => CompressedFormat::CSS,
                         _ => {
                             CompressedFormat::CR
                             // let rs2 = ((word >> 2) & 31) as u8;
@@ -149,6 +181,6 @@ impl InstructionDecoder {
                     // ),
                 }
             }
-        }
+        })
     }
 }
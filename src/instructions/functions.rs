@@ -15,15 +15,26 @@ pub enum Funct3 {
 #[derive(Debug, Clone, Copy, PartialEq, FromPrimitive)]
 #[repr(u8)]
 pub enum Funct5 {
-    AMOADD_W = 0b00000,
-    AMOSWAP_W = 0b00001,
-    AMOXOR_W = 0b00100,
-    AMOAND_W = 0b01100,
-    AMOOR_W = 0b01000,
-    AMOMIN_W = 0b10000,
-    AMOMAX_W = 0b10100,
-    AMOMINU_W = 0b11000,
-    AMOMAXU_W = 0b11100,
+    AMOADD = 0b00000,
+    AMOSWAP = 0b00001,
+    LR = 0b00010,
+    SC = 0b00011,
+    AMOXOR = 0b00100,
+    AMOAND = 0b01100,
+    AMOOR = 0b01000,
+    AMOMIN = 0b10000,
+    AMOMAX = 0b10100,
+    AMOMINU = 0b11000,
+    AMOMAXU = 0b11100,
+}
+
+/// The width of an AMO/LR/SC is selected by funct3
+#[allow(non_camel_case_types)]
+#[derive(Debug, PartialEq, FromPrimitive)]
+#[repr(u8)]
+pub enum Amo_Funct3 {
+    W = 0b010,
+    D = 0b011,
 }
 
 #[allow(non_camel_case_types)]
@@ -43,10 +54,10 @@ pub enum Funct7 {
 #[repr(u8)]
 pub enum C0_Funct3 {
     C_ADDI4SPN = 0b000,
-    C_LQ = 0b001, // also C_FLD on RV32
+    C_FLD = 0b001, // C_LQ on RV128
     C_LW = 0b010,
-    C_LD = 0b011, // also C_FLW on RV32
-    C_SQ = 0b101, // Also C_FSD on RV32
+    C_LD = 0b011,  // also C_FLW on RV32
+    C_FSD = 0b101, // C_SQ on RV128
     C_SW = 0b110,
     C_SD = 0b111,
 }
@@ -61,6 +72,22 @@ pub enum Store_Funct3 {
     SD = 0b011,
 }
 
+#[allow(non_camel_case_types)]
+#[derive(Debug, PartialEq, FromPrimitive)]
+#[repr(u8)]
+pub enum LoadFp_Funct3 {
+    FLW = 0b010,
+    FLD = 0b011,
+}
+
+#[allow(non_camel_case_types)]
+#[derive(Debug, PartialEq, FromPrimitive)]
+#[repr(u8)]
+pub enum StoreFp_Funct3 {
+    FSW = 0b010,
+    FSD = 0b011,
+}
+
 #[allow(non_camel_case_types)]
 #[derive(Debug, PartialEq, FromPrimitive)]
 #[repr(u8)]
@@ -194,9 +221,9 @@ pub enum C2_Funct3 {
     C_LDSP = 0b011, // C.FLWSP
     C_LWSP = 0b010,
     C_SWSP = 0b110,
-    B001 = 0b001,
+    C_FLDSP = 0b001,
     B100 = 0b100,
-    B101 = 0b101,
+    C_FSDSP = 0b101,
     C_SDSP = 0b111,
 }
 
@@ -210,3 +237,23 @@ pub enum C1_Funct6 {
     // C_ADDW = 0b100,
     C_SUBW = 0b100_111,
 }
+
+/// OP-FP operations, encoded in bits 31:27 (the rs3 field of R4-type)
+#[allow(non_camel_case_types)]
+#[derive(Debug, PartialEq, FromPrimitive)]
+#[repr(u8)]
+pub enum OpFp_Funct5 {
+    FADD = 0b00000,
+    FSUB = 0b00001,
+    FMUL = 0b00010,
+    FDIV = 0b00011,
+    FSGNJ = 0b00100,
+    FMIN_FMAX = 0b00101,
+    FCVT_FMT_FMT = 0b01000,
+    FSQRT = 0b01011,
+    FCMP = 0b10100,
+    FCVT_INT_FMT = 0b11000,
+    FCVT_FMT_INT = 0b11010,
+    FMV_X_FCLASS = 0b11100,
+    FMV_FMT_X = 0b11110,
+}
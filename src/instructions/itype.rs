@@ -9,7 +9,8 @@ use crate::{
 
 use super::{
     functions::{
-        CSR_Funct3, Funct3, Funct7, Load_Funct3, MiscMem_Funct3, OpImm32_Funct3, OpImm_Funct3,
+        CSR_Funct3, Funct3, Funct7, LoadFp_Funct3, Load_Funct3, MiscMem_Funct3, OpImm32_Funct3,
+        OpImm_Funct3,
     },
     opcodes::MajorOpcode,
     FormatDecoder, Instruction, InstructionFormatType, InstructionSelector, UncompressedFormatType,
@@ -50,7 +51,10 @@ impl Instruction<Itype> {
             mnemonic: &"CSRRW",
             args: Some(*args),
             funct: |core, args| {
-                let csr_register = num::FromPrimitive::from_u16(args.imm12).unwrap();
+                let csr_register = match core.csr_register(args.imm12, true) {
+                    Ok(reg) => reg,
+                    Err(cause) => return Stage::TRAP(cause),
+                };
                 // "If rd=x0, then the instruction shall not read the CSR"
                 let value = if args.rd != 0 {
                     Some(core.read_csr(csr_register))
@@ -75,7 +79,10 @@ impl Instruction<Itype> {
             mnemonic: &"CSRRWI",
             args: Some(*args),
             funct: |core, args| {
-                let csr_register = num::FromPrimitive::from_u16(args.imm12).unwrap();
+                let csr_register = match core.csr_register(args.imm12, true) {
+                    Ok(reg) => reg,
+                    Err(cause) => return Stage::TRAP(cause),
+                };
                 let value = core.read_csr(csr_register);
                 core.write_csr(csr_register, args.rs1 as u64);
 
@@ -92,7 +99,10 @@ impl Instruction<Itype> {
             mnemonic: &"CSRRS",
             args: Some(*args),
             funct: |core, args| {
-                let csr_register = num::FromPrimitive::from_u16(args.imm12).unwrap();
+                let csr_register = match core.csr_register(args.imm12, args.rs1 != 0) {
+                    Ok(reg) => reg,
+                    Err(cause) => return Stage::TRAP(cause),
+                };
                 let value = core.read_csr(csr_register);
                 if args.rs1 != 0 {
                     let rs1v = core.read_register(args.rs1);
@@ -111,7 +121,10 @@ impl Instruction<Itype> {
             mnemonic: &"CSRRC",
             args: Some(*args),
             funct: |core, args| {
-                let csr_register = num::FromPrimitive::from_u16(args.imm12).unwrap();
+                let csr_register = match core.csr_register(args.imm12, args.rs1 != 0) {
+                    Ok(reg) => reg,
+                    Err(cause) => return Stage::TRAP(cause),
+                };
                 let value = core.read_csr(csr_register);
                 println!("CSRRC read {:#x?} value {:#x?}", csr_register, value);
                 if args.rs1 != 0 {
@@ -131,9 +144,14 @@ impl Instruction<Itype> {
             mnemonic: &"CSRRCI",
             args: Some(*args),
             funct: |core, args| {
-                let csr_register = num::FromPrimitive::from_u16(args.imm12).unwrap();
+                let csr_register = match core.csr_register(args.imm12, args.rs1 != 0) {
+                    Ok(reg) => reg,
+                    Err(cause) => return Stage::TRAP(cause),
+                };
                 let value = core.read_csr(csr_register);
-                core.write_csr(csr_register, value & !(args.rs1 as u64));
+                if args.rs1 != 0 {
+                    core.write_csr(csr_register, value & !(args.rs1 as u64));
+                }
 
                 match args.rd {
                     0 => Stage::WRITEBACK(None),
@@ -148,14 +166,19 @@ impl Instruction<Itype> {
             mnemonic: &"CSRRSI",
             args: Some(*args),
             funct: |core, args| {
-                let csr_register = num::FromPrimitive::from_u16(args.imm12).unwrap();
+                let csr_register = match core.csr_register(args.imm12, args.rs1 != 0) {
+                    Ok(reg) => reg,
+                    Err(cause) => return Stage::TRAP(cause),
+                };
                 let value = core.read_csr(csr_register);
                 let new_value = value | (args.rs1 as u64);
                 println!(
                     "CSRRSI: reg: {:#x?} value: {:#x?}  new: {:#x?}",
                     csr_register as u64, value, new_value
                 );
-                core.write_csr(csr_register, new_value);
+                if args.rs1 != 0 {
+                    core.write_csr(csr_register, new_value);
+                }
                 match args.rd {
                     0 => Stage::WRITEBACK(None),
                     _ => Stage::writeback(args.rd, value),
@@ -482,6 +505,38 @@ impl Instruction<Itype> {
         }
     }
 
+    pub fn FLW(args: &Itype) -> Instruction<Itype> {
+        Instruction {
+            mnemonic: "FLW",
+            args: Some(*args),
+            funct: |core, args| {
+                if !core.fp_enabled() {
+                    return Stage::TRAP(core.illegal_instruction());
+                }
+                let se_imm12 = (args.imm12 as u64).sign_extend(64 - 12) as i64;
+                let rs1v = core.read_register(args.rs1);
+                let addr = (rs1v as i64 + se_imm12) as u64;
+                Stage::MEMORY(crate::pipeline::MemoryAccess::FREAD32(addr, args.rd))
+            },
+        }
+    }
+
+    pub fn FLD(args: &Itype) -> Instruction<Itype> {
+        Instruction {
+            mnemonic: "FLD",
+            args: Some(*args),
+            funct: |core, args| {
+                if !core.fp_enabled() {
+                    return Stage::TRAP(core.illegal_instruction());
+                }
+                let se_imm12 = (args.imm12 as u64).sign_extend(64 - 12) as i64;
+                let rs1v = core.read_register(args.rs1);
+                let addr = (rs1v as i64 + se_imm12) as u64;
+                Stage::MEMORY(crate::pipeline::MemoryAccess::FREAD64(addr, args.rd))
+            },
+        }
+    }
+
     pub fn LWU(args: &Itype) -> Instruction<Itype> {
         Instruction {
             mnemonic: &"LW",
@@ -545,9 +600,28 @@ impl Instruction<Itype> {
         Instruction {
             mnemonic: "SFENCE.VMA",
             args: Some(*args),
-            funct: |_core, _args| {
-                // NOP for now
-                Stage::WRITEBACK(None)
+            funct: |core, args| {
+                // Illegal in U-mode, and in S-mode while mstatus.TVM is set
+                let tvm = (core.read_csr(CSRRegister::mstatus) >> 20) & 1 == 1;
+                match core.pmode() {
+                    crate::cpu::PrivMode::User => return Stage::TRAP(core.illegal_instruction()),
+                    crate::cpu::PrivMode::Supervisor if tvm => {
+                        return Stage::TRAP(core.illegal_instruction())
+                    }
+                    _ => {}
+                }
+
+                // x0 as rs1 or rs2 selects all addresses or all address spaces
+                let rs2 = (args.imm12 & 0x1f) as u8;
+                let vaddr = match args.rs1 {
+                    0 => None,
+                    rs1 => Some(core.read_register(rs1)),
+                };
+                let asid = match rs2 {
+                    0 => None,
+                    rs2 => Some(core.read_register(rs2) as u16),
+                };
+                Stage::MEMORY(crate::pipeline::MemoryAccess::SFENCE_VMA(vaddr, asid))
             },
         }
     }
@@ -574,6 +648,9 @@ impl Instruction<Itype> {
             mnemonic: "MRET",
             args: Some(*args),
             funct: |core, _args| {
+                if core.pmode() != crate::cpu::PrivMode::Machine {
+                    return Stage::TRAP(core.illegal_instruction());
+                }
                 core.set_pc(core.read_csr(CSRRegister::mepc));
 
                 let status = core.read_csr(CSRRegister::mstatus);
@@ -603,12 +680,18 @@ impl Instruction<Itype> {
             mnemonic: "SRET",
             args: Some(*args),
             funct: |core, _args| {
-                let status = core.read_csr(CSRRegister::sstatus);
-                let tsr = (status >> 22) & 1 == 1;
-                if tsr {
-                    return Stage::TRAP(TrapCause::IllegalInstruction(core.pc()));
+                // Illegal in U-mode, and in S-mode while mstatus.TSR is set
+                let tsr = (core.read_csr(CSRRegister::mstatus) >> 22) & 1 == 1;
+                match core.pmode() {
+                    crate::cpu::PrivMode::User => return Stage::TRAP(core.illegal_instruction()),
+                    crate::cpu::PrivMode::Supervisor if tsr => {
+                        return Stage::TRAP(core.illegal_instruction())
+                    }
+                    _ => {}
                 }
 
+                let status = core.read_csr(CSRRegister::sstatus);
+
                 let sepc = core.read_csr(CSRRegister::sepc);
                 println!("SRET: sepc: {:#x?}", sepc);
                 core.set_pc(sepc);
@@ -620,8 +703,6 @@ impl Instruction<Itype> {
                     _ => 0,
                 };
 
-                // @TODO: SRET should also raise an illegal instruction exception when TSR=1 in mstatus
-
                 let new_status = (status & !0x20122) | (mprv << 17) | (spie << 1) | (1 << 5);
                 core.write_csr(CSRRegister::sstatus, new_status);
 
@@ -633,6 +714,22 @@ impl Instruction<Itype> {
         }
     }
 
+    pub fn WFI(args: &Itype) -> Instruction<Itype> {
+        Instruction {
+            mnemonic: "WFI",
+            args: Some(*args),
+            funct: |core, _args| {
+                // Waiting is optional, so this is a NOP. mstatus.TW makes it illegal
+                // outside of M-mode.
+                let tw = (core.read_csr(CSRRegister::mstatus) >> 21) & 1 == 1;
+                if tw && core.pmode() != crate::cpu::PrivMode::Machine {
+                    return Stage::TRAP(core.illegal_instruction());
+                }
+                Stage::WRITEBACK(None)
+            },
+        }
+    }
+
     pub fn FENCE(args: &Itype) -> Instruction<Itype> {
         Instruction {
             mnemonic: "FENCE",
@@ -658,78 +755,96 @@ impl Display for Instruction<Itype> {
 }
 
 impl InstructionSelector<Itype> for Itype {
-    fn select(&self, _xlen: Xlen) -> Instruction<Itype> {
+    fn select(&self, xlen: Xlen) -> Instruction<Itype> {
+        // Shift amounts are 5 bits wide on RV32 and 6 bits on RV64, the bits above them
+        // select the shift type
+        let shamt_bits = match xlen {
+            Xlen::Bits32 => 5,
+            _ => 6,
+        };
         match self.opcode {
             MajorOpcode::OP_IMM => match num::FromPrimitive::from_u8(self.funct3 as u8).unwrap() {
                 OpImm_Funct3::ADDI => Instruction::ADDI(self),
                 OpImm_Funct3::ANDI => Instruction::ANDI(self),
                 OpImm_Funct3::ORI => Instruction::ORI(self),
                 OpImm_Funct3::XORI => Instruction::XORI(self),
-                OpImm_Funct3::SLLI => Instruction::SLLI(self),
-                OpImm_Funct3::SRLI_SRAI => {
-                    //"a specialization of the I-type format"
-                    // "The right shift type is encoded in bit 30"
-                    match num::FromPrimitive::from_u8(self.funct7 as u8 & 0b1111110).unwrap() {
-                        Funct7::B0000000 => Instruction::SRLI(self),
-                        Funct7::B0100000 => Instruction::SRAI(self),
-                        _ => panic!("{:?} is unknown Funct7 for SRLI_SRAI", self.funct7),
-                    }
-                }
+                OpImm_Funct3::SLLI => match self.imm12 >> shamt_bits {
+                    0 => Instruction::SLLI(self),
+                    _ => Instruction::illegal(),
+                },
+                //"a specialization of the I-type format"
+                // "The right shift type is encoded in bit 30"
+                OpImm_Funct3::SRLI_SRAI => match self.imm12 >> shamt_bits << shamt_bits {
+                    0x000 => Instruction::SRLI(self),
+                    0x400 => Instruction::SRAI(self),
+                    _ => Instruction::illegal(),
+                },
                 OpImm_Funct3::SLTI => Instruction::SLTI(self),
                 OpImm_Funct3::SLTIU => Instruction::SLTIU(self),
             },
-            MajorOpcode::SYSTEM => match num::FromPrimitive::from_u8(self.funct3 as u8).unwrap() {
-                CSR_Funct3::CSRRS => Instruction::CSRRS(self),
-                CSR_Funct3::CSRRW => Instruction::CSRRW(self),
-                CSR_Funct3::CSRRC => Instruction::CSRRC(self),
-                CSR_Funct3::CSRRWI => Instruction::CSRRWI(self),
-                CSR_Funct3::CSRRSI => Instruction::CSRRSI(self),
-                CSR_Funct3::CSRRCI => Instruction::CSRRCI(self),
-                CSR_Funct3::ECALL_EBREAK_MRET => match self.funct7 {
-                    Funct7::B0001000 => Instruction::SRET(self),
-                    Funct7::B0011000 => Instruction::MRET(self),
-                    Funct7::B0001001 => Instruction::SFENCE_WMA(self),
-                    //Funct7::B0000000 => Instruction::ECALL(self),
-                    _ => {
-                        if self.rd == 0 && self.rs1 == 0 && self.imm12 == 0 {
-                            Instruction::ECALL(self)
-                        } else {
-                            //println!("self.funct7 : {:#?}", self.funct7);
-                            Instruction::EBREAK(self)
-                        }
-                    } //_ => panic!(),
-                },
-                _ => panic!("Unknown SYSTEM instruction"),
-            },
-            MajorOpcode::JALR => Instruction::JALR(self),
-            MajorOpcode::OP_IMM_32 => match num::FromPrimitive::from_u8(self.funct3 as u8).unwrap()
-            {
-                OpImm32_Funct3::ADDIW => Instruction::ADDIW(self),
-                OpImm32_Funct3::SLLIW => Instruction::SLLIW(self),
-                OpImm32_Funct3::SRLIW_SRAIW => {
-                    match num::FromPrimitive::from_u8(self.funct7 as u8).unwrap() {
-                        Funct7::B0000000 => Instruction::SRLIW(self),
-                        Funct7::B0100000 => Instruction::SRAIW(self),
-                        _ => panic!(),
+            MajorOpcode::SYSTEM => match num::FromPrimitive::from_u8(self.funct3 as u8) {
+                Some(CSR_Funct3::CSRRS) => Instruction::CSRRS(self),
+                Some(CSR_Funct3::CSRRW) => Instruction::CSRRW(self),
+                Some(CSR_Funct3::CSRRC) => Instruction::CSRRC(self),
+                Some(CSR_Funct3::CSRRWI) => Instruction::CSRRWI(self),
+                Some(CSR_Funct3::CSRRSI) => Instruction::CSRRSI(self),
+                Some(CSR_Funct3::CSRRCI) => Instruction::CSRRCI(self),
+                Some(CSR_Funct3::ECALL_EBREAK_MRET) if self.rd != 0 => Instruction::illegal(),
+                Some(CSR_Funct3::ECALL_EBREAK_MRET) => match self.funct7 {
+                    // SFENCE.VMA holds rs2 where the others have fixed bits
+                    Funct7::B0001001 if self.imm12 >> 5 == 0b0001001 => {
+                        Instruction::SFENCE_WMA(self)
                     }
-                }
+                    _ if self.rs1 != 0 => Instruction::illegal(),
+                    _ => match self.imm12 {
+                        0x000 => Instruction::ECALL(self),
+                        0x001 => Instruction::EBREAK(self),
+                        0x102 => Instruction::SRET(self),
+                        0x105 => Instruction::WFI(self),
+                        0x302 => Instruction::MRET(self),
+                        _ => Instruction::illegal(),
+                    },
+                },
+                None => Instruction::illegal(),
             },
-            MajorOpcode::LOAD => match num::FromPrimitive::from_u8(self.funct3 as u8).unwrap() {
-                Load_Funct3::LB => Instruction::LB(self),
-                Load_Funct3::LH => Instruction::LH(self),
-                Load_Funct3::LD => Instruction::LD(self),
-                Load_Funct3::LW => Instruction::LW(self),
-                Load_Funct3::LWU => Instruction::LWU(self),
-                Load_Funct3::LBU => Instruction::LBU(self),
-                Load_Funct3::LHU => Instruction::LHU(self),
-            },
-            MajorOpcode::MISC_MEM => {
-                match num::FromPrimitive::from_u8(self.funct3 as u8).unwrap() {
-                    MiscMem_Funct3::FENCE => Instruction::FENCE(self),
-                    MiscMem_Funct3::FENCE_I => Instruction::FENCE(self),
-                }
-            }
-            _ => panic!(),
+            MajorOpcode::JALR => match self.funct3 {
+                Funct3::B000 => Instruction::JALR(self),
+                _ => Instruction::illegal(),
+            },
+            MajorOpcode::OP_IMM_32 => match num::FromPrimitive::from_u8(self.funct3 as u8) {
+                Some(OpImm32_Funct3::ADDIW) => Instruction::ADDIW(self),
+                Some(OpImm32_Funct3::SLLIW) => match self.imm12 >> 5 {
+                    0 => Instruction::SLLIW(self),
+                    _ => Instruction::illegal(),
+                },
+                Some(OpImm32_Funct3::SRLIW_SRAIW) => match self.imm12 >> 5 {
+                    0b0000000 => Instruction::SRLIW(self),
+                    0b0100000 => Instruction::SRAIW(self),
+                    _ => Instruction::illegal(),
+                },
+                None => Instruction::illegal(),
+            },
+            MajorOpcode::LOAD => match num::FromPrimitive::from_u8(self.funct3 as u8) {
+                Some(Load_Funct3::LB) => Instruction::LB(self),
+                Some(Load_Funct3::LH) => Instruction::LH(self),
+                Some(Load_Funct3::LD) => Instruction::LD(self),
+                Some(Load_Funct3::LW) => Instruction::LW(self),
+                Some(Load_Funct3::LWU) => Instruction::LWU(self),
+                Some(Load_Funct3::LBU) => Instruction::LBU(self),
+                Some(Load_Funct3::LHU) => Instruction::LHU(self),
+                None => Instruction::illegal(),
+            },
+            MajorOpcode::LOAD_FP => match num::FromPrimitive::from_u8(self.funct3 as u8) {
+                Some(LoadFp_Funct3::FLW) => Instruction::FLW(self),
+                Some(LoadFp_Funct3::FLD) => Instruction::FLD(self),
+                None => Instruction::illegal(),
+            },
+            MajorOpcode::MISC_MEM => match num::FromPrimitive::from_u8(self.funct3 as u8) {
+                Some(MiscMem_Funct3::FENCE) => Instruction::FENCE(self),
+                Some(MiscMem_Funct3::FENCE_I) => Instruction::FENCE(self),
+                None => Instruction::illegal(),
+            },
+            _ => Instruction::illegal(),
         }
     }
 }
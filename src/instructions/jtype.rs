@@ -84,7 +84,7 @@ impl InstructionSelector<Jtype> for Jtype {
     fn select(&self, _xlen: Xlen) -> Instruction<Jtype> {
         match self.opcode {
             MajorOpcode::JAL => Instruction::JAL(self),
-            _ => panic!(),
+            _ => Instruction::illegal(),
         }
     }
 }
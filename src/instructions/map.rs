@@ -8,7 +8,7 @@ pub const FORMAT_MAP: [InstructionFormat; 128] = [
     InstructionFormat::Unknown, /*0000100 */
     InstructionFormat::Unknown, /*0000101 */
     InstructionFormat::Unknown, /*0000110 */
-    InstructionFormat::Unknown, /*0000111 */
+    InstructionFormat::I,       /*0000111 = 0x07 = FLW/FLD */
     InstructionFormat::Unknown, /*0001000 */
     InstructionFormat::Unknown, /*0001001 */
     InstructionFormat::Unknown, /*0001010 */
@@ -40,7 +40,7 @@ pub const FORMAT_MAP: [InstructionFormat; 128] = [
     InstructionFormat::Unknown, /*0100100 */
     InstructionFormat::Unknown, /*0100101 */
     InstructionFormat::Unknown, /*0100110 */
-    InstructionFormat::Unknown, /*0100111 */
+    InstructionFormat::S,       /*0100111 = FSW/FSD */
     InstructionFormat::Unknown, /*0101000 */
     InstructionFormat::Unknown, /*0101001 */
     InstructionFormat::Unknown, /*0101010 */
@@ -68,23 +68,23 @@ pub const FORMAT_MAP: [InstructionFormat; 128] = [
     InstructionFormat::Unknown, /*1000000 */
     InstructionFormat::Unknown, /*1000001 */
     InstructionFormat::Unknown, /*1000010 */
-    InstructionFormat::Unknown, /*1000011 */
+    InstructionFormat::R4,      /*1000011 = FMADD */
     InstructionFormat::Unknown, /*1000100 */
     InstructionFormat::Unknown, /*1000101 */
     InstructionFormat::Unknown, /*1000110 */
-    InstructionFormat::Unknown, /*1000111 */
+    InstructionFormat::R4,      /*1000111 = FMSUB */
     InstructionFormat::Unknown, /*1001000 */
     InstructionFormat::Unknown, /*1001001 */
     InstructionFormat::Unknown, /*1001010 */
-    InstructionFormat::Unknown, /*1001011 */
+    InstructionFormat::R4,      /*1001011 = FNMSUB */
     InstructionFormat::Unknown, /*1001100 */
     InstructionFormat::Unknown, /*1001101 */
     InstructionFormat::Unknown, /*1001110 */
-    InstructionFormat::Unknown, /*1001111 */
+    InstructionFormat::R4,      /*1001111 = FNMADD */
     InstructionFormat::Unknown, /*1010000 */
     InstructionFormat::Unknown, /*1010001 */
     InstructionFormat::Unknown, /*1010010 */
-    InstructionFormat::Unknown, /*1010011 OP-FP  */
+    InstructionFormat::R4,      /*1010011 = OP-FP (funct5 in place of rs3) */
     InstructionFormat::Unknown, /*1010100 */
     InstructionFormat::Unknown, /*1010101 */
     InstructionFormat::Unknown, /*1010110 */
@@ -25,6 +25,7 @@ pub mod itype;
 pub mod jtype;
 pub mod map;
 pub mod opcodes;
+pub mod r4type;
 pub mod rtype;
 pub mod stype;
 pub mod utype;
@@ -40,6 +41,7 @@ pub enum InstructionFormat {
     U,
     B,
     J,
+    R4,
 }
 
 /// Table 12.1: Compressed 16-bit RVC instruction formats.
@@ -68,6 +70,17 @@ pub struct Instruction<T> {
     pub funct: fn(&mut Core, &T) -> Stage,
 }
 
+impl<T> Instruction<T> {
+    /// Stands in for reserved and unimplemented encodings
+    pub fn illegal() -> Instruction<T> {
+        Instruction {
+            args: None,
+            mnemonic: "ILLEGAL",
+            funct: |core, _args| Stage::TRAP(core.illegal_instruction()),
+        }
+    }
+}
+
 pub trait FormatDecoder<T: UncompressedFormatType> {
     fn decode(word: u32) -> T;
 }
@@ -82,6 +95,6 @@ pub trait ImmediateDecoder<T, T2> {
 
 pub trait InstructionSelector<T> {
     fn select(&self, _xlen: Xlen) -> Instruction<T> {
-        todo!();
+        Instruction::illegal()
     }
 }
@@ -14,19 +14,23 @@ pub enum MajorOpcode {
     AUIPC = 0b0010111,
     BRANCH = 0b1100011,
     LOAD = 0b0000011,
+    LOAD_FP = 0b0000111,
     JALR = 0b1100111,
     JAL = 0b1101111,
     OP = 0b0110011,
     AMO = 0b0101111,
     OP_32 = 0b0111011,
-    // OP_FP,
+    OP_FP = 0b1010011,
     OP_IMM = 0b0010011,
     OP_IMM_32 = 0b0011011,
     LUI = 0b0110111,
     MISC_MEM = 0b0001111,
-    //MADD,
-    //MSUB,
+    MADD = 0b1000011,
+    MSUB = 0b1000111,
+    NMSUB = 0b1001011,
+    NMADD = 0b1001111,
     STORE = 0b0100011,
+    STORE_FP = 0b0100111,
     SYSTEM = 0b1110011,
 }
 
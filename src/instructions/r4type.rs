@@ -0,0 +1,727 @@
+use std::fmt::Display;
+
+use crate::{
+    cpu::{Core, Register, Xlen},
+    fpu::{FloatFormat, RoundingMode},
+    pipeline::Stage,
+};
+
+use super::{
+    functions::OpFp_Funct5, opcodes::MajorOpcode, FormatDecoder, Instruction,
+    InstructionFormatType, InstructionSelector, UncompressedFormatType,
+};
+
+/// R4-type is used by the fused multiply-add family. OP-FP shares the layout, but encodes
+/// its operation in the rs3 field, so both are decoded here.
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub struct R4type {
+    pub opcode: MajorOpcode,
+    pub rd: Register,
+    pub rs1: Register,
+    pub rs2: Register,
+    pub rs3: Register,
+    pub rm: u8,
+    pub fmt: u8,
+    pub funct5: u8,
+}
+
+impl InstructionFormatType for R4type {}
+impl UncompressedFormatType for R4type {}
+
+impl FormatDecoder<R4type> for R4type {
+    fn decode(word: u32) -> R4type {
+        R4type {
+            opcode: num::FromPrimitive::from_u8((word & 0x7f) as u8).unwrap(),
+            rd: ((word >> 7) & 31) as Register,
+            rs1: ((word >> 15) & 31) as Register,
+            rs2: ((word >> 20) & 31) as Register,
+            rs3: ((word >> 27) & 31) as Register,
+            rm: ((word >> 12) & 7) as u8,
+            fmt: ((word >> 25) & 3) as u8,
+            funct5: ((word >> 27) & 31) as u8,
+        }
+    }
+}
+
+impl Display for Instruction<R4type> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.args.is_none() {
+            write!(f, "{}", self.mnemonic)
+        } else {
+            let args = self.args.unwrap();
+            match args.opcode {
+                MajorOpcode::OP_FP => write!(
+                    f,
+                    "{} f{},f{},f{}",
+                    self.mnemonic, args.rd, args.rs1, args.rs2
+                ),
+                _ => write!(
+                    f,
+                    "{} f{},f{},f{},f{}",
+                    self.mnemonic, args.rd, args.rs1, args.rs2, args.rs3
+                ),
+            }
+        }
+    }
+}
+
+/// Resolves the rounding mode of an instruction, raising an illegal instruction trap
+/// for the reserved encodings
+macro_rules! rounding_mode {
+    ($core:expr, $args:expr) => {
+        match $core.rounding_mode($args.rm) {
+            Some(rm) => rm,
+            None => return Stage::TRAP($core.illegal_instruction()),
+        }
+    };
+}
+
+/// FP instructions raise an illegal instruction trap while mstatus.FS is Off
+macro_rules! fp_instruction {
+    ($name:ident, $mnemonic:expr, $op:expr) => {
+        pub fn $name(args: &R4type) -> Instruction<R4type> {
+            Instruction {
+                mnemonic: $mnemonic,
+                args: Some(*args),
+                funct: |core, args| {
+                    if !core.fp_enabled() {
+                        return Stage::TRAP(core.illegal_instruction());
+                    }
+                    let op: fn(&mut Core, &R4type) -> Stage = $op;
+                    op(core, args)
+                },
+            }
+        }
+    };
+}
+
+fn fp_arith(
+    core: &mut Core,
+    args: &R4type,
+    fmt: FloatFormat,
+    op: fn(FloatFormat, u64, u64, RoundingMode) -> (u64, u8),
+) -> Stage {
+    let rm = rounding_mode!(core, args);
+    let rs1v = fmt.unbox(core.read_fregister(args.rs1));
+    let rs2v = fmt.unbox(core.read_fregister(args.rs2));
+    let (value, flags) = op(fmt, rs1v, rs2v, rm);
+    core.accrue_fflags(flags);
+    core.write_fregister(args.rd, fmt.box_value(value));
+    Stage::WRITEBACK(None)
+}
+
+fn fp_sqrt(core: &mut Core, args: &R4type, fmt: FloatFormat) -> Stage {
+    let rm = rounding_mode!(core, args);
+    let rs1v = fmt.unbox(core.read_fregister(args.rs1));
+    let (value, flags) = fmt.sqrt(rs1v, rm);
+    core.accrue_fflags(flags);
+    core.write_fregister(args.rd, fmt.box_value(value));
+    Stage::WRITEBACK(None)
+}
+
+/// FMADD, FMSUB, FNMSUB and FNMADD differ only in which terms of `rs1 * rs2 + rs3` are negated
+fn fp_fused(
+    core: &mut Core,
+    args: &R4type,
+    fmt: FloatFormat,
+    negate_product: bool,
+    negate_addend: bool,
+) -> Stage {
+    let rm = rounding_mode!(core, args);
+    let mut rs1v = fmt.unbox(core.read_fregister(args.rs1));
+    let rs2v = fmt.unbox(core.read_fregister(args.rs2));
+    let mut rs3v = fmt.unbox(core.read_fregister(args.rs3));
+    if negate_product {
+        rs1v ^= fmt.sign_mask();
+    }
+    if negate_addend {
+        rs3v ^= fmt.sign_mask();
+    }
+    let (value, flags) = fmt.mul_add(rs1v, rs2v, rs3v, rm);
+    core.accrue_fflags(flags);
+    core.write_fregister(args.rd, fmt.box_value(value));
+    Stage::WRITEBACK(None)
+}
+
+fn fp_sign_inject(
+    core: &mut Core,
+    args: &R4type,
+    fmt: FloatFormat,
+    op: fn(FloatFormat, u64, u64) -> u64,
+) -> Stage {
+    let rs1v = fmt.unbox(core.read_fregister(args.rs1));
+    let rs2v = fmt.unbox(core.read_fregister(args.rs2));
+    core.write_fregister(args.rd, fmt.box_value(op(fmt, rs1v, rs2v)));
+    Stage::WRITEBACK(None)
+}
+
+fn fp_min_max(
+    core: &mut Core,
+    args: &R4type,
+    fmt: FloatFormat,
+    op: fn(FloatFormat, u64, u64) -> (u64, u8),
+) -> Stage {
+    let rs1v = fmt.unbox(core.read_fregister(args.rs1));
+    let rs2v = fmt.unbox(core.read_fregister(args.rs2));
+    let (value, flags) = op(fmt, rs1v, rs2v);
+    core.accrue_fflags(flags);
+    core.write_fregister(args.rd, fmt.box_value(value));
+    Stage::WRITEBACK(None)
+}
+
+fn fp_compare(
+    core: &mut Core,
+    args: &R4type,
+    fmt: FloatFormat,
+    op: fn(FloatFormat, u64, u64) -> (bool, u8),
+) -> Stage {
+    let rs1v = fmt.unbox(core.read_fregister(args.rs1));
+    let rs2v = fmt.unbox(core.read_fregister(args.rs2));
+    let (value, flags) = op(fmt, rs1v, rs2v);
+    core.accrue_fflags(flags);
+    Stage::writeback(args.rd, value as u64)
+}
+
+fn fp_classify(core: &mut Core, args: &R4type, fmt: FloatFormat) -> Stage {
+    let rs1v = fmt.unbox(core.read_fregister(args.rs1));
+    Stage::writeback(args.rd, fmt.classify(rs1v))
+}
+
+fn fp_to_int(core: &mut Core, args: &R4type, fmt: FloatFormat, signed: bool, width: u32) -> Stage {
+    let rm = rounding_mode!(core, args);
+    let rs1v = fmt.unbox(core.read_fregister(args.rs1));
+    let (value, flags) = fmt.to_integer(rs1v, signed, width, rm);
+    core.accrue_fflags(flags);
+    Stage::writeback(args.rd, value)
+}
+
+fn int_to_fp(core: &mut Core, args: &R4type, fmt: FloatFormat, signed: bool, width: u32) -> Stage {
+    let rm = rounding_mode!(core, args);
+    let rs1v = core.read_register(args.rs1);
+    let source = match (width, signed) {
+        (32, true) => rs1v as i32 as u64,
+        (32, false) => rs1v as u32 as u64,
+        _ => rs1v,
+    };
+    let (value, flags) = fmt.from_integer(source, signed, rm);
+    core.accrue_fflags(flags);
+    core.write_fregister(args.rd, fmt.box_value(value));
+    Stage::WRITEBACK(None)
+}
+
+fn fp_convert(core: &mut Core, args: &R4type, to: FloatFormat, from: FloatFormat) -> Stage {
+    let rm = rounding_mode!(core, args);
+    let rs1v = from.unbox(core.read_fregister(args.rs1));
+    let (value, flags) = to.convert(from, rs1v, rm);
+    core.accrue_fflags(flags);
+    core.write_fregister(args.rd, to.box_value(value));
+    Stage::WRITEBACK(None)
+}
+
+#[allow(non_snake_case)]
+impl Instruction<R4type> {
+    fp_instruction!(FADD_S, "FADD.S", |core, args| fp_arith(
+        core,
+        args,
+        FloatFormat::S,
+        FloatFormat::add
+    ));
+    fp_instruction!(FSUB_S, "FSUB.S", |core, args| fp_arith(
+        core,
+        args,
+        FloatFormat::S,
+        FloatFormat::sub
+    ));
+    fp_instruction!(FMUL_S, "FMUL.S", |core, args| fp_arith(
+        core,
+        args,
+        FloatFormat::S,
+        FloatFormat::mul
+    ));
+    fp_instruction!(FDIV_S, "FDIV.S", |core, args| fp_arith(
+        core,
+        args,
+        FloatFormat::S,
+        FloatFormat::div
+    ));
+    fp_instruction!(FSQRT_S, "FSQRT.S", |core, args| fp_sqrt(
+        core,
+        args,
+        FloatFormat::S
+    ));
+    fp_instruction!(FADD_D, "FADD.D", |core, args| fp_arith(
+        core,
+        args,
+        FloatFormat::D,
+        FloatFormat::add
+    ));
+    fp_instruction!(FSUB_D, "FSUB.D", |core, args| fp_arith(
+        core,
+        args,
+        FloatFormat::D,
+        FloatFormat::sub
+    ));
+    fp_instruction!(FMUL_D, "FMUL.D", |core, args| fp_arith(
+        core,
+        args,
+        FloatFormat::D,
+        FloatFormat::mul
+    ));
+    fp_instruction!(FDIV_D, "FDIV.D", |core, args| fp_arith(
+        core,
+        args,
+        FloatFormat::D,
+        FloatFormat::div
+    ));
+    fp_instruction!(FSQRT_D, "FSQRT.D", |core, args| fp_sqrt(
+        core,
+        args,
+        FloatFormat::D
+    ));
+}
+
+#[allow(non_snake_case)]
+impl Instruction<R4type> {
+    fp_instruction!(FMADD_S, "FMADD.S", |core, args| fp_fused(
+        core,
+        args,
+        FloatFormat::S,
+        false,
+        false
+    ));
+    fp_instruction!(FMSUB_S, "FMSUB.S", |core, args| fp_fused(
+        core,
+        args,
+        FloatFormat::S,
+        false,
+        true
+    ));
+    fp_instruction!(FNMSUB_S, "FNMSUB.S", |core, args| fp_fused(
+        core,
+        args,
+        FloatFormat::S,
+        true,
+        false
+    ));
+    fp_instruction!(FNMADD_S, "FNMADD.S", |core, args| fp_fused(
+        core,
+        args,
+        FloatFormat::S,
+        true,
+        true
+    ));
+    fp_instruction!(FMADD_D, "FMADD.D", |core, args| fp_fused(
+        core,
+        args,
+        FloatFormat::D,
+        false,
+        false
+    ));
+    fp_instruction!(FMSUB_D, "FMSUB.D", |core, args| fp_fused(
+        core,
+        args,
+        FloatFormat::D,
+        false,
+        true
+    ));
+    fp_instruction!(FNMSUB_D, "FNMSUB.D", |core, args| fp_fused(
+        core,
+        args,
+        FloatFormat::D,
+        true,
+        false
+    ));
+    fp_instruction!(FNMADD_D, "FNMADD.D", |core, args| fp_fused(
+        core,
+        args,
+        FloatFormat::D,
+        true,
+        true
+    ));
+}
+
+#[allow(non_snake_case)]
+impl Instruction<R4type> {
+    fp_instruction!(FSGNJ_S, "FSGNJ.S", |core, args| fp_sign_inject(
+        core,
+        args,
+        FloatFormat::S,
+        FloatFormat::sgnj
+    ));
+    fp_instruction!(FSGNJN_S, "FSGNJN.S", |core, args| fp_sign_inject(
+        core,
+        args,
+        FloatFormat::S,
+        FloatFormat::sgnjn
+    ));
+    fp_instruction!(FSGNJX_S, "FSGNJX.S", |core, args| fp_sign_inject(
+        core,
+        args,
+        FloatFormat::S,
+        FloatFormat::sgnjx
+    ));
+    fp_instruction!(FSGNJ_D, "FSGNJ.D", |core, args| fp_sign_inject(
+        core,
+        args,
+        FloatFormat::D,
+        FloatFormat::sgnj
+    ));
+    fp_instruction!(FSGNJN_D, "FSGNJN.D", |core, args| fp_sign_inject(
+        core,
+        args,
+        FloatFormat::D,
+        FloatFormat::sgnjn
+    ));
+    fp_instruction!(FSGNJX_D, "FSGNJX.D", |core, args| fp_sign_inject(
+        core,
+        args,
+        FloatFormat::D,
+        FloatFormat::sgnjx
+    ));
+
+    fp_instruction!(FMIN_S, "FMIN.S", |core, args| fp_min_max(
+        core,
+        args,
+        FloatFormat::S,
+        FloatFormat::min
+    ));
+    fp_instruction!(FMAX_S, "FMAX.S", |core, args| fp_min_max(
+        core,
+        args,
+        FloatFormat::S,
+        FloatFormat::max
+    ));
+    fp_instruction!(FMIN_D, "FMIN.D", |core, args| fp_min_max(
+        core,
+        args,
+        FloatFormat::D,
+        FloatFormat::min
+    ));
+    fp_instruction!(FMAX_D, "FMAX.D", |core, args| fp_min_max(
+        core,
+        args,
+        FloatFormat::D,
+        FloatFormat::max
+    ));
+
+    fp_instruction!(FEQ_S, "FEQ.S", |core, args| fp_compare(
+        core,
+        args,
+        FloatFormat::S,
+        FloatFormat::eq
+    ));
+    fp_instruction!(FLT_S, "FLT.S", |core, args| fp_compare(
+        core,
+        args,
+        FloatFormat::S,
+        FloatFormat::lt
+    ));
+    fp_instruction!(FLE_S, "FLE.S", |core, args| fp_compare(
+        core,
+        args,
+        FloatFormat::S,
+        FloatFormat::le
+    ));
+    fp_instruction!(FEQ_D, "FEQ.D", |core, args| fp_compare(
+        core,
+        args,
+        FloatFormat::D,
+        FloatFormat::eq
+    ));
+    fp_instruction!(FLT_D, "FLT.D", |core, args| fp_compare(
+        core,
+        args,
+        FloatFormat::D,
+        FloatFormat::lt
+    ));
+    fp_instruction!(FLE_D, "FLE.D", |core, args| fp_compare(
+        core,
+        args,
+        FloatFormat::D,
+        FloatFormat::le
+    ));
+
+    fp_instruction!(FCLASS_S, "FCLASS.S", |core, args| fp_classify(
+        core,
+        args,
+        FloatFormat::S
+    ));
+    fp_instruction!(FCLASS_D, "FCLASS.D", |core, args| fp_classify(
+        core,
+        args,
+        FloatFormat::D
+    ));
+}
+
+#[allow(non_snake_case)]
+impl Instruction<R4type> {
+    fp_instruction!(FCVT_W_S, "FCVT.W.S", |core, args| fp_to_int(
+        core,
+        args,
+        FloatFormat::S,
+        true,
+        32
+    ));
+    fp_instruction!(FCVT_WU_S, "FCVT.WU.S", |core, args| fp_to_int(
+        core,
+        args,
+        FloatFormat::S,
+        false,
+        32
+    ));
+    fp_instruction!(FCVT_L_S, "FCVT.L.S", |core, args| fp_to_int(
+        core,
+        args,
+        FloatFormat::S,
+        true,
+        64
+    ));
+    fp_instruction!(FCVT_LU_S, "FCVT.LU.S", |core, args| fp_to_int(
+        core,
+        args,
+        FloatFormat::S,
+        false,
+        64
+    ));
+    fp_instruction!(FCVT_W_D, "FCVT.W.D", |core, args| fp_to_int(
+        core,
+        args,
+        FloatFormat::D,
+        true,
+        32
+    ));
+    fp_instruction!(FCVT_WU_D, "FCVT.WU.D", |core, args| fp_to_int(
+        core,
+        args,
+        FloatFormat::D,
+        false,
+        32
+    ));
+    fp_instruction!(FCVT_L_D, "FCVT.L.D", |core, args| fp_to_int(
+        core,
+        args,
+        FloatFormat::D,
+        true,
+        64
+    ));
+    fp_instruction!(FCVT_LU_D, "FCVT.LU.D", |core, args| fp_to_int(
+        core,
+        args,
+        FloatFormat::D,
+        false,
+        64
+    ));
+
+    fp_instruction!(FCVT_S_W, "FCVT.S.W", |core, args| int_to_fp(
+        core,
+        args,
+        FloatFormat::S,
+        true,
+        32
+    ));
+    fp_instruction!(FCVT_S_WU, "FCVT.S.WU", |core, args| int_to_fp(
+        core,
+        args,
+        FloatFormat::S,
+        false,
+        32
+    ));
+    fp_instruction!(FCVT_S_L, "FCVT.S.L", |core, args| int_to_fp(
+        core,
+        args,
+        FloatFormat::S,
+        true,
+        64
+    ));
+    fp_instruction!(FCVT_S_LU, "FCVT.S.LU", |core, args| int_to_fp(
+        core,
+        args,
+        FloatFormat::S,
+        false,
+        64
+    ));
+    fp_instruction!(FCVT_D_W, "FCVT.D.W", |core, args| int_to_fp(
+        core,
+        args,
+        FloatFormat::D,
+        true,
+        32
+    ));
+    fp_instruction!(FCVT_D_WU, "FCVT.D.WU", |core, args| int_to_fp(
+        core,
+        args,
+        FloatFormat::D,
+        false,
+        32
+    ));
+    fp_instruction!(FCVT_D_L, "FCVT.D.L", |core, args| int_to_fp(
+        core,
+        args,
+        FloatFormat::D,
+        true,
+        64
+    ));
+    fp_instruction!(FCVT_D_LU, "FCVT.D.LU", |core, args| int_to_fp(
+        core,
+        args,
+        FloatFormat::D,
+        false,
+        64
+    ));
+
+    fp_instruction!(FCVT_S_D, "FCVT.S.D", |core, args| fp_convert(
+        core,
+        args,
+        FloatFormat::S,
+        FloatFormat::D
+    ));
+    fp_instruction!(FCVT_D_S, "FCVT.D.S", |core, args| fp_convert(
+        core,
+        args,
+        FloatFormat::D,
+        FloatFormat::S
+    ));
+}
+
+#[allow(non_snake_case)]
+impl Instruction<R4type> {
+    // The FMV instructions move bit patterns without interpreting them, so a single is
+    // sign-extended (not unboxed) when moved to an integer register
+    fp_instruction!(FMV_X_W, "FMV.X.W", |core, args| {
+        let rs1v = core.read_fregister(args.rs1);
+        Stage::writeback(args.rd, rs1v as i32 as u64)
+    });
+    fp_instruction!(FMV_X_D, "FMV.X.D", |core, args| {
+        let rs1v = core.read_fregister(args.rs1);
+        Stage::writeback(args.rd, rs1v)
+    });
+    fp_instruction!(FMV_W_X, "FMV.W.X", |core, args| {
+        let rs1v = core.read_register(args.rs1);
+        core.write_fregister(args.rd, FloatFormat::S.box_value(rs1v));
+        Stage::WRITEBACK(None)
+    });
+    fp_instruction!(FMV_D_X, "FMV.D.X", |core, args| {
+        let rs1v = core.read_register(args.rs1);
+        core.write_fregister(args.rd, rs1v);
+        Stage::WRITEBACK(None)
+    });
+}
+
+impl InstructionSelector<R4type> for R4type {
+    fn select(&self, _xlen: Xlen) -> Instruction<R4type> {
+        let fmt = match num::FromPrimitive::from_u8(self.fmt) {
+            Some(fmt) => fmt,
+            None => return Instruction::illegal(),
+        };
+        match self.opcode {
+            MajorOpcode::MADD => match fmt {
+                FloatFormat::S => Instruction::FMADD_S(self),
+                FloatFormat::D => Instruction::FMADD_D(self),
+            },
+            MajorOpcode::MSUB => match fmt {
+                FloatFormat::S => Instruction::FMSUB_S(self),
+                FloatFormat::D => Instruction::FMSUB_D(self),
+            },
+            MajorOpcode::NMSUB => match fmt {
+                FloatFormat::S => Instruction::FNMSUB_S(self),
+                FloatFormat::D => Instruction::FNMSUB_D(self),
+            },
+            MajorOpcode::NMADD => match fmt {
+                FloatFormat::S => Instruction::FNMADD_S(self),
+                FloatFormat::D => Instruction::FNMADD_D(self),
+            },
+            MajorOpcode::OP_FP => match num::FromPrimitive::from_u8(self.funct5) {
+                Some(OpFp_Funct5::FADD) => match fmt {
+                    FloatFormat::S => Instruction::FADD_S(self),
+                    FloatFormat::D => Instruction::FADD_D(self),
+                },
+                Some(OpFp_Funct5::FSUB) => match fmt {
+                    FloatFormat::S => Instruction::FSUB_S(self),
+                    FloatFormat::D => Instruction::FSUB_D(self),
+                },
+                Some(OpFp_Funct5::FMUL) => match fmt {
+                    FloatFormat::S => Instruction::FMUL_S(self),
+                    FloatFormat::D => Instruction::FMUL_D(self),
+                },
+                Some(OpFp_Funct5::FDIV) => match fmt {
+                    FloatFormat::S => Instruction::FDIV_S(self),
+                    FloatFormat::D => Instruction::FDIV_D(self),
+                },
+                // The unary instructions have no rs2, the field must be zero
+                Some(OpFp_Funct5::FSQRT) => match (fmt, self.rs2) {
+                    (FloatFormat::S, 0) => Instruction::FSQRT_S(self),
+                    (FloatFormat::D, 0) => Instruction::FSQRT_D(self),
+                    _ => Instruction::illegal(),
+                },
+                Some(OpFp_Funct5::FSGNJ) => match (fmt, self.rm) {
+                    (FloatFormat::S, 0b000) => Instruction::FSGNJ_S(self),
+                    (FloatFormat::S, 0b001) => Instruction::FSGNJN_S(self),
+                    (FloatFormat::S, 0b010) => Instruction::FSGNJX_S(self),
+                    (FloatFormat::D, 0b000) => Instruction::FSGNJ_D(self),
+                    (FloatFormat::D, 0b001) => Instruction::FSGNJN_D(self),
+                    (FloatFormat::D, 0b010) => Instruction::FSGNJX_D(self),
+                    _ => Instruction::illegal(),
+                },
+                Some(OpFp_Funct5::FMIN_FMAX) => match (fmt, self.rm) {
+                    (FloatFormat::S, 0b000) => Instruction::FMIN_S(self),
+                    (FloatFormat::S, 0b001) => Instruction::FMAX_S(self),
+                    (FloatFormat::D, 0b000) => Instruction::FMIN_D(self),
+                    (FloatFormat::D, 0b001) => Instruction::FMAX_D(self),
+                    _ => Instruction::illegal(),
+                },
+                Some(OpFp_Funct5::FCMP) => match (fmt, self.rm) {
+                    (FloatFormat::S, 0b000) => Instruction::FLE_S(self),
+                    (FloatFormat::S, 0b001) => Instruction::FLT_S(self),
+                    (FloatFormat::S, 0b010) => Instruction::FEQ_S(self),
+                    (FloatFormat::D, 0b000) => Instruction::FLE_D(self),
+                    (FloatFormat::D, 0b001) => Instruction::FLT_D(self),
+                    (FloatFormat::D, 0b010) => Instruction::FEQ_D(self),
+                    _ => Instruction::illegal(),
+                },
+                // The source format is selected by rs2
+                Some(OpFp_Funct5::FCVT_FMT_FMT) => match (fmt, self.rs2) {
+                    (FloatFormat::S, 0b00001) => Instruction::FCVT_S_D(self),
+                    (FloatFormat::D, 0b00000) => Instruction::FCVT_D_S(self),
+                    _ => Instruction::illegal(),
+                },
+                // The integer type is selected by rs2
+                Some(OpFp_Funct5::FCVT_INT_FMT) => match (fmt, self.rs2) {
+                    (FloatFormat::S, 0b00000) => Instruction::FCVT_W_S(self),
+                    (FloatFormat::S, 0b00001) => Instruction::FCVT_WU_S(self),
+                    (FloatFormat::S, 0b00010) => Instruction::FCVT_L_S(self),
+                    (FloatFormat::S, 0b00011) => Instruction::FCVT_LU_S(self),
+                    (FloatFormat::D, 0b00000) => Instruction::FCVT_W_D(self),
+                    (FloatFormat::D, 0b00001) => Instruction::FCVT_WU_D(self),
+                    (FloatFormat::D, 0b00010) => Instruction::FCVT_L_D(self),
+                    (FloatFormat::D, 0b00011) => Instruction::FCVT_LU_D(self),
+                    _ => Instruction::illegal(),
+                },
+                Some(OpFp_Funct5::FCVT_FMT_INT) => match (fmt, self.rs2) {
+                    (FloatFormat::S, 0b00000) => Instruction::FCVT_S_W(self),
+                    (FloatFormat::S, 0b00001) => Instruction::FCVT_S_WU(self),
+                    (FloatFormat::S, 0b00010) => Instruction::FCVT_S_L(self),
+                    (FloatFormat::S, 0b00011) => Instruction::FCVT_S_LU(self),
+                    (FloatFormat::D, 0b00000) => Instruction::FCVT_D_W(self),
+                    (FloatFormat::D, 0b00001) => Instruction::FCVT_D_WU(self),
+                    (FloatFormat::D, 0b00010) => Instruction::FCVT_D_L(self),
+                    (FloatFormat::D, 0b00011) => Instruction::FCVT_D_LU(self),
+                    _ => Instruction::illegal(),
+                },
+                Some(OpFp_Funct5::FMV_X_FCLASS) => match (fmt, self.rm, self.rs2) {
+                    (FloatFormat::S, 0b000, 0) => Instruction::FMV_X_W(self),
+                    (FloatFormat::S, 0b001, 0) => Instruction::FCLASS_S(self),
+                    (FloatFormat::D, 0b000, 0) => Instruction::FMV_X_D(self),
+                    (FloatFormat::D, 0b001, 0) => Instruction::FCLASS_D(self),
+                    _ => Instruction::illegal(),
+                },
+                Some(OpFp_Funct5::FMV_FMT_X) => match (fmt, self.rm, self.rs2) {
+                    (FloatFormat::S, 0b000, 0) => Instruction::FMV_W_X(self),
+                    (FloatFormat::D, 0b000, 0) => Instruction::FMV_D_X(self),
+                    _ => Instruction::illegal(),
+                },
+                None => Instruction::illegal(),
+            },
+            _ => Instruction::illegal(),
+        }
+    }
+}
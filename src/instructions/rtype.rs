@@ -2,11 +2,13 @@ use std::fmt::Display;
 
 use crate::{
     cpu::{Register, Xlen},
-    pipeline::{MemoryAccess, Stage},
+    pipeline::{AmoOperation, MemoryAccess, Stage},
 };
 
 use super::{
-    functions::{Funct3, Funct5, Funct7, Op32_Funct3, Op_Funct3, RV32M_Funct3, RV64M_Funct3},
+    functions::{
+        Amo_Funct3, Funct3, Funct5, Funct7, Op32_Funct3, Op_Funct3, RV32M_Funct3, RV64M_Funct3,
+    },
     opcodes::MajorOpcode,
     FormatDecoder, Instruction, InstructionFormatType, InstructionSelector, UncompressedFormatType,
 };
@@ -35,7 +37,9 @@ impl FormatDecoder<Rtype> for Rtype {
             funct3: num::FromPrimitive::from_u8(((word >> 12) & 7) as u8).unwrap(),
             funct7: num::FromPrimitive::from_u8(((word >> 25) & 0x7f) as u8)
                 .unwrap_or(Funct7::B0000000),
-            funct5: num::FromPrimitive::from_u8(((word >> 27) & 0x1f) as u8).unwrap(), // @FIXME: Default?
+            // Only meaningful for AMOs, the decoder rejects those with unknown funct5
+            funct5: num::FromPrimitive::from_u8(((word >> 27) & 0x1f) as u8)
+                .unwrap_or(Funct5::AMOADD),
         }
     }
 }
@@ -443,16 +447,86 @@ impl Instruction<Rtype> {
     }
 }
 
+macro_rules! amo_instruction {
+    ($name:ident, $mnemonic:expr, $access:ident, $op:expr) => {
+        pub fn $name(args: &Rtype) -> Instruction<Rtype> {
+            Instruction {
+                mnemonic: $mnemonic,
+                args: Some(*args),
+                funct: |core, args| {
+                    let rs1v = core.read_register(args.rs1);
+                    let rs2v = core.read_register(args.rs2);
+                    Stage::MEMORY(MemoryAccess::$access($op, rs1v, rs2v, args.rd))
+                },
+            }
+        }
+    };
+}
+
 #[allow(non_snake_case)]
 impl Instruction<Rtype> {
-    pub fn AMOSWAP_W(args: &Rtype) -> Instruction<Rtype> {
+    amo_instruction!(AMOSWAP_W, "AMOSWAP.W", AMO_W, AmoOperation::SWAP);
+    amo_instruction!(AMOADD_W, "AMOADD.W", AMO_W, AmoOperation::ADD);
+    amo_instruction!(AMOXOR_W, "AMOXOR.W", AMO_W, AmoOperation::XOR);
+    amo_instruction!(AMOAND_W, "AMOAND.W", AMO_W, AmoOperation::AND);
+    amo_instruction!(AMOOR_W, "AMOOR.W", AMO_W, AmoOperation::OR);
+    amo_instruction!(AMOMIN_W, "AMOMIN.W", AMO_W, AmoOperation::MIN);
+    amo_instruction!(AMOMAX_W, "AMOMAX.W", AMO_W, AmoOperation::MAX);
+    amo_instruction!(AMOMINU_W, "AMOMINU.W", AMO_W, AmoOperation::MINU);
+    amo_instruction!(AMOMAXU_W, "AMOMAXU.W", AMO_W, AmoOperation::MAXU);
+
+    amo_instruction!(AMOSWAP_D, "AMOSWAP.D", AMO_D, AmoOperation::SWAP);
+    amo_instruction!(AMOADD_D, "AMOADD.D", AMO_D, AmoOperation::ADD);
+    amo_instruction!(AMOXOR_D, "AMOXOR.D", AMO_D, AmoOperation::XOR);
+    amo_instruction!(AMOAND_D, "AMOAND.D", AMO_D, AmoOperation::AND);
+    amo_instruction!(AMOOR_D, "AMOOR.D", AMO_D, AmoOperation::OR);
+    amo_instruction!(AMOMIN_D, "AMOMIN.D", AMO_D, AmoOperation::MIN);
+    amo_instruction!(AMOMAX_D, "AMOMAX.D", AMO_D, AmoOperation::MAX);
+    amo_instruction!(AMOMINU_D, "AMOMINU.D", AMO_D, AmoOperation::MINU);
+    amo_instruction!(AMOMAXU_D, "AMOMAXU.D", AMO_D, AmoOperation::MAXU);
+
+    pub fn LR_W(args: &Rtype) -> Instruction<Rtype> {
+        Instruction {
+            mnemonic: "LR.W",
+            args: Some(*args),
+            funct: |core, args| {
+                let rs1v = core.read_register(args.rs1);
+                Stage::MEMORY(MemoryAccess::LR_W(rs1v, args.rd))
+            },
+        }
+    }
+
+    pub fn LR_D(args: &Rtype) -> Instruction<Rtype> {
+        Instruction {
+            mnemonic: "LR.D",
+            args: Some(*args),
+            funct: |core, args| {
+                let rs1v = core.read_register(args.rs1);
+                Stage::MEMORY(MemoryAccess::LR_D(rs1v, args.rd))
+            },
+        }
+    }
+
+    pub fn SC_W(args: &Rtype) -> Instruction<Rtype> {
+        Instruction {
+            mnemonic: "SC.W",
+            args: Some(*args),
+            funct: |core, args| {
+                let rs1v = core.read_register(args.rs1);
+                let rs2v = core.read_register(args.rs2);
+                Stage::MEMORY(MemoryAccess::SC_W(rs1v, rs2v, args.rd))
+            },
+        }
+    }
+
+    pub fn SC_D(args: &Rtype) -> Instruction<Rtype> {
         Instruction {
-            mnemonic: "AMOSWAP.W",
+            mnemonic: "SC.D",
             args: Some(*args),
             funct: |core, args| {
                 let rs1v = core.read_register(args.rs1);
                 let rs2v = core.read_register(args.rs2);
-                Stage::MEMORY(MemoryAccess::AMOSWAP_W(rs1v, rs2v, args.rd))
+                Stage::MEMORY(MemoryAccess::SC_D(rs1v, rs2v, args.rd))
             },
         }
     }
@@ -461,31 +535,62 @@ impl Instruction<Rtype> {
 impl InstructionSelector<Rtype> for Rtype {
     fn select(&self, _xlen: Xlen) -> Instruction<Rtype> {
         match self.opcode {
-            MajorOpcode::AMO => match self.funct5 {
-                Funct5::AMOSWAP_W => Instruction::AMOSWAP_W(self),
-                _ => panic!(),
+            MajorOpcode::AMO => match num::FromPrimitive::from_u8(self.funct3 as u8) {
+                Some(Amo_Funct3::W) => match self.funct5 {
+                    Funct5::LR if self.rs2 == 0 => Instruction::LR_W(self),
+                    Funct5::LR => Instruction::illegal(),
+                    Funct5::SC => Instruction::SC_W(self),
+                    Funct5::AMOSWAP => Instruction::AMOSWAP_W(self),
+                    Funct5::AMOADD => Instruction::AMOADD_W(self),
+                    Funct5::AMOXOR => Instruction::AMOXOR_W(self),
+                    Funct5::AMOAND => Instruction::AMOAND_W(self),
+                    Funct5::AMOOR => Instruction::AMOOR_W(self),
+                    Funct5::AMOMIN => Instruction::AMOMIN_W(self),
+                    Funct5::AMOMAX => Instruction::AMOMAX_W(self),
+                    Funct5::AMOMINU => Instruction::AMOMINU_W(self),
+                    Funct5::AMOMAXU => Instruction::AMOMAXU_W(self),
+                },
+                Some(Amo_Funct3::D) => match self.funct5 {
+                    Funct5::LR if self.rs2 == 0 => Instruction::LR_D(self),
+                    Funct5::LR => Instruction::illegal(),
+                    Funct5::SC => Instruction::SC_D(self),
+                    Funct5::AMOSWAP => Instruction::AMOSWAP_D(self),
+                    Funct5::AMOADD => Instruction::AMOADD_D(self),
+                    Funct5::AMOXOR => Instruction::AMOXOR_D(self),
+                    Funct5::AMOAND => Instruction::AMOAND_D(self),
+                    Funct5::AMOOR => Instruction::AMOOR_D(self),
+                    Funct5::AMOMIN => Instruction::AMOMIN_D(self),
+                    Funct5::AMOMAX => Instruction::AMOMAX_D(self),
+                    Funct5::AMOMINU => Instruction::AMOMINU_D(self),
+                    Funct5::AMOMAXU => Instruction::AMOMAXU_D(self),
+                },
+                None => Instruction::illegal(),
             },
             MajorOpcode::OP_32 => match self.funct7 {
-                Funct7::B0000001 => match num::FromPrimitive::from_u8(self.funct3 as u8).unwrap() {
-                    RV64M_Funct3::REMUW => Instruction::REMUW(self),
-                    RV64M_Funct3::DIVUW => Instruction::DIVUW(self),
-                    RV64M_Funct3::MULW => Instruction::MULW(self),
-                    RV64M_Funct3::DIVW => Instruction::DIVW(self),
-                    _ => panic!(),
-                },
-                _ => match num::FromPrimitive::from_u8(self.funct3 as u8).unwrap() {
-                    Op32_Funct3::SLLW => Instruction::SLLW(self),
-                    Op32_Funct3::SRAW_SRLW => match self.funct7 {
-                        Funct7::B0000000 => Instruction::SRLW(self),
-                        Funct7::B0100000 => Instruction::SRAW(self),
-                        _ => panic!(),
-                    },
-                    Op32_Funct3::ADDW_SUBW => match self.funct7 {
-                        Funct7::B0000000 => Instruction::ADDW(self),
-                        Funct7::B0100000 => Instruction::SUBW(self),
-                        _ => panic!(),
-                    },
+                Funct7::B0000001 => match num::FromPrimitive::from_u8(self.funct3 as u8) {
+                    Some(RV64M_Funct3::REMUW) => Instruction::REMUW(self),
+                    Some(RV64M_Funct3::DIVUW) => Instruction::DIVUW(self),
+                    Some(RV64M_Funct3::MULW) => Instruction::MULW(self),
+                    Some(RV64M_Funct3::DIVW) => Instruction::DIVW(self),
+                    _ => Instruction::illegal(),
                 },
+                Funct7::B0000000 | Funct7::B0100000 => {
+                    match num::FromPrimitive::from_u8(self.funct3 as u8) {
+                        Some(Op32_Funct3::SLLW) if self.funct7 == Funct7::B0000000 => {
+                            Instruction::SLLW(self)
+                        }
+                        Some(Op32_Funct3::SRAW_SRLW) => match self.funct7 {
+                            Funct7::B0000000 => Instruction::SRLW(self),
+                            _ => Instruction::SRAW(self),
+                        },
+                        Some(Op32_Funct3::ADDW_SUBW) => match self.funct7 {
+                            Funct7::B0000000 => Instruction::ADDW(self),
+                            _ => Instruction::SUBW(self),
+                        },
+                        _ => Instruction::illegal(),
+                    }
+                }
+                _ => Instruction::illegal(),
             },
             MajorOpcode::OP => match self.funct7 {
                 // RV32M
@@ -496,12 +601,12 @@ impl InstructionSelector<Rtype> for Rtype {
                     RV32M_Funct3::DIVU => Instruction::DIVU(self),
                     RV32M_Funct3::DIV => Instruction::DIV(self),
                     RV32M_Funct3::MULHSU => Instruction::MULHSU(self),
-                    _ => panic!("Unkown funct3 {:#x?}", self.funct3),
+                    _ => Instruction::illegal(),
                 },
                 Funct7::B0100000 => match num::FromPrimitive::from_u8(self.funct3 as u8).unwrap() {
                     Op_Funct3::ADD_SUB => Instruction::SUB(self),
-                    Op_Funct3::SRL_SRA => Instruction::SRA(self), //todo!("SRA, since Funct7!=0"),
-                    _ => todo!("keine anung"),
+                    Op_Funct3::SRL_SRA => Instruction::SRA(self),
+                    _ => Instruction::illegal(),
                 },
                 Funct7::B0000000 => match num::FromPrimitive::from_u8(self.funct3 as u8).unwrap() {
                     Op_Funct3::ADD_SUB => Instruction::ADD(self),
@@ -513,9 +618,9 @@ impl InstructionSelector<Rtype> for Rtype {
                     Op_Funct3::SLL => Instruction::SLL(self),
                     Op_Funct3::SLT => Instruction::SLT(self),
                 },
-                _ => todo!("R-type Funct7 not supported"),
+                _ => Instruction::illegal(),
             },
-            _ => panic!(),
+            _ => Instruction::illegal(),
         }
     }
 }
@@ -9,8 +9,10 @@ use crate::{
 };
 
 use super::{
-    functions::Store_Funct3, opcodes::MajorOpcode, FormatDecoder, ImmediateDecoder, Instruction,
-    InstructionFormatType, InstructionSelector, UncompressedFormatType,
+    functions::{StoreFp_Funct3, Store_Funct3},
+    opcodes::MajorOpcode,
+    FormatDecoder, ImmediateDecoder, Instruction, InstructionFormatType, InstructionSelector,
+    UncompressedFormatType,
 };
 
 #[derive(Debug, PartialEq, Copy, Clone)]
@@ -82,6 +84,41 @@ impl Instruction<Stype> {
         }
     }
 
+    pub fn FSW(args: &Stype) -> Instruction<Stype> {
+        Instruction {
+            mnemonic: "FSW",
+            args: Some(*args),
+            funct: |core, args| {
+                if !core.fp_enabled() {
+                    return Stage::TRAP(core.illegal_instruction());
+                }
+                let rs1v = core.read_register(args.rs1);
+                // FSW stores the low 32 bits as-is, without checking the NaN-boxing
+                let rs2v = core.read_fregister(args.rs2);
+
+                let addr = rs1v.wrapping_add((args.imm12 as u64).sign_extend(64 - 12) as VAddr);
+                Stage::MEMORY(MemoryAccess::WRITE32(addr, rs2v as u32))
+            },
+        }
+    }
+
+    pub fn FSD(args: &Stype) -> Instruction<Stype> {
+        Instruction {
+            mnemonic: "FSD",
+            args: Some(*args),
+            funct: |core, args| {
+                if !core.fp_enabled() {
+                    return Stage::TRAP(core.illegal_instruction());
+                }
+                let rs1v = core.read_register(args.rs1);
+                let rs2v = core.read_fregister(args.rs2);
+
+                let addr = rs1v.wrapping_add((args.imm12 as u64).sign_extend(64 - 12) as VAddr);
+                Stage::MEMORY(MemoryAccess::WRITE64(addr, rs2v))
+            },
+        }
+    }
+
     pub fn SB(args: &Stype) -> Instruction<Stype> {
         Instruction {
             mnemonic: "SB",
@@ -116,13 +153,19 @@ impl Instruction<Stype> {
 impl InstructionSelector<Stype> for Stype {
     fn select(&self, _xlen: Xlen) -> Instruction<Stype> {
         match self.opcode {
-            MajorOpcode::STORE => match num::FromPrimitive::from_u8(self.funct3).unwrap() {
-                Store_Funct3::SD => Instruction::SD(self),
-                Store_Funct3::SB => Instruction::SB(self),
-                Store_Funct3::SH => Instruction::SH(self),
-                Store_Funct3::SW => Instruction::SW(self),
+            MajorOpcode::STORE => match num::FromPrimitive::from_u8(self.funct3) {
+                Some(Store_Funct3::SD) => Instruction::SD(self),
+                Some(Store_Funct3::SB) => Instruction::SB(self),
+                Some(Store_Funct3::SH) => Instruction::SH(self),
+                Some(Store_Funct3::SW) => Instruction::SW(self),
+                None => Instruction::illegal(),
+            },
+            MajorOpcode::STORE_FP => match num::FromPrimitive::from_u8(self.funct3) {
+                Some(StoreFp_Funct3::FSW) => Instruction::FSW(self),
+                Some(StoreFp_Funct3::FSD) => Instruction::FSD(self),
+                None => Instruction::illegal(),
             },
-            _ => panic!(),
+            _ => Instruction::illegal(),
         }
     }
 }
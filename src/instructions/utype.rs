@@ -74,7 +74,7 @@ impl InstructionSelector<Utype> for Utype {
         match self.opcode {
             MajorOpcode::AUIPC => Instruction::AUIPC(self),
             MajorOpcode::LUI => Instruction::LUI(self),
-            _ => panic!(),
+            _ => Instruction::illegal(),
         }
     }
 }
@@ -2,15 +2,22 @@
 extern crate num_derive;
 extern crate include_bytes_aligned;
 
+pub mod aia;
+pub mod chardev;
 pub mod cpu;
 pub mod debugger;
+pub mod disk;
 pub mod elf;
+pub mod fpu;
 pub mod instructions;
 pub mod memory;
 pub mod mmio;
 pub mod mmu;
+pub mod netdev;
 pub mod pipeline;
 pub mod plic;
+pub mod pmp;
+pub mod tlb;
 pub mod uart;
 pub mod virtio;
 
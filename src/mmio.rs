@@ -1,7 +1,9 @@
+use std::time::Instant;
+
 use elfloader::VAddr;
 
 use crate::{
-    cpu::TrapCause,
+    cpu::{MipMask, RegisterValue, TrapCause},
     memory::{MemoryOperations, RAM},
     mmu::MemoryRange,
 };
@@ -15,9 +17,29 @@ use crate::{
 
 pub struct MMIODevice {}
 
+/// The core-local interruptor: a software interrupt and a timer compare register per
+/// hart, and the shared `mtime` counter
 pub struct CLINT {
     range: MemoryRange,
-    ram: RAM,
+    /// Host time the counts of `Timebase::Host` are taken from
+    epoch: Instant,
+    /// Difference between `mtime` and the counts since `epoch`, for `Timebase::Host`
+    offset: i128,
+    clock: u64,
+    timebase: Timebase,
+    mtime: u64,
+    msip: Vec<bool>,
+    mtimecmp: Vec<u64>,
+}
+
+/// How `mtime` advances
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Timebase {
+    /// One count every so many ticks, the same on every run
+    Ticks(u64),
+    /// So many counts per second of host time, like the `timebase-frequency` of the
+    /// device tree
+    Host(u64),
 }
 
 pub struct PhysicalMemory {
@@ -33,37 +55,108 @@ impl PhysicalMemory {
 }
 
 impl CLINT {
-    pub const CLINT_MTIME: usize = 0xbff8;
+    const MSIP: u64 = 0x0;
+    const MTIMECMP: u64 = 0x4000;
+    const MTIME: u64 = 0xbff8;
+
+    /// Creates a CLINT for `harts`, with `mtime` counting ticks
+    pub fn create(range: MemoryRange, harts: usize) -> CLINT {
+        CLINT {
+            range,
+            epoch: Instant::now(),
+            offset: 0,
+            clock: 0,
+            timebase: Timebase::Ticks(1),
+            mtime: 0,
+            msip: vec![false; harts],
+            // No timer interrupt until software asks for one
+            mtimecmp: vec![u64::MAX; harts],
+        }
+    }
 
-    pub fn create(range: MemoryRange) -> CLINT {
-        let ram = RAM::create(range.start, (range.end - range.start).try_into().unwrap());
-        CLINT { range, ram }
+    /// Switches how `mtime` advances, carrying on from its current value. Returns false,
+    /// leaving the timebase as it was, for a host frequency of 0.
+    pub fn set_timebase(&mut self, timebase: Timebase) -> bool {
+        match timebase {
+            Timebase::Host(0) => return false,
+            Timebase::Host(frequency) => {
+                self.offset = self.mtime as i128 - self.host_counts(frequency) as i128
+            }
+            Timebase::Ticks(_) => {}
+        }
+        self.timebase = timebase;
+        true
+    }
+
+    /// Counts at `frequency` since `epoch`
+    fn host_counts(&self, frequency: u64) -> u128 {
+        self.epoch.elapsed().as_nanos() * frequency as u128 / 1_000_000_000
+    }
+
+    pub fn mtime(&self) -> u64 {
+        self.mtime
     }
 
     pub fn tick(&mut self) {
-        let l = self
-            .read32(CLINT::CLINT_MTIME as VAddr + 0 + self.range.start)
-            .unwrap();
-
-        if l.wrapping_add(1) < l {
-            let h = self
-                .read32(CLINT::CLINT_MTIME as VAddr + 4 + self.range.start)
-                .unwrap();
-            self.write32(
-                CLINT::CLINT_MTIME as VAddr + 4 + self.range.start,
-                h.wrapping_add(1),
-            );
+        self.clock = self.clock.wrapping_add(1);
+        match self.timebase {
+            Timebase::Ticks(divider) => {
+                if self.clock % divider.max(1) == 0 {
+                    self.mtime = self.mtime.wrapping_add(1);
+                }
+            }
+            Timebase::Host(frequency) => {
+                self.mtime = (self.host_counts(frequency) as i128 + self.offset) as u64;
+            }
+        }
+    }
+
+    /// Returns the `mip` register value of `hart` with MSIP and MTIP set from its
+    /// `msip` and `mtimecmp` registers
+    pub fn update_mip(&self, hart: usize, mip: RegisterValue) -> RegisterValue {
+        let mut lines = 0;
+        if self.msip[hart] {
+            lines |= MipMask::MSIP as RegisterValue;
         }
-        if l % 10000000 == 0 {
-            println!("CLINT:MTIME: {:#?}", l);
+        if self.mtime >= self.mtimecmp[hart] {
+            lines |= MipMask::MTIP as RegisterValue;
         }
+        (mip & !((MipMask::MSIP | MipMask::MTIP) as RegisterValue)) | lines
+    }
 
-        self.write32(
-            CLINT::CLINT_MTIME as VAddr + 0 + self.range.start,
-            l.wrapping_add(1),
-        );
+    fn read_register(&self, offset: u64) -> u32 {
+        let harts = self.msip.len() as u64;
+        match offset {
+            CLINT::MSIP..=0x3fff if offset / 4 < harts => self.msip[(offset / 4) as usize] as u32,
+            CLINT::MTIMECMP..=0xbff7 if (offset - CLINT::MTIMECMP) / 8 < harts => {
+                let hart = ((offset - CLINT::MTIMECMP) / 8) as usize;
+                (self.mtimecmp[hart] >> (8 * (offset & 4))) as u32
+            }
+            CLINT::MTIME..=0xbfff => (self.mtime >> (8 * (offset & 4))) as u32,
+            _ => 0,
+        }
+    }
 
-        // Update CLINT_MTIME
+    fn write_register(&mut self, offset: u64, value: u32) {
+        let harts = self.msip.len() as u64;
+        let replace = |old: u64| match offset & 4 {
+            0 => (old & !0xffff_ffff) | value as u64,
+            _ => (old & 0xffff_ffff) | (value as u64) << 32,
+        };
+        match offset {
+            CLINT::MSIP..=0x3fff if offset / 4 < harts => {
+                self.msip[(offset / 4) as usize] = (value & 1) == 1
+            }
+            CLINT::MTIMECMP..=0xbff7 if (offset - CLINT::MTIMECMP) / 8 < harts => {
+                let hart = ((offset - CLINT::MTIMECMP) / 8) as usize;
+                self.mtimecmp[hart] = replace(self.mtimecmp[hart]);
+            }
+            CLINT::MTIME..=0xbfff => {
+                self.mtime = replace(self.mtime);
+                self.set_timebase(self.timebase);
+            }
+            _ => {}
+        }
     }
 }
 
@@ -100,7 +193,7 @@ impl MemoryOperations<PhysicalMemory, u8> for PhysicalMemory {
         self.ram.write64(addr, value)
     }
 
-    fn read16(&mut self, addr: VAddr) -> Option<u16> {
+    fn read16(&mut self, addr: VAddr) -> Result<u16, TrapCause> {
         self.ram.read16(addr)
     }
 
@@ -128,19 +221,33 @@ impl VirtualDevice for PhysicalMemory {
 
 impl MemoryOperations<CLINT, u8> for CLINT {
     fn read8(&mut self, addr: VAddr) -> Result<u8, TrapCause> {
-        self.ram.read8(addr)
+        let word = self.read_register((addr - self.range.start) & !3);
+        Ok((word >> (8 * (addr & 3))) as u8)
     }
 
     fn write8(&mut self, addr: VAddr, value: u8) -> Option<TrapCause> {
-        self.ram.write8(addr, value)
+        let offset = (addr - self.range.start) & !3;
+        let shift = 8 * (addr & 3);
+        let word = self.read_register(offset) & !(0xff << shift);
+        self.write_register(offset, word | (value as u32) << shift);
+        None
     }
 
     fn read32(&mut self, addr: VAddr) -> Result<u32, TrapCause> {
-        self.ram.read32(addr)
+        match addr & 3 {
+            0 => Ok(self.read_register(addr - self.range.start)),
+            _ => Err(TrapCause::LoadAccessFault(addr)),
+        }
     }
 
     fn write32(&mut self, addr: VAddr, value: u32) -> Option<TrapCause> {
-        self.ram.write32(addr, value)
+        match addr & 3 {
+            0 => {
+                self.write_register(addr - self.range.start, value);
+                None
+            }
+            _ => Some(TrapCause::StoreAccessFault(addr)),
+        }
     }
 }
 
@@ -154,10 +261,10 @@ impl VirtualDevice for CLINT {
     }
 
     fn write(&mut self, addr: VAddr, value: u8) -> Option<TrapCause> {
-        self.ram.write8(addr, value)
+        self.write8(addr, value)
     }
 
     fn read(&mut self, addr: VAddr) -> Result<u8, TrapCause> {
-        Ok(self.ram.read8(addr).unwrap())
+        self.read8(addr)
     }
 }
@@ -2,12 +2,15 @@ use elfloader::{PAddr, VAddr};
 use include_bytes_aligned::include_bytes_aligned;
 
 use crate::{
+    aia::AIA,
     cpu::{PrivMode, RegisterValue, TrapCause, Xlen},
     memory::{MemoryOperations, RAMOperations},
     mmio::{PhysicalMemory, VirtualDevice, CLINT},
     plic::PLIC,
+    pmp::PMP,
+    tlb::{TLBEntry, TLB},
     uart::UART,
-    virtio::VIRTIO,
+    virtio::{VirtioDevice, VIRTIO},
 };
 
 #[derive(Copy, Clone)]
@@ -15,11 +18,21 @@ pub struct MemoryRange {
     pub name: &'static str,
     pub start: VAddr,
     pub end: VAddr,
+    /// The `interrupts` property of the device, its PLIC source
+    pub interrupt: Option<u32>,
 }
 
 impl MemoryRange {
     pub fn includes(&self, addr: VAddr) -> bool {
-        self.start <= addr && addr <= self.end
+        self.start <= addr && addr < self.end
+    }
+
+    pub fn find_named_ranges(device_table: &[MemoryRange], name: &str) -> Vec<MemoryRange> {
+        device_table
+            .iter()
+            .filter(|range| range.name == name)
+            .copied()
+            .collect()
     }
 
     pub fn find_named_range(device_table: &Vec<MemoryRange>, name: &str) -> Option<MemoryRange> {
@@ -39,128 +52,191 @@ pub enum AddressingMode {
     None = 0,
     SV32 = 1,
     SV39 = 2,
+    SV48 = 3,
+    SV57 = 4,
 }
 
+impl AddressingMode {
+    /// Number of page table levels walked for a translation
+    pub fn levels(&self) -> usize {
+        match self {
+            AddressingMode::None => 0,
+            AddressingMode::SV32 => 2,
+            AddressingMode::SV39 => 3,
+            AddressingMode::SV48 => 4,
+            AddressingMode::SV57 => 5,
+        }
+    }
+
+    /// Width of each VPN field in a virtual address
+    pub fn vpn_bits(&self) -> u32 {
+        match self {
+            AddressingMode::SV32 => 10,
+            _ => 9,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub enum MemoryAccessType {
     READ = 1,
     WRITE = 2,
     EXECUTE = 3,
 }
 
+/// Checks the permission bits of a leaf PTE for an access made in `pmode`
+fn permitted(
+    pte: PageTableEntry,
+    access_type: MemoryAccessType,
+    pmode: PrivMode,
+    mstatus: RegisterValue,
+) -> bool {
+    // U-mode may only touch U pages. S-mode may never execute them, and may only
+    // read or write them with mstatus.SUM set
+    let u = pte.has_permission_bit(PTEPermBit::USER);
+    let sum = (mstatus >> 18) & 1 == 1;
+    let allowed = match pmode {
+        PrivMode::User => u,
+        _ => !u || (sum && access_type != MemoryAccessType::EXECUTE),
+    };
+
+    // mstatus.MXR makes executable pages readable as well
+    let mxr = (mstatus >> 19) & 1 == 1;
+    let x = pte.has_permission_bit(PTEPermBit::EXECUTE);
+    allowed
+        && match access_type {
+            MemoryAccessType::EXECUTE => x,
+            MemoryAccessType::READ => pte.has_permission_bit(PTEPermBit::READ) || (mxr && x),
+            MemoryAccessType::WRITE => pte.has_permission_bit(PTEPermBit::WRITE),
+        }
+}
+
+impl MemoryAccessType {
+    /// The page fault raised when `addr` cannot be translated for this kind of access
+    pub fn page_fault(&self, addr: VAddr) -> TrapCause {
+        match self {
+            MemoryAccessType::READ => TrapCause::LoadPageFault(addr),
+            MemoryAccessType::WRITE => TrapCause::StorePageFault(addr),
+            MemoryAccessType::EXECUTE => TrapCause::InstructionPageFault(addr),
+        }
+    }
+
+    /// The access fault raised when the physical address behind `addr` is not accessible
+    pub fn access_fault(&self, addr: VAddr) -> TrapCause {
+        match self {
+            MemoryAccessType::READ => TrapCause::LoadAccessFault(addr),
+            MemoryAccessType::WRITE => TrapCause::StoreAccessFault(addr),
+            MemoryAccessType::EXECUTE => TrapCause::InstructionAccessFault(addr),
+        }
+    }
+}
+
 //#[derive(Debug)]
 pub struct MMU {
     memory: PhysicalMemory,
     uart: UART,
     plic: PLIC,
+    /// Takes the place of the PLIC once selected
+    aia: Option<AIA>,
     clint: CLINT,
-    virtio: VIRTIO,
+    virtio: Vec<VIRTIO>,
     //protected: Vec<MemoryRange>,
     device_table: Vec<MemoryRange>,
     pmode: PrivMode,
     mstatus: RegisterValue,
     satp: RegisterValue,
     ppn: u64,
+    asid: u16,
     addressing_mode: AddressingMode,
+    tlb: TLB,
+    pmp: PMP,
 }
 
 impl MemoryOperations<MMU, u8> for MMU {
     // @TODO: Optimize this?
     fn read8(&mut self, addr: VAddr) -> Result<u8, TrapCause> {
-        let resolved = self.translate_address(&addr, MemoryAccessType::READ);
-        let addr = match resolved {
-            None => return Err(TrapCause::LoadAccessFault(addr)),
-            Some(val) => val,
-        };
-
-        if self.memory.includes(addr) {
-            self.memory.read8(addr)
-        } else if self.virtio.includes(addr) {
-            todo!("VIRTIO I/O")
-        } else if self.uart.includes(addr) {
-            self.uart.read(addr)
-        } else if self.clint.includes(addr) {
-            self.clint.read8(addr)
-        } else if self.plic.includes(addr) {
-            todo!("PLIC I/O")
+        let paddr = self.translate_address(&addr, MemoryAccessType::READ)?;
+
+        if self.memory.includes(paddr) {
+            self.memory.read8(paddr)
+        } else if let Some(virtio) = self.virtio.iter_mut().find(|v| v.includes(paddr)) {
+            virtio.read8(paddr)
+        } else if self.uart.includes(paddr) {
+            self.uart.read(paddr)
+        } else if self.clint.includes(paddr) {
+            self.clint.read8(paddr)
         } else {
-            Err(TrapCause::Breakpoint)
-            //panic!("{:#x?} is not mapped to memory", addr)
+            // Nothing is mapped here, or an interrupt controller that only takes 32-bit accesses
+            Err(TrapCause::LoadAccessFault(addr))
         }
     }
 
     // @TODO: Optimize this?
     fn write8(&mut self, addr: VAddr, value: u8) -> Option<TrapCause> {
-        let resolved = self.translate_address(&addr, MemoryAccessType::WRITE);
-        let addr = match resolved {
-            None => return Some(TrapCause::StoreAccessFault(addr)),
-            Some(val) => val,
+        let paddr = match self.translate_address(&addr, MemoryAccessType::WRITE) {
+            Err(cause) => return Some(cause),
+            Ok(paddr) => paddr,
         };
         // for i in 0..self.protected.len() {
         //     if self.protected[i].includes(addr) {
         //         panic!();
         //     }
         // }
-        if self.memory.includes(addr) {
-            self.memory.write8(addr, value)
-        } else if self.uart.includes(addr) {
-            self.uart.write(addr, value)
-        } else if self.clint.includes(addr) {
-            self.clint.write8(addr, value)
-        } else if self.plic.includes(addr) {
-            todo!("PLIC I/O")
-        } else if self.virtio.includes(addr) {
-            todo!("VIRTIO I/O")
+        if self.memory.includes(paddr) {
+            self.memory.write8(paddr, value)
+        } else if self.uart.includes(paddr) {
+            self.uart.write(paddr, value)
+        } else if self.clint.includes(paddr) {
+            self.clint.write8(paddr, value)
+        } else if let Some(virtio) = self.virtio.iter_mut().find(|v| v.includes(paddr)) {
+            virtio.write8(paddr, value)
         } else {
-            panic!("{:#x?} is not mapped to memory", addr);
-            Some(TrapCause::LoadAccessFault(addr))
+            // Nothing is mapped here, or an interrupt controller that only takes 32-bit accesses
+            Some(TrapCause::StoreAccessFault(addr))
         }
     }
 
     fn read32(&mut self, addr: VAddr) -> Result<u32, TrapCause> {
-        let resolved = self.translate_address(&addr, MemoryAccessType::READ);
-        let addr = match resolved {
-            None => return Err(TrapCause::LoadAccessFault(addr)),
-            Some(val) => val,
-        };
-
-        let value = if self.memory.includes(addr) {
-            self.memory.read32(addr)
-        } else if self.virtio.includes(addr) {
-            self.virtio.read32(addr)
-        } else if self.clint.includes(addr) {
-            self.clint.read32(addr)
-        } else if self.plic.includes(addr) {
-            self.plic.read32(addr)
+        let paddr = self.translate_access(&addr, 4, MemoryAccessType::READ)?;
+
+        let value = if self.memory.includes(paddr) {
+            self.memory.read32(paddr)
+        } else if let Some(virtio) = self.virtio.iter_mut().find(|v| v.includes(paddr)) {
+            virtio.read32(paddr)
+        } else if self.clint.includes(paddr) {
+            self.clint.read32(paddr)
+        } else if let Some(aia) = self.aia.as_mut().filter(|aia| aia.includes(paddr)) {
+            aia.read32(paddr)
+        } else if self.aia.is_none() && self.plic.includes(paddr) {
+            self.plic.read32(paddr)
         } else {
-            panic!(
-                "{:#x?} is not mapped to memory: {:#x?} - {:#x?}",
-                addr, self.memory.range.start, self.memory.range.end
-            );
             return Err(TrapCause::LoadAccessFault(addr));
         };
-        Ok(value.unwrap())
+        value
     }
 
     fn write32(&mut self, addr: VAddr, value: u32) -> Option<TrapCause> {
-        let resolved = self.translate_address(&addr, MemoryAccessType::WRITE);
-        let addr = match resolved {
-            None => return Some(TrapCause::StoreAccessFault(addr)),
-            Some(val) => val,
+        let paddr = match self.translate_access(&addr, 4, MemoryAccessType::WRITE) {
+            Err(cause) => return Some(cause),
+            Ok(paddr) => paddr,
         };
-        if self.memory.includes(addr) {
-            self.memory.write32(addr, value)
-        } else if self.virtio.includes(addr) {
-            self.virtio.write32(addr, value)
-        } else if self.clint.includes(addr) {
-            self.clint.write32(addr, value)
-        } else if self.plic.includes(addr) {
-            self.plic.write32(addr, value)
+        if self.memory.includes(paddr) {
+            self.memory.write32(paddr, value)
+        } else if let Some(virtio) = self.virtio.iter_mut().find(|v| v.includes(paddr)) {
+            virtio.write32(paddr, value)
+        } else if self.clint.includes(paddr) {
+            self.clint.write32(paddr, value)
+        } else if let Some(aia) = self.aia.as_mut().filter(|aia| aia.includes(paddr)) {
+            aia.write32(paddr, value)
+        } else if self.aia.is_none() && self.plic.includes(paddr) {
+            self.plic.write32(paddr, value)
         } else {
-            Some(TrapCause::LoadAccessFault(addr))
+            Some(TrapCause::StoreAccessFault(addr))
         }
     }
 
     fn read64(&mut self, addr: VAddr) -> Result<u64, TrapCause> {
+        self.check_access(addr, 8, MemoryAccessType::READ)?;
         let l = match self.read32(addr) {
             Err(cause) => return Err(cause),
             Ok(val) => val,
@@ -177,7 +253,7 @@ impl MemoryOperations<MMU, u8> for MMU {
         todo!()
     }
 
-    fn read16(&mut self, _addr: VAddr) -> Option<u16> {
+    fn read16(&mut self, _addr: VAddr) -> Result<u16, TrapCause> {
         todo!()
     }
 
@@ -191,21 +267,29 @@ impl RAMOperations<MMU> for MMU {}
 impl MMU {
     // @TODO: Panics if devicetable is corrupt or not what we expect
     pub fn create() -> MMU {
-        let device_table = MMU::parse_dtb();
+        let device_table = MMU::parse_dtb(include_bytes_aligned!(64, "../resources/dtb.dtb"));
         let memory =
             PhysicalMemory::create(MemoryRange::find_named_range(&device_table, "memory").unwrap());
         let uart = UART::create(MemoryRange::find_named_range(&device_table, "uart").unwrap());
+        // A single hart, as in the device tree
         let plic = PLIC::create(
             MemoryRange::find_named_range(&device_table, "interrupt-controller").unwrap(),
+            1,
+        );
+        let clint = CLINT::create(
+            MemoryRange::find_named_range(&device_table, "clint").unwrap(),
+            1,
         );
-        let clint = CLINT::create(MemoryRange::find_named_range(&device_table, "clint").unwrap());
-        let virtio =
-            VIRTIO::create(MemoryRange::find_named_range(&device_table, "virtio_mmio").unwrap());
+        let virtio = MemoryRange::find_named_ranges(&device_table, "virtio_mmio")
+            .into_iter()
+            .map(VIRTIO::create)
+            .collect();
 
         MMU {
             memory,
             uart,
             plic,
+            aia: None,
             clint,
             virtio,
             //protected: Vec::new(),
@@ -214,7 +298,10 @@ impl MMU {
             mstatus: 0,
             satp: 0,
             ppn: 0,
+            asid: 0,
             addressing_mode: AddressingMode::None,
+            tlb: TLB::create(),
+            pmp: PMP::create(),
         }
     }
 
@@ -222,30 +309,39 @@ impl MMU {
         if self.satp == satp {
             return;
         }
-        self.satp = satp;
-        self.addressing_mode = match xlen {
+        let addressing_mode = match xlen {
             Xlen::Bits32 => match satp & 0x80000000 {
                 0 => AddressingMode::None,
-                _ => panic!("not implemented: SV32"),
+                _ => AddressingMode::SV32,
             },
             Xlen::Bits64 => match satp >> 60 {
                 0 => AddressingMode::None,
                 8 => AddressingMode::SV39,
-                9 => panic!("not implemented: SV48"),
-                _ => {
-                    println!("Unknown addressing_mode {:x}", satp >> 60);
-                    panic!();
-                }
+                9 => AddressingMode::SV48,
+                10 => AddressingMode::SV57,
+                // satp is WARL, writes selecting a scheme we do not implement are ignored
+                _ => return,
             },
             Xlen::Bits128 => panic!("not implemented"),
         };
+        self.satp = satp;
 
+        // Entries are tagged with their ASID, so only a change of translation scheme
+        // has to flush them; reusing an ASID for a new table requires SFENCE.VMA
+        if addressing_mode != self.addressing_mode {
+            self.tlb.flush(None, None);
+        }
+        self.addressing_mode = addressing_mode;
+
+        self.asid = match xlen {
+            Xlen::Bits32 => (satp >> 22) & 0x1ff,
+            _ => (satp >> 44) & 0xffff,
+        } as u16;
         self.ppn = match xlen {
             Xlen::Bits32 => satp & 0x3fffff,
             Xlen::Bits64 => satp & 0xfffffffffff,
             Xlen::Bits128 => satp & 0xfffffffffff,
         };
-        println!("self.addressing_mode: {:?}", self.addressing_mode);
     }
 
     fn traverse_pagetable(
@@ -253,15 +349,29 @@ impl MMU {
         addr: VAddr,
         level: usize,
         parent_ppn: u64,
-        vpns: &[u64; 3],
         access_type: MemoryAccessType,
-    ) -> Option<PAddr> {
+        pmode: PrivMode,
+        global: bool,
+    ) -> Result<PAddr, TrapCause> {
         const PAGESIZE: u64 = 4096;
         let ptesize: u64 = match self.addressing_mode {
             AddressingMode::SV32 => 4,
             _ => 8,
         };
-        let pte_addr = parent_ppn * PAGESIZE + vpns[level] * ptesize;
+        let vpn_bits = self.addressing_mode.vpn_bits();
+        let vpn = (addr >> (12 + vpn_bits * level as u32)) & ((1 << vpn_bits) - 1);
+        let pte_addr = parent_ppn * PAGESIZE + vpn * ptesize;
+        // Page table accesses are checked by the PMP as S-mode reads
+        if !self.memory.includes(pte_addr)
+            || !self.pmp.check(
+                pte_addr,
+                ptesize,
+                MemoryAccessType::READ,
+                PrivMode::Supervisor,
+            )
+        {
+            return Err(access_type.access_fault(addr));
+        }
         let pte = match self.addressing_mode {
             AddressingMode::SV32 => self.memory.read32(pte_addr).unwrap() as PageTableEntry,
             _ => self.memory.read64(pte_addr).unwrap() as PageTableEntry,
@@ -276,26 +386,40 @@ impl MMU {
         let v = pte.has_permission_bit(PTEPermBit::VALID);
         let w = pte.has_permission_bit(PTEPermBit::WRITE);
         let r = pte.has_permission_bit(PTEPermBit::READ);
+        // A global non-leaf PTE makes every mapping below it global
+        let global = global || pte.has_permission_bit(PTEPermBit::GLOBAL);
         if v == false || (r == false && w == true) {
-            return None;
+            return Err(access_type.page_fault(addr));
         }
 
         if r == false && x == false {
             return match level {
-                0 => return None,
-                _ => self.traverse_pagetable(addr, level - 1, ppn, vpns, access_type),
+                0 => Err(access_type.page_fault(addr)),
+                _ => self.traverse_pagetable(addr, level - 1, ppn, access_type, pmode, global),
             };
         }
 
         // Leaf page!
 
+        if !permitted(pte, access_type, pmode, self.mstatus) {
+            return Err(access_type.page_fault(addr));
+        }
+
+        // A superpage must be aligned to its own size, so the PPN fields below
+        // the leaf level have to be zero
+        let superpage_mask = (1 << (vpn_bits * level as u32)) - 1;
+        if ppn & superpage_mask != 0 {
+            return Err(access_type.page_fault(addr));
+        }
+
+        let mut new_pte = pte;
         if a == false
             || (match access_type {
                 MemoryAccessType::WRITE => d == false,
                 _ => false,
             })
         {
-            let new_pte = pte
+            new_pte = pte
                 | (1 << PTEPermBit::ACCESSED as u8)
                 | (match access_type {
                     MemoryAccessType::WRITE => 1 << 7,
@@ -307,107 +431,147 @@ impl MMU {
             };
         }
 
-        match access_type {
-            MemoryAccessType::EXECUTE => {
-                if x == false {
-                    return None;
-                }
-            }
-            MemoryAccessType::READ => {
-                if r == false {
-                    return None;
-                }
-            }
-            MemoryAccessType::WRITE => {
-                if w == false {
-                    return None;
-                }
-            }
-            _ => {}
-        };
-
-        let ppns = match self.addressing_mode {
-            AddressingMode::SV32 => [(pte >> 10) & 0x3ff, (pte >> 20) & 0xfff, 0 /*dummy*/],
-            AddressingMode::SV39 => [
-                (pte >> 10) & 0x1ff,
-                (pte >> 19) & 0x1ff,
-                (pte >> 28) & 0x3ffffff,
-            ],
-            _ => unreachable!(),
-        };
+        // Superpages take the remaining VPN fields straight from the virtual address
+        let p_address = ((ppn | ((addr >> 12) & superpage_mask)) << 12) | addr.offset();
 
-        let p_address = match self.addressing_mode {
-            AddressingMode::SV32 => match level {
-                1 => {
-                    if ppns[0] != 0 {
-                        return None;
-                    }
-                    (ppns[1] << 22) | (vpns[0] << 12) | addr.offset()
-                }
-                0 => (ppn << 12) | addr.offset(),
-                _ => unreachable!(),
-            },
-            _ => match level {
-                2 => {
-                    if ppns[1] != 0 || ppns[0] != 0 {
-                        return None;
-                    }
-                    (ppns[2] << 30) | (vpns[1] << 21) | (vpns[0] << 12) | addr.offset()
-                }
-                1 => {
-                    if ppns[0] != 0 {
-                        return None;
-                    }
-                    (ppns[2] << 30) | (ppns[1] << 21) | (vpns[0] << 12) | addr.offset()
-                }
-                0 => (ppn << 12) | addr.offset(),
-                _ => unreachable!(),
-            },
-        };
+        self.tlb.insert(TLBEntry {
+            vpn: addr >> 12,
+            asid: self.asid,
+            global,
+            ppn: p_address >> 12,
+            pte: new_pte,
+            superpage_mask,
+        });
 
         //println!("PA:{:X}", p_address);
-        Some(p_address)
+        Ok(p_address)
     }
 
+    /// Translates `va` and checks the resulting physical address against the PMP
     pub fn translate_address(
         &mut self,
         va: &dyn SV39Addr,
         access_type: MemoryAccessType,
-    ) -> Option<PAddr> {
-        match self.pmode {
-            PrivMode::Machine => match access_type {
-                MemoryAccessType::EXECUTE => Some(va.address() as PAddr),
-                _ => match (self.mstatus >> 17) & 1 {
-                    0 => Some(va.address()),
-                    _ => match num::FromPrimitive::from_u64((self.mstatus >> 9) & 3).unwrap() {
-                        PrivMode::Machine => Some(va.address() as PAddr),
-                        _ => {
-                            panic!("");
-                        }
-                    },
-                },
+    ) -> Result<PAddr, TrapCause> {
+        self.translate_access(va, 1, access_type)
+    }
+
+    /// Translates `va` and checks the `len` bytes at the resulting physical address
+    /// against the PMP, which fails accesses straddling the edge of a region
+    fn translate_access(
+        &mut self,
+        va: &dyn SV39Addr,
+        len: u64,
+        access_type: MemoryAccessType,
+    ) -> Result<PAddr, TrapCause> {
+        // With mstatus.MPRV set, M-mode loads and stores are translated and checked
+        // as if running in the mode held in mstatus.MPP
+        let pmode = match access_type {
+            MemoryAccessType::EXECUTE => self.pmode,
+            _ => match self.pmode == PrivMode::Machine && (self.mstatus >> 17) & 1 == 1 {
+                true => num::FromPrimitive::from_u64((self.mstatus >> 11) & 3).unwrap(),
+                false => self.pmode,
             },
+        };
+
+        let paddr = self.translate(va.address(), access_type, pmode)?;
+        match self.pmp.check(paddr, len, access_type, pmode) {
+            true => Ok(paddr),
+            false => Err(access_type.access_fault(va.address())),
+        }
+    }
+
+    /// Checks that a store of `len` bytes to `addr` can go through, for stores that are
+    /// carried out in parts and must not fault after some of them have been written
+    pub fn check_store(&mut self, addr: VAddr, len: u64) -> Option<TrapCause> {
+        self.check_access(addr, len, MemoryAccessType::WRITE).err()
+    }
+
+    /// Checks that all `len` bytes at `addr` can be accessed. The parts on either side
+    /// of a page boundary are translated on their own.
+    fn check_access(
+        &mut self,
+        addr: VAddr,
+        len: u64,
+        access_type: MemoryAccessType,
+    ) -> Result<(), TrapCause> {
+        let first = len.min(0x1000 - (addr & 0xfff));
+        for (addr, len) in [(addr, first), (addr.wrapping_add(first), len - first)] {
+            if len == 0 {
+                continue;
+            }
+            let paddr = self.translate_access(&addr, len, access_type)?;
+            if !self.is_mapped(paddr) || !self.is_mapped(paddr + len - 1) {
+                return Err(access_type.access_fault(addr));
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether memory or a device is found at `paddr`
+    fn is_mapped(&self, paddr: PAddr) -> bool {
+        self.memory.includes(paddr)
+            || self.virtio.iter().any(|virtio| virtio.includes(paddr))
+            || self.uart.includes(paddr)
+            || self.clint.includes(paddr)
+            || self.aia.as_ref().map_or(false, |aia| aia.includes(paddr))
+            || self.plic.includes(paddr)
+    }
+
+    fn translate(
+        &mut self,
+        va: VAddr,
+        access_type: MemoryAccessType,
+        pmode: PrivMode,
+    ) -> Result<PAddr, TrapCause> {
+        let va = match pmode {
+            PrivMode::Machine => return Ok(va as PAddr),
             PrivMode::Supervisor | PrivMode::User => match self.addressing_mode {
-                AddressingMode::None => Some(va.address()),
-                AddressingMode::SV32 => todo!(),
-                AddressingMode::SV39 => {
-                    let pa = self.traverse_pagetable(
-                        va.address(),
-                        3 - 1,
-                        self.ppn,
-                        &va.get_vpns(),
-                        access_type,
-                    );
-                    // if pa.is_none() {
-                    //     panic!("Failed to translate {:#x?}", va.address());
-                    // }
-                    pa
+                AddressingMode::None => return Ok(va),
+                // Sv32 produces 34-bit physical addresses from 32-bit virtual ones
+                AddressingMode::SV32 => va & 0xffffffff,
+                AddressingMode::SV39 | AddressingMode::SV48 | AddressingMode::SV57 => {
+                    // Bits above the translated range must all equal the topmost translated bit
+                    let va_bits = 12 + 9 * self.addressing_mode.levels() as u32;
+                    let upper = (va as i64) >> (va_bits - 1);
+                    if upper != 0 && upper != -1 {
+                        return Err(access_type.page_fault(va));
+                    }
+                    va
                 }
             },
-            _ => panic!(),
+            PrivMode::Reserved => return Err(access_type.access_fault(va)),
+        };
+
+        // Stores to clean pages miss, so the walk can set the D bit
+        let mstatus = self.mstatus;
+        let cached = self.tlb.lookup(va >> 12, self.asid, |pte| {
+            permitted(pte, access_type, pmode, mstatus)
+                && (access_type != MemoryAccessType::WRITE
+                    || pte.has_permission_bit(PTEPermBit::DIRTY))
+        });
+        match cached {
+            Some(ppn) => Ok((ppn << 12) | va.offset()),
+            None => {
+                let levels = self.addressing_mode.levels();
+                self.traverse_pagetable(va, levels - 1, self.ppn, access_type, pmode, false)
+            }
         }
     }
 
+    /// Flushes cached translations, see `TLB::flush`
+    pub fn sfence_vma(&mut self, vaddr: Option<VAddr>, asid: Option<u16>) {
+        self.tlb.flush(vaddr.map(|vaddr| vaddr >> 12), asid);
+    }
+
+    pub fn tlb(&self) -> &TLB {
+        &self.tlb
+    }
+
+    pub fn update_pmp(&mut self, pmp: PMP) {
+        self.pmp = pmp;
+    }
+
     pub fn update_mstatus(&mut self, mstatus: RegisterValue) {
         self.mstatus = mstatus;
     }
@@ -419,43 +583,107 @@ impl MMU {
         }
     }
 
-    pub fn virtio_mut(&mut self) -> &mut VIRTIO {
-        return &mut self.virtio;
+    /// Attaches `device` to the first free virtio-mmio transport, in device tree order.
+    /// Returns the index of the transport, or None if they are all taken.
+    pub fn attach_virtio(&mut self, device: Box<dyn VirtioDevice>) -> Option<usize> {
+        let index = self
+            .virtio
+            .iter()
+            .position(|virtio| !virtio.is_attached())?;
+        self.virtio[index].attach(device);
+        Some(index)
+    }
+
+    pub fn virtio_mut(&mut self, index: usize) -> &mut VIRTIO {
+        &mut self.virtio[index]
+    }
+
+    /// The `mtime` register of the CLINT, which the `time` CSR reads
+    pub fn mtime(&self) -> u64 {
+        self.clint.mtime()
+    }
+
+    pub fn clint_mut(&mut self) -> &mut CLINT {
+        &mut self.clint
+    }
+
+    pub fn uart_mut(&mut self) -> &mut UART {
+        &mut self.uart
+    }
+
+    /// Replaces the PLIC with the AIA: an APLIC with an M-level and an S-level domain,
+    /// and an IMSIC for the hart. The device table then comes from the AIA device tree,
+    /// which describes them in place of the PLIC.
+    pub fn select_aia(&mut self) {
+        self.device_table = MMU::parse_dtb(include_bytes_aligned!(64, "../resources/dtb-aia.dtb"));
+        self.aia = Some(AIA::create(1));
+    }
+
+    pub fn has_aia(&self) -> bool {
+        self.aia.is_some()
+    }
+
+    /// Takes the MSIs sent to the interrupt files of `hart`, with the level of the file
+    /// and the interrupt identity
+    pub fn take_msis(&mut self, hart: usize) -> Vec<(PrivMode, u32)> {
+        match self.aia.as_mut() {
+            Some(aia) => aia.imsic.take(hart),
+            None => vec![],
+        }
     }
 
     /// Returns new `mip` register value
     pub fn tick(&mut self, mip: RegisterValue) -> RegisterValue {
         self.clint.tick();
-        self.virtio.tick();
+        for virtio in self.virtio.iter_mut() {
+            virtio.tick(&mut self.memory);
+        }
         self.uart.tick();
-        self.plic.tick(self.virtio.is_interrupting(), false, mip)
+        let mut irqs: Vec<(u32, bool)> = self
+            .virtio
+            .iter()
+            .filter_map(|virtio| Some((virtio.interrupt()?, virtio.is_interrupting())))
+            .collect();
+        if let Some(irq) = self.uart.interrupt() {
+            irqs.push((irq, self.uart.is_interrupting()));
+        }
+        let mip = match self.aia.as_mut() {
+            Some(aia) => {
+                aia.aplic.tick(&irqs);
+                // MSIs go to an IMSIC, or anywhere else in memory
+                for (address, data) in aia.aplic.take_msis() {
+                    if aia.imsic.includes(address) {
+                        aia.imsic.write32(address, data);
+                    } else if self.memory.includes(address) {
+                        self.memory.write32(address, data);
+                    }
+                }
+                aia.aplic.update_mip(0, mip)
+            }
+            None => {
+                self.plic.tick(&irqs);
+                self.plic.update_mip(0, mip)
+            }
+        };
+        self.clint.update_mip(0, mip)
     }
 
     /// Used for instruction fetch, accesses memory with perm EXECUTE
     pub fn fetch(&mut self, addr: VAddr) -> Result<u32, TrapCause> {
-        let addr = self.translate_address(&addr, MemoryAccessType::EXECUTE);
-
-        match addr {
-            Some(addr) => {
-                if self.memory.includes(addr) {
-                    match self.memory.read32(addr) {
-                        Ok(value) => Ok(value),
-                        Err(_cause) => Err(TrapCause::InstructionAccessFault(addr)),
-                    }
-                } else {
-                    panic!("Cannot fetch from {:#x?}", addr);
-                    Err(TrapCause::InstructionPageFault)
-                }
+        let paddr = self.translate_address(&addr, MemoryAccessType::EXECUTE)?;
+
+        if self.memory.includes(paddr) {
+            match self.memory.read32(paddr) {
+                Ok(value) => Ok(value),
+                Err(_cause) => Err(TrapCause::InstructionAccessFault(addr)),
             }
-            None => Err(TrapCause::InstructionPageFault),
+        } else {
+            Err(TrapCause::InstructionAccessFault(addr))
         }
     }
 
-    fn parse_dtb() -> Vec<MemoryRange> {
+    fn parse_dtb(content: &'static [u8]) -> Vec<MemoryRange> {
         let mut devs = Vec::<MemoryRange>::new();
-        let content: &'static [u8; 1590] = (include_bytes_aligned!(64, "../resources/dtb.dtb"))
-            .try_into()
-            .unwrap();
         let mut curr_range: Option<MemoryRange> = None;
         let mut size = 0 as u32;
         match dtb::Reader::read(content) {
@@ -473,9 +701,8 @@ impl MMU {
                     if rme.is_begin_node() && node_name.is_some() && name.unwrap().contains(&"@") {
                         match curr_range {
                             Some(range) => devs.push(MemoryRange {
-                                name: range.name,
-                                start: range.start,
                                 end: range.start.saturating_add(size as u64),
+                                ..range
                             }),
                             None => {}
                         }
@@ -488,6 +715,7 @@ impl MMU {
                                 name: node_name.unwrap(),
                                 start: addr,
                                 end: addr,
+                                interrupt: None,
                             })
                         } else {
                             curr_range = None
@@ -498,14 +726,17 @@ impl MMU {
                     {
                         let val = rme.value().expect("No reg value!?");
                         size = u32::from_be_bytes(val[12..16].try_into().unwrap());
+                    } else if name == Some("interrupts") && curr_range.is_some() {
+                        let val = rme.value().expect("No interrupts value!?");
+                        curr_range.as_mut().unwrap().interrupt =
+                            Some(u32::from_be_bytes(val[0..4].try_into().unwrap()));
                     }
                 });
 
                 match curr_range {
                     Some(range) => devs.push(MemoryRange {
-                        name: range.name,
-                        start: range.start,
                         end: range.start.saturating_add(size as u64),
+                        ..range
                     }),
                     None => {}
                 }
@@ -515,6 +746,10 @@ impl MMU {
         devs
     }
 
+    pub fn device_table(&self) -> &[MemoryRange] {
+        &self.device_table
+    }
+
     pub fn dump_device_table(&self) {
         for i in 0..self.device_table.len() {
             println!(
@@ -592,7 +827,7 @@ impl PTE for PageTableEntry {
     }
 
     fn has_permission_bit(&self, bit: PTEPermBit) -> bool {
-        ((self & 0x1f) & (1 << (bit as u8))) != 0
+        (self & (1 << (bit as u8))) != 0
     }
 
     fn set_permission_bit(&mut self, bit: PTEPermBit) {
@@ -0,0 +1,157 @@
+use std::{
+    collections::VecDeque,
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    os::unix::net::UnixDatagram,
+    path::{Path, PathBuf},
+};
+
+/// Largest Ethernet frame the backends pass on, a 1500 byte MTU plus the header
+pub const MAX_FRAME_SIZE: usize = 1514;
+
+/// The host side of a network device, moving whole Ethernet frames
+pub trait NetBackend {
+    /// Sends `frame` out of the guest
+    fn send(&mut self, frame: &[u8]) -> io::Result<()>;
+
+    /// Takes the next frame for the guest without blocking, if one has arrived
+    fn recv(&mut self) -> io::Result<Option<Vec<u8>>>;
+}
+
+const PCAP_MAGIC: u32 = 0xa1b2_c3d4;
+const PCAP_MAGIC_NANOSECONDS: u32 = 0xa1b2_3c4d;
+const PCAP_LINKTYPE_ETHERNET: u32 = 1;
+
+/// Frames sent by the guest are written to a pcap file, and the frames of another pcap
+/// file are received by the guest, in order, as soon as it has buffers for them.
+///
+/// Written records all have a zero timestamp, so the same guest run writes the same file.
+pub struct Pcap {
+    output: BufWriter<File>,
+    input: VecDeque<Vec<u8>>,
+}
+
+impl Pcap {
+    /// Creates the pcap file `output`, and queues the frames in the pcap file `input`
+    pub fn create(output: &Path, input: Option<&Path>) -> io::Result<Pcap> {
+        let input = match input {
+            Some(path) => Pcap::read_frames(&fs::read(path)?)?,
+            None => VecDeque::new(),
+        };
+        let mut output = BufWriter::new(File::create(output)?);
+        let mut header = vec![];
+        header.extend_from_slice(&PCAP_MAGIC.to_le_bytes());
+        header.extend_from_slice(&2u16.to_le_bytes());
+        header.extend_from_slice(&4u16.to_le_bytes());
+        header.extend_from_slice(&0i32.to_le_bytes()); // thiszone
+        header.extend_from_slice(&0u32.to_le_bytes()); // sigfigs
+        header.extend_from_slice(&(MAX_FRAME_SIZE as u32).to_le_bytes());
+        header.extend_from_slice(&PCAP_LINKTYPE_ETHERNET.to_le_bytes());
+        output.write_all(&header)?;
+        output.flush()?;
+        Ok(Pcap { output, input })
+    }
+
+    /// Parses the records of a pcap file of either byte order
+    fn read_frames(data: &[u8]) -> io::Result<VecDeque<Vec<u8>>> {
+        let invalid = |msg| io::Error::new(io::ErrorKind::InvalidData, msg);
+        if data.len() < 24 {
+            return Err(invalid("pcap file too short"));
+        }
+        let magic = u32::from_le_bytes(data[0..4].try_into().unwrap());
+        let read_u32 = match magic {
+            PCAP_MAGIC | PCAP_MAGIC_NANOSECONDS => u32::from_le_bytes,
+            _ if magic.swap_bytes() == PCAP_MAGIC
+                || magic.swap_bytes() == PCAP_MAGIC_NANOSECONDS =>
+            {
+                u32::from_be_bytes
+            }
+            _ => return Err(invalid("not a pcap file")),
+        };
+        if read_u32(data[20..24].try_into().unwrap()) != PCAP_LINKTYPE_ETHERNET {
+            return Err(invalid("pcap file does not hold Ethernet frames"));
+        }
+
+        let mut frames = VecDeque::new();
+        let mut offset = 24;
+        while offset < data.len() {
+            if offset + 16 > data.len() {
+                return Err(invalid("truncated pcap record header"));
+            }
+            let len = read_u32(data[offset + 8..offset + 12].try_into().unwrap()) as usize;
+            let start = offset + 16;
+            if start + len > data.len() {
+                return Err(invalid("truncated pcap record"));
+            }
+            frames.push_back(data[start..start + len].to_vec());
+            offset = start + len;
+        }
+        Ok(frames)
+    }
+}
+
+impl NetBackend for Pcap {
+    fn send(&mut self, frame: &[u8]) -> io::Result<()> {
+        let len = frame.len() as u32;
+        let mut record = vec![0; 8]; // ts_sec, ts_usec
+        record.extend_from_slice(&len.to_le_bytes());
+        record.extend_from_slice(&len.to_le_bytes());
+        record.extend_from_slice(frame);
+        self.output.write_all(&record)?;
+        self.output.flush()
+    }
+
+    fn recv(&mut self) -> io::Result<Option<Vec<u8>>> {
+        Ok(self.input.pop_front())
+    }
+}
+
+/// A Unix datagram socket exchanging one frame per datagram with a peer socket, such as
+/// the backend of another emulator. Frames sent while the peer is not up are dropped.
+pub struct UnixSocket {
+    socket: UnixDatagram,
+    peer: PathBuf,
+}
+
+impl UnixSocket {
+    /// Binds to `path`, which must not exist yet, and sends to the socket at `peer`
+    pub fn create(path: &Path, peer: &Path) -> io::Result<UnixSocket> {
+        let socket = UnixDatagram::bind(path)?;
+        socket.set_nonblocking(true)?;
+        Ok(UnixSocket {
+            socket,
+            peer: peer.to_path_buf(),
+        })
+    }
+}
+
+impl NetBackend for UnixSocket {
+    fn send(&mut self, frame: &[u8]) -> io::Result<()> {
+        match self.socket.send_to(frame, &self.peer) {
+            Ok(_) => Ok(()),
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::NotFound
+                        | io::ErrorKind::ConnectionRefused
+                        | io::ErrorKind::WouldBlock
+                ) =>
+            {
+                Ok(())
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    fn recv(&mut self) -> io::Result<Option<Vec<u8>>> {
+        let mut buf = vec![0; MAX_FRAME_SIZE];
+        match self.socket.recv(&mut buf) {
+            Ok(len) => {
+                buf.truncate(len);
+                Ok(Some(buf))
+            }
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+}
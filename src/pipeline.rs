@@ -2,7 +2,8 @@ use elfloader::VAddr;
 use quark::Signs;
 
 use crate::{
-    cpu::{CSRRegister, Core, MipMask, PrivMode, Register, RegisterValue, TrapCause, Xlen},
+    cpu::{CSRRegister, Core, MipMask, PrivMode, Register, RegisterValue, TrapCause},
+    fpu::FloatFormat,
     instructions::{
         decoder::{DecodedInstruction, InstructionDecoder},
         InstructionSelector,
@@ -21,16 +22,69 @@ macro_rules! pipeline_trace {
 #[allow(non_camel_case_types)]
 #[derive(Debug, Copy, Clone)]
 pub enum MemoryAccess {
-    AMOSWAP_W(VAddr, RegisterValue, Register),
-    AMOSWAP_D(VAddr, VAddr, Register),
+    AMO_W(AmoOperation, VAddr, RegisterValue, Register),
+    AMO_D(AmoOperation, VAddr, RegisterValue, Register),
+    LR_W(VAddr, Register),
+    LR_D(VAddr, Register),
+    SC_W(VAddr, RegisterValue, Register),
+    SC_D(VAddr, RegisterValue, Register),
     READ8(VAddr, Register, bool),
     READ16(VAddr, Register, bool),
     READ32(VAddr, Register, bool),
     READ64(VAddr, Register, bool),
+    FREAD32(VAddr, Register),
+    FREAD64(VAddr, Register),
     WRITE8(VAddr, u8),
     WRITE16(VAddr, u16),
     WRITE32(VAddr, u32),
     WRITE64(VAddr, u64),
+    SFENCE_VMA(Option<VAddr>, Option<u16>),
+}
+
+/// The read-modify-write operation performed by an AMO instruction
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum AmoOperation {
+    SWAP,
+    ADD,
+    XOR,
+    AND,
+    OR,
+    MIN,
+    MAX,
+    MINU,
+    MAXU,
+}
+
+impl AmoOperation {
+    /// Combines the `loaded` word with the `operand` from rs2, returning the word to store
+    pub fn apply_w(&self, loaded: u32, operand: u32) -> u32 {
+        match self {
+            AmoOperation::SWAP => operand,
+            AmoOperation::ADD => loaded.wrapping_add(operand),
+            AmoOperation::XOR => loaded ^ operand,
+            AmoOperation::AND => loaded & operand,
+            AmoOperation::OR => loaded | operand,
+            AmoOperation::MIN => (loaded as i32).min(operand as i32) as u32,
+            AmoOperation::MAX => (loaded as i32).max(operand as i32) as u32,
+            AmoOperation::MINU => loaded.min(operand),
+            AmoOperation::MAXU => loaded.max(operand),
+        }
+    }
+
+    /// Combines the `loaded` doubleword with the `operand` from rs2, returning the doubleword to store
+    pub fn apply_d(&self, loaded: u64, operand: u64) -> u64 {
+        match self {
+            AmoOperation::SWAP => operand,
+            AmoOperation::ADD => loaded.wrapping_add(operand),
+            AmoOperation::XOR => loaded ^ operand,
+            AmoOperation::AND => loaded & operand,
+            AmoOperation::OR => loaded | operand,
+            AmoOperation::MIN => (loaded as i64).min(operand as i64) as u64,
+            AmoOperation::MAX => (loaded as i64).max(operand as i64) as u64,
+            AmoOperation::MINU => loaded.min(operand),
+            AmoOperation::MAXU => loaded.max(operand),
+        }
+    }
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -127,6 +181,16 @@ macro_rules! exec {
     };
 }
 
+/// AMOs and SCs report faults of their read half as store/AMO faults
+fn as_store_fault(cause: TrapCause) -> TrapCause {
+    match cause {
+        TrapCause::LoadAccessFault(addr) => TrapCause::StoreAccessFault(addr),
+        TrapCause::LoadAddressMisaligned => TrapCause::StoreAddressMisaligned,
+        TrapCause::LoadPageFault(addr) => TrapCause::StorePageFault(addr),
+        _ => cause,
+    }
+}
+
 impl PipelineStages for Core {
     fn fetch(&mut self, mmu: &mut MMU) -> Stage {
         match mmu.fetch(self.pc()) {
@@ -141,10 +205,14 @@ impl PipelineStages for Core {
     }
 
     fn decode(&mut self, instruction: &RawInstruction) -> Stage {
+        self.instruction_bits = instruction.word;
         let decoded = self.instruction_decoder.decode_instruction(*instruction);
         pipeline_trace!(println!("d:    {:?}", decoded));
 
-        Stage::EXECUTE(decoded)
+        match decoded {
+            Ok(decoded) => Stage::EXECUTE(decoded),
+            Err(cause) => Stage::TRAP(cause),
+        }
     }
 
     fn execute(&mut self, decoded: &DecodedInstruction) -> Stage {
@@ -173,6 +241,9 @@ impl PipelineStages for Core {
             DecodedInstruction::R(typ) => {
                 exec!(self, typ.select(self.xlen), &typ);
             }
+            DecodedInstruction::R4(typ) => {
+                exec!(self, typ.select(self.xlen), &typ);
+            }
             DecodedInstruction::CSS(typ) => {
                 exec!(self, typ.select(self.xlen), &typ);
             }
@@ -212,8 +283,14 @@ impl PipelineStages for Core {
                 }))
             }
             MemoryAccess::READ16(offset, register, sign_extend) => {
-                let h = mmu.read8(offset + 1).unwrap() as u16;
-                let l = mmu.read8(offset).unwrap() as u16;
+                let l = match mmu.read8(offset) {
+                    Err(cause) => return Stage::TRAP(cause),
+                    Ok(val) => val as u16,
+                };
+                let h = match mmu.read8(offset + 1) {
+                    Err(cause) => return Stage::TRAP(cause),
+                    Ok(val) => val as u16,
+                };
                 let value = (h << 8 | l) as i16 as u64;
                 // pipeline_trace!(println!("m:    READ16 @ {:#x?}: {:#x?}", offset, value));
 
@@ -237,16 +314,10 @@ impl PipelineStages for Core {
             },
 
             MemoryAccess::READ64(offset, register, sign_extend) => {
-                let l = match mmu.read_32(offset) {
+                let comp = match mmu.read64(offset) {
                     Err(cause) => return Stage::TRAP(cause),
                     Ok(val) => val,
                 };
-                let h = match mmu.read_32(offset + 4) {
-                    Err(cause) => return Stage::TRAP(cause),
-                    Ok(val) => val,
-                };
-
-                let comp = ((h as u64) << 32) | l as u64;
                 let value = match sign_extend {
                     true => comp.sign_extend(64 - 32),
                     false => comp as u64,
@@ -260,35 +331,80 @@ impl PipelineStages for Core {
                     value,
                 }))
             }
+            MemoryAccess::FREAD32(offset, register) => match mmu.read_32(offset) {
+                Ok(value) => {
+                    self.write_fregister(register, FloatFormat::S.box_value(value as u64));
+                    Stage::WRITEBACK(None)
+                }
+                Err(cause) => Stage::TRAP(cause),
+            },
+            MemoryAccess::FREAD64(offset, register) => match mmu.read64(offset) {
+                Ok(value) => {
+                    self.write_fregister(register, value);
+                    Stage::WRITEBACK(None)
+                }
+                Err(cause) => Stage::TRAP(cause),
+            },
             MemoryAccess::WRITE8(offset, value) => {
                 pipeline_trace!(println!("m:    WRITE8 @ {:#x?}: {:#x}", offset, value));
-                mmu.write8(offset, value);
-                Stage::WRITEBACK(None)
+                self.invalidate_reservation(offset, 1);
+                match mmu.write8(offset, value) {
+                    Some(cause) => Stage::TRAP(cause),
+                    None => Stage::WRITEBACK(None),
+                }
             }
             MemoryAccess::WRITE16(offset, value) => {
                 pipeline_trace!(println!("m:    WRITE16 @ {:#x?}: {:#x}", offset, value));
-                mmu.write8(offset + 1, (value >> 8) as u8);
-                mmu.write8(offset, (value & 0xff) as u8);
-                Stage::WRITEBACK(None)
+                if let Some(cause) = mmu.check_store(offset, 2) {
+                    return Stage::TRAP(cause);
+                }
+                self.invalidate_reservation(offset, 2);
+                match mmu
+                    .write8(offset + 1, (value >> 8) as u8)
+                    .or_else(|| mmu.write8(offset, (value & 0xff) as u8))
+                {
+                    Some(cause) => Stage::TRAP(cause),
+                    None => Stage::WRITEBACK(None),
+                }
             }
             MemoryAccess::WRITE32(offset, value) => {
                 pipeline_trace!(println!("m:    WRITE32 @ {:#x?}: {:#x?}", offset, value));
-                mmu.write_32(offset, value);
-                Stage::WRITEBACK(None)
+                self.invalidate_reservation(offset, 4);
+                match mmu.write_32(offset, value) {
+                    Some(cause) => Stage::TRAP(cause),
+                    None => Stage::WRITEBACK(None),
+                }
             }
             MemoryAccess::WRITE64(offset, value) => {
                 pipeline_trace!(println!("m:    WRITE64 @ {:#x?}: {:#x?}", offset, value));
-                mmu.write_32(offset + 0, value as u32);
-                mmu.write_32(offset + 4, (value >> 32) as u32);
-                Stage::WRITEBACK(None)
+                if let Some(cause) = mmu.check_store(offset, 8) {
+                    return Stage::TRAP(cause);
+                }
+                self.invalidate_reservation(offset, 8);
+                match mmu
+                    .write_32(offset + 0, value as u32)
+                    .or_else(|| mmu.write_32(offset + 4, (value >> 32) as u32))
+                {
+                    Some(cause) => Stage::TRAP(cause),
+                    None => Stage::WRITEBACK(None),
+                }
             }
-            MemoryAccess::AMOSWAP_W(from, rs2v, rd) => {
-                let v1 = mmu.read_32(from).unwrap();
-                //let v2 = mmu.read_32(to).unwrap();
-                mmu.write_32(from, rs2v as u32);
+            MemoryAccess::AMO_W(op, addr, rs2v, rd) => {
+                if addr % 4 != 0 {
+                    return Stage::TRAP(TrapCause::StoreAddressMisaligned);
+                }
+                let loaded = match mmu.read_32(addr) {
+                    Ok(value) => value,
+                    Err(cause) => return Stage::TRAP(as_store_fault(cause)),
+                };
+                let value = op.apply_w(loaded, rs2v as u32);
+                self.invalidate_reservation(addr, 4);
+                if let Some(cause) = mmu.write32(addr, value) {
+                    return Stage::TRAP(cause);
+                }
                 pipeline_trace!(println!(
-                    "m:    AMOSWAP.W @ {:#x?} was {:#x?}, now {:#x?}",
-                    from, v1, rs2v
+                    "m:    AMO{:?}.W @ {:#x?} was {:#x?}, now {:#x?}",
+                    op, addr, loaded, value
                 ));
 
                 // "AMOs can be used to implement parallel reduction operations,
@@ -296,17 +412,99 @@ impl PipelineStages for Core {
                 if rd == 0 {
                     Stage::WRITEBACK(None)
                 } else {
-                    //  AMOs can either operate on 64-bit (RV64 only) or 32-bit words in memory.
-                    // For RV64, 32-bit AMOs always sign-extend the value placed in rd.
-                    let value = match self.xlen {
-                        Xlen::Bits32 => v1 as u64,
-                        Xlen::Bits64 => v1 as i32 as i64 as u64,
-                        Xlen::Bits128 => panic!("v1  as i64 as u128"),
-                    };
-                    Stage::writeback(rd, value as u64)
+                    Stage::writeback(rd, self.extend_word(loaded))
+                }
+            }
+            MemoryAccess::AMO_D(op, addr, rs2v, rd) => {
+                if addr % 8 != 0 {
+                    return Stage::TRAP(TrapCause::StoreAddressMisaligned);
+                }
+                if let Some(cause) = mmu.check_store(addr, 8) {
+                    return Stage::TRAP(cause);
+                }
+                let loaded = match mmu.read64(addr) {
+                    Ok(value) => value,
+                    Err(cause) => return Stage::TRAP(as_store_fault(cause)),
+                };
+                let value = op.apply_d(loaded, rs2v);
+                self.invalidate_reservation(addr, 8);
+                if let Some(cause) = mmu.write32(addr, value as u32) {
+                    return Stage::TRAP(cause);
+                }
+                if let Some(cause) = mmu.write32(addr + 4, (value >> 32) as u32) {
+                    return Stage::TRAP(cause);
+                }
+                pipeline_trace!(println!(
+                    "m:    AMO{:?}.D @ {:#x?} was {:#x?}, now {:#x?}",
+                    op, addr, loaded, value
+                ));
+
+                if rd == 0 {
+                    Stage::WRITEBACK(None)
+                } else {
+                    Stage::writeback(rd, loaded)
                 }
             }
-            MemoryAccess::AMOSWAP_D(_from, _to, _rd) => todo!(),
+            MemoryAccess::LR_W(addr, rd) => {
+                if addr % 4 != 0 {
+                    return Stage::TRAP(TrapCause::LoadAddressMisaligned);
+                }
+                match mmu.read_32(addr) {
+                    Ok(value) => {
+                        self.set_reservation(addr);
+                        Stage::writeback(rd, self.extend_word(value))
+                    }
+                    Err(cause) => Stage::TRAP(cause),
+                }
+            }
+            MemoryAccess::LR_D(addr, rd) => {
+                if addr % 8 != 0 {
+                    return Stage::TRAP(TrapCause::LoadAddressMisaligned);
+                }
+                match mmu.read64(addr) {
+                    Ok(value) => {
+                        self.set_reservation(addr);
+                        Stage::writeback(rd, value)
+                    }
+                    Err(cause) => Stage::TRAP(cause),
+                }
+            }
+            MemoryAccess::SC_W(addr, rs2v, rd) => {
+                if addr % 4 != 0 {
+                    return Stage::TRAP(TrapCause::StoreAddressMisaligned);
+                }
+                // "Regardless of success or failure, executing an SC instruction invalidates
+                //  any reservation held by this hart."
+                if !self.take_reservation(addr) {
+                    return Stage::writeback(rd, 1);
+                }
+                match mmu.write32(addr, rs2v as u32) {
+                    None => Stage::writeback(rd, 0),
+                    Some(cause) => Stage::TRAP(cause),
+                }
+            }
+            MemoryAccess::SC_D(addr, rs2v, rd) => {
+                if addr % 8 != 0 {
+                    return Stage::TRAP(TrapCause::StoreAddressMisaligned);
+                }
+                if !self.take_reservation(addr) {
+                    return Stage::writeback(rd, 1);
+                }
+                if let Some(cause) = mmu.check_store(addr, 8) {
+                    return Stage::TRAP(cause);
+                }
+                if let Some(cause) = mmu.write32(addr, rs2v as u32) {
+                    return Stage::TRAP(cause);
+                }
+                match mmu.write32(addr + 4, (rs2v >> 32) as u32) {
+                    None => Stage::writeback(rd, 0),
+                    Some(cause) => Stage::TRAP(cause),
+                }
+            }
+            MemoryAccess::SFENCE_VMA(vaddr, asid) => {
+                mmu.sfence_vma(vaddr, asid);
+                Stage::WRITEBACK(None)
+            }
         }
     }
 
@@ -412,7 +610,7 @@ impl PipelineStages for Core {
             println!("IRQ NOT masked out!");
 
             let msie = (ie >> 3) & 1;
-            let ssie = current_sie;
+            let ssie = (ie >> 1) & 1;
             let usie = ie & 1;
 
             let mtie = (ie >> 7) & 1;
@@ -436,19 +634,20 @@ impl PipelineStages for Core {
                 _ => {} // Not masked!
             }
         } else {
-            // Not sure how to implement this, but epc should be instruction address for EBREAK/ECALL
+            // Exceptions point epc at the instruction that raised them. Fetch faults are raised
+            // before the pc is advanced; everything else happens after it.
             epc_value = match cause {
-                TrapCause::Breakpoint
-                | TrapCause::EnvCallFromUMode
-                | TrapCause::EnvCallFromSMode
-                | TrapCause::EnvCallFromMMode => epc_value.wrapping_sub(4),
-                _ => epc_value,
+                TrapCause::InstructionAccessFault(_) | TrapCause::InstructionPageFault(_) => {
+                    epc_value
+                }
+                _ => self.prev_pc,
             };
         }
         println!("Trap not masked out!. epc: {:#x?}", epc_value);
 
         // Masking passed, execute trap
         self.set_pmode(new_privilege_mode);
+        self.clear_reservation();
 
         let epc_address = match new_privilege_mode {
             PrivMode::Machine => CSRRegister::mepc,
@@ -481,15 +680,13 @@ impl PipelineStages for Core {
         self.write_csr(epc_address, epc_value);
         println!("IRQ: writing cause to {:?}: {:#x?}", cause, csr_cause);
         self.write_csr(cause_reg, csr_cause);
-        self.write_csr(tval_reg, self.pc()); // @TODO: Not always correct (could be -4 for IllegalInstruction etc?)
+        self.write_csr(tval_reg, cause.tval());
 
         let tvec_val = self.read_csr(tvec_reg);
-        //print!("tvec_val from {:?}: {:#x?}", tvec_reg, tvec_val);
-        self.set_pc(tvec_val);
-        //println!("trap: tvec_reg={:?}", tvec_reg);
-        // Add 4 * cause if tvec has vector type address
-        if (tvec_val & 0x3) != 0 {
-            self.set_pc((tvec_val & !0x3) + 4 * (csr_cause & 0xffff));
+        // Only interrupts are vectored, exceptions always go to BASE
+        match (tvec_val & 0x3, is_interrupt) {
+            (1, true) => self.set_pc((tvec_val & !0x3) + 4 * (csr_cause & 0xffff)),
+            _ => self.set_pc(tvec_val & !0x3),
         }
 
         match self.pmode() {
@@ -7,119 +7,214 @@ use crate::{
     mmu::MemoryRange,
 };
 
+/// Number of interrupt sources, including the reserved source 0
+const NIPS: usize = 1024;
+/// 32 bit words of the pending and enable bit arrays
+const NWORDS: usize = NIPS / 32;
+
+/// Implemented bits of the priority registers, priorities go from 0 (never) to 7
+const PRIORITY_MASK: u32 = 7;
+
+/// Register map, as offsets from the base of the PLIC
+const PRIORITY_BASE: u64 = 0x00_0000;
+const PENDING_BASE: u64 = 0x00_1000;
+const ENABLE_BASE: u64 = 0x00_2000;
+const ENABLE_STRIDE: u64 = 0x80;
+const CONTEXT_BASE: u64 = 0x20_0000;
+const CONTEXT_STRIDE: u64 = 0x1000;
+const THRESHOLD: u64 = 0x0;
+const CLAIM_COMPLETE: u64 = 0x4;
+
+/// An interrupt target, context `2 * hart` is M-mode of the hart and `2 * hart + 1`
+/// its S-mode
+struct Context {
+    enabled: [u32; NWORDS],
+    threshold: u32,
+    /// The context's interrupt line, MEIP or SEIP of its hart
+    asserted: bool,
+}
+
+/// A SiFive style platform-level interrupt controller
 pub struct PLIC {
     range: MemoryRange,
     clock: u64,
-    irq: u32,
-    enabled: u64,
-    threshold: u32,
-    ips: [u8; 1024],
-    prios: [u32; 1024],
+    priorities: [u32; NIPS],
+    pending: [u32; NWORDS],
+    /// Sources claimed by a context and not completed yet, their gateways hold back
+    /// further requests
+    claimed: [u32; NWORDS],
+    contexts: Vec<Context>,
+    /// Interrupt lines as last seen by `update_mip`, per hart
+    mip: Vec<RegisterValue>,
     needs_update_irq: bool,
-    virtio_ip_cache: bool,
 }
 
-const NIPS: usize = 1024;
-
-const VIRTIO_IRQ_NUM: u32 = 4; // @TODO: From device tree!
-const UART_IRQ_NUM: u32 = 40;
-
 impl PLIC {
-    pub fn create(range: MemoryRange) -> PLIC {
+    /// Creates a PLIC with an M-mode and an S-mode context for each of `harts`
+    pub fn create(range: MemoryRange, harts: usize) -> PLIC {
         PLIC {
             range,
             clock: 0,
-            irq: 0,
-            enabled: 0,
-            threshold: 0,
-            ips: [0; NIPS],
-            prios: [0; NIPS],
+            priorities: [0; NIPS],
+            pending: [0; NWORDS],
+            claimed: [0; NWORDS],
+            contexts: (0..2 * harts)
+                .map(|_| Context {
+                    enabled: [0; NWORDS],
+                    threshold: 0,
+                    asserted: false,
+                })
+                .collect(),
+            mip: vec![0; harts],
             needs_update_irq: false,
-            virtio_ip_cache: false,
         }
     }
 
-    // Returns new `mip` register value
-    pub fn tick(&mut self, virtio_irq: bool, uart_irq: bool, mip: RegisterValue) -> RegisterValue {
+    /// Samples the `(source, level)` interrupt lines of the devices
+    pub fn tick(&mut self, irqs: &[(u32, bool)]) {
         self.clock = self.clock.wrapping_add(1);
 
-        // Detect rising edge of virtio irq
-        if self.virtio_ip_cache != virtio_irq {
-            if virtio_irq {
-                self.set_ip(VIRTIO_IRQ_NUM);
+        // Level-triggered gateways: a source held high becomes pending again once
+        // its previous interrupt is completed
+        for (irq, level) in irqs.iter().copied() {
+            if level && !is_set(&self.pending, irq) && !is_set(&self.claimed, irq) {
+                set(&mut self.pending, irq, true);
+                self.needs_update_irq = true;
             }
-            self.virtio_ip_cache = virtio_irq;
-        }
-
-        // UART irq is only true for one tick
-        if uart_irq {
-            self.set_ip(UART_IRQ_NUM);
         }
 
         if self.needs_update_irq {
             self.needs_update_irq = false;
-            self.update_irq(mip)
-        } else {
-            mip
+            for context in 0..self.contexts.len() {
+                let asserted = self.best_irq(context) != 0;
+                self.contexts[context].asserted = asserted;
+            }
         }
     }
 
-    fn update_irq(&mut self, mip: u64) -> u64 {
-        // Hardcoded VirtIO and UART
-        // @TODO: Should be configurable with device tree
-
-        let virtio_ip =
-            ((self.ips[(VIRTIO_IRQ_NUM >> 3) as usize] >> (VIRTIO_IRQ_NUM & 7)) & 1) == 1;
-        let uart_ip = ((self.ips[(UART_IRQ_NUM >> 3) as usize] >> (UART_IRQ_NUM & 7)) & 1) == 1;
-
-        // Which should be prioritized, virtio or uart?
-
-        let virtio_priority = self.prios[VIRTIO_IRQ_NUM as usize];
-        let uart_priority = self.prios[UART_IRQ_NUM as usize];
-
-        let virtio_enabled = ((self.enabled >> (VIRTIO_IRQ_NUM >> 2)) & 1) == 1;
-        let uart_enabled = ((self.enabled >> (UART_IRQ_NUM >> 2)) & 1) == 1;
-
-        let ips = [virtio_ip, uart_ip];
-        let enables = [virtio_enabled, uart_enabled];
-        let priorities = [virtio_priority, uart_priority];
-        let irqs = [VIRTIO_IRQ_NUM >> 2, UART_IRQ_NUM >> 2];
-
-        // println!(
-        //     "ips: {:?} enables: {:?} priorities: {:?}  threshold: {:#?}",
-        //     ips, enables, priorities, self.threshold
-        // );
+    /// Returns the `mip` register value of `hart` with MEIP and SEIP updated from its
+    /// contexts. The bits are only touched when the interrupt lines change, so software
+    /// can still set SEIP.
+    pub fn update_mip(&mut self, hart: usize, mip: RegisterValue) -> RegisterValue {
+        let mut lines = 0;
+        for (context, mask) in [(2 * hart, MipMask::MEIP), (2 * hart + 1, MipMask::SEIP)] {
+            if self.contexts[context].asserted {
+                lines |= mask as RegisterValue;
+            }
+        }
+        let changed = lines ^ self.mip[hart];
+        self.mip[hart] = lines;
+        (mip & !changed) | (lines & changed)
+    }
 
+    /// The pending and enabled source with the highest priority above the threshold
+    /// of `context`, ties go to the lowest source number. 0 if there is none.
+    fn best_irq(&self, context: usize) -> u32 {
+        let context = &self.contexts[context];
         let mut irq = 0;
-        let mut priority = 0;
-        for i in 0..2 {
-            if ips[i] && enables[i] && priorities[i] > self.threshold && priorities[i] > priority {
-                irq = irqs[i];
-                priority = priorities[i];
+        let mut priority = context.threshold;
+        for word in 0..NWORDS {
+            let mut candidates = self.pending[word] & context.enabled[word];
+            while candidates != 0 {
+                let source = (word * 32) as u32 + candidates.trailing_zeros();
+                candidates &= candidates - 1;
+                if self.priorities[source as usize] > priority {
+                    irq = source;
+                    priority = self.priorities[source as usize];
+                }
             }
         }
+        irq
+    }
 
-        self.irq = irq;
-        if self.irq != 0 {
-            println!("PLIC: IRQ: {:X}", self.irq);
+    fn claim(&mut self, context: usize) -> u32 {
+        let irq = self.best_irq(context);
+        if irq != 0 {
+            set(&mut self.pending, irq, false);
+            set(&mut self.claimed, irq, true);
+            self.needs_update_irq = true;
         }
+        irq
+    }
 
-        mip | (if irq != 0 { MipMask::SEIP as u64 } else { 0 })
+    fn complete(&mut self, context: usize, irq: u32) {
+        // Completions of sources the context does not have enabled are ignored
+        if (irq as usize) < NIPS && is_set(&self.contexts[context].enabled, irq) {
+            set(&mut self.claimed, irq, false);
+            self.needs_update_irq = true;
+        }
     }
 
-    fn set_ip(&mut self, irq: u32) {
-        let index = (irq >> 3) as usize;
-        self.ips[index] = self.ips[index] | (1 << irq);
-        self.needs_update_irq = true;
+    fn read_register(&mut self, offset: u64) -> u32 {
+        match offset {
+            PRIORITY_BASE..=0x0fff => self.priorities[(offset >> 2) as usize],
+            PENDING_BASE..=0x107f => self.pending[((offset - PENDING_BASE) >> 2) as usize],
+            ENABLE_BASE..=0x1f_ffff => {
+                let context = ((offset - ENABLE_BASE) / ENABLE_STRIDE) as usize;
+                let word = (((offset - ENABLE_BASE) % ENABLE_STRIDE) >> 2) as usize;
+                match self.contexts.get(context) {
+                    Some(context) if word < NWORDS => context.enabled[word],
+                    _ => 0,
+                }
+            }
+            CONTEXT_BASE.. => {
+                let context = ((offset - CONTEXT_BASE) / CONTEXT_STRIDE) as usize;
+                if context >= self.contexts.len() {
+                    return 0;
+                }
+                match (offset - CONTEXT_BASE) % CONTEXT_STRIDE {
+                    THRESHOLD => self.contexts[context].threshold,
+                    CLAIM_COMPLETE => self.claim(context),
+                    _ => 0,
+                }
+            }
+            _ => 0,
+        }
     }
 
-    fn clear_ip(&mut self, irq: u32) {
-        let index = (irq >> 3) as usize;
-        self.ips[index] = self.ips[index] & !(1 << irq);
+    fn write_register(&mut self, offset: u64, value: u32) {
+        match offset {
+            // Source 0 does not exist
+            0x0004..=0x0fff => self.priorities[(offset >> 2) as usize] = value & PRIORITY_MASK,
+            ENABLE_BASE..=0x1f_ffff => {
+                let context = ((offset - ENABLE_BASE) / ENABLE_STRIDE) as usize;
+                let word = (((offset - ENABLE_BASE) % ENABLE_STRIDE) >> 2) as usize;
+                match self.contexts.get_mut(context) {
+                    Some(context) if word == 0 => context.enabled[word] = value & !1,
+                    Some(context) if word < NWORDS => context.enabled[word] = value,
+                    _ => {}
+                }
+            }
+            CONTEXT_BASE.. => {
+                let context = ((offset - CONTEXT_BASE) / CONTEXT_STRIDE) as usize;
+                if context >= self.contexts.len() {
+                    return;
+                }
+                match (offset - CONTEXT_BASE) % CONTEXT_STRIDE {
+                    THRESHOLD => self.contexts[context].threshold = value & PRIORITY_MASK,
+                    CLAIM_COMPLETE => self.complete(context, value),
+                    _ => {}
+                }
+            }
+            // The pending bits are read-only
+            _ => {}
+        }
         self.needs_update_irq = true;
     }
 }
 
+fn is_set(bits: &[u32; NWORDS], irq: u32) -> bool {
+    ((bits[(irq >> 5) as usize] >> (irq & 31)) & 1) == 1
+}
+
+fn set(bits: &mut [u32; NWORDS], irq: u32, value: bool) {
+    let word = &mut bits[(irq >> 5) as usize];
+    match value {
+        true => *word |= 1 << (irq & 31),
+        false => *word &= !(1 << (irq & 31)),
+    }
+}
+
 impl VirtualDevice for PLIC {
     fn includes(&self, addr: VAddr) -> bool {
         self.range.includes(addr)
@@ -129,66 +224,31 @@ impl VirtualDevice for PLIC {
         self.range.name
     }
 
-    fn write(&mut self, addr: VAddr, value: u8) -> Option<TrapCause> {
-        todo!();
+    fn write(&mut self, addr: VAddr, _value: u8) -> Option<TrapCause> {
+        Some(TrapCause::StoreAccessFault(addr))
     }
 
     fn read(&mut self, addr: VAddr) -> Result<u8, TrapCause> {
-        todo!();
+        Err(TrapCause::LoadAccessFault(addr))
     }
 }
 
+/// Only naturally aligned 32 bit accesses are supported
 impl MemoryOperations<PLIC, u8> for PLIC {
-    fn read8(&mut self, address: VAddr) -> Result<u8, TrapCause> {
-        panic!();
-        match address {
-            0x0c000000..=0x0c000fff => {
-                let offset = address % 4;
-                let index = ((address - 0xc000000) >> 2) as usize;
-                let pos = offset << 3;
-                Ok((self.prios[index] >> pos) as u8)
-            }
-            _ => panic!(),
-        }
-    }
-
     fn read32(&mut self, address: VAddr) -> Result<u32, TrapCause> {
-        match address {
-            0x0c201004 => Ok(self.irq),
-            _ => todo!("PLIC.read32 {:#x?}", address),
+        match address & 3 {
+            0 => Ok(self.read_register(address - self.range.start)),
+            _ => Err(TrapCause::LoadAccessFault(address)),
         }
     }
 
     fn write32(&mut self, address: VAddr, value: u32) -> Option<TrapCause> {
-        match address {
-            0x0c000000..=0x0c000fff => {
-                let index = (address - 0xc000000) as usize;
-                self.prios[index] = value;
-                self.needs_update_irq = true;
-                println!("plint: prio for {:#?}: {:#?}", index, value);
-                None
-            }
-            0x0c002080 => {
-                self.enabled = (self.enabled & !0xffffffff) | (value as u64);
-                self.needs_update_irq = true;
+        match address & 3 {
+            0 => {
+                self.write_register(address - self.range.start, value);
                 None
             }
-            0x0c002084 => {
-                self.enabled = (self.enabled & !(0xffffffff << 32)) | ((value as u64) << 32);
-                None
-            }
-
-            0x0c201000 => {
-                self.threshold = value;
-                self.needs_update_irq = true;
-                None
-            }
-            0x0c201004 => {
-                self.clear_ip(value);
-                None
-            }
-
-            _ => todo!("{:#x?}", address),
+            _ => Some(TrapCause::StoreAccessFault(address)),
         }
     }
 }
@@ -0,0 +1,158 @@
+use elfloader::PAddr;
+
+use crate::{
+    cpu::{PrivMode, RegisterValue, Xlen},
+    mmu::MemoryAccessType,
+};
+
+/// Number of implemented PMP entries, backed by pmpcfg0-pmpcfg15 and pmpaddr0-pmpaddr63
+pub const PMP_ENTRIES: usize = 64;
+
+// Bits of a single entry in the `pmpcfg` CSR registers
+#[non_exhaustive]
+pub struct PmpCfg {}
+impl PmpCfg {
+    pub const R: u8 = 1 << 0;
+    pub const W: u8 = 1 << 1;
+    pub const X: u8 = 1 << 2;
+    pub const A: u8 = 0b11 << 3;
+    pub const L: u8 = 1 << 7;
+
+    pub const OFF: u8 = 0 << 3;
+    pub const TOR: u8 = 1 << 3;
+    pub const NA4: u8 = 2 << 3;
+    pub const NAPOT: u8 = 3 << 3;
+}
+
+/// Physical Memory Protection state, as configured through the pmpcfg/pmpaddr CSRs
+#[derive(Copy, Clone)]
+pub struct PMP {
+    cfg: [u8; PMP_ENTRIES],
+    addr: [u64; PMP_ENTRIES],
+}
+
+impl PMP {
+    pub fn create() -> PMP {
+        PMP {
+            cfg: [0; PMP_ENTRIES],
+            addr: [0; PMP_ENTRIES],
+        }
+    }
+
+    /// Entries packed into `pmpcfg<reg>`: four per register on RV32, eight on RV64
+    /// where only the even numbered registers exist
+    fn cfg_entries(reg: usize, xlen: Xlen) -> Option<std::ops::Range<usize>> {
+        match xlen {
+            Xlen::Bits32 => Some(reg * 4..reg * 4 + 4),
+            _ => match reg % 2 {
+                0 => Some(reg * 4..reg * 4 + 8),
+                _ => None,
+            },
+        }
+    }
+
+    pub fn read_cfg(&self, reg: usize, xlen: Xlen) -> RegisterValue {
+        match PMP::cfg_entries(reg, xlen) {
+            Some(entries) => entries
+                .rev()
+                .fold(0, |value, i| (value << 8) | self.cfg[i] as RegisterValue),
+            None => 0,
+        }
+    }
+
+    pub fn write_cfg(&mut self, reg: usize, value: RegisterValue, xlen: Xlen) {
+        let entries = match PMP::cfg_entries(reg, xlen) {
+            Some(entries) => entries,
+            None => return,
+        };
+        for (n, i) in entries.enumerate() {
+            // Locked entries ignore writes until reset
+            if self.cfg[i] & PmpCfg::L != 0 {
+                continue;
+            }
+            let mut cfg = (value >> (8 * n)) as u8 & !0x60;
+            // R=0 W=1 is reserved
+            if cfg & (PmpCfg::R | PmpCfg::W) == PmpCfg::W {
+                cfg &= !PmpCfg::W;
+            }
+            self.cfg[i] = cfg;
+        }
+    }
+
+    pub fn read_addr(&self, i: usize) -> RegisterValue {
+        self.addr[i]
+    }
+
+    pub fn write_addr(&mut self, i: usize, value: RegisterValue, xlen: Xlen) {
+        // The top of a TOR region is locked along with the entry it belongs to
+        let locked = |i: usize| self.cfg[i] & PmpCfg::L != 0;
+        let top_locked =
+            i + 1 < PMP_ENTRIES && locked(i + 1) && self.cfg[i + 1] & PmpCfg::A == PmpCfg::TOR;
+        if locked(i) || top_locked {
+            return;
+        }
+        self.addr[i] = match xlen {
+            Xlen::Bits32 => value & 0xffff_ffff,
+            // Bits 55:2 of a 56-bit physical address
+            _ => value & 0x003f_ffff_ffff_ffff,
+        };
+    }
+
+    /// The byte range `[start, end)` covered by entry `i`, if it is enabled
+    fn range(&self, i: usize) -> Option<(u128, u128)> {
+        let addr = self.addr[i] as u128;
+        match self.cfg[i] & PmpCfg::A {
+            PmpCfg::TOR => {
+                let start = match i {
+                    0 => 0,
+                    _ => (self.addr[i - 1] as u128) << 2,
+                };
+                Some((start, addr << 2))
+            }
+            PmpCfg::NA4 => Some((addr << 2, (addr << 2) + 4)),
+            PmpCfg::NAPOT => {
+                // Each trailing one doubles the region, starting at 8 bytes
+                let ones = self.addr[i].trailing_ones();
+                let start = (addr & !((1 << ones) - 1)) << 2;
+                Some((start, start + (1 << (ones + 3))))
+            }
+            _ => None,
+        }
+    }
+
+    /// Checks an access to the `len` bytes at `addr` made in `pmode`. The lowest numbered
+    /// entry matching any of the bytes decides, and fails the access unless it matches
+    /// all of them; M-mode is only bound by locked entries, and without a match only
+    /// M-mode accesses succeed.
+    pub fn check(
+        &self,
+        addr: PAddr,
+        len: u64,
+        access_type: MemoryAccessType,
+        pmode: PrivMode,
+    ) -> bool {
+        let (first, end) = (addr as u128, addr as u128 + len as u128);
+        for i in 0..PMP_ENTRIES {
+            match self.range(i) {
+                Some((start, stop)) if start < end && first < stop => {
+                    if first < start || stop < end {
+                        return false;
+                    }
+                    let cfg = self.cfg[i];
+                    if pmode == PrivMode::Machine && cfg & PmpCfg::L == 0 {
+                        return true;
+                    }
+                    return cfg
+                        & match access_type {
+                            MemoryAccessType::READ => PmpCfg::R,
+                            MemoryAccessType::WRITE => PmpCfg::W,
+                            MemoryAccessType::EXECUTE => PmpCfg::X,
+                        }
+                        != 0;
+                }
+                _ => {}
+            }
+        }
+        pmode == PrivMode::Machine
+    }
+}
@@ -0,0 +1,87 @@
+const ENTRIES: usize = 256;
+
+/// A cached leaf translation. Superpages are cached one 4 KiB page at a time, and
+/// remember their size so SFENCE.VMA on any address inside them flushes them all.
+#[derive(Debug, Copy, Clone)]
+pub struct TLBEntry {
+    pub vpn: u64,
+    pub asid: u16,
+    pub global: bool,
+    pub ppn: u64,
+    /// The leaf PTE, after its A/D bits were updated by the walk
+    pub pte: u64,
+    /// VPN bits covered by the leaf; non-zero for superpages
+    pub superpage_mask: u64,
+}
+
+impl TLBEntry {
+    fn matches(&self, vpn: u64, asid: u16) -> bool {
+        self.vpn == vpn && (self.global || self.asid == asid)
+    }
+
+    fn covers(&self, vpn: u64) -> bool {
+        (self.vpn & !self.superpage_mask) == (vpn & !self.superpage_mask)
+    }
+}
+
+/// Direct-mapped translation lookaside buffer, indexed by the low VPN bits
+pub struct TLB {
+    entries: [Option<TLBEntry>; ENTRIES],
+    hits: u64,
+    misses: u64,
+}
+
+impl TLB {
+    pub fn create() -> TLB {
+        TLB {
+            entries: [None; ENTRIES],
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    /// Returns the cached physical page number for `vpn` if there is an entry
+    /// for it that `usable` accepts, given the entry's PTE
+    pub fn lookup(&mut self, vpn: u64, asid: u16, usable: impl Fn(u64) -> bool) -> Option<u64> {
+        match self.entries[vpn as usize % ENTRIES] {
+            Some(entry) if entry.matches(vpn, asid) && usable(entry.pte) => {
+                self.hits += 1;
+                Some(entry.ppn)
+            }
+            _ => {
+                self.misses += 1;
+                None
+            }
+        }
+    }
+
+    pub fn insert(&mut self, entry: TLBEntry) {
+        self.entries[entry.vpn as usize % ENTRIES] = Some(entry);
+    }
+
+    /// SFENCE.VMA semantics: with neither argument everything is flushed. An address
+    /// flushes the leaf covering it, and an ASID limits the flush to non-global
+    /// entries of that address space.
+    pub fn flush(&mut self, vpn: Option<u64>, asid: Option<u16>) {
+        for slot in self.entries.iter_mut() {
+            let flush = match slot {
+                None => false,
+                Some(entry) => {
+                    vpn.map_or(true, |vpn| entry.covers(vpn))
+                        && asid.map_or(true, |asid| !entry.global && entry.asid == asid)
+                }
+            };
+            if flush {
+                *slot = None;
+            }
+        }
+    }
+
+    pub fn hits(&self) -> u64 {
+        self.hits
+    }
+
+    pub fn misses(&self) -> u64 {
+        self.misses
+    }
+}
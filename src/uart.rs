@@ -1,41 +1,102 @@
-use std::cell::RefCell;
+use std::{cell::RefCell, collections::VecDeque};
 
 use elfloader::VAddr;
 
-use crate::{cpu::TrapCause, mmio::VirtualDevice, mmu::MemoryRange};
+use crate::{chardev::CharDevice, cpu::TrapCause, mmio::VirtualDevice, mmu::MemoryRange};
 
-#[derive(Copy, Clone)]
+/// Interrupt IDs of the IIR register, from the highest priority to the lowest
+#[derive(Copy, Clone, PartialEq)]
 #[repr(u8)]
 enum UartIirMask {
-    Zero = 0,
-    ThrEmpty = 0x2,
+    LineStatus = 0x6,
     RdAvail = 0x4,
-    NoIrq = 0x7,
+    CharTimeout = 0xc,
+    ThrEmpty = 0x2,
+    ModemStatus = 0x0,
+    NoIrq = 0x1,
 }
 
+/// A National Semiconductor 16550A UART
 //#[derive(Debug)]
 pub struct UART {
     range: MemoryRange,
     state: RefCell<UartState>,
+    /// Host side of the serial line, transmitted bytes are dropped without one
+    backend: Option<Box<dyn CharDevice>>,
 }
 //#[derive(Debug)]
 pub struct UartState {
     clock: u64,
-    rbr: u8,          // receiver buffer register
-    thr: u8,          // transmitter holding register
-    ier: u8,          // interrupt enable register
-    iir: UartIirMask, // interrupt identification register
-    lcr: u8,          // line control register
-    mcr: u8,          // modem control register
-    lsr: u8,          // line status register
-    scr: u8,          // scratch,
+    rx_fifo: VecDeque<u8>, // receiver buffer register, and the FIFO behind it
+    tx_fifo: VecDeque<u8>, // transmitter holding register, and the FIFO behind it
+    dll: u8,               // divisor latch LSB
+    dlm: u8,               // divisor latch MSB
+    ier: u8,               // interrupt enable register
+    fcr: u8,               // FIFO control register, without the self-clearing bits
+    lcr: u8,               // line control register
+    mcr: u8,               // modem control register
+    lsr: u8,               // line status register, only the error bits
+    msr: u8,               // modem status register
+    scr: u8,               // scratch,
     thre_ip: bool,
-    interrupting: bool,
+    /// Clocks since a character last entered or left the receive FIFO
+    rx_idle: u64,
 }
 
+const RBR: u64 = 0; // DLL with DLAB set
+const IER: u64 = 1; // DLM with DLAB set
+const IIR: u64 = 2; // FCR on writes
+const LCR: u64 = 3;
+const MCR: u64 = 4;
+const LSR: u64 = 5;
+const MSR: u64 = 6;
+const SCR: u64 = 7;
+
 const IER_RX_ENABLE_BIT: u8 = 0x1;
 const IER_TX_ENABLE_BIT: u8 = 0x2;
+const IER_LINE_STATUS_BIT: u8 = 0x4;
+const IER_MODEM_STATUS_BIT: u8 = 0x8;
+
+const IIR_FIFOS_ENABLED: u8 = 0xc0;
+
+const FCR_FIFO_ENABLE: u8 = 1 << 0;
+const FCR_CLEAR_RX: u8 = 1 << 1;
+const FCR_CLEAR_TX: u8 = 1 << 2;
+
+const LCR_DLAB: u8 = 1 << 7;
+
+const MCR_DTR: u8 = 1 << 0;
+const MCR_RTS: u8 = 1 << 1;
+const MCR_OUT1: u8 = 1 << 2;
+const MCR_OUT2: u8 = 1 << 3;
+const MCR_LOOP: u8 = 1 << 4;
+
+const LSR_DATA_READY: u8 = 1 << 0;
+const LSR_OVERRUN: u8 = 1 << 1;
+const LSR_ERRORS: u8 = 0x1e; // overrun, parity, framing and break
 const LSR_TX_IDLE: u8 = 1 << 5;
+const LSR_TX_EMPTY: u8 = 1 << 6;
+
+const MSR_CTS: u8 = 1 << 4;
+const MSR_DSR: u8 = 1 << 5;
+const MSR_RI: u8 = 1 << 6;
+const MSR_DCD: u8 = 1 << 7;
+const MSR_DELTAS: u8 = 0x0f;
+
+/// Size of both FIFOs
+const FIFO_SIZE: usize = 16;
+
+/// Receive FIFO fill levels raising the received data interrupt, by FCR bits 7:6
+const RX_TRIGGER_LEVELS: [usize; 4] = [1, 4, 8, 14];
+
+/// Clocks to shift out a character
+const CHAR_CLOCKS: u64 = 0x10;
+
+/// Clocks without receive FIFO activity raising the character timeout interrupt
+const RX_TIMEOUT_CLOCKS: u64 = 4 * CHAR_CLOCKS;
+
+/// Clocks between looking for host input
+const RX_POLL_INTERVAL: u64 = 0x100;
 
 impl UART {
     pub fn create(range: MemoryRange) -> UART {
@@ -43,64 +104,165 @@ impl UART {
             range,
             state: RefCell::new(UartState {
                 clock: 0,
-                rbr: 0,
-                thr: 0,
+                rx_fifo: VecDeque::with_capacity(FIFO_SIZE),
+                tx_fifo: VecDeque::with_capacity(FIFO_SIZE),
+                dll: 0,
+                dlm: 0,
                 ier: 0,
-                iir: UartIirMask::Zero,
+                fcr: 0,
                 lcr: 0,
                 mcr: 0,
                 lsr: 0,
+                msr: MSR_CTS | MSR_DSR | MSR_DCD,
                 scr: 0,
                 thre_ip: false,
-                interrupting: false,
+                rx_idle: 0,
             }),
+            backend: None,
         }
     }
 
+    /// Connects the serial line to `backend`, which receives the transmitted bytes and
+    /// feeds the receive FIFO
+    pub fn connect(&mut self, backend: Box<dyn CharDevice>) {
+        self.backend = Some(backend);
+    }
+
+    /// The PLIC interrupt the UART raises, from the device tree
+    pub fn interrupt(&self) -> Option<u32> {
+        self.range.interrupt
+    }
+
+    /// Indicates whether the UART raises an interrupt signal
+    pub fn is_interrupting(&self) -> bool {
+        self.state.borrow().iir() != UartIirMask::NoIrq
+    }
+
     pub fn tick(&mut self) {
         let mut state = self.state.borrow_mut();
         state.clock = state.clock.wrapping_add(1);
-        let mut rx_ip = false;
-        if (state.clock % 0x10) == 0 && state.thr != 0 {
-            eprint!("{}", state.thr as char);
-            //            state.terminal.put_byte(state.thr);
-            state.thr = 0;
-            (_, rx_ip) = UART::update_iir(false, &mut state);
-            state.lsr |= LSR_TX_IDLE;
-            if (state.ier & IER_TX_ENABLE_BIT) != 0 {
-                state.thre_ip = true;
+
+        if (state.clock % CHAR_CLOCKS) == 0 {
+            if let Some(byte) = state.tx_fifo.pop_front() {
+                match (state.mcr & MCR_LOOP) != 0 {
+                    true => state.receive(byte),
+                    false => {
+                        if let Some(backend) = self.backend.as_mut() {
+                            if let Err(e) = backend.write(&[byte]) {
+                                println!("UART: can't write to the serial backend: {}", e);
+                            }
+                        }
+                    }
+                }
+                if state.tx_fifo.is_empty() {
+                    state.thre_ip = true;
+                }
             }
         }
 
-        if state.thre_ip || rx_ip {
-            state.interrupting = true;
-            state.thre_ip = false;
-        } else {
-            state.interrupting = false;
+        // The serial input is disconnected in loopback mode
+        if (state.clock % RX_POLL_INTERVAL) == 0 && (state.mcr & MCR_LOOP) == 0 {
+            if let Some(backend) = self.backend.as_mut() {
+                let mut buf = [0; FIFO_SIZE];
+                let space = state.fifo_size() - state.rx_fifo.len();
+                if let Ok(count) = backend.read(&mut buf[..space]) {
+                    for byte in buf[..count].iter() {
+                        state.receive(*byte);
+                    }
+                }
+            }
+        }
+
+        if !state.rx_fifo.is_empty() {
+            state.rx_idle = state.rx_idle.saturating_add(1);
         }
     }
+}
+
+impl UartState {
+    fn fifos_enabled(&self) -> bool {
+        (self.fcr & FCR_FIFO_ENABLE) != 0
+    }
 
-    fn update_iir(is_load: bool, state: &mut UartState) -> (u8, bool) {
-        let rbr = state.rbr;
-        if is_load {
-            state.rbr = 0;
-            state.lsr &= !0x1; // Data Available
+    fn fifo_size(&self) -> usize {
+        match self.fifos_enabled() {
+            true => FIFO_SIZE,
+            false => 1,
         }
+    }
 
-        let rx_ip = (state.ier & IER_RX_ENABLE_BIT) != 0 && state.rbr != 0;
-        let thre_ip = (state.ier & IER_TX_ENABLE_BIT) != 0 && state.thr == 0;
+    fn rx_trigger_level(&self) -> usize {
+        match self.fifos_enabled() {
+            true => RX_TRIGGER_LEVELS[(self.fcr >> 6) as usize],
+            false => 1,
+        }
+    }
 
-        // Which should be prioritized? RX or THRE interrupt?
-        if rx_ip {
-            state.iir = UartIirMask::RdAvail;
-        } else if thre_ip {
-            eprint!("thre_ip");
-            state.iir = UartIirMask::ThrEmpty;
+    /// A character arrives on the serial input
+    fn receive(&mut self, byte: u8) {
+        match self.rx_fifo.len() < self.fifo_size() {
+            true => self.rx_fifo.push_back(byte),
+            false => self.lsr |= LSR_OVERRUN,
+        }
+        self.rx_idle = 0;
+    }
+
+    /// The pending interrupt with the highest priority
+    fn iir(&self) -> UartIirMask {
+        let rx_enabled = (self.ier & IER_RX_ENABLE_BIT) != 0;
+        if (self.ier & IER_LINE_STATUS_BIT) != 0 && (self.lsr & LSR_ERRORS) != 0 {
+            UartIirMask::LineStatus
+        } else if rx_enabled && self.rx_fifo.len() >= self.rx_trigger_level() {
+            UartIirMask::RdAvail
+        } else if rx_enabled && !self.rx_fifo.is_empty() && self.rx_idle >= RX_TIMEOUT_CLOCKS {
+            UartIirMask::CharTimeout
+        } else if (self.ier & IER_TX_ENABLE_BIT) != 0 && self.thre_ip {
+            UartIirMask::ThrEmpty
+        } else if (self.ier & IER_MODEM_STATUS_BIT) != 0 && (self.msr & MSR_DELTAS) != 0 {
+            UartIirMask::ModemStatus
         } else {
-            state.iir = UartIirMask::NoIrq;
+            UartIirMask::NoIrq
         }
+    }
 
-        (rbr, rx_ip)
+    fn lsr(&self) -> u8 {
+        let mut lsr = self.lsr;
+        if !self.rx_fifo.is_empty() {
+            lsr |= LSR_DATA_READY;
+        }
+        if self.tx_fifo.is_empty() {
+            lsr |= LSR_TX_IDLE | LSR_TX_EMPTY;
+        }
+        lsr
+    }
+
+    /// Updates the modem status inputs after an MCR change, looped back from the
+    /// outputs in loopback mode, and always asserted otherwise
+    fn write_mcr(&mut self, value: u8) {
+        self.mcr = value;
+        let mut status = MSR_CTS | MSR_DSR | MSR_DCD;
+        if (value & MCR_LOOP) != 0 {
+            status = 0;
+            for (output, input) in [
+                (MCR_RTS, MSR_CTS),
+                (MCR_DTR, MSR_DSR),
+                (MCR_OUT1, MSR_RI),
+                (MCR_OUT2, MSR_DCD),
+            ] {
+                if (value & output) != 0 {
+                    status |= input;
+                }
+            }
+        }
+        let changed = (self.msr ^ status) & !MSR_DELTAS;
+        let mut deltas = self.msr & MSR_DELTAS;
+        deltas |= (changed & (MSR_CTS | MSR_DSR)) >> 4;
+        deltas |= (changed & MSR_DCD) >> 4;
+        // Trailing edge of the ring indicator
+        if (changed & self.msr & MSR_RI) != 0 {
+            deltas |= MSR_RI >> 4;
+        }
+        self.msr = status | deltas;
     }
 }
 
@@ -115,69 +277,100 @@ impl VirtualDevice for UART {
 
     fn write(&mut self, addr: VAddr, value: u8) -> Option<TrapCause> {
         let mut state = self.state.borrow_mut();
-        let lcr = state.lcr >> 7;
+        let dlab = (state.lcr & LCR_DLAB) != 0;
         let offs = addr - self.range.start;
         match offs {
-            0 => match lcr == 0 {
-                true => {
-                    state.thr = value;
-                    //eprintln!("UART out @ {:#x?}: {}", offs, value);
-                    UART::update_iir(false, &mut state);
-                    state.lsr &= !LSR_TX_IDLE;
+            RBR => match dlab {
+                false => {
+                    if state.tx_fifo.len() < state.fifo_size() {
+                        state.tx_fifo.push_back(value);
+                    }
+                    state.thre_ip = false;
                 }
-                false => {} // @TODO: Implement properly
+                true => state.dll = value,
             },
-            0x10000001 => match lcr == 0 {
-                true => {
+            IER => match dlab {
+                false => {
                     // This bahavior isn't written in the data sheet
                     // but some drivers seem to rely on it.
                     if (state.ier & IER_TX_ENABLE_BIT) == 0
                         && (value & IER_TX_ENABLE_BIT) != 0
-                        && state.thr == 0
+                        && state.tx_fifo.is_empty()
                     {
                         state.thre_ip = true;
                     }
-                    state.ier = value;
-                    UART::update_iir(false, &mut state);
-                    state.lsr &= !LSR_TX_IDLE;
+                    state.ier = value & 0x0f;
                 }
-                false => {} // @TODO: Implement properly
+                true => state.dlm = value,
             },
-            0x10000003 => {
+            IIR => {
+                // FCR, turning the FIFOs on or off clears them
+                if ((state.fcr ^ value) & FCR_FIFO_ENABLE) != 0 {
+                    state.rx_fifo.clear();
+                    state.tx_fifo.clear();
+                }
+                if (value & FCR_CLEAR_RX) != 0 {
+                    state.rx_fifo.clear();
+                }
+                if (value & FCR_CLEAR_TX) != 0 {
+                    state.tx_fifo.clear();
+                }
+                state.fcr = value & 0xc9;
+            }
+            LCR => {
                 state.lcr = value;
             }
-            0x10000004 => {
-                state.mcr = value;
+            MCR => {
+                state.write_mcr(value & 0x1f);
             }
-            0x10000007 => {
+            SCR => {
                 state.scr = value;
             }
-            _ => {}
+            _ => {} // LSR and MSR are read-only
         }
-        //panic!("UART::write");
         None
     }
 
-    //    fn
-
     fn read(&mut self, addr: VAddr) -> Result<u8, TrapCause> {
         let mut state = self.state.borrow_mut();
-        let lcr = state.lcr;
+        let dlab = (state.lcr & LCR_DLAB) != 0;
         let offs = addr - self.range.start;
         Ok(match offs {
-            0 => match (lcr >> 7) == 0 {
-                true => UART::update_iir(true, &mut state).0,
-                false => 0, // @TODO: DLL divisor latch LSB
+            RBR => match dlab {
+                false => {
+                    state.rx_idle = 0;
+                    state.rx_fifo.pop_front().unwrap_or(0)
+                }
+                true => state.dll,
             },
-            1 => match (lcr >> 7) == 0 {
-                true => state.ier,
-                false => 0, // @TODO: DLM divisor latch MSB
+            IER => match dlab {
+                false => state.ier,
+                true => state.dlm,
             },
-            2 => state.iir as u8,
-            3 => state.lcr,
-            4 => state.mcr,
-            5 => state.lsr,
-            7 => state.scr,
+            IIR => {
+                let iir = state.iir();
+                // Reading the IIR acknowledges the THR empty interrupt
+                if iir == UartIirMask::ThrEmpty {
+                    state.thre_ip = false;
+                }
+                match state.fifos_enabled() {
+                    true => iir as u8 | IIR_FIFOS_ENABLED,
+                    false => iir as u8,
+                }
+            }
+            LCR => state.lcr,
+            MCR => state.mcr,
+            LSR => {
+                let lsr = state.lsr();
+                state.lsr &= !LSR_ERRORS;
+                lsr
+            }
+            MSR => {
+                let msr = state.msr;
+                state.msr &= !MSR_DELTAS;
+                msr
+            }
+            SCR => state.scr,
             _ => 0,
         })
     }
@@ -0,0 +1,151 @@
+use crate::{cpu::TrapCause, disk::DiskImage, mmio::PhysicalMemory};
+
+use super::{gather, scatter, VirtioDevice, VirtqDescriptor, Virtqueue};
+
+// To simulate disk access time.
+// @TODO: Set more proper number. 500 core clocks may be too short.
+const DISK_ACCESS_DELAY: u64 = 4500;
+
+const SECTOR_SIZE: u64 = 512;
+
+/// VIRTIO_BLK_F_RO, the disk is read-only
+const VIRTIO_BLK_F_RO: u64 = 1 << 5;
+/// VIRTIO_BLK_F_FLUSH, the device understands VIRTIO_BLK_T_FLUSH
+const VIRTIO_BLK_F_FLUSH: u64 = 1 << 9;
+
+/// Request types of `virtio_blk_req.type`
+const VIRTIO_BLK_T_IN: u32 = 0;
+const VIRTIO_BLK_T_OUT: u32 = 1;
+const VIRTIO_BLK_T_FLUSH: u32 = 4;
+const VIRTIO_BLK_T_GET_ID: u32 = 8;
+
+/// Values written to the status byte ending each request
+const VIRTIO_BLK_S_OK: u8 = 0;
+const VIRTIO_BLK_S_IOERR: u8 = 1;
+const VIRTIO_BLK_S_UNSUPP: u8 = 2;
+
+/// Length of the device ID string returned by VIRTIO_BLK_T_GET_ID
+const VIRTIO_BLK_ID_BYTES: usize = 20;
+
+// Mostly ripped from https://github.com/takahirox/riscv-rust/blob/master/src/device/virtio_block_disk.rs
+pub struct VirtioBlockDisk {
+    disk: Box<dyn DiskImage>,
+}
+
+impl VirtioBlockDisk {
+    /// Creates a new `VirtioBlockDisk`.
+    ///
+    /// # Arguments
+    /// * `disk` filesystem image, see `disk::open`
+    pub fn new(disk: Box<dyn DiskImage>) -> Self {
+        VirtioBlockDisk { disk }
+    }
+
+    /// Executes the `virtio_blk_req` in `chain`, returning the number of bytes written
+    /// to the device-writable part of it.
+    ///
+    /// The driver-readable descriptors hold the header, followed by the data for OUT
+    /// requests. The device-writable descriptors take the data for IN and GET_ID requests,
+    /// and end in the status byte.
+    fn handle_request(
+        &mut self,
+        memory: &mut PhysicalMemory,
+        chain: &[VirtqDescriptor],
+    ) -> Result<u32, TrapCause> {
+        // struct virtio_blk_req {
+        //   uint32 type;
+        //   uint32 reserved;
+        //   uint64 sector;
+        //   uint8 data[];
+        //   uint8 status;
+        // }
+        let (readable, writable): (Vec<VirtqDescriptor>, Vec<VirtqDescriptor>) =
+            chain.iter().partition(|d| !d.device_writable());
+        let request = gather(memory, &readable)?;
+        let writable_len: u64 = writable.iter().map(|d| d.len as u64).sum();
+        if request.len() < 16 || writable_len == 0 {
+            // Without a header or a status byte there is nothing to answer
+            return Ok(0);
+        }
+        let blk_type = u32::from_le_bytes(request[0..4].try_into().unwrap());
+        let sector = u64::from_le_bytes(request[8..16].try_into().unwrap());
+        let data = &request[16..];
+        let data_len = writable_len as usize - 1;
+
+        let (mut response, status) = match blk_type {
+            VIRTIO_BLK_T_IN => {
+                let mut data = vec![0; data_len];
+                match self.disk_offset(sector) {
+                    Some(offset) => match self.disk.read(offset, &mut data) {
+                        Ok(()) => (data, VIRTIO_BLK_S_OK),
+                        Err(_) => (vec![], VIRTIO_BLK_S_IOERR),
+                    },
+                    None => (vec![], VIRTIO_BLK_S_IOERR),
+                }
+            }
+            VIRTIO_BLK_T_OUT => match self.disk_offset(sector) {
+                Some(offset) => match self.disk.write(offset, data) {
+                    Ok(()) => (vec![], VIRTIO_BLK_S_OK),
+                    Err(_) => (vec![], VIRTIO_BLK_S_IOERR),
+                },
+                None => (vec![], VIRTIO_BLK_S_IOERR),
+            },
+            VIRTIO_BLK_T_FLUSH => match self.disk.flush() {
+                Ok(()) => (vec![], VIRTIO_BLK_S_OK),
+                Err(_) => (vec![], VIRTIO_BLK_S_IOERR),
+            },
+            VIRTIO_BLK_T_GET_ID => {
+                let mut id = b"rriscv-virtio-blk".to_vec();
+                id.resize(VIRTIO_BLK_ID_BYTES.min(data_len), 0);
+                (id, VIRTIO_BLK_S_OK)
+            }
+            _ => (vec![], VIRTIO_BLK_S_UNSUPP),
+        };
+
+        // The status byte always goes last, after the full data area
+        response.resize(data_len, 0);
+        response.push(status);
+        scatter(memory, &writable, &response)?;
+        Ok(response.len() as u32)
+    }
+
+    /// Byte offset of `sector` on the disk, the image checks the end of each access
+    fn disk_offset(&self, sector: u64) -> Option<u64> {
+        sector.checked_mul(SECTOR_SIZE)
+    }
+}
+
+impl VirtioDevice for VirtioBlockDisk {
+    fn device_id(&self) -> u32 {
+        2
+    }
+
+    fn features(&self) -> u64 {
+        match self.disk.read_only() {
+            true => VIRTIO_BLK_F_FLUSH | VIRTIO_BLK_F_RO,
+            false => VIRTIO_BLK_F_FLUSH,
+        }
+    }
+
+    fn queues(&self) -> usize {
+        1
+    }
+
+    /// `struct virtio_blk_config`, of which only the capacity in sectors is implemented
+    fn config(&self) -> Vec<u8> {
+        (self.disk.len() / SECTOR_SIZE).to_le_bytes().to_vec()
+    }
+
+    fn access_delay(&self) -> u64 {
+        DISK_ACCESS_DELAY
+    }
+
+    fn process(
+        &mut self,
+        memory: &mut PhysicalMemory,
+        queue: usize,
+        queues: &mut [Virtqueue],
+    ) -> Result<bool, TrapCause> {
+        queues[queue].drain(memory, |memory, chain| self.handle_request(memory, chain))
+    }
+}
@@ -0,0 +1,297 @@
+use std::collections::VecDeque;
+
+use crate::{chardev::CharDevice, cpu::TrapCause, mmio::PhysicalMemory};
+
+use super::{gather, VirtioDevice, Virtqueue};
+
+/// VIRTIO_CONSOLE_F_MULTIPORT, ports beyond the console and the control queues exist
+const VIRTIO_CONSOLE_F_MULTIPORT: u64 = 1 << 1;
+/// VIRTIO_CONSOLE_F_EMERG_WRITE, the driver may write characters to `emerg_wr`
+const VIRTIO_CONSOLE_F_EMERG_WRITE: u64 = 1 << 2;
+
+/// Offset of `emerg_wr` in `struct virtio_console_config`
+const EMERG_WR_OFFSET: usize = 8;
+
+/// Events of `struct virtio_console_control`
+const VIRTIO_CONSOLE_DEVICE_READY: u16 = 0;
+const VIRTIO_CONSOLE_DEVICE_ADD: u16 = 1;
+const VIRTIO_CONSOLE_PORT_READY: u16 = 3;
+const VIRTIO_CONSOLE_CONSOLE_PORT: u16 = 4;
+const VIRTIO_CONSOLE_PORT_OPEN: u16 = 6;
+const VIRTIO_CONSOLE_PORT_NAME: u16 = 7;
+
+/// Queues of port 0, and the control queues of a multiport device
+const RECEIVEQ: usize = 0;
+const CONTROL_RECEIVEQ: usize = 2;
+const CONTROL_TRANSMITQ: usize = 3;
+
+/// Most bytes of host input buffered per port, until the guest takes them
+const INPUT_BUFFER_SIZE: usize = 0x1000;
+
+struct Port {
+    name: String,
+    backend: Box<dyn CharDevice>,
+    /// Host input not yet handed to the guest
+    input: VecDeque<u8>,
+    /// The guest has the port open
+    open: bool,
+}
+
+/// A virtio console. Port 0 is the console itself (`hvc0` on Linux), further ports
+/// show up as `/dev/vportNpM` with their name in `/sys/class/virtio-ports`.
+pub struct VirtioConsole {
+    ports: Vec<Port>,
+    /// The driver accepted VIRTIO_CONSOLE_F_MULTIPORT
+    multiport: bool,
+    /// Control messages waiting for a buffer on the control receiveq
+    control: VecDeque<Vec<u8>>,
+}
+
+impl VirtioConsole {
+    /// Creates a new `VirtioConsole` with a single port.
+    ///
+    /// # Arguments
+    /// * `console` host side of the console port, see `chardev`
+    pub fn new(console: Box<dyn CharDevice>) -> Self {
+        VirtioConsole {
+            ports: vec![Port {
+                name: String::new(),
+                backend: console,
+                input: VecDeque::new(),
+                open: false,
+            }],
+            multiport: false,
+            control: VecDeque::new(),
+        }
+    }
+
+    /// Adds a port named `name`, making this a multiport device. Ports have to be added
+    /// before the device is attached.
+    pub fn add_port(&mut self, name: &str, backend: Box<dyn CharDevice>) {
+        self.ports.push(Port {
+            name: name.to_string(),
+            backend,
+            input: VecDeque::new(),
+            open: false,
+        });
+    }
+
+    /// Whether the guest has port `id` open
+    pub fn is_open(&self, id: usize) -> bool {
+        self.ports.get(id).map_or(false, |port| port.open)
+    }
+
+    /// The receiveq of port `id`, transmitq is the one after it
+    fn receiveq(id: usize) -> usize {
+        match id {
+            0 => RECEIVEQ,
+            _ => 2 * id + 2,
+        }
+    }
+
+    /// The port using `queue`, if any
+    fn port(&self, queue: usize) -> Option<usize> {
+        let id = match queue {
+            0 | 1 => 0,
+            CONTROL_RECEIVEQ | CONTROL_TRANSMITQ => return None,
+            _ => queue / 2 - 1,
+        };
+        match id == 0 || self.multiport {
+            true => Some(id),
+            false => None,
+        }
+    }
+
+    /// Queues a `struct virtio_console_control` for the driver, followed by `data`
+    fn send_control(&mut self, id: usize, event: u16, value: u16, data: &[u8]) {
+        let mut message = (id as u32).to_le_bytes().to_vec();
+        message.extend_from_slice(&event.to_le_bytes());
+        message.extend_from_slice(&value.to_le_bytes());
+        message.extend_from_slice(data);
+        self.control.push_back(message);
+    }
+
+    fn handle_control(&mut self, message: &[u8]) {
+        if message.len() < 8 {
+            return;
+        }
+        let id = u32::from_le_bytes(message[0..4].try_into().unwrap()) as usize;
+        let event = u16::from_le_bytes(message[4..6].try_into().unwrap());
+        let value = u16::from_le_bytes(message[6..8].try_into().unwrap());
+        match event {
+            VIRTIO_CONSOLE_DEVICE_READY if value == 1 => {
+                for id in 0..self.ports.len() {
+                    self.send_control(id, VIRTIO_CONSOLE_DEVICE_ADD, 0, &[]);
+                }
+            }
+            VIRTIO_CONSOLE_PORT_READY if value == 1 && id < self.ports.len() => {
+                match id {
+                    0 => self.send_control(id, VIRTIO_CONSOLE_CONSOLE_PORT, 1, &[]),
+                    _ => {
+                        let name = self.ports[id].name.clone().into_bytes();
+                        self.send_control(id, VIRTIO_CONSOLE_PORT_NAME, 1, &name)
+                    }
+                }
+                // The host end is always connected
+                self.send_control(id, VIRTIO_CONSOLE_PORT_OPEN, 1, &[]);
+            }
+            VIRTIO_CONSOLE_PORT_OPEN if id < self.ports.len() => {
+                self.ports[id].open = value == 1;
+            }
+            _ => {}
+        }
+    }
+
+    /// Hands queued control messages to the driver, one per buffer
+    fn flush_control(
+        &mut self,
+        memory: &mut PhysicalMemory,
+        vq: &mut Virtqueue,
+    ) -> Result<bool, TrapCause> {
+        let mut used = false;
+        while let Some(message) = self.control.front() {
+            match vq.put(memory, message)? {
+                Some(_) => self.control.pop_front(),
+                None => break,
+            };
+            used = true;
+        }
+        Ok(used)
+    }
+
+    /// Hands buffered host input of port `id` to the driver
+    fn deliver_input(
+        &mut self,
+        memory: &mut PhysicalMemory,
+        id: usize,
+        vq: &mut Virtqueue,
+    ) -> Result<bool, TrapCause> {
+        let input = &mut self.ports[id].input;
+        let mut used = false;
+        while !input.is_empty() {
+            match vq.put(memory, input.make_contiguous())? {
+                Some(len) => input.drain(..len),
+                None => break,
+            };
+            used = true;
+        }
+        Ok(used)
+    }
+}
+
+impl VirtioDevice for VirtioConsole {
+    fn device_id(&self) -> u32 {
+        3
+    }
+
+    fn features(&self) -> u64 {
+        match self.ports.len() {
+            1 => VIRTIO_CONSOLE_F_EMERG_WRITE,
+            _ => VIRTIO_CONSOLE_F_EMERG_WRITE | VIRTIO_CONSOLE_F_MULTIPORT,
+        }
+    }
+
+    /// A receiveq and transmitq per port, plus the two control queues when there is
+    /// more than one port
+    fn queues(&self) -> usize {
+        match self.ports.len() {
+            1 => 2,
+            ports => 2 * ports + 2,
+        }
+    }
+
+    /// `struct virtio_console_config`, the console size is not reported
+    fn config(&self) -> Vec<u8> {
+        let mut config = vec![0; 4];
+        config.extend_from_slice(&(self.ports.len() as u32).to_le_bytes());
+        config.extend_from_slice(&[0; 4]);
+        config
+    }
+
+    fn process(
+        &mut self,
+        memory: &mut PhysicalMemory,
+        queue: usize,
+        queues: &mut [Virtqueue],
+    ) -> Result<bool, TrapCause> {
+        match queue {
+            CONTROL_RECEIVEQ if self.multiport => self.flush_control(memory, &mut queues[queue]),
+            CONTROL_TRANSMITQ if self.multiport => {
+                let mut messages = vec![];
+                let used = queues[queue].drain(memory, |memory, chain| {
+                    messages.push(gather(memory, chain)?);
+                    Ok(0)
+                })?;
+                for message in messages {
+                    self.handle_control(&message);
+                }
+                let replied = self.flush_control(memory, &mut queues[CONTROL_RECEIVEQ])?;
+                Ok(used | replied)
+            }
+            _ => match self.port(queue) {
+                Some(id) if queue == VirtioConsole::receiveq(id) => {
+                    self.deliver_input(memory, id, &mut queues[queue])
+                }
+                Some(id) => {
+                    let backend = &mut self.ports[id].backend;
+                    queues[queue].drain(memory, |memory, chain| {
+                        let output = gather(memory, chain)?;
+                        if let Err(e) = backend.write(&output) {
+                            println!("virtio-console: port {} output lost: {}", id, e);
+                        }
+                        Ok(0)
+                    })
+                }
+                None => Ok(false),
+            },
+        }
+    }
+
+    fn poll(
+        &mut self,
+        memory: &mut PhysicalMemory,
+        queues: &mut [Virtqueue],
+    ) -> Result<bool, TrapCause> {
+        let mut used = false;
+        let ports = match self.multiport {
+            true => self.ports.len(),
+            false => 1,
+        };
+        for id in 0..ports {
+            let port = &mut self.ports[id];
+            let mut buf = [0; 256];
+            if port.input.len() < INPUT_BUFFER_SIZE {
+                match port.backend.read(&mut buf) {
+                    Ok(count) => port.input.extend(&buf[..count]),
+                    Err(e) => println!("virtio-console: port {} input lost: {}", id, e),
+                }
+            }
+            let receiveq = VirtioConsole::receiveq(id);
+            used |= self.deliver_input(memory, id, &mut queues[receiveq])?;
+        }
+        if self.multiport {
+            used |= self.flush_control(memory, &mut queues[CONTROL_RECEIVEQ])?;
+        }
+        Ok(used)
+    }
+
+    fn activate(&mut self, features: u64) {
+        self.multiport = self.ports.len() > 1 && features & VIRTIO_CONSOLE_F_MULTIPORT != 0;
+    }
+
+    fn reset(&mut self) {
+        self.multiport = false;
+        self.control.clear();
+        for port in self.ports.iter_mut() {
+            port.input.clear();
+            port.open = false;
+        }
+    }
+
+    /// Characters written to `emerg_wr` go straight to the console
+    fn write_config(&mut self, offset: usize, value: u32) {
+        if offset == EMERG_WR_OFFSET {
+            let _ = self.ports[0].backend.write(&[value as u8]);
+        }
+    }
+}
@@ -0,0 +1,640 @@
+use elfloader::VAddr;
+
+use crate::{
+    cpu::TrapCause,
+    memory::MemoryOperations,
+    mmio::{PhysicalMemory, VirtualDevice},
+    mmu::MemoryRange,
+};
+
+pub mod block;
+pub mod console;
+pub mod net;
+pub mod p9;
+pub mod rng;
+
+#[derive(FromPrimitive)]
+#[repr(usize)]
+pub enum VirtioRegister {
+    MagicValue = 0x0,
+    Version = 0x4,
+    DeviceId = 0x8,
+    VendorId = 0xc,
+    DeviceFeatures = 0x10,
+    DeviceFeaturesSel = 0x14,
+    DriverFeatures = 0x20,
+    DriverFeaturesSel = 0x24,
+    QueueSel = 0x30,
+    QueueMax = 0x34,
+    QueueNum = 0x38,
+    QueueReady = 0x44,
+    QueueNotify = 0x50,
+    InterruptStatus = 0x60,
+    InterruptACK = 0x64,
+    Status = 0x70,
+    QueueDescLow = 0x80,
+    QueueDescHigh = 0x84,
+    QueueDriverLow = 0x90,
+    QueueDriverHigh = 0x94,
+    QueueDeviceLow = 0xa0,
+    QueueDeviceHigh = 0xa4,
+    ConfigGeneration = 0x0fc,
+    Config = 0x100,
+}
+// 0x2000 is an arbitary number.
+const MAX_QUEUE_SIZE: u32 = 0x2000;
+
+const VIRTQ_DESC_F_NEXT: u16 = 1;
+
+// 0: buffer is read-only = write to disk operation
+// 1: buffer is write-only = read from disk operation
+const VIRTQ_DESC_F_WRITE: u16 = 2;
+
+/// VIRTIO_F_VERSION_1, required by the modern (version 2) MMIO layout
+const VIRTIO_F_VERSION_1: u64 = 1 << 32;
+
+/// Set in `Status` once the driver is set up and the device may use its queues
+const STATUS_DRIVER_OK: u32 = 0x4;
+/// Set in `Status` when the device hit an error it can not recover from without a reset
+const STATUS_DEVICE_NEEDS_RESET: u32 = 0x40;
+
+/// Clocks between polls of devices for input from the host
+const POLL_INTERVAL: u64 = 0x400;
+
+/// Bits of the `InterruptStatus` register
+const INTERRUPT_USED_BUFFER: u32 = 0x1;
+const INTERRUPT_CONFIG_CHANGE: u32 = 0x2;
+
+// Virtqueue layout, the three areas are placed independently by the driver
+// through the QueueDesc, QueueDriver and QueueDevice registers
+//
+// struct virtq_desc {          // Descriptor area: queue_size * 16 bytes
+//   uint64 addr;
+//   uint32 len;
+//   uint16 flags;
+//   uint16 next;
+// }
+//
+// struct virtq_avail {         // Driver area
+//   uint16 flags;
+//   uint16 idx;
+//   uint16 ring[queue_size];
+// }
+//
+// struct virtq_used {          // Device area
+//   uint16 flags;
+//   uint16 idx;
+//   struct virtq_used_elem ring[queue_size];
+// }
+//
+// struct virtq_used_elem {
+//   uint32 id;
+//   uint32 len;
+// }
+
+#[derive(Debug, Copy, Clone)]
+pub struct VirtqDescriptor {
+    pub addr: u64,
+    pub len: u32,
+    pub flags: u16,
+    pub next: u16,
+}
+
+impl VirtqDescriptor {
+    pub fn device_writable(&self) -> bool {
+        self.flags & VIRTQ_DESC_F_WRITE != 0
+    }
+}
+
+/// A single virtqueue, as set up by the driver through the queue registers
+#[derive(Debug, Copy, Clone, Default)]
+pub struct Virtqueue {
+    num: u32,
+    ready: bool,
+    desc: u64,
+    driver: u64,
+    device: u64,
+    /// Index into the available ring of the next buffer to process
+    last_avail_idx: u16,
+    used_idx: u16,
+}
+
+impl Virtqueue {
+    /// Takes the head of the next available descriptor chain, if any
+    pub fn pop(&mut self, memory: &mut PhysicalMemory) -> Result<Option<u16>, TrapCause> {
+        if !self.ready || self.num == 0 {
+            return Ok(None);
+        }
+        let avail_idx = memory.read16(self.driver + 2)?;
+        if avail_idx == self.last_avail_idx {
+            return Ok(None);
+        }
+        let slot = self.last_avail_idx as u64 % self.num as u64;
+        let head = memory.read16(self.driver + 4 + slot * 2)?;
+        self.last_avail_idx = self.last_avail_idx.wrapping_add(1);
+        Ok(Some(head))
+    }
+
+    /// Collects the descriptor chain starting at `head`
+    pub fn chain(
+        &self,
+        memory: &mut PhysicalMemory,
+        head: u16,
+    ) -> Result<Vec<VirtqDescriptor>, TrapCause> {
+        let mut chain = vec![];
+        let mut index = head;
+        loop {
+            // A chain can not be longer than the queue, anything else is a loop
+            if index as u32 >= self.num || chain.len() as u32 >= self.num {
+                return Err(TrapCause::LoadAccessFault(self.desc + 16 * index as u64));
+            }
+            let address = self.desc + 16 * index as u64;
+            let descriptor = VirtqDescriptor {
+                addr: memory.read64(address)?,
+                len: memory.read32(address + 8)?,
+                flags: memory.read16(address + 12)?,
+                next: memory.read16(address + 14)?,
+            };
+            chain.push(descriptor);
+            if descriptor.flags & VIRTQ_DESC_F_NEXT == 0 {
+                return Ok(chain);
+            }
+            index = descriptor.next;
+        }
+    }
+
+    /// Returns the chain at `head` to the driver, with `len` bytes written to it
+    pub fn push_used(
+        &mut self,
+        memory: &mut PhysicalMemory,
+        head: u16,
+        len: u32,
+    ) -> Option<TrapCause> {
+        let slot = self.used_idx as u64 % self.num as u64;
+        let elem = self.device + 4 + slot * 8;
+        if let Some(cause) = memory.write32(elem, head as u32) {
+            return Some(cause);
+        }
+        if let Some(cause) = memory.write32(elem + 4, len) {
+            return Some(cause);
+        }
+        self.used_idx = self.used_idx.wrapping_add(1);
+        memory.write16(self.device + 2, self.used_idx)
+    }
+
+    /// Writes as much of `bytes` as fits into the device-writable buffers of the next
+    /// available chain, and returns it to the driver. Returns the number of bytes written,
+    /// or `None` when the driver has no chain available.
+    pub fn put(
+        &mut self,
+        memory: &mut PhysicalMemory,
+        bytes: &[u8],
+    ) -> Result<Option<usize>, TrapCause> {
+        let head = match self.pop(memory)? {
+            Some(head) => head,
+            None => return Ok(None),
+        };
+        let writable: Vec<VirtqDescriptor> = self
+            .chain(memory, head)?
+            .into_iter()
+            .filter(|d| d.device_writable())
+            .collect();
+        let capacity: usize = writable.iter().map(|d| d.len as usize).sum();
+        let len = capacity.min(bytes.len());
+        scatter(memory, &writable, &bytes[..len])?;
+        match self.push_used(memory, head, len as u32) {
+            Some(cause) => Err(cause),
+            None => Ok(Some(len)),
+        }
+    }
+
+    /// Hands every available chain to `handle`, which returns the number of bytes it
+    /// wrote to the chain. Returns whether any chain was used.
+    pub fn drain(
+        &mut self,
+        memory: &mut PhysicalMemory,
+        mut handle: impl FnMut(&mut PhysicalMemory, &[VirtqDescriptor]) -> Result<u32, TrapCause>,
+    ) -> Result<bool, TrapCause> {
+        let mut used = false;
+        while let Some(head) = self.pop(memory)? {
+            let chain = self.chain(memory, head)?;
+            let written = handle(memory, &chain)?;
+            if let Some(cause) = self.push_used(memory, head, written) {
+                return Err(cause);
+            }
+            used = true;
+        }
+        Ok(used)
+    }
+}
+
+/// Reads the contents of the `descriptors` buffers, in order
+pub fn gather(
+    memory: &mut PhysicalMemory,
+    descriptors: &[VirtqDescriptor],
+) -> Result<Vec<u8>, TrapCause> {
+    let mut bytes = vec![];
+    for descriptor in descriptors {
+        for i in 0..descriptor.len as u64 {
+            bytes.push(memory.read8(descriptor.addr + i)?);
+        }
+    }
+    Ok(bytes)
+}
+
+/// Writes `bytes` across the `descriptors` buffers, in order
+pub fn scatter(
+    memory: &mut PhysicalMemory,
+    descriptors: &[VirtqDescriptor],
+    bytes: &[u8],
+) -> Result<(), TrapCause> {
+    let addresses = descriptors
+        .iter()
+        .flat_map(|d| (0..d.len as u64).map(move |i| d.addr + i));
+    for (address, byte) in addresses.zip(bytes) {
+        if let Some(cause) = memory.write8(address, *byte) {
+            return Err(cause);
+        }
+    }
+    Ok(())
+}
+
+/// A virtio device, attached to the guest through a `VIRTIO` transport
+pub trait VirtioDevice {
+    /// Virtio device ID, e.g. 2 for block devices
+    fn device_id(&self) -> u32;
+
+    /// Device specific feature bits, the transport adds VIRTIO_F_VERSION_1
+    fn features(&self) -> u64;
+
+    /// Number of virtqueues the device uses
+    fn queues(&self) -> usize;
+
+    /// Device configuration space
+    fn config(&self) -> Vec<u8>;
+
+    /// Clocks between a queue notification and the device handling it
+    fn access_delay(&self) -> u64 {
+        0
+    }
+
+    /// Handles the buffers the driver made available on the `queue`th of `queues`.
+    /// Returns whether any buffer was used.
+    fn process(
+        &mut self,
+        memory: &mut PhysicalMemory,
+        queue: usize,
+        queues: &mut [Virtqueue],
+    ) -> Result<bool, TrapCause>;
+
+    /// Called periodically once the driver is up, to hand input from the host to the
+    /// guest. Returns whether any buffer was used.
+    fn poll(
+        &mut self,
+        _memory: &mut PhysicalMemory,
+        _queues: &mut [Virtqueue],
+    ) -> Result<bool, TrapCause> {
+        Ok(false)
+    }
+
+    /// The driver is up, having accepted `features`
+    fn activate(&mut self, _features: u64) {}
+
+    /// The driver reset the device
+    fn reset(&mut self) {}
+
+    /// The driver wrote `value` at `offset` in the configuration space
+    fn write_config(&mut self, _offset: usize, _value: u32) {}
+}
+
+/// A virtio-mmio transport. Without a device attached it reads as device ID 0,
+/// which drivers skip.
+pub struct VIRTIO {
+    range: MemoryRange,
+    device: Option<Box<dyn VirtioDevice>>,
+    clock: u64,
+    /// Clock of each queue notification, paired with the notified queue
+    notifications: Vec<(u64, u32)>,
+    interrupt_status: u32,
+    registers: Vec<u32>,
+    driver_features: u64,
+    queues: Vec<Virtqueue>,
+}
+
+impl VIRTIO {
+    pub fn create(range: MemoryRange) -> VIRTIO {
+        let mut reg_vec = vec![0; 0x1000];
+        reg_vec[VirtioRegister::MagicValue as usize] = 0x74726976;
+        reg_vec[VirtioRegister::Version as usize] = 0x2;
+        reg_vec[VirtioRegister::VendorId as usize] = 0x554d4551;
+
+        VIRTIO {
+            range,
+            device: None,
+            clock: 0,
+            notifications: vec![],
+            interrupt_status: 0,
+            registers: reg_vec,
+            driver_features: 0,
+            queues: vec![],
+        }
+    }
+
+    /// Connects `device` to this transport
+    pub fn attach(&mut self, device: Box<dyn VirtioDevice>) {
+        assert!(self.device.is_none()); // Only call me once please
+        self.queues = vec![Virtqueue::default(); device.queues()];
+        self.device = Some(device);
+    }
+
+    pub fn is_attached(&self) -> bool {
+        self.device.is_some()
+    }
+
+    /// The PLIC interrupt the transport raises, from the device tree
+    pub fn interrupt(&self) -> Option<u32> {
+        self.range.interrupt
+    }
+
+    /// Indicates whether the transport raises an interrupt signal
+    pub fn is_interrupting(&self) -> bool {
+        self.interrupt_status != 0
+    }
+
+    /// Processes queued requests, reading and writing guest `memory`
+    pub fn tick(&mut self, memory: &mut PhysicalMemory) {
+        let device = match self.device.as_mut() {
+            Some(device) => device,
+            None => return,
+        };
+        let mut result = Ok(false);
+        if self.notifications.len() > 0
+            && self.clock >= self.notifications[0].0 + device.access_delay()
+        {
+            let (_, queue) = self.notifications.remove(0);
+            if (queue as usize) < self.queues.len() {
+                result = device.process(memory, queue as usize, &mut self.queues);
+            }
+        }
+        if self.registers[VirtioRegister::Status as usize] & STATUS_DRIVER_OK != 0
+            && self.clock % POLL_INTERVAL == 0
+        {
+            result = result.and_then(|used| {
+                device
+                    .poll(memory, &mut self.queues)
+                    .map(|polled| used | polled)
+            });
+        }
+        match result {
+            // bit 0 in interrupt_status register indicates
+            // the interrupt was asserted because the device has used a buffer
+            // in at least one of the active virtual queues.
+            Ok(true) => self.interrupt_status |= INTERRUPT_USED_BUFFER,
+            Ok(false) => {}
+            Err(cause) => {
+                println!("virtio: bad virtqueue access: {:#x?}", cause);
+                self.registers[VirtioRegister::Status as usize] |= STATUS_DEVICE_NEEDS_RESET;
+                self.interrupt_status |= INTERRUPT_CONFIG_CHANGE;
+            }
+        }
+        self.clock = self.clock.wrapping_add(1);
+    }
+
+    fn features(&self) -> u64 {
+        self.device
+            .as_ref()
+            .map_or(0, |device| device.features() | VIRTIO_F_VERSION_1)
+    }
+
+    /// The queue picked by `QueueSel`, if the device has it
+    fn selected_queue(&mut self) -> Option<&mut Virtqueue> {
+        let sel = self.registers[VirtioRegister::QueueSel as usize] as usize;
+        self.queues.get_mut(sel)
+    }
+
+    /// Loads the register at `offs` from the start of the device
+    pub fn load(&mut self, offs: usize) -> u32 {
+        let features_sel = self.registers[VirtioRegister::DeviceFeaturesSel as usize];
+        let driver_features_sel = self.registers[VirtioRegister::DriverFeaturesSel as usize];
+        match num::FromPrimitive::from_usize(offs) {
+            Some(VirtioRegister::DeviceId) => self.device.as_ref().map_or(0, |d| d.device_id()),
+            Some(VirtioRegister::DeviceFeatures) => match features_sel {
+                0 => self.features() as u32,
+                1 => (self.features() >> 32) as u32,
+                _ => 0,
+            },
+            Some(VirtioRegister::DriverFeatures) => match driver_features_sel {
+                0 => self.driver_features as u32,
+                1 => (self.driver_features >> 32) as u32,
+                _ => 0,
+            },
+            Some(VirtioRegister::QueueMax) => match self.selected_queue() {
+                Some(_) => MAX_QUEUE_SIZE,
+                None => 0,
+            },
+            Some(VirtioRegister::QueueNum) => self.selected_queue().map_or(0, |q| q.num),
+            Some(VirtioRegister::QueueReady) => self.selected_queue().map_or(0, |q| q.ready as u32),
+            Some(VirtioRegister::InterruptStatus) => self.interrupt_status,
+            _ if offs >= VirtioRegister::Config as usize => {
+                let config = self.device.as_ref().map_or(vec![], |d| d.config());
+                let start = offs - VirtioRegister::Config as usize;
+                (0..4).fold(0, |value, i| match config.get(start + i) {
+                    Some(byte) => value | (*byte as u32) << (8 * i),
+                    None => value,
+                })
+            }
+            _ => self.registers[offs],
+        }
+    }
+
+    /// Stores `value` to the register at `offs` from the start of the device
+    pub fn store(&mut self, offs: usize, value: u32) {
+        let driver_features_sel = self.registers[VirtioRegister::DriverFeaturesSel as usize];
+        match num::FromPrimitive::from_usize(offs) {
+            Some(VirtioRegister::DriverFeatures) => match driver_features_sel {
+                0 => self.driver_features = (self.driver_features & !0xffff_ffff) | value as u64,
+                1 => {
+                    self.driver_features =
+                        (self.driver_features & 0xffff_ffff) | (value as u64) << 32
+                }
+                _ => {}
+            },
+            Some(VirtioRegister::QueueNum) => {
+                if let Some(queue) = self.selected_queue() {
+                    queue.num = value.min(MAX_QUEUE_SIZE)
+                }
+            }
+            Some(VirtioRegister::QueueReady) => {
+                if let Some(queue) = self.selected_queue() {
+                    queue.ready = value & 1 == 1
+                }
+            }
+            Some(VirtioRegister::QueueDescLow) => {
+                if let Some(queue) = self.selected_queue() {
+                    queue.desc = (queue.desc & !0xffff_ffff) | value as u64
+                }
+            }
+            Some(VirtioRegister::QueueDescHigh) => {
+                if let Some(queue) = self.selected_queue() {
+                    queue.desc = (queue.desc & 0xffff_ffff) | (value as u64) << 32
+                }
+            }
+            Some(VirtioRegister::QueueDriverLow) => {
+                if let Some(queue) = self.selected_queue() {
+                    queue.driver = (queue.driver & !0xffff_ffff) | value as u64
+                }
+            }
+            Some(VirtioRegister::QueueDriverHigh) => {
+                if let Some(queue) = self.selected_queue() {
+                    queue.driver = (queue.driver & 0xffff_ffff) | (value as u64) << 32
+                }
+            }
+            Some(VirtioRegister::QueueDeviceLow) => {
+                if let Some(queue) = self.selected_queue() {
+                    queue.device = (queue.device & !0xffff_ffff) | value as u64
+                }
+            }
+            Some(VirtioRegister::QueueDeviceHigh) => {
+                if let Some(queue) = self.selected_queue() {
+                    queue.device = (queue.device & 0xffff_ffff) | (value as u64) << 32
+                }
+            }
+            Some(VirtioRegister::QueueNotify) => {
+                if self.device.is_some() {
+                    self.notifications.push((self.clock, value));
+                }
+            }
+            Some(VirtioRegister::InterruptACK) => {
+                self.interrupt_status &= !value;
+            }
+            // Writing zero to the status register resets the device
+            Some(VirtioRegister::Status) if value == 0 => self.reset(),
+            Some(VirtioRegister::Status) => {
+                let status = self.registers[VirtioRegister::Status as usize];
+                if value & !status & STATUS_DRIVER_OK != 0 {
+                    if let Some(device) = self.device.as_mut() {
+                        device.activate(self.driver_features);
+                    }
+                }
+            }
+            // Everything else is read-only
+            Some(VirtioRegister::MagicValue)
+            | Some(VirtioRegister::Version)
+            | Some(VirtioRegister::DeviceId)
+            | Some(VirtioRegister::VendorId)
+            | Some(VirtioRegister::DeviceFeatures)
+            | Some(VirtioRegister::QueueMax)
+            | Some(VirtioRegister::InterruptStatus)
+            | Some(VirtioRegister::ConfigGeneration) => return,
+            _ if offs >= VirtioRegister::Config as usize => {
+                if let Some(device) = self.device.as_mut() {
+                    device.write_config(offs - VirtioRegister::Config as usize, value);
+                }
+                return;
+            }
+            _ => {}
+        }
+        self.registers[offs] = value;
+    }
+
+    /// Offset of `addr` in the device configuration space, the only part of the
+    /// transport that may be accessed with other than 32-bit loads and stores
+    fn config_offset(&self, addr: VAddr) -> Option<usize> {
+        ((addr - self.range.start) as usize).checked_sub(VirtioRegister::Config as usize)
+    }
+
+    fn read_config<const N: usize>(&self, addr: VAddr) -> Result<[u8; N], TrapCause> {
+        let start = match self.config_offset(addr) {
+            Some(start) => start,
+            None => return Err(TrapCause::LoadAccessFault(addr)),
+        };
+        let config = self.device.as_ref().map_or(vec![], |d| d.config());
+        Ok(std::array::from_fn(|i| {
+            config.get(start + i).copied().unwrap_or(0)
+        }))
+    }
+
+    fn write_config(&mut self, addr: VAddr, value: u32) -> Option<TrapCause> {
+        let offset = match self.config_offset(addr) {
+            Some(offset) => offset,
+            None => return Some(TrapCause::StoreAccessFault(addr)),
+        };
+        if let Some(device) = self.device.as_mut() {
+            device.write_config(offset, value);
+        }
+        None
+    }
+
+    fn reset(&mut self) {
+        self.driver_features = 0;
+        self.interrupt_status = 0;
+        self.notifications.clear();
+        if let Some(device) = self.device.as_mut() {
+            device.reset();
+        }
+        for queue in self.queues.iter_mut() {
+            *queue = Virtqueue::default();
+        }
+        for reg in [
+            VirtioRegister::DeviceFeaturesSel,
+            VirtioRegister::DriverFeaturesSel,
+            VirtioRegister::QueueSel,
+            VirtioRegister::Status,
+        ] {
+            self.registers[reg as usize] = 0;
+        }
+    }
+}
+
+impl VirtualDevice for VIRTIO {
+    fn includes(&self, addr: VAddr) -> bool {
+        self.range.includes(addr)
+    }
+
+    fn name(&self) -> &str {
+        self.range.name
+    }
+    fn write(&mut self, addr: VAddr, value: u8) -> Option<TrapCause> {
+        self.write8(addr, value)
+    }
+
+    fn read(&mut self, addr: VAddr) -> Result<u8, TrapCause> {
+        self.read8(addr)
+    }
+}
+
+impl MemoryOperations<VIRTIO, u8> for VIRTIO {
+    fn read8(&mut self, addr: VAddr) -> Result<u8, TrapCause> {
+        self.read_config(addr).map(u8::from_le_bytes)
+    }
+
+    fn write8(&mut self, addr: VAddr, value: u8) -> Option<TrapCause> {
+        self.write_config(addr, value as u32)
+    }
+
+    fn read32(&mut self, addr: VAddr) -> Result<u32, TrapCause> {
+        Ok(self.load((addr - self.range.start) as usize))
+    }
+
+    fn write32(&mut self, addr: VAddr, value: u32) -> Option<TrapCause> {
+        self.store((addr - self.range.start) as usize, value);
+        None
+    }
+
+    fn read64(&mut self, addr: VAddr) -> Result<u64, TrapCause> {
+        self.read_config(addr).map(u64::from_le_bytes)
+    }
+
+    fn write64(&mut self, addr: VAddr, value: u64) -> Option<TrapCause> {
+        self.write_config(addr, value as u32)
+            .or_else(|| self.write_config(addr + 4, (value >> 32) as u32))
+    }
+
+    fn read16(&mut self, addr: VAddr) -> Result<u16, TrapCause> {
+        self.read_config(addr).map(u16::from_le_bytes)
+    }
+
+    fn write16(&mut self, addr: VAddr, value: u16) -> Option<TrapCause> {
+        self.write_config(addr, value as u32)
+    }
+}
@@ -0,0 +1,134 @@
+use std::collections::VecDeque;
+
+use crate::{cpu::TrapCause, mmio::PhysicalMemory, netdev::NetBackend};
+
+use super::{gather, VirtioDevice, Virtqueue};
+
+/// VIRTIO_NET_F_MAC, the device has a MAC address in its configuration space
+const VIRTIO_NET_F_MAC: u64 = 1 << 5;
+/// VIRTIO_NET_F_STATUS, the configuration space reports the link status
+const VIRTIO_NET_F_STATUS: u64 = 1 << 16;
+
+/// VIRTIO_NET_S_LINK_UP in `struct virtio_net_config.status`
+const VIRTIO_NET_S_LINK_UP: u16 = 1;
+
+/// Size of `struct virtio_net_hdr` with VIRTIO_F_VERSION_1, leading every frame
+const VIRTIO_NET_HDR_SIZE: usize = 12;
+
+const RECEIVEQ: usize = 0;
+const TRANSMITQ: usize = 1;
+
+/// Most frames from the host buffered until the guest takes them, later ones are dropped
+const MAX_PENDING_FRAMES: usize = 64;
+
+/// A virtio network card without any offloads, moving plain Ethernet frames between the
+/// guest and a `NetBackend`
+pub struct VirtioNet {
+    mac: [u8; 6],
+    backend: Box<dyn NetBackend>,
+    /// Frames from the host not yet handed to the guest
+    pending: VecDeque<Vec<u8>>,
+}
+
+impl VirtioNet {
+    /// Creates a new `VirtioNet`.
+    ///
+    /// # Arguments
+    /// * `mac` MAC address of the card
+    /// * `backend` host side of the link, see `netdev`
+    pub fn new(mac: [u8; 6], backend: Box<dyn NetBackend>) -> Self {
+        VirtioNet {
+            mac,
+            backend,
+            pending: VecDeque::new(),
+        }
+    }
+
+    /// Hands pending frames to the driver, each behind a `struct virtio_net_hdr`
+    fn deliver(
+        &mut self,
+        memory: &mut PhysicalMemory,
+        vq: &mut Virtqueue,
+    ) -> Result<bool, TrapCause> {
+        let mut used = false;
+        while let Some(frame) = self.pending.front() {
+            // No offloads, only num_buffers is set
+            let mut packet = vec![0; VIRTIO_NET_HDR_SIZE];
+            packet[10..12].copy_from_slice(&1u16.to_le_bytes());
+            packet.extend_from_slice(frame);
+            match vq.put(memory, &packet)? {
+                Some(_) => self.pending.pop_front(),
+                None => break,
+            };
+            used = true;
+        }
+        Ok(used)
+    }
+}
+
+impl VirtioDevice for VirtioNet {
+    fn device_id(&self) -> u32 {
+        1
+    }
+
+    fn features(&self) -> u64 {
+        VIRTIO_NET_F_MAC | VIRTIO_NET_F_STATUS
+    }
+
+    fn queues(&self) -> usize {
+        2
+    }
+
+    /// `struct virtio_net_config`, up to the link status
+    fn config(&self) -> Vec<u8> {
+        let mut config = self.mac.to_vec();
+        config.extend_from_slice(&VIRTIO_NET_S_LINK_UP.to_le_bytes());
+        config
+    }
+
+    fn process(
+        &mut self,
+        memory: &mut PhysicalMemory,
+        queue: usize,
+        queues: &mut [Virtqueue],
+    ) -> Result<bool, TrapCause> {
+        match queue {
+            RECEIVEQ => self.deliver(memory, &mut queues[RECEIVEQ]),
+            TRANSMITQ => {
+                let backend = &mut self.backend;
+                queues[TRANSMITQ].drain(memory, |memory, chain| {
+                    let packet = gather(memory, chain)?;
+                    if packet.len() > VIRTIO_NET_HDR_SIZE {
+                        if let Err(e) = backend.send(&packet[VIRTIO_NET_HDR_SIZE..]) {
+                            println!("virtio-net: frame not sent: {}", e);
+                        }
+                    }
+                    Ok(0)
+                })
+            }
+            _ => Ok(false),
+        }
+    }
+
+    fn poll(
+        &mut self,
+        memory: &mut PhysicalMemory,
+        queues: &mut [Virtqueue],
+    ) -> Result<bool, TrapCause> {
+        while self.pending.len() < MAX_PENDING_FRAMES {
+            match self.backend.recv() {
+                Ok(Some(frame)) => self.pending.push_back(frame),
+                Ok(None) => break,
+                Err(e) => {
+                    println!("virtio-net: frame not received: {}", e);
+                    break;
+                }
+            }
+        }
+        self.deliver(memory, &mut queues[RECEIVEQ])
+    }
+
+    fn reset(&mut self) {
+        self.pending.clear();
+    }
+}
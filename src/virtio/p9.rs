@@ -0,0 +1,786 @@
+use std::{
+    collections::HashMap,
+    ffi::CString,
+    fs::{self, File, Metadata, OpenOptions},
+    io::{self, Read, Seek, SeekFrom, Write},
+    os::unix::{
+        ffi::OsStrExt,
+        fs::{DirBuilderExt, FileTypeExt, MetadataExt, OpenOptionsExt, PermissionsExt},
+    },
+    path::{Path, PathBuf},
+};
+
+use crate::{cpu::TrapCause, mmio::PhysicalMemory};
+
+use super::{gather, scatter, VirtioDevice, VirtqDescriptor, Virtqueue};
+
+/// VIRTIO_9P_MOUNT_TAG, the configuration space holds the tag to mount the share by
+const VIRTIO_9P_MOUNT_TAG: u64 = 1 << 0;
+
+/// Largest message the device handles, clients negotiate down from it
+const MAX_MSIZE: u32 = 0x20000;
+
+/// size[4] type[1] tag[2]
+const HEADER_SIZE: usize = 7;
+
+const VERSION_9P2000_L: &str = "9P2000.L";
+
+/// Message types, a response is always its request plus one
+const TLERROR: u8 = 6;
+const TSTATFS: u8 = 8;
+const TLOPEN: u8 = 12;
+const TLCREATE: u8 = 14;
+const TSYMLINK: u8 = 16;
+const TRENAME: u8 = 20;
+const TREADLINK: u8 = 22;
+const TGETATTR: u8 = 24;
+const TSETATTR: u8 = 26;
+const TREADDIR: u8 = 40;
+const TFSYNC: u8 = 50;
+const TLOCK: u8 = 52;
+const TGETLOCK: u8 = 54;
+const TLINK: u8 = 70;
+const TMKDIR: u8 = 72;
+const TRENAMEAT: u8 = 74;
+const TUNLINKAT: u8 = 76;
+const TVERSION: u8 = 100;
+const TATTACH: u8 = 104;
+const TFLUSH: u8 = 108;
+const TWALK: u8 = 110;
+const TREAD: u8 = 116;
+const TWRITE: u8 = 118;
+const TCLUNK: u8 = 120;
+const TREMOVE: u8 = 122;
+
+/// Linux errno values, as carried by Rlerror
+const EIO: u32 = 5;
+const EBADF: u32 = 9;
+const EACCES: u32 = 13;
+const EEXIST: u32 = 17;
+const ENOTDIR: u32 = 20;
+const EINVAL: u32 = 22;
+const EOPNOTSUPP: u32 = 95;
+const EPROTO: u32 = 71;
+
+/// Bits of `qid.type`
+const QTDIR: u8 = 0x80;
+const QTSYMLINK: u8 = 0x02;
+const QTFILE: u8 = 0;
+
+/// Linux open flags of Tlopen and Tlcreate
+const O_ACCMODE: u32 = 0o3;
+const O_WRONLY: u32 = 0o1;
+const O_RDWR: u32 = 0o2;
+const O_TRUNC: u32 = 0o1000;
+const O_APPEND: u32 = 0o2000;
+
+/// Valid bits of Tsetattr
+const SETATTR_MODE: u32 = 0x1;
+const SETATTR_UID: u32 = 0x2;
+const SETATTR_GID: u32 = 0x4;
+const SETATTR_SIZE: u32 = 0x8;
+const SETATTR_ATIME: u32 = 0x10;
+const SETATTR_MTIME: u32 = 0x20;
+const SETATTR_ATIME_SET: u32 = 0x80;
+const SETATTR_MTIME_SET: u32 = 0x100;
+
+/// Every field of Rgetattr up to `blocks`, plus the times
+const GETATTR_BASIC: u64 = 0x7ff;
+
+/// Tunlinkat flag removing a directory
+const AT_REMOVEDIR: u32 = 0x200;
+
+/// `type` of Rgetlock telling no lock is in the way
+const F_UNLCK: u8 = 2;
+
+/// Magic number Rstatfs reports for the file system
+const V9FS_MAGIC: u32 = 0x0102_1997;
+
+/// A request or response is malformed, or a host call failed with an errno
+type Errno = u32;
+
+fn errno(e: io::Error) -> Errno {
+    e.raw_os_error().map_or(EIO, |e| e as Errno)
+}
+
+/// Reads the fields of a message body
+struct Fields<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Fields<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], Errno> {
+        if self.data.len() < len {
+            return Err(EPROTO);
+        }
+        let (bytes, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, Errno> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, Errno> {
+        Ok(u16::from_le_bytes(self.bytes(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32, Errno> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, Errno> {
+        Ok(u64::from_le_bytes(self.bytes(8)?.try_into().unwrap()))
+    }
+
+    fn string(&mut self) -> Result<String, Errno> {
+        let len = self.u16()? as usize;
+        String::from_utf8(self.bytes(len)?.to_vec()).map_err(|_| EINVAL)
+    }
+
+    /// A single path element, which can not leave its directory other than through ".."
+    fn name(&mut self) -> Result<String, Errno> {
+        let name = self.string()?;
+        match name.is_empty() || name.contains('/') || name == "." || name == ".." {
+            true => Err(EINVAL),
+            false => Ok(name),
+        }
+    }
+}
+
+/// Appends the fields of a message body
+trait Put {
+    fn put_string(&mut self, s: &[u8]);
+    fn put_qid(&mut self, qid: &[u8; 13]);
+}
+
+impl Put for Vec<u8> {
+    fn put_string(&mut self, s: &[u8]) {
+        self.extend_from_slice(&(s.len() as u16).to_le_bytes());
+        self.extend_from_slice(s);
+    }
+
+    fn put_qid(&mut self, qid: &[u8; 13]) {
+        self.extend_from_slice(qid);
+    }
+}
+
+/// The server's unique id of a file, type[1] version[4] path[8]
+fn qid(metadata: &Metadata) -> [u8; 13] {
+    let mut qid = [0; 13];
+    qid[0] = match metadata.file_type() {
+        t if t.is_dir() => QTDIR,
+        t if t.is_symlink() => QTSYMLINK,
+        _ => QTFILE,
+    };
+    qid[5..13].copy_from_slice(&metadata.ino().to_le_bytes());
+    qid
+}
+
+fn c_path(path: &Path) -> Result<CString, Errno> {
+    CString::new(path.as_os_str().as_bytes()).map_err(|_| EINVAL)
+}
+
+/// A file the client refers to by number
+struct Fid {
+    path: PathBuf,
+    /// Set once a regular file is opened
+    file: Option<File>,
+    /// Directory entries, listed when the client starts reading the directory
+    entries: Option<Vec<Vec<u8>>>,
+}
+
+/// A virtio 9P transport, serving a host directory over 9P2000.L.
+///
+/// Linux guests mount it with `mount -t 9p -o trans=virtio,version=9p2000.L <tag> <dir>`.
+/// Files are accessed with the permissions of the emulator. Symbolic links are resolved
+/// by the guest, the host refuses to follow them out of the shared directory.
+pub struct Virtio9p {
+    root: PathBuf,
+    tag: String,
+    msize: u32,
+    fids: HashMap<u32, Fid>,
+}
+
+impl Virtio9p {
+    /// Creates a new `Virtio9p`.
+    ///
+    /// # Arguments
+    /// * `root` host directory to share
+    /// * `tag` name the guest mounts the share by
+    pub fn new(root: &Path, tag: &str) -> io::Result<Self> {
+        let root = root.canonicalize()?;
+        if !root.is_dir() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "not a directory",
+            ));
+        }
+        Ok(Virtio9p {
+            root,
+            tag: tag.to_string(),
+            msize: MAX_MSIZE,
+            fids: HashMap::new(),
+        })
+    }
+
+    fn fid(&mut self, fid: u32) -> Result<&mut Fid, Errno> {
+        self.fids.get_mut(&fid).ok_or(EBADF)
+    }
+
+    /// The path of `fid`, see `confine`
+    fn path(&mut self, fid: u32) -> Result<PathBuf, Errno> {
+        let path = self.fid(fid)?.path.clone();
+        self.confine(path)
+    }
+
+    /// The path of `name` in the directory of `dfid`
+    fn child(&mut self, dfid: u32, name: &str) -> Result<PathBuf, Errno> {
+        let path = self.path(dfid)?.join(name);
+        self.confine(path)
+    }
+
+    /// Fails unless `path` is in the share once the host follows the symbolic links on
+    /// the way to its last component. The last component is never followed.
+    fn confine(&self, path: PathBuf) -> Result<PathBuf, Errno> {
+        let parent = match path.parent() {
+            Some(parent) if path != self.root => parent,
+            _ => return Ok(path),
+        };
+        match parent
+            .canonicalize()
+            .map_err(errno)?
+            .starts_with(&self.root)
+        {
+            true => Ok(path),
+            false => Err(EACCES),
+        }
+    }
+
+    /// Adds `fid` for `path`, which must not be in use
+    fn add_fid(&mut self, fid: u32, path: PathBuf) -> Result<(), Errno> {
+        if self.fids.contains_key(&fid) {
+            return Err(EEXIST);
+        }
+        self.fids.insert(
+            fid,
+            Fid {
+                path,
+                file: None,
+                entries: None,
+            },
+        );
+        Ok(())
+    }
+
+    /// Handles the request in `message`, returning the response
+    fn handle(&mut self, message: &[u8]) -> Vec<u8> {
+        if message.len() < HEADER_SIZE {
+            return vec![];
+        }
+        let kind = message[4];
+        let tag = &message[5..7];
+        let size = u32::from_le_bytes(message[0..4].try_into().unwrap()) as usize;
+        let body = &message[HEADER_SIZE..message.len().min(size.max(HEADER_SIZE))];
+        let (kind, body) = match self.dispatch(kind, Fields { data: body }) {
+            Ok(body) => (kind + 1, body),
+            Err(errno) => (TLERROR + 1, errno.to_le_bytes().to_vec()),
+        };
+        let mut response = ((HEADER_SIZE + body.len()) as u32).to_le_bytes().to_vec();
+        response.push(kind);
+        response.extend_from_slice(tag);
+        response.extend(body);
+        response
+    }
+
+    fn dispatch(&mut self, kind: u8, mut fields: Fields) -> Result<Vec<u8>, Errno> {
+        let mut body = vec![];
+        match kind {
+            TVERSION => {
+                let msize = fields.u32()?;
+                let version = fields.string()?;
+                self.fids.clear();
+                self.msize = msize.clamp(HEADER_SIZE as u32 + 4, MAX_MSIZE);
+                body.extend_from_slice(&self.msize.to_le_bytes());
+                match version.starts_with(VERSION_9P2000_L) {
+                    true => body.put_string(VERSION_9P2000_L.as_bytes()),
+                    false => body.put_string(b"unknown"),
+                }
+            }
+            TATTACH => {
+                let fid = fields.u32()?;
+                let root = self.root.clone();
+                let metadata = fs::metadata(&root).map_err(errno)?;
+                self.add_fid(fid, root)?;
+                body.put_qid(&qid(&metadata));
+            }
+            TFLUSH => {
+                // Requests complete before the next one is read, there is nothing to flush
+            }
+            TWALK => {
+                let fid = fields.u32()?;
+                let newfid = fields.u32()?;
+                let nwname = fields.u16()?;
+                let mut path = self.path(fid)?;
+                let mut qids = vec![];
+                for i in 0..nwname {
+                    let name = fields.string()?;
+                    let next = match name.as_str() {
+                        "" | "." => path.clone(),
+                        ".." if path == self.root => path.clone(),
+                        ".." => path.parent().unwrap().to_path_buf(),
+                        _ if name.contains('/') => return Err(EINVAL),
+                        _ => path.join(&name),
+                    };
+                    let metadata = self
+                        .confine(next.clone())
+                        .and_then(|next| fs::symlink_metadata(next).map_err(errno));
+                    match metadata {
+                        Ok(metadata) => qids.push(qid(&metadata)),
+                        Err(e) if i == 0 => return Err(e),
+                        Err(_) => break,
+                    }
+                    path = next;
+                }
+                if qids.len() == nwname as usize {
+                    if newfid != fid {
+                        self.add_fid(newfid, path)?;
+                    } else {
+                        let fid = self.fid(fid)?;
+                        fid.path = path;
+                        fid.file = None;
+                        fid.entries = None;
+                    }
+                }
+                body.extend_from_slice(&(qids.len() as u16).to_le_bytes());
+                for qid in qids.iter() {
+                    body.put_qid(qid);
+                }
+            }
+            TCLUNK => {
+                let fid = fields.u32()?;
+                self.fids.remove(&fid).ok_or(EBADF)?;
+            }
+            TREMOVE => {
+                let fid = fields.u32()?;
+                let fid = self.fids.remove(&fid).ok_or(EBADF)?;
+                let path = self.confine(fid.path)?;
+                match fs::symlink_metadata(&path).map_err(errno)?.is_dir() {
+                    true => fs::remove_dir(&path),
+                    false => fs::remove_file(&path),
+                }
+                .map_err(errno)?;
+            }
+            TLOPEN => {
+                let fid = fields.u32()?;
+                let flags = fields.u32()?;
+                let path = self.path(fid)?;
+                let fid = self.fid(fid)?;
+                let metadata = fs::symlink_metadata(&path).map_err(errno)?;
+                if !metadata.is_dir() {
+                    fid.file = Some(Virtio9p::open(&path, flags, None)?);
+                }
+                fid.entries = None;
+                body.put_qid(&qid(&metadata));
+                body.extend_from_slice(&0u32.to_le_bytes()); // iounit
+            }
+            TLCREATE => {
+                let fid = fields.u32()?;
+                let name = fields.name()?;
+                let flags = fields.u32()?;
+                let mode = fields.u32()?;
+                let path = self.child(fid, &name)?;
+                let fid = self.fid(fid)?;
+                let file = Virtio9p::open(&path, flags, Some(mode))?;
+                let metadata = file.metadata().map_err(errno)?;
+                fid.path = path;
+                fid.file = Some(file);
+                body.put_qid(&qid(&metadata));
+                body.extend_from_slice(&0u32.to_le_bytes()); // iounit
+            }
+            TREAD => {
+                let fid = fields.u32()?;
+                let offset = fields.u64()?;
+                let count = fields.u32()?.min(self.msize - HEADER_SIZE as u32 - 4);
+                let file = self.fid(fid)?.file.as_mut().ok_or(EBADF)?;
+                let mut data = vec![0; count as usize];
+                file.seek(SeekFrom::Start(offset)).map_err(errno)?;
+                let mut len = 0;
+                while len < data.len() {
+                    match file.read(&mut data[len..]).map_err(errno)? {
+                        0 => break,
+                        n => len += n,
+                    }
+                }
+                data.truncate(len);
+                body.extend_from_slice(&(len as u32).to_le_bytes());
+                body.extend(data);
+            }
+            TWRITE => {
+                let fid = fields.u32()?;
+                let offset = fields.u64()?;
+                let count = fields.u32()?;
+                let data = fields.bytes(count as usize)?;
+                let file = self.fid(fid)?.file.as_mut().ok_or(EBADF)?;
+                file.seek(SeekFrom::Start(offset)).map_err(errno)?;
+                file.write_all(data).map_err(errno)?;
+                body.extend_from_slice(&count.to_le_bytes());
+            }
+            TREADDIR => {
+                let fid = fields.u32()?;
+                let offset = fields.u64()?;
+                let count = fields.u32()?.min(self.msize - HEADER_SIZE as u32 - 4);
+                let root = self.root.clone();
+                let path = self.path(fid)?;
+                let fid = self.fid(fid)?;
+                if offset == 0 || fid.entries.is_none() {
+                    fid.entries = Some(Virtio9p::list(&root, &path)?);
+                }
+                let mut data = vec![];
+                for entry in fid.entries.as_ref().unwrap().iter().skip(offset as usize) {
+                    if data.len() + entry.len() > count as usize {
+                        break;
+                    }
+                    data.extend_from_slice(entry);
+                }
+                body.extend_from_slice(&(data.len() as u32).to_le_bytes());
+                body.extend(data);
+            }
+            TGETATTR => {
+                let fid = fields.u32()?;
+                let metadata = fs::symlink_metadata(self.path(fid)?).map_err(errno)?;
+                body.extend_from_slice(&GETATTR_BASIC.to_le_bytes());
+                body.put_qid(&qid(&metadata));
+                body.extend_from_slice(&metadata.mode().to_le_bytes());
+                body.extend_from_slice(&metadata.uid().to_le_bytes());
+                body.extend_from_slice(&metadata.gid().to_le_bytes());
+                for value in [
+                    metadata.nlink(),
+                    metadata.rdev(),
+                    metadata.size(),
+                    metadata.blksize(),
+                    metadata.blocks(),
+                    metadata.atime() as u64,
+                    metadata.atime_nsec() as u64,
+                    metadata.mtime() as u64,
+                    metadata.mtime_nsec() as u64,
+                    metadata.ctime() as u64,
+                    metadata.ctime_nsec() as u64,
+                    0, // btime_sec
+                    0, // btime_nsec
+                    0, // gen
+                    0, // data_version
+                ] {
+                    body.extend_from_slice(&value.to_le_bytes());
+                }
+            }
+            TSETATTR => {
+                let fid = fields.u32()?;
+                let valid = fields.u32()?;
+                let mode = fields.u32()?;
+                let uid = fields.u32()?;
+                let gid = fields.u32()?;
+                let size = fields.u64()?;
+                let times = [fields.u64()?, fields.u64()?, fields.u64()?, fields.u64()?];
+                let path = self.path(fid)?;
+                Virtio9p::setattr(&path, valid, mode, uid, gid, size, times)?;
+            }
+            TSTATFS => {
+                let fid = fields.u32()?;
+                let path = c_path(&self.path(fid)?)?;
+                let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+                if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } != 0 {
+                    return Err(errno(io::Error::last_os_error()));
+                }
+                body.extend_from_slice(&V9FS_MAGIC.to_le_bytes());
+                body.extend_from_slice(&(stat.f_bsize as u32).to_le_bytes());
+                for value in [
+                    stat.f_blocks,
+                    stat.f_bfree,
+                    stat.f_bavail,
+                    stat.f_files,
+                    stat.f_ffree,
+                    stat.f_fsid,
+                ] {
+                    body.extend_from_slice(&value.to_le_bytes());
+                }
+                body.extend_from_slice(&(stat.f_namemax as u32).to_le_bytes());
+            }
+            TMKDIR => {
+                let dfid = fields.u32()?;
+                let name = fields.name()?;
+                let mode = fields.u32()?;
+                let path = self.child(dfid, &name)?;
+                fs::DirBuilder::new()
+                    .mode(mode & 0o7777)
+                    .create(&path)
+                    .map_err(errno)?;
+                body.put_qid(&qid(&fs::symlink_metadata(&path).map_err(errno)?));
+            }
+            TSYMLINK => {
+                let dfid = fields.u32()?;
+                let name = fields.name()?;
+                let target = fields.string()?;
+                let path = self.child(dfid, &name)?;
+                std::os::unix::fs::symlink(target, &path).map_err(errno)?;
+                body.put_qid(&qid(&fs::symlink_metadata(&path).map_err(errno)?));
+            }
+            TREADLINK => {
+                let fid = fields.u32()?;
+                let target = fs::read_link(self.path(fid)?).map_err(errno)?;
+                body.put_string(target.as_os_str().as_bytes());
+            }
+            TLINK => {
+                let dfid = fields.u32()?;
+                let fid = fields.u32()?;
+                let name = fields.name()?;
+                let target = self.path(fid)?;
+                fs::hard_link(target, self.child(dfid, &name)?).map_err(errno)?;
+            }
+            TRENAME => {
+                let fid = fields.u32()?;
+                let dfid = fields.u32()?;
+                let name = fields.name()?;
+                let to = self.child(dfid, &name)?;
+                fs::rename(self.path(fid)?, &to).map_err(errno)?;
+                self.fid(fid)?.path = to;
+            }
+            TRENAMEAT => {
+                let olddirfid = fields.u32()?;
+                let oldname = fields.name()?;
+                let newdirfid = fields.u32()?;
+                let newname = fields.name()?;
+                let from = self.child(olddirfid, &oldname)?;
+                fs::rename(from, self.child(newdirfid, &newname)?).map_err(errno)?;
+            }
+            TUNLINKAT => {
+                let dirfid = fields.u32()?;
+                let name = fields.name()?;
+                let flags = fields.u32()?;
+                let path = self.child(dirfid, &name)?;
+                match flags & AT_REMOVEDIR {
+                    0 => fs::remove_file(path),
+                    _ => fs::remove_dir(path),
+                }
+                .map_err(errno)?;
+            }
+            TFSYNC => {
+                let fid = fields.u32()?;
+                if let Some(file) = self.fid(fid)?.file.as_mut() {
+                    file.sync_all().map_err(errno)?;
+                }
+            }
+            TLOCK => {
+                // Only this guest uses the share, its own kernel sorts out conflicts
+                let fid = fields.u32()?;
+                self.fid(fid)?;
+                body.push(0); // P9_LOCK_SUCCESS
+            }
+            TGETLOCK => {
+                let fid = fields.u32()?;
+                self.fid(fid)?;
+                fields.u8()?;
+                body.push(F_UNLCK);
+                body.extend_from_slice(fields.bytes(8 + 8 + 4)?);
+                body.put_string(fields.string()?.as_bytes());
+            }
+            // Extended attributes, device nodes and the 9P2000 messages Linux does not send
+            _ => return Err(EOPNOTSUPP),
+        }
+        Ok(body)
+    }
+
+    /// Opens `path` with the Linux open `flags` of Tlopen and Tlcreate, creating it with
+    /// `mode` if given
+    fn open(path: &Path, flags: u32, mode: Option<u32>) -> Result<File, Errno> {
+        let mut options = OpenOptions::new();
+        match flags & O_ACCMODE {
+            O_WRONLY => options.write(true),
+            O_RDWR => options.read(true).write(true),
+            _ => options.read(true),
+        };
+        if flags & O_TRUNC != 0 {
+            options.write(true).truncate(true);
+        }
+        if flags & O_APPEND != 0 {
+            options.append(true);
+        }
+        if let Some(mode) = mode {
+            options.write(true).create_new(true).mode(mode & 0o7777);
+        }
+        options.custom_flags(libc::O_NOFOLLOW);
+        options.open(path).map_err(errno)
+    }
+
+    /// The Treaddir entries of the directory at `path`, starting with "." and ".."
+    fn list(root: &Path, path: &Path) -> Result<Vec<Vec<u8>>, Errno> {
+        let metadata = fs::symlink_metadata(path).map_err(errno)?;
+        if !metadata.is_dir() {
+            return Err(ENOTDIR);
+        }
+        let parent = match path == root {
+            true => path,
+            false => path.parent().unwrap(),
+        };
+        let mut names = vec![
+            (b".".to_vec(), metadata),
+            (b"..".to_vec(), fs::metadata(parent).map_err(errno)?),
+        ];
+        let mut children = vec![];
+        for entry in fs::read_dir(path).map_err(errno)? {
+            let entry = entry.map_err(errno)?;
+            if let Ok(metadata) = entry.metadata() {
+                children.push((entry.file_name().as_bytes().to_vec(), metadata));
+            }
+        }
+        children.sort_by(|a, b| a.0.cmp(&b.0));
+        names.extend(children);
+
+        // qid[13] offset[8] type[1] name[s], the offset being that of the next entry
+        Ok(names
+            .iter()
+            .enumerate()
+            .map(|(i, (name, metadata))| {
+                let mut entry = vec![];
+                entry.put_qid(&qid(metadata));
+                entry.extend_from_slice(&(i as u64 + 1).to_le_bytes());
+                let file_type = metadata.file_type();
+                entry.push(match file_type {
+                    t if t.is_dir() => libc::DT_DIR,
+                    t if t.is_symlink() => libc::DT_LNK,
+                    t if t.is_file() => libc::DT_REG,
+                    t if t.is_fifo() => libc::DT_FIFO,
+                    t if t.is_socket() => libc::DT_SOCK,
+                    t if t.is_char_device() => libc::DT_CHR,
+                    t if t.is_block_device() => libc::DT_BLK,
+                    _ => libc::DT_UNKNOWN,
+                });
+                entry.put_string(name);
+                entry
+            })
+            .collect())
+    }
+
+    /// Applies the attributes of Tsetattr flagged in `valid`. `times` are the access and
+    /// modification time, each in seconds and nanoseconds.
+    fn setattr(
+        path: &Path,
+        valid: u32,
+        mode: u32,
+        uid: u32,
+        gid: u32,
+        size: u64,
+        times: [u64; 4],
+    ) -> Result<(), Errno> {
+        if valid & SETATTR_MODE != 0 {
+            // chmod follows symbolic links, and their own mode cannot be changed
+            if fs::symlink_metadata(path).map_err(errno)?.is_symlink() {
+                return Err(EOPNOTSUPP);
+            }
+            fs::set_permissions(path, fs::Permissions::from_mode(mode & 0o7777)).map_err(errno)?;
+        }
+        if valid & (SETATTR_UID | SETATTR_GID) != 0 {
+            let uid = match valid & SETATTR_UID {
+                0 => u32::MAX,
+                _ => uid,
+            };
+            let gid = match valid & SETATTR_GID {
+                0 => u32::MAX,
+                _ => gid,
+            };
+            let path = c_path(path)?;
+            if unsafe { libc::lchown(path.as_ptr(), uid, gid) } != 0 {
+                return Err(errno(io::Error::last_os_error()));
+            }
+        }
+        if valid & SETATTR_SIZE != 0 {
+            let file = OpenOptions::new()
+                .write(true)
+                .custom_flags(libc::O_NOFOLLOW)
+                .open(path)
+                .map_err(errno)?;
+            file.set_len(size).map_err(errno)?;
+        }
+        if valid & (SETATTR_ATIME | SETATTR_MTIME) != 0 {
+            let time = |set, given, sec, nsec| match (valid & set != 0, valid & given != 0) {
+                (false, _) => libc::timespec {
+                    tv_sec: 0,
+                    tv_nsec: libc::UTIME_OMIT,
+                },
+                (true, false) => libc::timespec {
+                    tv_sec: 0,
+                    tv_nsec: libc::UTIME_NOW,
+                },
+                (true, true) => libc::timespec {
+                    tv_sec: sec as libc::time_t,
+                    tv_nsec: nsec as libc::c_long,
+                },
+            };
+            let times = [
+                time(SETATTR_ATIME, SETATTR_ATIME_SET, times[0], times[1]),
+                time(SETATTR_MTIME, SETATTR_MTIME_SET, times[2], times[3]),
+            ];
+            let path = c_path(path)?;
+            let result = unsafe {
+                libc::utimensat(
+                    libc::AT_FDCWD,
+                    path.as_ptr(),
+                    times.as_ptr(),
+                    libc::AT_SYMLINK_NOFOLLOW,
+                )
+            };
+            if result != 0 {
+                return Err(errno(io::Error::last_os_error()));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl VirtioDevice for Virtio9p {
+    fn device_id(&self) -> u32 {
+        9
+    }
+
+    fn features(&self) -> u64 {
+        VIRTIO_9P_MOUNT_TAG
+    }
+
+    fn queues(&self) -> usize {
+        1
+    }
+
+    /// `struct virtio_9p_config`, the tag length and the tag
+    fn config(&self) -> Vec<u8> {
+        let mut config = vec![];
+        config.put_string(self.tag.as_bytes());
+        config
+    }
+
+    fn process(
+        &mut self,
+        memory: &mut PhysicalMemory,
+        queue: usize,
+        queues: &mut [Virtqueue],
+    ) -> Result<bool, TrapCause> {
+        queues[queue].drain(memory, |memory, chain| {
+            let (readable, writable): (Vec<VirtqDescriptor>, Vec<VirtqDescriptor>) =
+                chain.iter().partition(|d| !d.device_writable());
+            let request = gather(memory, &readable)?;
+            let response = self.handle(&request);
+            let capacity: usize = writable.iter().map(|d| d.len as usize).sum();
+            if response.len() > capacity {
+                println!("virtio-9p: response of {} bytes dropped", response.len());
+                return Ok(0);
+            }
+            scatter(memory, &writable, &response)?;
+            Ok(response.len() as u32)
+        })
+    }
+
+    fn reset(&mut self) {
+        self.msize = MAX_MSIZE;
+        self.fids.clear();
+    }
+}
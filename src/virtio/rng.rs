@@ -0,0 +1,98 @@
+use std::{
+    fs::File,
+    io::{self, Read},
+};
+
+use crate::{cpu::TrapCause, mmio::PhysicalMemory};
+
+use super::{scatter, VirtioDevice, VirtqDescriptor, Virtqueue};
+
+enum Source {
+    /// The host's random device
+    Host(File),
+    /// SplitMix64 state
+    Seeded(u64),
+}
+
+/// A virtio entropy device, filling every buffer the driver hands it with random bytes.
+///
+/// Attached to one of the `virtio_mmio` nodes of the device tree, Linux picks it up as
+/// `/dev/hwrng` and feeds its entropy pool from it at boot.
+pub struct VirtioRng {
+    source: Source,
+}
+
+impl VirtioRng {
+    /// Creates a new `VirtioRng` reading from the host's `/dev/urandom`
+    pub fn host() -> io::Result<Self> {
+        Ok(VirtioRng {
+            source: Source::Host(File::open("/dev/urandom")?),
+        })
+    }
+
+    /// Creates a new `VirtioRng` producing the same bytes on every run with the same `seed`
+    pub fn seeded(seed: u64) -> Self {
+        VirtioRng {
+            source: Source::Seeded(seed),
+        }
+    }
+
+    fn fill(&mut self, buf: &mut [u8]) -> io::Result<()> {
+        match &mut self.source {
+            Source::Host(file) => file.read_exact(buf),
+            Source::Seeded(state) => {
+                for chunk in buf.chunks_mut(8) {
+                    *state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+                    let mut z = *state;
+                    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+                    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+                    z ^= z >> 31;
+                    chunk.copy_from_slice(&z.to_le_bytes()[..chunk.len()]);
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl VirtioDevice for VirtioRng {
+    fn device_id(&self) -> u32 {
+        4
+    }
+
+    fn features(&self) -> u64 {
+        0
+    }
+
+    fn queues(&self) -> usize {
+        1
+    }
+
+    fn config(&self) -> Vec<u8> {
+        vec![]
+    }
+
+    fn process(
+        &mut self,
+        memory: &mut PhysicalMemory,
+        queue: usize,
+        queues: &mut [Virtqueue],
+    ) -> Result<bool, TrapCause> {
+        queues[queue].drain(memory, |memory, chain| {
+            let writable: Vec<VirtqDescriptor> = chain
+                .iter()
+                .filter(|d| d.device_writable())
+                .copied()
+                .collect();
+            let len = writable.iter().map(|d| d.len as usize).sum();
+            let mut bytes = vec![0; len];
+            if let Err(e) = self.fill(&mut bytes) {
+                // Nothing written, the driver asks again
+                println!("virtio-rng: no entropy: {}", e);
+                return Ok(0);
+            }
+            scatter(memory, &writable, &bytes)?;
+            Ok(len as u32)
+        })
+    }
+}
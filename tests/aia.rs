@@ -0,0 +1,244 @@
+mod common;
+
+use common::{run, VBASE};
+use rriscv::{
+    aia::{
+        APLIC, APLIC_M_BASE, APLIC_SIZE, APLIC_S_BASE, IMSIC_M_BASE, IMSIC_PAGE_SIZE, IMSIC_S_BASE,
+    },
+    cpu::{CSRRegister, Core, MipMask, PrivMode, TrapCause},
+    memory::MemoryOperations,
+    mmu::MMU,
+};
+
+const A0: u8 = 10;
+
+const MEIP: u64 = MipMask::MEIP as u64;
+const SEIP: u64 = MipMask::SEIP as u64;
+
+const UART_IER: u64 = 0x1000_0001;
+const UART_IRQ: u64 = 10;
+
+// APLIC registers, as offsets from the base of a domain
+const DOMAINCFG: u64 = 0x0000;
+const MSIADDRCFG_S: u64 = 0x1bc8;
+const SETIE: u64 = 0x1e00;
+const SETIENUM: u64 = 0x1edc;
+const GENMSI: u64 = 0x3000;
+const IDELIVERY: u64 = 0x4000;
+const IFORCE: u64 = 0x4004;
+const TOPI: u64 = 0x4018;
+const CLAIMI: u64 = 0x401c;
+
+const IE: u32 = 1 << 8;
+const DM: u32 = 1 << 2;
+const DELEGATE: u32 = 1 << 10;
+const EDGE1: u32 = 4;
+const LEVEL1: u32 = 6;
+
+fn sourcecfg(source: u64) -> u64 {
+    4 * source
+}
+
+fn target(source: u64) -> u64 {
+    0x3000 + 4 * source
+}
+
+const PROGRAM: [u32; 2] = [
+    0x35c02573, // csrr a0, mtopei
+    0x351025f3, // csrr a1, mireg
+];
+
+fn setup(mmu: &mut MMU) -> Core {
+    common::setup(mmu, &PROGRAM)
+}
+
+fn assert_illegal(core: &Core, pc: u64) {
+    assert_eq!(
+        core.read_csr(CSRRegister::mcause),
+        u16::from(TrapCause::IllegalInstruction(0)) as u64
+    );
+    assert_eq!(core.read_csr(CSRRegister::mepc), pc);
+}
+
+#[test]
+pub fn aia_csrs_need_the_imsic() {
+    let mmu = &mut MMU::create();
+    let core = &mut setup(mmu);
+    run(core, mmu, 1);
+    assert_illegal(core, VBASE);
+
+    // With the AIA, the odd eip registers still do not exist on RV64
+    mmu.select_aia();
+    let core = &mut setup(mmu);
+    core.write_csr(CSRRegister::miselect, 0x81);
+    run(core, mmu, 2);
+    assert_eq!(core.read_register(A0), 0);
+    assert_illegal(core, VBASE + 4);
+}
+
+#[test]
+pub fn device_tree_describes_the_aia() {
+    let mmu = &mut MMU::create();
+    let ranges = |mmu: &MMU, name| {
+        mmu.device_table()
+            .iter()
+            .filter(|range| range.name == name)
+            .map(|range| (range.start, range.end))
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(ranges(mmu, "interrupt-controller").len(), 1);
+    assert!(ranges(mmu, "aplic").is_empty());
+
+    mmu.select_aia();
+    assert!(ranges(mmu, "interrupt-controller").is_empty());
+    assert_eq!(
+        ranges(mmu, "aplic"),
+        vec![
+            (APLIC_M_BASE, APLIC_M_BASE + APLIC_SIZE),
+            (APLIC_S_BASE, APLIC_S_BASE + APLIC_SIZE)
+        ]
+    );
+    assert_eq!(
+        ranges(mmu, "imsics"),
+        vec![
+            (IMSIC_M_BASE, IMSIC_M_BASE + IMSIC_PAGE_SIZE),
+            (IMSIC_S_BASE, IMSIC_S_BASE + IMSIC_PAGE_SIZE)
+        ]
+    );
+    // The devices keep their sources, now on the APLIC
+    let uart = mmu.device_table().iter().find(|range| range.name == "uart");
+    assert_eq!(uart.unwrap().interrupt, Some(UART_IRQ as u32));
+}
+
+#[test]
+pub fn imsic_interrupt_files() {
+    let mmu = &mut MMU::create();
+    mmu.select_aia();
+    let core = &mut setup(mmu);
+    core.write_csr(CSRRegister::mie, MEIP | SEIP);
+    mmu.write32(IMSIC_M_BASE, 3);
+    mmu.write32(IMSIC_M_BASE, 40);
+    mmu.write32(IMSIC_S_BASE, 5);
+    core.cycle(mmu);
+
+    core.write_csr(CSRRegister::miselect, 0x80);
+    assert_eq!(core.read_csr(CSRRegister::mireg), 1 << 40 | 1 << 3);
+    core.write_csr(CSRRegister::miselect, 0xc0);
+    core.write_csr(CSRRegister::mireg, u64::MAX);
+    assert_eq!(
+        core.read_csr(CSRRegister::mireg),
+        !1,
+        "identity 0 does not exist"
+    );
+    assert_eq!(core.read_csr(CSRRegister::mtopei), 3 << 16 | 3);
+    assert_eq!(
+        core.read_csr(CSRRegister::mip) & MEIP,
+        0,
+        "eidelivery is off"
+    );
+
+    core.write_csr(CSRRegister::miselect, 0x70);
+    core.write_csr(CSRRegister::mireg, 1);
+    assert_eq!(core.read_csr(CSRRegister::mip) & MEIP, MEIP);
+    assert_eq!(core.read_csr(CSRRegister::mtopi), 11 << 16 | 1);
+
+    // Identities at or above eithreshold are masked
+    core.write_csr(CSRRegister::miselect, 0x72);
+    core.write_csr(CSRRegister::mireg, 3);
+    assert_eq!(core.read_csr(CSRRegister::mtopei), 0);
+    assert_eq!(core.read_csr(CSRRegister::mip) & MEIP, 0);
+    core.write_csr(CSRRegister::mireg, 0);
+    core.write_csr(CSRRegister::mtopei, 0);
+    assert_eq!(core.read_csr(CSRRegister::mtopei), 40 << 16 | 40);
+    core.write_csr(CSRRegister::mtopei, 0);
+    assert_eq!(core.read_csr(CSRRegister::mtopei), 0);
+    assert_eq!(core.read_csr(CSRRegister::mip) & MEIP, 0);
+
+    // The S-level file raises SEIP
+    core.write_csr(CSRRegister::siselect, 0x70);
+    core.write_csr(CSRRegister::sireg, 1);
+    core.write_csr(CSRRegister::siselect, 0xc0);
+    core.write_csr(CSRRegister::sireg, 1 << 5);
+    assert_eq!(core.read_csr(CSRRegister::stopei), 5 << 16 | 5);
+    assert_eq!(core.read_csr(CSRRegister::mip) & SEIP, SEIP);
+    core.write_csr(CSRRegister::mideleg, SEIP);
+    assert_eq!(core.read_csr(CSRRegister::stopi), 9 << 16 | 1);
+    assert_eq!(core.read_csr(CSRRegister::mtopi), 0);
+}
+
+#[test]
+pub fn aplic_direct_delivery() {
+    let mut aplic = APLIC::create(1);
+    let m = |offset| APLIC_M_BASE + offset;
+    let s = |offset| APLIC_S_BASE + offset;
+    aplic.write32(m(DOMAINCFG), IE);
+    assert_eq!(aplic.read32(m(DOMAINCFG)).unwrap(), 0x8000_0000 | IE);
+    aplic.write32(m(sourcecfg(10)), LEVEL1);
+    aplic.write32(m(target(10)), 0);
+    assert_eq!(aplic.read32(m(target(10))).unwrap(), 1, "priority 0 is 1");
+    aplic.write32(m(SETIENUM), 10);
+    aplic.write32(m(IDELIVERY), 1);
+
+    // A level-sensitive source stays pending while its input is high
+    aplic.tick(&[(10, true)]);
+    assert_eq!(aplic.update_mip(0, 0), MEIP);
+    assert_eq!(aplic.read32(m(TOPI)).unwrap(), 10 << 16 | 1);
+    assert_eq!(aplic.read32(m(CLAIMI)).unwrap(), 10 << 16 | 1);
+    assert_eq!(aplic.read32(m(TOPI)).unwrap(), 10 << 16 | 1);
+    aplic.tick(&[(10, false)]);
+    assert_eq!(aplic.update_mip(0, MEIP), 0);
+
+    // Source 3 is delegated to the S-level domain, and inactive in the root
+    aplic.write32(m(sourcecfg(3)), DELEGATE | LEVEL1);
+    assert_eq!(aplic.read32(m(sourcecfg(3))).unwrap(), DELEGATE);
+    aplic.write32(m(SETIENUM), 3);
+    assert_eq!(aplic.read32(m(SETIE)).unwrap(), 1 << 10);
+    aplic.write32(s(DOMAINCFG), IE);
+    aplic.write32(s(sourcecfg(3)), EDGE1);
+    aplic.write32(s(target(3)), 2);
+    aplic.write32(s(SETIENUM), 3);
+    aplic.write32(s(IDELIVERY), 1);
+    aplic.tick(&[(3, true)]);
+    assert_eq!(aplic.update_mip(0, 0), SEIP);
+    assert_eq!(aplic.read32(s(CLAIMI)).unwrap(), 3 << 16 | 2);
+    aplic.tick(&[(3, true)]);
+    assert_eq!(aplic.update_mip(0, SEIP), 0, "edges pend once");
+
+    aplic.write32(m(IFORCE), 1);
+    aplic.tick(&[]);
+    assert_eq!(aplic.update_mip(0, 0), MEIP);
+    assert_eq!(aplic.read32(m(CLAIMI)).unwrap(), 0);
+    assert_eq!(aplic.read32(m(IFORCE)).unwrap(), 0);
+}
+
+#[test]
+pub fn aplic_msi_delivery() {
+    let mmu = &mut MMU::create();
+    mmu.select_aia();
+    let s = |offset| APLIC_S_BASE + offset;
+    mmu.write32(APLIC_M_BASE + sourcecfg(UART_IRQ), DELEGATE);
+    mmu.write32(s(DOMAINCFG), IE | DM);
+    mmu.write32(s(sourcecfg(UART_IRQ)), LEVEL1);
+    mmu.write32(s(target(UART_IRQ)), 7);
+    mmu.write32(s(SETIENUM), UART_IRQ as u32);
+
+    // The UART's THR empty interrupt is sent once, while it stays high
+    mmu.write8(UART_IER, 2);
+    mmu.tick(0);
+    assert_eq!(mmu.take_msis(0), vec![(PrivMode::Supervisor, 7)]);
+    mmu.tick(0);
+    assert!(mmu.take_msis(0).is_empty());
+    mmu.write8(UART_IER, 0);
+    mmu.tick(0);
+    mmu.write8(UART_IER, 2);
+    mmu.tick(0);
+    assert_eq!(mmu.take_msis(0), vec![(PrivMode::Supervisor, 7)]);
+
+    // MSIs can go anywhere in memory
+    mmu.write32(s(GENMSI), 9);
+    mmu.write32(APLIC_M_BASE + MSIADDRCFG_S, ((VBASE + 0x1000) >> 12) as u32);
+    mmu.write32(s(GENMSI), 11);
+    mmu.tick(0);
+    assert_eq!(mmu.take_msis(0), vec![(PrivMode::Supervisor, 9)]);
+    assert_eq!(mmu.read32(VBASE + 0x1000).unwrap(), 11);
+}
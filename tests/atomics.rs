@@ -0,0 +1,195 @@
+mod common;
+
+use common::run;
+use rriscv::{
+    cpu::{CSRRegister, Core, TrapCause},
+    memory::MemoryOperations,
+    mmu::MMU,
+    pmp::PmpCfg,
+};
+
+const DATA: u64 = 0x8000_1000;
+
+const A0: u8 = 10;
+const A1: u8 = 11;
+const A2: u8 = 12;
+const A3: u8 = 13;
+const A4: u8 = 14;
+
+/// Loads `program` at VBASE and returns a core ready to execute it, with a1 pointing
+/// at DATA
+fn setup(mmu: &mut MMU, program: &[u32]) -> Core {
+    let mut core = common::setup(mmu, program);
+    core.write_register(A1, DATA);
+    core
+}
+
+#[test]
+pub fn lr_sc_w() {
+    let mmu = &mut MMU::create();
+    let core = &mut setup(
+        mmu,
+        &[
+            0x1005a52f, // lr.w a0, (a1)
+            0x18d5a62f, // sc.w a2, a3, (a1)
+            0x18d5a62f, // sc.w a2, a3, (a1)
+        ],
+    );
+    mmu.write32(DATA, 0xfffffffe);
+    core.write_register(A3, 0x1234);
+
+    run(core, mmu, 1);
+    assert_eq!(core.read_register(A0), 0xfffffffffffffffe);
+
+    run(core, mmu, 1);
+    assert_eq!(
+        core.read_register(A2),
+        0,
+        "SC.W with reservation should succeed"
+    );
+    assert_eq!(mmu.read32(DATA).unwrap(), 0x1234);
+
+    core.write_register(A3, 0x5678);
+    run(core, mmu, 1);
+    assert_eq!(
+        core.read_register(A2),
+        1,
+        "SC.W without reservation should fail"
+    );
+    assert_eq!(mmu.read32(DATA).unwrap(), 0x1234);
+}
+
+#[test]
+pub fn sc_d_fails_after_store() {
+    let mmu = &mut MMU::create();
+    let core = &mut setup(
+        mmu,
+        &[
+            0x1005b52f, // lr.d a0, (a1)
+            0x00d5b023, // sd a3, 0(a1)
+            0x18d5b62f, // sc.d a2, a3, (a1)
+        ],
+    );
+    core.write_register(A3, 0x1122334455667788);
+
+    run(core, mmu, 3);
+    assert_eq!(
+        core.read_register(A2),
+        1,
+        "store should invalidate the reservation"
+    );
+}
+
+#[test]
+pub fn amo_w() {
+    let mmu = &mut MMU::create();
+    let core = &mut setup(
+        mmu,
+        &[
+            0x00d5a72f, // amoadd.w a4, a3, (a1)
+            0x80d5a72f, // amomin.w a4, a3, (a1)
+            0xe0d5a72f, // amomaxu.w a4, a3, (a1)
+        ],
+    );
+    mmu.write32(DATA, 0xfffffff0);
+    core.write_register(A3, 1);
+
+    run(core, mmu, 1);
+    assert_eq!(core.read_register(A4), 0xfffffffffffffff0);
+    assert_eq!(mmu.read32(DATA).unwrap(), 0xfffffff1);
+
+    run(core, mmu, 1);
+    assert_eq!(core.read_register(A4), 0xfffffffffffffff1);
+    assert_eq!(mmu.read32(DATA).unwrap(), 0xfffffff1);
+
+    run(core, mmu, 1);
+    assert_eq!(mmu.read32(DATA).unwrap(), 0xfffffff1);
+}
+
+#[test]
+pub fn amo_d() {
+    let mmu = &mut MMU::create();
+    let core = &mut setup(
+        mmu,
+        &[
+            0x08d5b72f, // amoswap.d a4, a3, (a1)
+            0xa0d5b72f, // amomax.d a4, a3, (a1)
+            0x60d5b72f, // amoand.d a4, a3, (a1)
+        ],
+    );
+    mmu.write32(DATA, 0x89abcdef);
+    mmu.write32(DATA + 4, 0x01234567);
+    core.write_register(A3, 0xffffffff00000001);
+
+    run(core, mmu, 1);
+    assert_eq!(core.read_register(A4), 0x0123456789abcdef);
+    assert_eq!(mmu.read64(DATA).unwrap(), 0xffffffff00000001);
+
+    core.write_register(A3, 0x10);
+    run(core, mmu, 1);
+    assert_eq!(mmu.read64(DATA).unwrap(), 0x10);
+
+    core.write_register(A3, 0x18);
+    run(core, mmu, 1);
+    assert_eq!(core.read_register(A4), 0x10);
+    assert_eq!(mmu.read64(DATA).unwrap(), 0x10);
+}
+
+/// Covers each half of the doubleword at DATA with its own locked NA4 PMP entry, the
+/// upper one with the permissions `upper`
+fn split_pmp(core: &mut Core, upper: u8) {
+    core.write_csr(CSRRegister::pmpaddr0, DATA >> 2);
+    core.write_csr(CSRRegister::pmpaddr1, (DATA + 4) >> 2);
+    let lower = PmpCfg::NA4 | PmpCfg::L | PmpCfg::R | PmpCfg::W;
+    let upper = PmpCfg::NA4 | PmpCfg::L | upper;
+    core.write_csr(CSRRegister::pmpcfg0, (upper as u64) << 8 | lower as u64);
+}
+
+fn assert_store_fault(core: &Core, mmu: &mut MMU) {
+    assert_eq!(
+        core.read_csr(CSRRegister::mcause),
+        u16::from(TrapCause::StoreAccessFault(0)) as u64
+    );
+    assert_eq!(core.read_csr(CSRRegister::mtval), DATA);
+    assert_eq!(mmu.read32(DATA).unwrap(), 0x89abcdef, "nothing is stored");
+    assert_eq!(mmu.read32(DATA + 4).unwrap(), 0x01234567);
+}
+
+#[test]
+pub fn doubleword_stores_across_pmp_entries_fault() {
+    // Whether the upper half is read-only, or writable through another entry
+    for upper in [PmpCfg::R, PmpCfg::R | PmpCfg::W] {
+        let mmu = &mut MMU::create();
+        let core = &mut setup(
+            mmu,
+            &[
+                0x1005b52f, // lr.d a0, (a1)
+                0x00000013, // nop
+                0x18d5b62f, // sc.d a2, a3, (a1)
+            ],
+        );
+        mmu.write32(DATA, 0x89abcdef);
+        mmu.write32(DATA + 4, 0x01234567);
+        core.write_register(A3, u64::MAX);
+        run(core, mmu, 1);
+        // Takes effect once the nop retires
+        split_pmp(core, upper);
+        run(core, mmu, 2);
+        assert_store_fault(core, mmu);
+
+        let mmu = &mut MMU::create();
+        let core = &mut setup(
+            mmu,
+            &[
+                0x00000013, // nop
+                0x08d5b72f, // amoswap.d a4, a3, (a1)
+            ],
+        );
+        mmu.write32(DATA, 0x89abcdef);
+        mmu.write32(DATA + 4, 0x01234567);
+        core.write_register(A3, u64::MAX);
+        split_pmp(core, upper);
+        run(core, mmu, 2);
+        assert_store_fault(core, mmu);
+    }
+}
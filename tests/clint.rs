@@ -0,0 +1,85 @@
+use rriscv::{cpu::MipMask, memory::MemoryOperations, mmio::Timebase, mmu::MMU};
+
+const CLINT_BASE: u64 = 0x0200_0000;
+const MSIP: u64 = CLINT_BASE;
+const MTIMECMP: u64 = CLINT_BASE + 0x4000;
+const MTIME: u64 = CLINT_BASE + 0xbff8;
+
+const MSIP_BIT: u64 = MipMask::MSIP as u64;
+const MTIP: u64 = MipMask::MTIP as u64;
+
+fn run(mmu: &mut MMU, mip: u64, ticks: usize) -> u64 {
+    let mut mip = mip;
+    for _ in 0..ticks {
+        mip = mmu.tick(mip);
+    }
+    mip
+}
+
+#[test]
+pub fn timer_compare() {
+    let mut mmu = MMU::create();
+    assert!(mmu.clint_mut().set_timebase(Timebase::Ticks(4)));
+    let mip = run(&mut mmu, 0, 40);
+    assert_eq!(mmu.read64(MTIME).unwrap(), 10);
+    assert_eq!(mip & MTIP, 0, "mtimecmp starts out of reach");
+
+    mmu.write32(MTIMECMP, 15);
+    mmu.write32(MTIMECMP + 4, 0);
+    assert_eq!(mmu.read64(MTIMECMP).unwrap(), 15);
+    let mip = run(&mut mmu, mip, 19);
+    assert_eq!(mip & MTIP, 0);
+    let mip = run(&mut mmu, mip, 1);
+    assert_eq!(mip & MTIP, MTIP);
+
+    // Moving mtimecmp ahead clears the interrupt, as does winding back mtime
+    mmu.write32(MTIMECMP, 100);
+    let mip = run(&mut mmu, mip, 1);
+    assert_eq!(mip & MTIP, 0);
+    mmu.write32(MTIME, 0xffff_ffff);
+    mmu.write32(MTIME + 4, 0);
+    let mip = run(&mut mmu, mip, 4);
+    assert_eq!(mmu.read64(MTIME).unwrap(), 0x1_0000_0000);
+    assert_eq!(mip & MTIP, MTIP);
+    mmu.write32(MTIME + 4, 0);
+    let mip = run(&mut mmu, mip, 1);
+    assert_eq!(mip & MTIP, 0);
+}
+
+#[test]
+pub fn software_interrupts() {
+    let mut mmu = MMU::create();
+    mmu.write32(MSIP, 0xffff_ffff);
+    assert_eq!(mmu.read32(MSIP).unwrap(), 1);
+    let mip = mmu.tick(0);
+    assert_eq!(mip, MSIP_BIT);
+    mmu.write8(MSIP, 0);
+    assert_eq!(mmu.tick(mip), 0);
+    assert_eq!(mmu.read32(MSIP + 4).unwrap(), 0, "a single hart");
+}
+
+#[test]
+pub fn host_timebase() {
+    let mut mmu = MMU::create();
+    run(&mut mmu, 0, 1000);
+    assert!(mmu.clint_mut().set_timebase(Timebase::Host(10_000_000)));
+    mmu.tick(0);
+    let start = mmu.clint_mut().mtime();
+    assert!(start >= 1000, "carries on from {}", start);
+    std::thread::sleep(std::time::Duration::from_millis(10));
+    mmu.tick(0);
+    assert!(mmu.clint_mut().mtime() >= start + 100_000);
+}
+
+#[test]
+pub fn host_timebase_takes_any_mtime() {
+    let mut mmu = MMU::create();
+    assert!(!mmu.clint_mut().set_timebase(Timebase::Host(0)));
+    assert!(mmu.clint_mut().set_timebase(Timebase::Host(1)));
+
+    // Far more counts than the host clock has seen
+    mmu.write32(MTIME + 4, 0xffff_ffff);
+    mmu.write32(MTIME, 0xffff_fff0);
+    mmu.tick(0);
+    assert_eq!(mmu.read64(MTIME).unwrap(), 0xffff_ffff_ffff_fff0);
+}
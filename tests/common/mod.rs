@@ -0,0 +1,54 @@
+// Fixtures shared by the integration tests, each test crate uses a part of them
+#![allow(dead_code)]
+
+pub mod virtqueue;
+
+use std::{fs, path::PathBuf};
+
+use rriscv::{
+    cpu::{self, CSRRegister, Core},
+    memory::MemoryOperations,
+    mmu::MMU,
+    pipeline::Stage,
+    pmp::PmpCfg,
+};
+
+pub const VBASE: u64 = 0x8000_0000;
+/// Where the cores returned by `setup` take their traps
+pub const MTVEC: u64 = 0x8000_0100;
+
+/// Loads `program` at VBASE and returns a core ready to execute it
+pub fn setup(mmu: &mut MMU, program: &[u32]) -> Core {
+    for (i, word) in program.iter().enumerate() {
+        mmu.write32(VBASE + 4 * i as u64, *word);
+    }
+    let mut core = cpu::Core::create(0x0);
+    core.reset(VBASE);
+    core.write_csr(CSRRegister::mtvec, MTVEC);
+    // Let lower privilege modes access all of memory
+    core.write_csr(CSRRegister::pmpaddr0, u64::MAX);
+    core.write_csr(
+        CSRRegister::pmpcfg0,
+        (PmpCfg::NAPOT | PmpCfg::R | PmpCfg::W | PmpCfg::X) as u64,
+    );
+    core
+}
+
+/// Runs the pipeline until `instructions` instructions have been retired or trapped
+pub fn run(core: &mut Core, mmu: &mut MMU, instructions: usize) {
+    for _ in 0..instructions {
+        loop {
+            core.cycle(mmu);
+            if let Stage::FETCH = core.stage {
+                break;
+            }
+        }
+    }
+}
+
+/// A path in the temporary directory that is unique to this test process
+pub fn temp_path(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("rriscv-{}-{}", std::process::id(), name));
+    let _ = fs::remove_file(&path);
+    path
+}
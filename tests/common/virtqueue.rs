@@ -0,0 +1,125 @@
+// A minimal virtio driver for the virtio-mmio transports, enough to feed buffers to a
+// device and look at what it gives back
+
+use rriscv::{memory::MemoryOperations, mmu::MMU};
+
+pub const VIRTIO_BASE: u64 = 0x1000_1000;
+
+const DRIVER_FEATURES: u64 = 0x20;
+const DRIVER_FEATURES_SEL: u64 = 0x24;
+pub const QUEUE_SEL: u64 = 0x30;
+const QUEUE_NUM: u64 = 0x38;
+pub const QUEUE_READY: u64 = 0x44;
+pub const QUEUE_NOTIFY: u64 = 0x50;
+pub const INTERRUPT_STATUS: u64 = 0x60;
+pub const INTERRUPT_ACK: u64 = 0x64;
+const STATUS: u64 = 0x70;
+const QUEUE_DESC_LOW: u64 = 0x80;
+const QUEUE_DRIVER_LOW: u64 = 0x90;
+const QUEUE_DEVICE_LOW: u64 = 0xa0;
+
+/// Requests in flight on a queue, each a chain of up to `CHAIN` descriptors
+const REQUESTS: u64 = 8;
+const CHAIN: u64 = 4;
+const QUEUE_SIZE: u64 = REQUESTS * CHAIN;
+
+const F_NEXT: u16 = 1;
+const F_WRITE: u16 = 2;
+
+/// Descriptor table, available and used ring of queue `q`
+pub fn queue_areas(q: u64) -> (u64, u64, u64) {
+    let base = 0x8001_0000 + q * 0x3000;
+    (base, base + 0x1000, base + 0x2000)
+}
+
+/// Buffer `n` of queue `q`, the first part of the `n`th request
+pub fn buffer(q: u64, n: u64) -> u64 {
+    part(q, n, 0)
+}
+
+/// Part `i` of the `n`th request on queue `q`, all but the last part of a chain hold at
+/// most 4KiB
+pub fn part(q: u64, n: u64, i: u64) -> u64 {
+    0x8010_0000 + q * 0x20000 + (n % REQUESTS) * 0x4000 + i * 0x1000
+}
+
+/// Sets up `queues` of the transport at `base` and tells the device the driver is ready,
+/// having accepted `features`. All transports share the queue areas.
+pub fn setup(mut mmu: MMU, base: u64, queues: u64, features: u32) -> MMU {
+    mmu.write32(base + DRIVER_FEATURES_SEL, 0);
+    mmu.write32(base + DRIVER_FEATURES, features);
+    for q in 0..queues {
+        let (desc, avail, used) = queue_areas(q);
+        let regs = [
+            (QUEUE_SEL, q as u32),
+            (QUEUE_NUM, QUEUE_SIZE as u32),
+            (QUEUE_DESC_LOW, desc as u32),
+            (QUEUE_DRIVER_LOW, avail as u32),
+            (QUEUE_DEVICE_LOW, used as u32),
+            (QUEUE_READY, 1),
+        ];
+        for (reg, value) in regs {
+            mmu.write32(base + reg, value);
+        }
+    }
+    mmu.write32(base + STATUS, 0xf);
+    mmu
+}
+
+/// Makes buffer `n` of queue `q` available to the device as the `n`th request, holding
+/// `bytes` or `len` device-writable bytes
+pub fn give(mmu: &mut MMU, base: u64, q: u64, n: u64, bytes: &[u8], len: u32) {
+    give_chain(mmu, base, q, n, &[(bytes, len)])
+}
+
+/// Makes the `n`th request of queue `q` available to the device as a chain of `parts`,
+/// each holding its bytes or that many device-writable bytes
+pub fn give_chain(mmu: &mut MMU, base: u64, q: u64, n: u64, parts: &[(&[u8], u32)]) {
+    assert!(parts.len() as u64 <= CHAIN);
+    let (desc, avail, _) = queue_areas(q);
+    // The head is descriptor `n`, the rest of the chain follows it `REQUESTS` apart
+    let index = |i: u64| n % REQUESTS + i * REQUESTS;
+    for (i, (bytes, len)) in parts.iter().enumerate() {
+        let i = i as u64;
+        for (b, byte) in bytes.iter().enumerate() {
+            mmu.write8(part(q, n, i) + b as u64, *byte);
+        }
+        let mut flags = match bytes.len() {
+            0 => F_WRITE,
+            _ => 0,
+        };
+        if i + 1 < parts.len() as u64 {
+            flags |= F_NEXT;
+        }
+        let desc = desc + 16 * index(i);
+        mmu.write32(desc, part(q, n, i) as u32);
+        mmu.write32(desc + 4, 0);
+        mmu.write32(desc + 8, (*len).max(bytes.len() as u32));
+        mmu.write32(desc + 12, flags as u32 | (index(i + 1) as u32) << 16);
+    }
+
+    let slot = avail + 4 + 2 * (n % QUEUE_SIZE);
+    let word = mmu.read32(slot & !3).unwrap();
+    let shift = 8 * (slot & 3);
+    mmu.write32(
+        slot & !3,
+        (word & !(0xffff << shift)) | (index(0) as u32) << shift,
+    );
+    mmu.write32(avail, ((n as u32) + 1) << 16);
+    mmu.write32(base + QUEUE_NOTIFY, q as u32);
+    run(mmu);
+}
+
+/// The `len` the device reported for the `n`th used element of queue `q`
+pub fn used_len(mmu: &mut MMU, q: u64, n: u64) -> u32 {
+    let (_, _, used) = queue_areas(q);
+    mmu.read32(used + 4 + 8 * (n % QUEUE_SIZE) + 4).unwrap()
+}
+
+/// Ticks the devices long enough for them to act on what they were given, the disk being
+/// the slowest
+pub fn run(mmu: &mut MMU) {
+    for _ in 0..0x2000 {
+        mmu.tick(0);
+    }
+}
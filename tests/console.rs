@@ -0,0 +1,122 @@
+mod common;
+
+use std::{fs, io::Write, os::unix::net::UnixStream};
+
+use common::{
+    temp_path,
+    virtqueue::{buffer, give, queue_areas, run, setup, VIRTIO_BASE},
+};
+use rriscv::{
+    chardev::{LogFile, UnixSocket},
+    memory::MemoryOperations,
+    mmu::MMU,
+    virtio::console::VirtioConsole,
+};
+
+/// used.idx of queue `q`, and the bytes written to each used buffer
+fn used(mmu: &mut MMU, q: u64) -> (u32, Vec<Vec<u8>>) {
+    let (_, _, used) = queue_areas(q);
+    let idx = mmu.read32(used).unwrap() >> 16;
+    let buffers = (0..idx as u64)
+        .map(|i| {
+            let head = mmu.read32(used + 4 + 8 * i).unwrap() as u64;
+            let len = mmu.read32(used + 8 + 8 * i).unwrap() as u64;
+            (0..len)
+                .map(|b| mmu.read8(buffer(q, head) + b).unwrap())
+                .collect()
+        })
+        .collect();
+    (idx, buffers)
+}
+
+/// A `struct virtio_console_control`
+fn control(id: u32, event: u16, value: u16) -> Vec<u8> {
+    let mut message = id.to_le_bytes().to_vec();
+    message.extend_from_slice(&event.to_le_bytes());
+    message.extend_from_slice(&value.to_le_bytes());
+    message
+}
+
+#[test]
+pub fn console_output() {
+    let log = temp_path("console.log");
+    let mut mmu = MMU::create();
+    let console = VirtioConsole::new(Box::new(LogFile::create(&log).unwrap()));
+    mmu.attach_virtio(Box::new(console));
+    assert_eq!(mmu.read32(VIRTIO_BASE + 0x8).unwrap(), 3);
+    assert_eq!(mmu.read32(VIRTIO_BASE + 0x104).unwrap(), 1, "max_nr_ports");
+    let mmu = &mut setup(mmu, VIRTIO_BASE, 2, 1 << 2);
+
+    give(mmu, VIRTIO_BASE, 1, 0, b"hello, ", 0);
+    give(mmu, VIRTIO_BASE, 1, 1, b"world\n", 0);
+    assert_eq!(used(mmu, 1).0, 2);
+
+    // emerg_wr
+    mmu.write32(VIRTIO_BASE + 0x108, b'!' as u32);
+    assert_eq!(fs::read(&log).unwrap(), b"hello, world\n!");
+    fs::remove_file(log).unwrap();
+}
+
+#[test]
+pub fn console_input() {
+    let path = temp_path("console.sock");
+    let mut mmu = MMU::create();
+    let console = VirtioConsole::new(Box::new(UnixSocket::create(&path).unwrap()));
+    mmu.attach_virtio(Box::new(console));
+    let mmu = &mut setup(mmu, VIRTIO_BASE, 2, 0);
+
+    let mut client = UnixStream::connect(&path).unwrap();
+    client.write_all(b"ls -l\n").unwrap();
+    give(mmu, VIRTIO_BASE, 0, 0, &[], 4);
+    give(mmu, VIRTIO_BASE, 0, 1, &[], 16);
+    run(mmu);
+    assert_eq!(used(mmu, 0), (2, vec![b"ls -".to_vec(), b"l\n".to_vec()]));
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+pub fn multiport_handshake() {
+    let log = temp_path("multiport.log");
+    let mut mmu = MMU::create();
+    let mut console = VirtioConsole::new(Box::new(LogFile::create(&log).unwrap()));
+    let port_log = temp_path("multiport-port.log");
+    console.add_port(
+        "org.rriscv.0",
+        Box::new(LogFile::create(&port_log).unwrap()),
+    );
+    mmu.attach_virtio(Box::new(console));
+    assert_eq!(mmu.read32(VIRTIO_BASE + 0x10).unwrap() & 0x6, 0x6);
+    let mmu = &mut setup(mmu, VIRTIO_BASE, 6, 1 << 1);
+
+    for n in 0..6 {
+        give(mmu, VIRTIO_BASE, 2, n, &[], 64);
+    }
+    // DEVICE_READY, then PORT_READY for both ports
+    give(mmu, VIRTIO_BASE, 3, 0, &control(0xffff_ffff, 0, 1), 0);
+    give(mmu, VIRTIO_BASE, 3, 1, &control(0, 3, 1), 0);
+    give(mmu, VIRTIO_BASE, 3, 2, &control(1, 3, 1), 0);
+    let mut name = control(1, 7, 1);
+    name.extend_from_slice(b"org.rriscv.0");
+    assert_eq!(
+        used(mmu, 2),
+        (
+            6,
+            vec![
+                control(0, 1, 0),
+                control(1, 1, 0),
+                control(0, 4, 1),
+                control(0, 6, 1),
+                name,
+                control(1, 6, 1)
+            ]
+        )
+    );
+
+    // Port 1 writes to its own backend
+    give(mmu, VIRTIO_BASE, 3, 3, &control(1, 6, 1), 0);
+    give(mmu, VIRTIO_BASE, 5, 0, b"data", 0);
+    assert_eq!(fs::read(&port_log).unwrap(), b"data");
+    assert_eq!(fs::read(&log).unwrap(), b"");
+    fs::remove_file(log).unwrap();
+    fs::remove_file(port_log).unwrap();
+}
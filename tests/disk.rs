@@ -0,0 +1,82 @@
+use std::{fs, path::PathBuf};
+
+use rriscv::disk::{self, DiskMode};
+
+/// A fresh image of four sectors in the temp dir, each filled with its sector number
+fn image(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("rriscv-{}-{}", std::process::id(), name));
+    let contents: Vec<u8> = (0..4).flat_map(|sector| vec![sector as u8; 512]).collect();
+    fs::write(&path, contents).unwrap();
+    path
+}
+
+#[test]
+pub fn writable_image_is_updated_in_place() {
+    let path = image("writable.img");
+    {
+        let mut disk = disk::open(&path, DiskMode::Writable).unwrap();
+        assert_eq!(disk.len(), 2048);
+        disk.write(510, &[0xaa; 4]).unwrap();
+        disk.flush().unwrap();
+        assert!(
+            disk.write(2046, &[0; 4]).is_err(),
+            "writes past the end fail"
+        );
+    }
+    let contents = fs::read(&path).unwrap();
+    assert_eq!(contents.len(), 2048);
+    assert_eq!(contents[509..515], [0, 0xaa, 0xaa, 0xaa, 0xaa, 1]);
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+pub fn read_only_image_rejects_writes() {
+    let path = image("read-only.img");
+    let mut disk = disk::open(&path, DiskMode::ReadOnly).unwrap();
+    assert!(disk.read_only());
+    assert!(disk.write(0, &[0xff]).is_err());
+
+    let mut buf = [0xff; 4];
+    disk.read(1022, &mut buf).unwrap();
+    assert_eq!(buf, [1, 1, 2, 2]);
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+pub fn overlay_keeps_base_image_intact() {
+    let path = image("base.img");
+    let overlay = path.with_extension("overlay");
+    let _ = fs::remove_file(&overlay);
+    {
+        let mut disk = disk::open(&path, DiskMode::CopyOnWrite(&overlay)).unwrap();
+        // Straddles sectors 1 and 2, neither fully overwritten
+        disk.write(1020, &[0xaa; 8]).unwrap();
+        disk.write(1536, &[0xbb; 512]).unwrap();
+        disk.flush().unwrap();
+
+        let mut buf = [0; 12];
+        disk.read(1018, &mut buf).unwrap();
+        assert_eq!(
+            buf,
+            [1, 1, 0xaa, 0xaa, 0xaa, 0xaa, 0xaa, 0xaa, 0xaa, 0xaa, 2, 2]
+        );
+    }
+    let contents = fs::read(&path).unwrap();
+    assert!(contents[1020..1028].iter().all(|b| *b == 1 || *b == 2));
+
+    // Writes are still there when the overlay is reopened
+    let mut disk = disk::open(&path, DiskMode::CopyOnWrite(&overlay)).unwrap();
+    let mut buf = [0; 4];
+    disk.read(0, &mut buf).unwrap();
+    assert_eq!(buf, [0; 4]);
+    disk.read(1026, &mut buf).unwrap();
+    assert_eq!(buf, [0xaa, 0xaa, 2, 2]);
+    disk.read(2044, &mut buf).unwrap();
+    assert_eq!(buf, [0xbb; 4]);
+
+    // An overlay only fits the image it was created for
+    fs::write(&path, [0u8; 4096]).unwrap();
+    assert!(disk::open(&path, DiskMode::CopyOnWrite(&overlay)).is_err());
+    fs::remove_file(path).unwrap();
+    fs::remove_file(overlay).unwrap();
+}
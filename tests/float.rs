@@ -0,0 +1,183 @@
+mod common;
+
+use common::run;
+use rriscv::{
+    cpu::{CSRRegister, Core},
+    memory::MemoryOperations,
+    mmu::MMU,
+};
+
+const DATA: u64 = 0x8000_1000;
+
+const SP: u8 = 2;
+const A0: u8 = 10;
+const A1: u8 = 11;
+const FA0: u8 = 10;
+const FA1: u8 = 11;
+const FA2: u8 = 12;
+const FA3: u8 = 13;
+
+/// Loads `program` at VBASE and returns a core ready to execute it, with a1 pointing
+/// at DATA
+fn setup(mmu: &mut MMU, program: &[u32]) -> Core {
+    let mut core = common::setup(mmu, program);
+    core.write_register(A1, DATA);
+    core
+}
+
+#[test]
+pub fn arithmetic_and_flags() {
+    let mmu = &mut MMU::create();
+    let core = &mut setup(
+        mmu,
+        &[
+            0x00c5f553, // fadd.s fa0, fa1, fa2
+            0x18c5f553, // fdiv.s fa0, fa1, fa2
+            0x00102573, // frflags a0
+        ],
+    );
+    core.write_fregister(FA1, 0xffffffff_3f800000); // 1.0
+    core.write_fregister(FA2, 0xffffffff_40000000); // 2.0
+
+    run(core, mmu, 1);
+    assert_eq!(core.read_fregister(FA0), 0xffffffff_40400000);
+
+    core.write_fregister(FA2, 0xffffffff_40400000); // 3.0
+    run(core, mmu, 2);
+    assert_eq!(core.read_fregister(FA0), 0xffffffff_3eaaaaab);
+    assert_eq!(core.read_register(A0), 0x1, "1/3 is inexact");
+}
+
+#[test]
+pub fn unboxed_single_is_nan() {
+    let mmu = &mut MMU::create();
+    let core = &mut setup(
+        mmu,
+        &[
+            0x00c5f553, // fadd.s fa0, fa1, fa2
+        ],
+    );
+    core.write_fregister(FA1, 0x3f800000);
+    core.write_fregister(FA2, 0xffffffff_3f800000);
+
+    run(core, mmu, 1);
+    assert_eq!(core.read_fregister(FA0), 0xffffffff_7fc00000);
+}
+
+#[test]
+pub fn fused_multiply_add() {
+    let mmu = &mut MMU::create();
+    let core = &mut setup(
+        mmu,
+        &[
+            0x6ac5f543, // fmadd.d fa0, fa1, fa2, fa3
+            0x6ac5f54b, // fnmsub.d fa0, fa1, fa2, fa3
+        ],
+    );
+    core.write_fregister(FA1, 0x4000000000000000); // 2.0
+    core.write_fregister(FA2, 0x4008000000000000); // 3.0
+    core.write_fregister(FA3, 0x3ff0000000000000); // 1.0
+
+    run(core, mmu, 1);
+    assert_eq!(core.read_fregister(FA0), 0x401c000000000000);
+
+    run(core, mmu, 1);
+    assert_eq!(core.read_fregister(FA0), 0xc014000000000000);
+}
+
+#[test]
+pub fn conversions() {
+    let mmu = &mut MMU::create();
+    let core = &mut setup(
+        mmu,
+        &[
+            0xc2059553, // fcvt.w.d a0, fa1, rtz
+            0xc005f553, // fcvt.w.s a0, fa1
+            0x00102573, // frflags a0
+            0xe2059553, // fclass.d a0, fa1
+        ],
+    );
+    core.write_fregister(FA1, 0xc004000000000000); // -2.5
+
+    run(core, mmu, 1);
+    assert_eq!(core.read_register(A0), (-2i64) as u64);
+
+    core.write_fregister(FA1, 0xffffffff_7fc00000);
+    run(core, mmu, 1);
+    assert_eq!(core.read_register(A0), 0x7fffffff);
+
+    run(core, mmu, 1);
+    assert_eq!(
+        core.read_register(A0),
+        0x11,
+        "inexact -2.5, then invalid NaN"
+    );
+
+    core.write_fregister(FA1, 0xfff0000000000000);
+    run(core, mmu, 1);
+    assert_eq!(core.read_register(A0), 0x1, "negative infinity");
+}
+
+#[test]
+pub fn loads_and_stores() {
+    let mmu = &mut MMU::create();
+    let core = &mut setup(
+        mmu,
+        &[
+            0x0005a507, // flw fa0, 0(a1)
+            0x00a5b427, // fsd fa0, 8(a1)
+            0x0085b587, // fld fa1, 8(a1)
+            0xe0050553, // fmv.x.w a0, fa0
+        ],
+    );
+    mmu.write32(DATA, 0xbf800000);
+
+    run(core, mmu, 2);
+    assert_eq!(core.read_fregister(FA0), 0xffffffff_bf800000);
+    assert_eq!(mmu.read64(DATA + 8).unwrap(), 0xffffffff_bf800000);
+
+    run(core, mmu, 2);
+    assert_eq!(core.read_fregister(FA1), 0xffffffff_bf800000);
+    assert_eq!(core.read_register(A0), 0xffffffff_bf800000);
+}
+
+#[test]
+pub fn compressed_doubleword_offsets() {
+    let mmu = &mut MMU::create();
+    let core = &mut setup(
+        mmu,
+        &[
+            0x61c82588, // c.fld fa0, 8(a1) ; c.ld a0, 128(a1)
+            0x262aa52e, // c.fsdsp fa1, 136(sp) ; c.fldsp fa2, 136(sp)
+        ],
+    );
+    core.write_register(SP, DATA);
+    core.write_fregister(FA1, 0x400921fb54442d18);
+    mmu.write32(DATA + 8, 0x9abcdef0);
+    mmu.write32(DATA + 12, 0x12345678);
+    mmu.write32(DATA + 128, 0x55555555);
+    mmu.write32(DATA + 132, 0x0);
+
+    run(core, mmu, 2);
+    assert_eq!(core.read_fregister(FA0), 0x12345678_9abcdef0);
+    assert_eq!(core.read_register(A0), 0x55555555);
+
+    run(core, mmu, 2);
+    assert_eq!(mmu.read64(DATA + 136).unwrap(), 0x400921fb54442d18);
+    assert_eq!(core.read_fregister(FA2), 0x400921fb54442d18);
+}
+
+#[test]
+pub fn disabled_fpu_traps() {
+    let mmu = &mut MMU::create();
+    let core = &mut setup(
+        mmu,
+        &[
+            0x00c5f553, // fadd.s fa0, fa1, fa2
+        ],
+    );
+    core.write_csr(CSRRegister::mstatus, 0);
+
+    run(core, mmu, 1);
+    assert_eq!(core.read_csr(CSRRegister::mcause), 2);
+}
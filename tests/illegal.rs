@@ -0,0 +1,154 @@
+mod common;
+
+use common::{run, setup, MTVEC, VBASE};
+use rriscv::{
+    cpu::{CSRRegister, Core, PrivMode, TrapCause},
+    mmu::MMU,
+};
+
+const A0: u8 = 10;
+
+fn assert_illegal(core: &Core, epc: u64, bits: u64) {
+    assert_eq!(
+        core.read_csr(CSRRegister::mcause),
+        u16::from(TrapCause::IllegalInstruction(0)) as u64
+    );
+    assert_eq!(core.read_csr(CSRRegister::mepc), epc);
+    assert_eq!(core.read_csr(CSRRegister::mtval), bits);
+    assert_eq!(core.pc(), MTVEC);
+}
+
+#[test]
+pub fn unknown_encodings_trap_with_instruction_bits() {
+    let mmu = &mut MMU::create();
+    let core = &mut setup(mmu, &[0x0000_0000]);
+    run(core, mmu, 1);
+    assert_illegal(core, VBASE, 0);
+
+    let core = &mut setup(mmu, &[0xffff_ffff]);
+    run(core, mmu, 1);
+    assert_illegal(core, VBASE, 0xffff_ffff);
+
+    // c.addi4spn with a zero immediate is reserved, only the 16 low bits are reported
+    let core = &mut setup(mmu, &[0x1234_0004]);
+    run(core, mmu, 1);
+    assert_illegal(core, VBASE, 0x0004);
+}
+
+#[test]
+pub fn unknown_csr_traps() {
+    let mmu = &mut MMU::create();
+    let core = &mut setup(
+        mmu,
+        &[
+            0x7c002573, // csrr a0, 0x7c0
+        ],
+    );
+    run(core, mmu, 1);
+    assert_illegal(core, VBASE, 0x7c002573);
+}
+
+#[test]
+pub fn supervisor_satp_access_honors_tvm() {
+    let mmu = &mut MMU::create();
+    let core = &mut setup(
+        mmu,
+        &[
+            0x30200073, // mret
+            0x18002573, // csrr a0, satp
+            0x30200073, // mret
+        ],
+    );
+    core.write_csr(CSRRegister::mstatus, 1 << 11 | 1 << 20);
+    core.write_csr(CSRRegister::mepc, VBASE + 4);
+
+    run(core, mmu, 2);
+    assert_illegal(core, VBASE + 4, 0x18002573);
+
+    // Without TVM the read succeeds, but S-mode still cannot use MRET
+    core.write_csr(CSRRegister::mstatus, 1 << 11);
+    core.write_register(A0, 0xdead);
+    core.set_pc(VBASE);
+    run(core, mmu, 3);
+    assert_eq!(core.read_register(A0), 0);
+    assert_eq!(core.pmode(), PrivMode::Machine);
+    assert_illegal(core, VBASE + 8, 0x30200073);
+}
+
+#[test]
+pub fn counters_honor_counteren() {
+    let mmu = &mut MMU::create();
+    for _ in 0..100 {
+        mmu.tick(0);
+    }
+    let core = &mut setup(
+        mmu,
+        &[
+            0xc0102573, // rdtime a0
+            0xc0151073, // csrw time, a0
+        ],
+    );
+    run(core, mmu, 1);
+    assert_eq!(core.read_register(A0), 100);
+    run(core, mmu, 1);
+    assert_illegal(core, VBASE + 4, 0xc0151073);
+
+    let program = [
+        0x30200073, // mret
+        0xc0102573, // rdtime a0
+        0xc0002573, // rdcycle a0
+    ];
+    core.write_csr(CSRRegister::mcounteren, 0xffff_ffff);
+    assert_eq!(core.read_csr(CSRRegister::mcounteren), 0x7);
+    // (mode, mcounteren, scounteren, epc of the illegal instruction)
+    for (mode, mcounteren, scounteren, epc) in [
+        (PrivMode::Supervisor, 0b000, 0b111, VBASE + 4),
+        (PrivMode::Supervisor, 0b010, 0b000, VBASE + 8),
+        (PrivMode::User, 0b111, 0b000, VBASE + 4),
+        (PrivMode::User, 0b010, 0b011, VBASE + 8),
+    ] {
+        let core = &mut setup(mmu, &program);
+        core.write_csr(CSRRegister::mstatus, (mode as u64) << 11);
+        core.write_csr(CSRRegister::mepc, VBASE + 4);
+        core.write_csr(CSRRegister::mcounteren, mcounteren);
+        core.write_csr(CSRRegister::scounteren, scounteren);
+        let index = (epc - VBASE) / 4;
+        run(core, mmu, index as usize + 1);
+        assert_illegal(core, epc, program[index as usize] as u64);
+        if index == 2 {
+            assert_eq!(core.read_register(A0), 100, "rdtime in {:?}", mode);
+        }
+    }
+}
+
+#[test]
+pub fn system_and_shift_encodings() {
+    let mmu = &mut MMU::create();
+    let core = &mut setup(
+        mmu,
+        &[
+            0x10500073, // wfi
+            0x42855513, // srai a0, a0, 40
+        ],
+    );
+    core.write_register(A0, 0x8000_0000_0000_0000);
+
+    run(core, mmu, 2);
+    assert_eq!(core.pc(), VBASE + 8, "WFI is a NOP in M-mode");
+    assert_eq!(core.read_register(A0), 0xffff_ffff_ff80_0000);
+}
+
+#[test]
+pub fn reserved_fp_fields() {
+    let mmu = &mut MMU::create();
+    for bits in [
+        0x5815f553, // fsqrt.s fa0, fa1 with rs2 = 1
+        0xe0158553, // fmv.x.w a0, fa1 with rs2 = 1
+        0xe2259553, // fclass.d a0, fa1 with rs2 = 2
+        0xf2059553, // fmv.d.x fa0, a1 with rm = 1
+    ] {
+        let core = &mut setup(mmu, &[bits]);
+        run(core, mmu, 1);
+        assert_illegal(core, VBASE, bits as u64);
+    }
+}
@@ -1,4 +1,7 @@
-use rriscv::memory::{MemoryOperations, RAM};
+use rriscv::{
+    cpu::TrapCause,
+    memory::{MemoryOperations, RAM},
+};
 
 #[test]
 pub fn zero_initialized() {
@@ -51,3 +54,49 @@ pub fn write_read_single() {
     //         assert!(ret2 == 0, "{} != 0", ret2)
     //     }
 }
+
+#[test]
+pub fn little_endian_accessors() {
+    let vbase: u64 = 0x8000_0000;
+    let memory = &mut RAM::create(vbase, 4096);
+
+    memory.write64(vbase + 8, 0x0807_0605_0403_0201);
+    for i in 0..8 {
+        assert_eq!(memory.read8(vbase + 8 + i).unwrap(), i as u8 + 1);
+    }
+    assert_eq!(memory.read16(vbase + 9).unwrap(), 0x0302);
+    assert_eq!(memory.read32(vbase + 10).unwrap(), 0x0605_0403);
+
+    memory.write16(vbase + 12, 0xbbaa);
+    assert_eq!(memory.read64(vbase + 8).unwrap(), 0x0807_bbaa_0403_0201);
+}
+
+#[test]
+pub fn out_of_range_accesses_fault() {
+    let vbase: u64 = 0x8000_0000;
+    let memory = &mut RAM::create(vbase, 4096);
+
+    assert_eq!(
+        memory.read8(vbase - 1),
+        Err(TrapCause::LoadAccessFault(vbase - 1))
+    );
+    assert_eq!(
+        memory.read32(vbase + 4094),
+        Err(TrapCause::LoadAccessFault(vbase + 4094)),
+        "accesses straddling the end fault"
+    );
+    assert_eq!(
+        memory.read64(u64::MAX),
+        Err(TrapCause::LoadAccessFault(u64::MAX))
+    );
+    assert_eq!(
+        memory.write64(vbase + 4092, u64::MAX),
+        Some(TrapCause::StoreAccessFault(vbase + 4092))
+    );
+    assert_eq!(
+        memory.read32(vbase + 4092).unwrap(),
+        0,
+        "faulting stores leave memory untouched"
+    );
+    assert_eq!(memory.write16(vbase + 4094, 0xffff), None);
+}
@@ -0,0 +1,126 @@
+mod common;
+
+use std::fs;
+
+use common::{
+    temp_path,
+    virtqueue::{buffer, give, queue_areas, run, setup, VIRTIO_BASE},
+};
+use rriscv::{
+    memory::MemoryOperations,
+    mmu::MMU,
+    netdev::{Pcap, UnixSocket},
+    virtio::net::VirtioNet,
+};
+
+const HDR_SIZE: usize = 12;
+
+/// The frames received in the used buffers of the receiveq
+fn received(mmu: &mut MMU) -> Vec<Vec<u8>> {
+    let (_, _, used) = queue_areas(0);
+    let idx = mmu.read32(used).unwrap() >> 16;
+    (0..idx as u64)
+        .map(|i| {
+            let head = mmu.read32(used + 4 + 8 * i).unwrap() as u64;
+            let len = mmu.read32(used + 8 + 8 * i).unwrap() as u64;
+            assert_eq!(
+                mmu.read32(buffer(0, head) + 8).unwrap() >> 16,
+                1,
+                "num_buffers"
+            );
+            (HDR_SIZE as u64..len)
+                .map(|b| mmu.read8(buffer(0, head) + b).unwrap())
+                .collect()
+        })
+        .collect()
+}
+
+/// A broadcast frame carrying `payload`
+fn frame(payload: &[u8]) -> Vec<u8> {
+    let mut frame = vec![0xff; 6];
+    frame.extend_from_slice(&[0x52, 0x54, 0, 0x12, 0x34, 0x56, 0x88, 0xb5]);
+    frame.extend_from_slice(payload);
+    frame
+}
+
+/// A frame queued for transmission, behind an empty `struct virtio_net_hdr`
+fn packet(payload: &[u8]) -> Vec<u8> {
+    let mut packet = vec![0; HDR_SIZE];
+    packet.extend(frame(payload));
+    packet
+}
+
+#[test]
+pub fn pcap_capture_and_replay() {
+    let output = temp_path("net.pcap");
+    let mut mmu = MMU::create();
+    let pcap = Pcap::create(&output, None).unwrap();
+    mmu.attach_virtio(Box::new(VirtioNet::new(
+        [0x52, 0x54, 0, 0x12, 0x34, 0x56],
+        Box::new(pcap),
+    )));
+    assert_eq!(mmu.read32(VIRTIO_BASE + 0x8).unwrap(), 1);
+    assert_eq!(mmu.read32(VIRTIO_BASE + 0x100).unwrap(), 0x1200_5452);
+    assert_eq!(
+        mmu.read32(VIRTIO_BASE + 0x104).unwrap(),
+        0x0001_5634,
+        "link up"
+    );
+    let mmu = &mut setup(mmu, VIRTIO_BASE, 2, 0);
+
+    give(mmu, VIRTIO_BASE, 1, 0, &packet(b"first"), 0);
+    give(mmu, VIRTIO_BASE, 1, 1, &packet(b"second"), 0);
+    let capture = fs::read(&output).unwrap();
+    assert_eq!(capture[0..4], [0xd4, 0xc3, 0xb2, 0xa1]);
+    assert_eq!(capture.len(), 24 + 16 + 19 + 16 + 20);
+    assert_eq!(capture[24 + 8..24 + 12], [19, 0, 0, 0]);
+    assert_eq!(capture[24 + 16..24 + 16 + 19], frame(b"first")[..]);
+
+    // The capture replays into another guest
+    let replay = temp_path("replay.pcap");
+    let mut mmu = MMU::create();
+    let pcap = Pcap::create(&replay, Some(&output)).unwrap();
+    mmu.attach_virtio(Box::new(VirtioNet::new([2, 0, 0, 0, 0, 1], Box::new(pcap))));
+    let mmu = &mut setup(mmu, VIRTIO_BASE, 2, 0);
+    give(mmu, VIRTIO_BASE, 0, 0, &[], 1526);
+    run(mmu);
+    assert_eq!(received(mmu), vec![frame(b"first")]);
+    give(mmu, VIRTIO_BASE, 0, 1, &[], 1526);
+    assert_eq!(received(mmu), vec![frame(b"first"), frame(b"second")]);
+    fs::remove_file(output).unwrap();
+    fs::remove_file(replay).unwrap();
+}
+
+#[test]
+pub fn datagram_socket_connects_two_guests() {
+    let a = temp_path("net-a.sock");
+    let b = temp_path("net-b.sock");
+    let mut mmu_a = MMU::create();
+    let mut mmu_b = MMU::create();
+    let backend_a = UnixSocket::create(&a, &b).unwrap();
+    // Frames to a peer that is not up yet are lost
+    mmu_a.attach_virtio(Box::new(VirtioNet::new(
+        [2, 0, 0, 0, 0, 1],
+        Box::new(backend_a),
+    )));
+    let mmu_a = &mut setup(mmu_a, VIRTIO_BASE, 2, 0);
+    give(mmu_a, VIRTIO_BASE, 1, 0, &packet(b"lost"), 0);
+
+    let backend_b = UnixSocket::create(&b, &a).unwrap();
+    mmu_b.attach_virtio(Box::new(VirtioNet::new(
+        [2, 0, 0, 0, 0, 2],
+        Box::new(backend_b),
+    )));
+    let mmu_b = &mut setup(mmu_b, VIRTIO_BASE, 2, 0);
+    give(mmu_b, VIRTIO_BASE, 0, 0, &[], 1526);
+    give(mmu_a, VIRTIO_BASE, 1, 1, &packet(b"ping"), 0);
+    run(mmu_b);
+    assert_eq!(received(mmu_b), vec![frame(b"ping")]);
+
+    give(mmu_a, VIRTIO_BASE, 0, 0, &[], 1526);
+    give(mmu_b, VIRTIO_BASE, 1, 0, &packet(b"pong"), 0);
+    run(mmu_a);
+    assert_eq!(received(mmu_a), vec![frame(b"pong")]);
+    fs::remove_file(a).unwrap();
+    fs::remove_file(b).unwrap();
+}
@@ -0,0 +1,459 @@
+mod common;
+
+use common::{run, MTVEC, VBASE};
+use rriscv::{
+    cpu::{CSRRegister, Core, PrivMode, TrapCause, Xlen},
+    memory::MemoryOperations,
+    mmu::{MemoryAccessType, MMU},
+    pmp::{PmpCfg, PMP},
+};
+
+const ROOT: u64 = 0x8010_0000;
+
+const V: u64 = 1 << 0;
+const R: u64 = 1 << 1;
+const X: u64 = 1 << 3;
+const U: u64 = 1 << 4;
+const RWX: u64 = 0b1110;
+const AD: u64 = 0b11 << 6;
+
+fn write_pte(mmu: &mut MMU, addr: u64, pte: u64) {
+    mmu.write32(addr, pte as u32);
+    mmu.write32(addr + 4, (pte >> 32) as u32);
+}
+
+/// Builds a chain of page tables from ROOT down to `leaf_level`, one page per level,
+/// mapping `va` to `pa` with the leaf permissions `perms`. Must be called while
+/// translation is still off.
+fn map(mmu: &mut MMU, levels: usize, va: u64, pa: u64, leaf_level: usize, perms: u64) {
+    let mut table = ROOT;
+    for level in (leaf_level..levels).rev() {
+        let vpn = (va >> (12 + 9 * level)) & 0x1ff;
+        if level == leaf_level {
+            write_pte(mmu, table + vpn * 8, ((pa >> 12) << 10) | perms | AD | V);
+        } else {
+            let next = table + 0x1000;
+            write_pte(mmu, table + vpn * 8, ((next >> 12) << 10) | V);
+            table = next;
+        }
+    }
+}
+
+/// Grants S and U-mode access to all of physical memory, like firmware does
+/// before leaving M-mode
+fn allow_all(mmu: &mut MMU) {
+    let mut pmp = PMP::create();
+    pmp.write_addr(0, u64::MAX, Xlen::Bits64);
+    pmp.write_cfg(
+        0,
+        (PmpCfg::NAPOT | PmpCfg::R | PmpCfg::W | PmpCfg::X) as u64,
+        Xlen::Bits64,
+    );
+    mmu.update_pmp(pmp);
+}
+
+fn enable(mmu: &mut MMU, mode: u64) {
+    allow_all(mmu);
+    mmu.update_satp((mode << 60) | (ROOT >> 12), Xlen::Bits64);
+    mmu.update_privilege_mode(PrivMode::Supervisor);
+}
+
+#[test]
+pub fn sv48_page() {
+    let mmu = &mut MMU::create();
+    let va = 0x0000_7f12_3456_7abc;
+    map(mmu, 4, va, 0x8020_0000, 0, RWX);
+    mmu.write32(0x8020_0abc, 0xcafebabe);
+    enable(mmu, 9);
+
+    assert_eq!(
+        mmu.translate_address(&va, MemoryAccessType::READ),
+        Ok(0x8020_0abc)
+    );
+    assert_eq!(mmu.read32(va).unwrap(), 0xcafebabe);
+}
+
+#[test]
+pub fn sv57_megapage_in_upper_half() {
+    let mmu = &mut MMU::create();
+    let va = 0xff00_0000_0020_0000;
+    map(mmu, 5, va, 0x8040_0000, 1, RWX);
+    enable(mmu, 10);
+
+    assert_eq!(
+        mmu.translate_address(&(va + 0x12345), MemoryAccessType::WRITE),
+        Ok(0x8041_2345)
+    );
+}
+
+#[test]
+pub fn sv48_misaligned_superpage_faults() {
+    let mmu = &mut MMU::create();
+    let va = 0x4000_0000;
+    // A 1 GiB gigapage whose physical address is only 2 MiB aligned
+    map(mmu, 4, va, 0x8020_0000, 2, RWX);
+    enable(mmu, 9);
+
+    assert_eq!(
+        mmu.translate_address(&va, MemoryAccessType::READ),
+        Err(TrapCause::LoadPageFault(va))
+    );
+}
+
+#[test]
+pub fn sv48_non_canonical_address_faults() {
+    let mmu = &mut MMU::create();
+    let va = 0x0000_8000_0000_0000;
+    map(mmu, 4, va, 0x8020_0000, 0, RWX);
+    enable(mmu, 9);
+
+    assert_eq!(
+        mmu.translate_address(&va, MemoryAccessType::WRITE),
+        Err(TrapCause::StorePageFault(va))
+    );
+}
+
+/// Sv32 counterpart of `map`: two levels of 1024 four-byte entries
+fn map_sv32(mmu: &mut MMU, va: u64, pa: u64, leaf_level: usize) {
+    let vpn1 = (va >> 22) & 0x3ff;
+    if leaf_level == 1 {
+        mmu.write32(ROOT + vpn1 * 4, (((pa >> 12) << 10) | RWX | AD | V) as u32);
+    } else {
+        let next = ROOT + 0x1000;
+        mmu.write32(ROOT + vpn1 * 4, (((next >> 12) << 10) | V) as u32);
+        let vpn0 = (va >> 12) & 0x3ff;
+        mmu.write32(next + vpn0 * 4, (((pa >> 12) << 10) | RWX | AD | V) as u32);
+    }
+}
+
+fn enable_sv32(mmu: &mut MMU) {
+    allow_all(mmu);
+    mmu.update_satp(0x8000_0000 | (ROOT >> 12), Xlen::Bits32);
+    mmu.update_privilege_mode(PrivMode::Supervisor);
+}
+
+#[test]
+pub fn sv32_page() {
+    let mmu = &mut MMU::create();
+    let va = 0xc012_3456;
+    map_sv32(mmu, va, 0x8020_3000, 0);
+    mmu.write32(0x8020_3456, 0x600df00d);
+    enable_sv32(mmu);
+
+    assert_eq!(
+        mmu.translate_address(&va, MemoryAccessType::READ),
+        Ok(0x8020_3456)
+    );
+    assert_eq!(mmu.read32(va).unwrap(), 0x600df00d);
+}
+
+#[test]
+pub fn sv32_megapage_above_4gib() {
+    let mmu = &mut MMU::create();
+    let va = 0x4000_0000;
+    map_sv32(mmu, va, 0x3_0040_0000, 1);
+    enable_sv32(mmu);
+
+    assert_eq!(
+        mmu.translate_address(&(va + 0x23_4567), MemoryAccessType::EXECUTE),
+        Ok(0x3_0063_4567)
+    );
+}
+
+#[test]
+pub fn sv32_misaligned_megapage_faults() {
+    let mmu = &mut MMU::create();
+    let va = 0x4000_0000;
+    map_sv32(mmu, va, 0x8020_1000, 1);
+    enable_sv32(mmu);
+
+    assert_eq!(
+        mmu.translate_address(&va, MemoryAccessType::EXECUTE),
+        Err(TrapCause::InstructionPageFault(va))
+    );
+}
+
+/// Identity maps the gigapage holding VBASE under Sv39 and returns a core that
+/// drops to S-mode at `entry` through `mret`
+fn supervisor_core(mmu: &mut MMU, program: &[u32], entry: u64) -> Core {
+    map(mmu, 3, VBASE, VBASE, 2, RWX);
+    let mut core = common::setup(mmu, program);
+    core.write_csr(CSRRegister::mstatus, 1 << 11);
+    core.write_csr(CSRRegister::mepc, entry);
+    core.write_csr(CSRRegister::satp, (8 << 60) | (ROOT >> 12));
+    core
+}
+
+#[test]
+pub fn load_page_fault_reports_address() {
+    let program = [
+        0x30200073, // mret
+        0x00063503, // ld a0, 0(a2)
+        0x00061503, // lh a0, 0(a2)
+    ];
+    for entry in [VBASE + 4, VBASE + 8] {
+        let mmu = &mut MMU::create();
+        let core = &mut supervisor_core(mmu, &program, entry);
+        core.write_register(12, 0x1008);
+
+        run(core, mmu, 2);
+        assert_eq!(core.read_csr(CSRRegister::mcause), 13);
+        assert_eq!(core.read_csr(CSRRegister::mtval), 0x1008);
+        assert_eq!(core.read_csr(CSRRegister::mepc), entry);
+        assert_eq!(core.pc(), MTVEC);
+    }
+}
+
+#[test]
+pub fn instruction_page_fault_reports_address() {
+    let mmu = &mut MMU::create();
+    let core = &mut supervisor_core(
+        mmu,
+        &[
+            0x30200073, // mret
+        ],
+        0x2000,
+    );
+
+    run(core, mmu, 2);
+    assert_eq!(core.read_csr(CSRRegister::mcause), 12);
+    assert_eq!(core.read_csr(CSRRegister::mtval), 0x2000);
+    assert_eq!(core.read_csr(CSRRegister::mepc), 0x2000);
+}
+
+#[test]
+pub fn store_page_fault_writes_nothing() {
+    let program = [
+        0x30200073, // mret
+        0x00a63023, // sd a0, 0(a2)
+        0xfea63e23, // sd a0, -4(a2)
+    ];
+    for (entry, tval) in [(VBASE + 4, 0x2000), (VBASE + 8, 0x2000)] {
+        let mmu = &mut MMU::create();
+        map(mmu, 3, 0x1000, 0x8020_0000, 0, RWX);
+        map(mmu, 3, 0x2000, 0x8020_1000, 0, R);
+        let core = &mut supervisor_core(mmu, &program, entry);
+        core.write_register(10, u64::MAX);
+        core.write_register(12, 0x2000);
+
+        run(core, mmu, 2);
+        assert_eq!(
+            core.read_csr(CSRRegister::mcause),
+            u16::from(TrapCause::StorePageFault(0)) as u64
+        );
+        assert_eq!(core.read_csr(CSRRegister::mtval), tval);
+        assert_eq!(core.read_csr(CSRRegister::mepc), entry);
+        assert_eq!(mmu.read32(0x8020_0ffc).unwrap(), 0, "no part is stored");
+        assert_eq!(mmu.read32(0x8020_1000).unwrap(), 0);
+    }
+}
+
+const SUM: u64 = 1 << 18;
+const MXR: u64 = 1 << 19;
+const MPRV: u64 = 1 << 17;
+
+#[test]
+pub fn supervisor_needs_sum_for_user_pages() {
+    let mmu = &mut MMU::create();
+    let va = 0x1000;
+    map(mmu, 3, va, 0x8020_0000, 0, RWX | U);
+    enable(mmu, 8);
+
+    assert_eq!(
+        mmu.translate_address(&va, MemoryAccessType::READ),
+        Err(TrapCause::LoadPageFault(va))
+    );
+
+    mmu.update_mstatus(SUM);
+    assert_eq!(
+        mmu.translate_address(&va, MemoryAccessType::WRITE),
+        Ok(0x8020_0000)
+    );
+    assert_eq!(
+        mmu.translate_address(&va, MemoryAccessType::EXECUTE),
+        Err(TrapCause::InstructionPageFault(va)),
+        "S-mode never executes U pages"
+    );
+}
+
+#[test]
+pub fn user_mode_only_touches_user_pages() {
+    let mmu = &mut MMU::create();
+    map(mmu, 3, 0x1000, 0x8020_0000, 0, RWX);
+    map(mmu, 3, 0x2000, 0x8020_1000, 0, RWX | U);
+    enable(mmu, 8);
+    mmu.update_privilege_mode(PrivMode::User);
+
+    assert_eq!(
+        mmu.translate_address(&0x1000, MemoryAccessType::EXECUTE),
+        Err(TrapCause::InstructionPageFault(0x1000))
+    );
+    assert_eq!(
+        mmu.translate_address(&0x2000, MemoryAccessType::EXECUTE),
+        Ok(0x8020_1000)
+    );
+}
+
+#[test]
+pub fn mxr_makes_executable_pages_readable() {
+    let mmu = &mut MMU::create();
+    let va = 0x1000;
+    map(mmu, 3, va, 0x8020_0000, 0, X);
+    enable(mmu, 8);
+
+    assert_eq!(
+        mmu.translate_address(&va, MemoryAccessType::READ),
+        Err(TrapCause::LoadPageFault(va))
+    );
+
+    mmu.update_mstatus(MXR);
+    assert_eq!(
+        mmu.translate_address(&va, MemoryAccessType::READ),
+        Ok(0x8020_0000)
+    );
+    assert_eq!(
+        mmu.translate_address(&va, MemoryAccessType::WRITE),
+        Err(TrapCause::StorePageFault(va))
+    );
+}
+
+#[test]
+pub fn unsupported_satp_mode_is_ignored() {
+    let mmu = &mut MMU::create();
+    let va = 0x1000;
+    map(mmu, 3, va, 0x8020_0000, 0, RWX);
+    let core = &mut common::setup(mmu, &[]);
+    let satp = (8 << 60) | (ROOT >> 12);
+    core.write_csr(CSRRegister::satp, satp);
+    core.write_csr(CSRRegister::satp, (5 << 60) | (ROOT >> 12));
+    assert_eq!(core.read_csr(CSRRegister::satp), satp);
+
+    enable(mmu, 8);
+    mmu.update_satp(5 << 60, Xlen::Bits64);
+    assert_eq!(
+        mmu.translate_address(&va, MemoryAccessType::READ),
+        Ok(0x8020_0000)
+    );
+}
+
+#[test]
+pub fn reserved_mpp_is_not_taken() {
+    let mmu = &mut MMU::create();
+    let core = &mut common::setup(mmu, &[]);
+    core.write_csr(CSRRegister::mstatus, 1 << 11);
+    core.write_csr(CSRRegister::mstatus, MPRV | 2 << 11);
+    assert_eq!(
+        core.read_csr(CSRRegister::mstatus) & (MPRV | 3 << 11),
+        MPRV | 1 << 11
+    );
+
+    mmu.update_mstatus(MPRV | 2 << 11);
+    assert_eq!(
+        mmu.translate_address(&VBASE, MemoryAccessType::WRITE),
+        Err(TrapCause::StoreAccessFault(VBASE))
+    );
+}
+
+#[test]
+pub fn mprv_translates_machine_loads_and_stores() {
+    let mmu = &mut MMU::create();
+    let va = 0x1000;
+    map(mmu, 3, va, 0x8020_0000, 0, R | U);
+    allow_all(mmu);
+    mmu.update_satp((8 << 60) | (ROOT >> 12), Xlen::Bits64);
+
+    // MPP = U
+    mmu.update_mstatus(MPRV);
+    assert_eq!(
+        mmu.translate_address(&va, MemoryAccessType::READ),
+        Ok(0x8020_0000)
+    );
+    assert_eq!(
+        mmu.translate_address(&va, MemoryAccessType::EXECUTE),
+        Ok(va),
+        "fetches are not affected by MPRV"
+    );
+
+    // MPP = S, without SUM
+    mmu.update_mstatus(MPRV | (1 << 11));
+    assert_eq!(
+        mmu.translate_address(&va, MemoryAccessType::READ),
+        Err(TrapCause::LoadPageFault(va))
+    );
+}
+
+#[test]
+pub fn tlb_counts_hits_and_misses() {
+    let mmu = &mut MMU::create();
+    let va = 0x1000;
+    map(mmu, 3, va, 0x8020_0000, 0, RWX);
+    enable(mmu, 8);
+
+    for offset in [0, 8, 0xff8] {
+        assert_eq!(
+            mmu.translate_address(&(va + offset), MemoryAccessType::READ),
+            Ok(0x8020_0000 + offset)
+        );
+    }
+    assert_eq!(mmu.tlb().misses(), 1);
+    assert_eq!(mmu.tlb().hits(), 2);
+}
+
+#[test]
+pub fn tlb_keeps_stale_entries_until_sfence() {
+    let mmu = &mut MMU::create();
+    let va = 0x1000;
+    map(mmu, 3, va, 0x8020_0000, 0, RWX);
+    enable(mmu, 8);
+    assert_eq!(
+        mmu.translate_address(&va, MemoryAccessType::READ),
+        Ok(0x8020_0000)
+    );
+
+    // Remap the page behind the TLB's back
+    mmu.update_privilege_mode(PrivMode::Machine);
+    map(mmu, 3, va, 0x8030_0000, 0, RWX);
+    mmu.update_privilege_mode(PrivMode::Supervisor);
+    assert_eq!(
+        mmu.translate_address(&va, MemoryAccessType::READ),
+        Ok(0x8020_0000)
+    );
+
+    mmu.sfence_vma(Some(va + 0x10), None);
+    assert_eq!(
+        mmu.translate_address(&va, MemoryAccessType::READ),
+        Ok(0x8030_0000)
+    );
+}
+
+#[test]
+pub fn tlb_entries_are_tagged_with_asid() {
+    let mmu = &mut MMU::create();
+    const G: u64 = 1 << 5;
+    map(mmu, 3, 0x1000, 0x8020_0000, 0, RWX);
+    map(mmu, 3, 0x2000, 0x8020_1000, 0, RWX | G);
+    let satp = (8 << 60) | (ROOT >> 12);
+    allow_all(mmu);
+    mmu.update_satp(satp | (1 << 44), Xlen::Bits64);
+    mmu.update_privilege_mode(PrivMode::Supervisor);
+    mmu.translate_address(&0x1000, MemoryAccessType::READ)
+        .unwrap();
+    mmu.translate_address(&0x2000, MemoryAccessType::READ)
+        .unwrap();
+
+    // Switching address spaces keeps the cached entries around
+    mmu.update_satp(satp | (2 << 44), Xlen::Bits64);
+    mmu.update_satp(satp | (1 << 44), Xlen::Bits64);
+    mmu.translate_address(&0x1000, MemoryAccessType::READ)
+        .unwrap();
+    assert_eq!(mmu.tlb().hits(), 1);
+
+    // Flushing ASID 1 spares the global page
+    mmu.sfence_vma(None, Some(1));
+    mmu.translate_address(&0x1000, MemoryAccessType::READ)
+        .unwrap();
+    mmu.translate_address(&0x2000, MemoryAccessType::READ)
+        .unwrap();
+    assert_eq!(mmu.tlb().hits(), 2);
+    assert_eq!(mmu.tlb().misses(), 3);
+}
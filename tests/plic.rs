@@ -0,0 +1,149 @@
+use rriscv::{
+    cpu::{MipMask, TrapCause},
+    memory::MemoryOperations,
+    mmu::{MemoryRange, MMU},
+    plic::PLIC,
+};
+
+const PLIC_BASE: u64 = 0x0c00_0000;
+
+const MEIP: u64 = MipMask::MEIP as u64;
+const SEIP: u64 = MipMask::SEIP as u64;
+
+fn plic(harts: usize) -> PLIC {
+    PLIC::create(
+        MemoryRange {
+            name: "interrupt-controller",
+            start: PLIC_BASE,
+            end: PLIC_BASE + 0x400_0000,
+            interrupt: None,
+        },
+        harts,
+    )
+}
+
+fn priority(source: u64) -> u64 {
+    PLIC_BASE + 4 * source
+}
+
+fn pending(source: u64) -> u64 {
+    PLIC_BASE + 0x1000 + 4 * (source / 32)
+}
+
+fn enable(context: u64, source: u64) -> u64 {
+    PLIC_BASE + 0x2000 + 0x80 * context + 4 * (source / 32)
+}
+
+fn threshold(context: u64) -> u64 {
+    PLIC_BASE + 0x20_0000 + 0x1000 * context
+}
+
+fn claim(context: u64) -> u64 {
+    threshold(context) + 4
+}
+
+#[test]
+pub fn priorities_and_thresholds() {
+    let mut plic = plic(1);
+    for (source, prio) in [(3, 2), (40, 5), (1000, 5)] {
+        plic.write32(priority(source), prio);
+        plic.write32(enable(1, source), 1 << (source % 32));
+    }
+    plic.write32(priority(7), 0xff);
+    assert_eq!(plic.read32(priority(7)).unwrap(), 7, "3 bit priorities");
+    plic.write32(priority(0), 1);
+    assert_eq!(
+        plic.read32(priority(0)).unwrap(),
+        0,
+        "source 0 does not exist"
+    );
+
+    plic.tick(&[(3, true), (40, true), (1000, true)]);
+    assert_eq!(plic.read32(pending(3)).unwrap(), 1 << 3);
+    assert_eq!(plic.read32(pending(40)).unwrap(), 1 << 8);
+    assert_eq!(plic.read32(pending(1000)).unwrap(), 1 << 8);
+    assert_eq!(plic.update_mip(0, 0), SEIP, "S-mode context only");
+
+    // Sources of equal priority go in order of their number
+    plic.write32(threshold(1), 2);
+    assert_eq!(plic.read32(claim(1)).unwrap(), 40);
+    assert_eq!(plic.read32(pending(40)).unwrap(), 0);
+    assert_eq!(plic.read32(claim(1)).unwrap(), 1000);
+    assert_eq!(
+        plic.read32(claim(1)).unwrap(),
+        0,
+        "source 3 is at the threshold"
+    );
+    plic.tick(&[]);
+    assert_eq!(plic.update_mip(0, SEIP), 0);
+
+    plic.write32(threshold(1), 1);
+    plic.tick(&[]);
+    assert_eq!(plic.update_mip(0, 0), SEIP);
+    assert_eq!(plic.read32(claim(1)).unwrap(), 3);
+}
+
+#[test]
+pub fn claim_and_complete_gate_level_triggered_sources() {
+    let mut plic = plic(1);
+    plic.write32(priority(10), 1);
+    plic.write32(enable(0, 10), 1 << 10);
+    plic.tick(&[(10, true)]);
+    assert_eq!(plic.update_mip(0, 0), MEIP);
+
+    // No new request while the source is claimed, even with its line still high
+    assert_eq!(plic.read32(claim(0)).unwrap(), 10);
+    plic.tick(&[(10, true)]);
+    assert_eq!(plic.update_mip(0, MEIP), 0);
+    assert_eq!(plic.read32(pending(10)).unwrap(), 0);
+
+    // Completing a source the context does not have enabled does nothing
+    plic.write32(claim(1), 10);
+    plic.tick(&[(10, true)]);
+    assert_eq!(plic.read32(pending(10)).unwrap(), 0);
+
+    plic.write32(claim(0), 10);
+    plic.tick(&[(10, true)]);
+    assert_eq!(plic.update_mip(0, 0), MEIP, "still high after completion");
+    assert_eq!(plic.read32(claim(0)).unwrap(), 10);
+    plic.write32(claim(0), 10);
+    plic.tick(&[(10, false)]);
+    assert_eq!(plic.update_mip(0, MEIP), 0);
+}
+
+#[test]
+pub fn contexts_of_each_hart() {
+    let mut plic = plic(2);
+    plic.write32(priority(5), 1);
+    plic.write32(priority(6), 1);
+    // Hart 1: source 5 in M-mode, source 6 in S-mode
+    plic.write32(enable(2, 5), 1 << 5);
+    plic.write32(enable(3, 6), 1 << 6);
+    assert_eq!(plic.read32(enable(2, 5)).unwrap(), 1 << 5);
+    assert_eq!(plic.read32(enable(1, 6)).unwrap(), 0);
+
+    plic.tick(&[(5, true)]);
+    assert_eq!(plic.update_mip(0, 0), 0);
+    assert_eq!(plic.update_mip(1, 0), MEIP);
+    plic.tick(&[(6, true)]);
+    assert_eq!(plic.update_mip(1, MEIP), MEIP | SEIP);
+    assert_eq!(plic.read32(claim(3)).unwrap(), 6);
+    assert_eq!(plic.read32(claim(2)).unwrap(), 5);
+    plic.tick(&[]);
+    assert_eq!(plic.update_mip(1, MEIP | SEIP), 0);
+}
+
+#[test]
+pub fn byte_accesses_fault() {
+    let mmu = &mut MMU::create();
+    mmu.write32(priority(5), 3);
+    assert_eq!(
+        mmu.read8(priority(5)),
+        Err(TrapCause::LoadAccessFault(priority(5)))
+    );
+    assert_eq!(
+        mmu.write8(priority(5), 1),
+        Some(TrapCause::StoreAccessFault(priority(5)))
+    );
+    assert_eq!(mmu.read32(priority(5)).unwrap(), 3);
+}
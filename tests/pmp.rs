@@ -0,0 +1,179 @@
+mod common;
+
+use common::{run, setup, VBASE};
+use rriscv::{
+    cpu::{CSRRegister, PrivMode, TrapCause, Xlen},
+    memory::MemoryOperations,
+    mmu::{MemoryAccessType, MMU},
+    pmp::{PmpCfg, PMP},
+};
+
+const R: u8 = PmpCfg::R;
+const W: u8 = PmpCfg::W;
+const X: u8 = PmpCfg::X;
+const L: u8 = PmpCfg::L;
+
+/// `pmpaddr` value of a naturally aligned power-of-two region
+fn napot(base: u64, size: u64) -> u64 {
+    (base | (size / 2 - 1)) >> 2
+}
+
+#[test]
+pub fn napot_and_na4_regions() {
+    let mut pmp = PMP::create();
+    pmp.write_addr(0, VBASE >> 2, Xlen::Bits64);
+    pmp.write_addr(1, napot(VBASE, 0x1000), Xlen::Bits64);
+    pmp.write_cfg(
+        0,
+        ((PmpCfg::NAPOT | R | W | X) as u64) << 8 | (PmpCfg::NA4 | R) as u64,
+        Xlen::Bits64,
+    );
+
+    let check = |addr, access| pmp.check(addr, 1, access, PrivMode::Supervisor);
+    // The lowest numbered match wins, even though entry 1 allows writes
+    assert!(check(VBASE, MemoryAccessType::READ));
+    assert!(!check(VBASE + 3, MemoryAccessType::WRITE));
+    assert!(check(VBASE + 4, MemoryAccessType::WRITE));
+    assert!(check(VBASE + 0xfff, MemoryAccessType::EXECUTE));
+    assert!(!check(VBASE + 0x1000, MemoryAccessType::READ));
+    assert!(!check(VBASE - 1, MemoryAccessType::READ));
+}
+
+#[test]
+pub fn accesses_must_lie_within_one_entry() {
+    let mut pmp = PMP::create();
+    pmp.write_addr(0, VBASE >> 2, Xlen::Bits64);
+    pmp.write_addr(1, napot(VBASE, 0x1000), Xlen::Bits64);
+    pmp.write_cfg(
+        0,
+        ((PmpCfg::NAPOT | R | W) as u64) << 8 | (PmpCfg::NA4 | R | W) as u64,
+        Xlen::Bits64,
+    );
+
+    let check = |addr, len| pmp.check(addr, len, MemoryAccessType::WRITE, PrivMode::Supervisor);
+    assert!(check(VBASE, 4));
+    assert!(check(VBASE + 4, 8));
+    assert!(
+        !check(VBASE, 8),
+        "both halves are allowed, by different entries"
+    );
+    assert!(!check(VBASE + 2, 4));
+    assert!(!check(VBASE + 0xffc, 8), "only the first half matches");
+    assert!(!check(VBASE - 4, 8), "entry 0 matches the second half");
+
+    // M-mode fails partial matches of entries that do not even apply to it
+    assert!(!pmp.check(VBASE + 0xffc, 8, MemoryAccessType::WRITE, PrivMode::Machine));
+}
+
+#[test]
+pub fn tor_region_starts_at_previous_address() {
+    let mut pmp = PMP::create();
+    pmp.write_addr(0, 0x8000_1000 >> 2, Xlen::Bits64);
+    pmp.write_addr(1, 0x8000_3000 >> 2, Xlen::Bits64);
+    pmp.write_cfg(0, ((PmpCfg::TOR | R) as u64) << 8, Xlen::Bits64);
+
+    let check = |addr| pmp.check(addr, 1, MemoryAccessType::READ, PrivMode::User);
+    assert!(!check(0x8000_0fff));
+    assert!(check(0x8000_1000));
+    assert!(check(0x8000_2fff));
+    assert!(!check(0x8000_3000));
+}
+
+#[test]
+pub fn machine_mode_is_only_bound_by_locked_entries() {
+    let mut pmp = PMP::create();
+    pmp.write_addr(0, napot(VBASE, 0x1000), Xlen::Bits64);
+    pmp.write_cfg(0, (PmpCfg::NAPOT | R) as u64, Xlen::Bits64);
+
+    assert!(pmp.check(VBASE, 1, MemoryAccessType::WRITE, PrivMode::Machine));
+    assert!(!pmp.check(VBASE, 1, MemoryAccessType::WRITE, PrivMode::Supervisor));
+    assert!(
+        pmp.check(0, 1, MemoryAccessType::WRITE, PrivMode::Machine),
+        "M-mode succeeds without a match"
+    );
+
+    pmp.write_cfg(0, (PmpCfg::NAPOT | R | L) as u64, Xlen::Bits64);
+    assert!(!pmp.check(VBASE, 1, MemoryAccessType::WRITE, PrivMode::Machine));
+
+    // Locked entries ignore writes until reset
+    pmp.write_cfg(0, (PmpCfg::NAPOT | R | W) as u64, Xlen::Bits64);
+    pmp.write_addr(0, 0, Xlen::Bits64);
+    assert_eq!(
+        pmp.read_cfg(0, Xlen::Bits64),
+        (PmpCfg::NAPOT | R | L) as u64
+    );
+    assert_eq!(pmp.read_addr(0), napot(VBASE, 0x1000));
+}
+
+#[test]
+pub fn cfg_register_layout() {
+    let mut pmp = PMP::create();
+    pmp.write_cfg(2, 0x1b << 8, Xlen::Bits64);
+    assert_eq!(pmp.read_cfg(2, Xlen::Bits64), 0x1b << 8);
+    assert_eq!(
+        pmp.read_cfg(2, Xlen::Bits32),
+        0x1b << 8,
+        "entry 9 is byte 1 of pmpcfg2 on both RV32 and RV64"
+    );
+    assert_eq!(
+        pmp.read_cfg(1, Xlen::Bits64),
+        0,
+        "odd pmpcfg do not exist on RV64"
+    );
+
+    // R=0 W=1 is reserved
+    pmp.write_cfg(0, (PmpCfg::NAPOT | W) as u64, Xlen::Bits64);
+    assert_eq!(pmp.read_cfg(0, Xlen::Bits64), PmpCfg::NAPOT as u64);
+}
+
+#[test]
+pub fn supervisor_load_outside_region_faults() {
+    let mmu = &mut MMU::create();
+    let core = &mut setup(
+        mmu,
+        &[
+            0x30200073, // mret
+            0x00063503, // ld a0, 0(a2)
+        ],
+    );
+    core.write_csr(CSRRegister::mstatus, 1 << 11);
+    core.write_csr(CSRRegister::mepc, VBASE + 4);
+    core.write_csr(CSRRegister::pmpaddr0, napot(VBASE, 0x1000));
+    core.write_csr(CSRRegister::pmpcfg0, (PmpCfg::NAPOT | R | X) as u64);
+    core.write_register(12, VBASE + 0x2000);
+
+    run(core, mmu, 2);
+    assert_eq!(
+        core.read_csr(CSRRegister::mcause),
+        u16::from(TrapCause::LoadAccessFault(0)) as u64
+    );
+    assert_eq!(core.read_csr(CSRRegister::mtval), VBASE + 0x2000);
+    assert_eq!(core.read_csr(CSRRegister::mepc), VBASE + 4);
+}
+
+#[test]
+pub fn supervisor_store_outside_region_faults() {
+    let mmu = &mut MMU::create();
+    let core = &mut setup(
+        mmu,
+        &[
+            0x30200073, // mret
+            0x00a63023, // sd a0, 0(a2)
+        ],
+    );
+    core.write_csr(CSRRegister::mstatus, 1 << 11);
+    core.write_csr(CSRRegister::mepc, VBASE + 4);
+    core.write_csr(CSRRegister::pmpaddr0, napot(VBASE, 0x1000));
+    core.write_csr(CSRRegister::pmpcfg0, (PmpCfg::NAPOT | R | X) as u64);
+    core.write_register(10, u64::MAX);
+    core.write_register(12, VBASE + 0x2000);
+
+    run(core, mmu, 2);
+    assert_eq!(
+        core.read_csr(CSRRegister::mcause),
+        u16::from(TrapCause::StoreAccessFault(0)) as u64
+    );
+    assert_eq!(core.read_csr(CSRRegister::mtval), VBASE + 0x2000);
+    assert_eq!(core.read_csr(CSRRegister::mepc), VBASE + 4);
+    assert_eq!(mmu.read32(VBASE + 0x2000).unwrap(), 0);
+}
@@ -0,0 +1,85 @@
+mod common;
+
+use common::{run, VBASE};
+use rriscv::{
+    cpu::{CSRRegister, Core, MenvcfgMask, MipMask, PrivMode, TrapCause},
+    mmu::MMU,
+};
+
+const A0: u8 = 10;
+const A1: u8 = 11;
+
+const STIP: u64 = MipMask::STIP as u64;
+
+const PROGRAM: [u32; 3] = [
+    0x30200073, // mret
+    0x14d51073, // csrw stimecmp, a0
+    0x14d025f3, // csrr a1, stimecmp
+];
+
+/// Loads `PROGRAM` at VBASE and returns a core about to enter S-mode at its second
+/// instruction
+fn setup(mmu: &mut MMU) -> Core {
+    let mut core = common::setup(mmu, &PROGRAM);
+    core.write_csr(CSRRegister::mstatus, (PrivMode::Supervisor as u64) << 11);
+    core.write_csr(CSRRegister::mepc, VBASE + 4);
+    core
+}
+
+#[test]
+pub fn stimecmp_needs_stce_and_tm() {
+    let mmu = &mut MMU::create();
+    for (menvcfg, mcounteren) in [(0, 0b111), (MenvcfgMask::STCE, 0b101)] {
+        let core = &mut setup(mmu);
+        core.write_csr(CSRRegister::menvcfg, menvcfg);
+        core.write_csr(CSRRegister::mcounteren, mcounteren);
+        run(core, mmu, 2);
+        assert_eq!(
+            core.read_csr(CSRRegister::mcause),
+            u16::from(TrapCause::IllegalInstruction(0)) as u64
+        );
+        assert_eq!(core.read_csr(CSRRegister::mepc), VBASE + 4);
+    }
+
+    // Without STCE, STIP is left to M-mode software
+    let core = &mut setup(mmu);
+    core.write_csr(CSRRegister::menvcfg, u64::MAX);
+    assert_eq!(core.read_csr(CSRRegister::menvcfg), MenvcfgMask::STCE);
+    core.write_csr(CSRRegister::menvcfg, 0);
+    core.write_csr(CSRRegister::stimecmp, 0);
+    core.write_csr(CSRRegister::mip, STIP);
+    run(core, mmu, 1);
+    assert_eq!(core.read_csr(CSRRegister::mip) & STIP, STIP);
+    core.write_csr(CSRRegister::mip, 0);
+    run(core, mmu, 1);
+    assert_eq!(core.read_csr(CSRRegister::mip) & STIP, 0);
+}
+
+#[test]
+pub fn stimecmp_drives_stip() {
+    let mmu = &mut MMU::create();
+    let core = &mut setup(mmu);
+    core.write_csr(CSRRegister::menvcfg, MenvcfgMask::STCE);
+    core.write_csr(CSRRegister::mcounteren, 0b010);
+    core.write_register(A0, 150);
+    run(core, mmu, 3);
+    assert_eq!(core.pmode(), PrivMode::Supervisor);
+    assert_eq!(core.read_register(A1), 150);
+    assert_eq!(core.read_csr(CSRRegister::mip) & STIP, 0);
+
+    for _ in 0..149 {
+        mmu.tick(0);
+    }
+    core.cycle(mmu);
+    assert_eq!(core.read_csr(CSRRegister::mip) & STIP, 0);
+    mmu.tick(0);
+    core.cycle(mmu);
+    assert_eq!(core.read_csr(CSRRegister::mip) & STIP, STIP);
+
+    // STIP is read-only, only a new stimecmp clears it
+    core.write_csr(CSRRegister::mip, 0);
+    core.write_csr(CSRRegister::sip, 0);
+    assert_eq!(core.read_csr(CSRRegister::mip) & STIP, STIP);
+    core.write_csr(CSRRegister::stimecmp, u64::MAX);
+    assert_eq!(core.read_csr(CSRRegister::mip) & STIP, 0);
+}
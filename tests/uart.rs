@@ -0,0 +1,216 @@
+use std::{
+    collections::VecDeque,
+    fs::{self, OpenOptions},
+    io::{self, Read, Write},
+};
+
+use rriscv::{
+    chardev::{CharDevice, LogFile, Pty},
+    cpu::MipMask,
+    memory::MemoryOperations,
+    mmu::MMU,
+};
+
+const UART_BASE: u64 = 0x1000_0000;
+const UART_IRQ: u64 = 10;
+
+const RBR: u64 = 0;
+const IER: u64 = 1;
+const IIR: u64 = 2;
+const LCR: u64 = 3;
+const MCR: u64 = 4;
+const LSR: u64 = 5;
+const MSR: u64 = 6;
+const SCR: u64 = 7;
+
+const PLIC_PRIORITY: u64 = 0x0c00_0000;
+const PLIC_SENABLE: u64 = 0x0c00_2080;
+const PLIC_STHRESHOLD: u64 = 0x0c20_1000;
+const PLIC_SCLAIM: u64 = 0x0c20_1004;
+
+const SEIP: u64 = MipMask::SEIP as u64;
+
+/// Keys typed on the host
+struct Keyboard {
+    keys: VecDeque<u8>,
+}
+
+impl CharDevice for Keyboard {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut count = 0;
+        while count < buf.len() {
+            match self.keys.pop_front() {
+                Some(key) => buf[count] = key,
+                None => break,
+            }
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    fn write(&mut self, _bytes: &[u8]) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn run(mmu: &mut MMU, mip: u64) -> u64 {
+    run_for(mmu, mip, 0x200)
+}
+
+fn run_for(mmu: &mut MMU, mip: u64, clocks: usize) -> u64 {
+    let mut mip = mip;
+    for _ in 0..clocks {
+        mip = mmu.tick(mip);
+    }
+    mip
+}
+
+#[test]
+pub fn received_bytes_raise_interrupts() {
+    let mut mmu = MMU::create();
+    mmu.uart_mut().connect(Box::new(Keyboard {
+        keys: VecDeque::from(b"l\0".to_vec()),
+    }));
+    mmu.write32(PLIC_PRIORITY + 4 * UART_IRQ, 1);
+    mmu.write32(PLIC_SENABLE, 1 << UART_IRQ);
+    mmu.write32(PLIC_STHRESHOLD, 0);
+
+    // Nothing is signalled until the driver enables the receive interrupt
+    let mip = run(&mut mmu, 0);
+    assert_eq!(mip & SEIP, 0);
+    assert_eq!(mmu.read8(UART_BASE + LSR).unwrap() & 1, 1, "data ready");
+    mmu.write8(UART_BASE + IER, 1);
+    let mip = run(&mut mmu, mip);
+    assert_eq!(mip & SEIP, SEIP);
+
+    for key in b"l\0" {
+        assert_eq!(mmu.read32(PLIC_SCLAIM).unwrap(), UART_IRQ as u32);
+        assert_eq!(mmu.read8(UART_BASE + RBR).unwrap(), *key);
+        assert_eq!(mmu.read8(UART_BASE + LSR).unwrap() & 1, 0);
+        mmu.write32(PLIC_SCLAIM, UART_IRQ as u32);
+        let mip = mmu.tick(SEIP);
+        assert_eq!(mip & SEIP, 0, "completed");
+        let mip = run(&mut mmu, mip);
+        assert_eq!(mip & SEIP, SEIP * (*key == b'l') as u64);
+    }
+}
+
+#[test]
+pub fn divisor_latch_shares_offsets() {
+    let mut mmu = MMU::create();
+    let uart = |reg| UART_BASE + reg;
+    mmu.write8(uart(IER), 0x05);
+    mmu.write8(uart(SCR), 0x5a);
+
+    // 38400 baud, 8N1
+    mmu.write8(uart(LCR), 0x80);
+    mmu.write8(uart(RBR), 0x03);
+    mmu.write8(uart(IER), 0x00);
+    assert_eq!(mmu.read8(uart(RBR)).unwrap(), 0x03, "DLL");
+    assert_eq!(mmu.read8(uart(IER)).unwrap(), 0x00, "DLM");
+    mmu.write8(uart(LCR), 0x03);
+
+    assert_eq!(mmu.read8(uart(IER)).unwrap(), 0x05);
+    assert_eq!(mmu.read8(uart(LCR)).unwrap(), 0x03);
+    assert_eq!(mmu.read8(uart(SCR)).unwrap(), 0x5a);
+    assert_eq!(mmu.read8(uart(LSR)).unwrap(), 0x60, "transmitter empty");
+    assert_eq!(mmu.read8(uart(IIR)).unwrap(), 0x01, "no interrupt");
+    assert_eq!(mmu.read8(uart(MSR)).unwrap(), 0xb0, "CTS, DSR and DCD");
+}
+
+#[test]
+pub fn loopback_fifo_trigger_levels_and_overrun() {
+    let mut mmu = MMU::create();
+    let uart = |reg| UART_BASE + reg;
+    // FIFOs enabled with a trigger level of 4, loopback
+    mmu.write8(uart(IIR), 0x47);
+    mmu.write8(uart(MCR), 0x10);
+    mmu.write8(uart(IER), 0x0f);
+    assert_eq!(mmu.read8(uart(IIR)).unwrap(), 0xc2, "transmitter empty");
+    assert_eq!(mmu.read8(uart(IIR)).unwrap(), 0xc0, "modem status change");
+    assert_eq!(
+        mmu.read8(uart(MSR)).unwrap(),
+        0x0b,
+        "CTS, DSR and DCD dropped"
+    );
+    assert_eq!(mmu.read8(uart(IIR)).unwrap(), 0xc1);
+
+    for byte in b"abc" {
+        mmu.write8(uart(RBR), *byte);
+    }
+    assert_eq!(mmu.read8(uart(LSR)).unwrap() & 0x60, 0, "transmitting");
+    run_for(&mut mmu, 0, 0x30);
+    assert_eq!(mmu.read8(uart(LSR)).unwrap(), 0x61);
+    // Below the trigger level, until the character timeout
+    assert_eq!(mmu.read8(uart(IIR)).unwrap(), 0xc2);
+    run_for(&mut mmu, 0, 0x40);
+    assert_eq!(mmu.read8(uart(IIR)).unwrap(), 0xcc, "character timeout");
+
+    mmu.write8(uart(RBR), b'd');
+    run(&mut mmu, 0);
+    assert_eq!(mmu.read8(uart(IIR)).unwrap(), 0xc4, "trigger level reached");
+    for byte in b"abcd" {
+        assert_eq!(mmu.read8(uart(RBR)).unwrap(), *byte);
+    }
+    assert_eq!(mmu.read8(uart(LSR)).unwrap(), 0x60);
+
+    // Overrun of the 16 byte FIFO takes precedence
+    for _ in 0..2 {
+        for byte in 0..9 {
+            mmu.write8(uart(RBR), byte);
+        }
+        run(&mut mmu, 0);
+    }
+    assert_eq!(mmu.read8(uart(IIR)).unwrap(), 0xc6, "line status");
+    assert_eq!(mmu.read8(uart(LSR)).unwrap(), 0x63, "overrun");
+    assert_eq!(mmu.read8(uart(LSR)).unwrap(), 0x61);
+    assert_eq!(mmu.read8(uart(IIR)).unwrap(), 0xc4);
+
+    // Clearing the receive FIFO
+    mmu.write8(uart(IIR), 0x43);
+    assert_eq!(mmu.read8(uart(LSR)).unwrap(), 0x60);
+    assert_eq!(mmu.read8(uart(IIR)).unwrap(), 0xc2);
+    assert_eq!(mmu.read8(uart(IIR)).unwrap(), 0xc1);
+}
+
+#[test]
+pub fn transmitted_bytes_reach_the_backend() {
+    let log = std::env::temp_dir().join(format!("rriscv-{}-uart.log", std::process::id()));
+    let _ = fs::remove_file(&log);
+    let mut mmu = MMU::create();
+    mmu.uart_mut()
+        .connect(Box::new(LogFile::create(&log).unwrap()));
+    for byte in b"$ ls\n" {
+        mmu.write8(UART_BASE + RBR, *byte);
+        run_for(&mut mmu, 0, 0x10);
+    }
+    assert_eq!(fs::read(&log).unwrap(), b"$ ls\n");
+    fs::remove_file(log).unwrap();
+}
+
+#[test]
+pub fn pseudo_terminal() {
+    let pty = Pty::create().unwrap();
+    let mut terminal = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(pty.path())
+        .unwrap();
+    let mut mmu = MMU::create();
+    mmu.uart_mut().connect(Box::new(pty));
+
+    mmu.write8(UART_BASE + RBR, b'#');
+    run_for(&mut mmu, 0, 0x10);
+    let mut byte = [0];
+    terminal.read_exact(&mut byte).unwrap();
+    assert_eq!(byte, *b"#");
+
+    terminal.write_all(b"x").unwrap();
+    for _ in 0..0x100 {
+        run(&mut mmu, 0);
+        if mmu.read8(UART_BASE + LSR).unwrap() & 1 == 1 {
+            break;
+        }
+    }
+    assert_eq!(mmu.read8(UART_BASE + RBR).unwrap(), b'x');
+}
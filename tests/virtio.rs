@@ -0,0 +1,230 @@
+mod common;
+
+use common::{
+    temp_path,
+    virtqueue::{
+        buffer, give, give_chain, part, queue_areas, setup, used_len, INTERRUPT_ACK,
+        INTERRUPT_STATUS, QUEUE_NOTIFY, QUEUE_READY, QUEUE_SEL, VIRTIO_BASE,
+    },
+};
+use rriscv::{
+    cpu::TrapCause,
+    disk::{self, DiskMode},
+    memory::MemoryOperations,
+    mmu::MMU,
+    virtio::{block::VirtioBlockDisk, rng::VirtioRng},
+};
+
+/// A disk of four sectors, each filled with its own sector number
+fn disk() -> Vec<u8> {
+    (0..4).flat_map(|sector| vec![sector as u8; 512]).collect()
+}
+
+/// Sets up queue 0 the way a driver would
+fn setup_disk() -> MMU {
+    let mut mmu = MMU::create();
+    mmu.attach_virtio(Box::new(VirtioBlockDisk::new(Box::new(disk()))));
+    setup(mmu, VIRTIO_BASE, 1, 0)
+}
+
+/// Where the data of the `n`th request goes
+fn data(n: u64) -> u64 {
+    part(0, n, 1)
+}
+
+/// Submits a `virtio_blk_req` as the `n`th request, with `bytes` to write or room for
+/// `len` bytes to read, and returns its status once the device is done
+fn request(mmu: &mut MMU, n: u64, blk_type: u32, sector: u64, bytes: &[u8], len: u32) -> u8 {
+    request_on(mmu, VIRTIO_BASE, n, blk_type, sector, bytes, len)
+}
+
+fn request_on(
+    mmu: &mut MMU,
+    base: u64,
+    n: u64,
+    blk_type: u32,
+    sector: u64,
+    bytes: &[u8],
+    len: u32,
+) -> u8 {
+    let mut header = blk_type.to_le_bytes().to_vec();
+    header.extend_from_slice(&0u32.to_le_bytes());
+    header.extend_from_slice(&sector.to_le_bytes());
+    let mut parts = vec![(&header[..], 0)];
+    if !bytes.is_empty() || len > 0 {
+        parts.push((bytes, len));
+    }
+    parts.push((&[], 1));
+    give_chain(mmu, base, 0, n, &parts);
+
+    assert_eq!(mmu.read32(base + INTERRUPT_STATUS).unwrap(), 1);
+    mmu.write32(base + INTERRUPT_ACK, 1);
+    assert_eq!(mmu.read32(base + INTERRUPT_STATUS).unwrap(), 0);
+
+    let (_, _, used) = queue_areas(0);
+    assert_eq!(
+        mmu.read32(used).unwrap() >> 16,
+        n as u32 + 1,
+        "used.idx is bumped"
+    );
+    mmu.read8(part(0, n, parts.len() as u64 - 1)).unwrap()
+}
+
+#[test]
+pub fn device_identification() {
+    let mmu = &mut setup_disk();
+    assert_eq!(mmu.read32(VIRTIO_BASE).unwrap(), 0x74726976);
+    assert_eq!(mmu.read32(VIRTIO_BASE + 0x4).unwrap(), 2);
+    assert_eq!(mmu.read32(VIRTIO_BASE + 0x8).unwrap(), 2);
+
+    // VIRTIO_F_VERSION_1 is bit 32
+    mmu.write32(VIRTIO_BASE + 0x14, 1);
+    assert_eq!(mmu.read32(VIRTIO_BASE + 0x10).unwrap() & 1, 1);
+
+    // capacity, in sectors
+    assert_eq!(mmu.read32(VIRTIO_BASE + 0x100).unwrap(), 4);
+    assert_eq!(mmu.read32(VIRTIO_BASE + 0x104).unwrap(), 0);
+
+    // Only queue 0 exists
+    assert_eq!(mmu.read32(VIRTIO_BASE + QUEUE_READY).unwrap(), 1);
+    mmu.write32(VIRTIO_BASE + QUEUE_SEL, 1);
+    assert_eq!(mmu.read32(VIRTIO_BASE + 0x34).unwrap(), 0);
+}
+
+#[test]
+pub fn only_config_space_takes_byte_accesses() {
+    let mmu = &mut setup_disk();
+    assert_eq!(mmu.read8(VIRTIO_BASE + 0x100).unwrap(), 4);
+    assert_eq!(mmu.read8(VIRTIO_BASE + 0x101).unwrap(), 0);
+    assert_eq!(mmu.write8(VIRTIO_BASE + 0x100, 1), None);
+    assert_eq!(
+        mmu.read8(VIRTIO_BASE + 0x100).unwrap(),
+        4,
+        "capacity is read-only"
+    );
+
+    assert_eq!(
+        mmu.read8(VIRTIO_BASE),
+        Err(TrapCause::LoadAccessFault(VIRTIO_BASE))
+    );
+    assert_eq!(
+        mmu.write8(VIRTIO_BASE + QUEUE_NOTIFY, 0),
+        Some(TrapCause::StoreAccessFault(VIRTIO_BASE + QUEUE_NOTIFY))
+    );
+}
+
+#[test]
+pub fn read_and_write_sectors() {
+    let mmu = &mut setup_disk();
+
+    assert_eq!(request(mmu, 0, 0, 2, &[], 1024), 0);
+    assert_eq!(used_len(mmu, 0, 0), 1025);
+    assert_eq!(mmu.read32(data(0)).unwrap(), 0x0202_0202);
+    assert_eq!(mmu.read32(data(0) + 1020).unwrap(), 0x0303_0303);
+
+    let sector = 0xdead_beefu32.to_le_bytes().repeat(128);
+    assert_eq!(request(mmu, 1, 1, 1, &sector, 0), 0);
+    assert_eq!(used_len(mmu, 0, 1), 1, "only the status byte was written");
+
+    assert_eq!(request(mmu, 2, 0, 1, &[], 512), 0);
+    assert_eq!(mmu.read32(data(2)).unwrap(), 0xdead_beef);
+    assert_eq!(mmu.read32(data(2) + 508).unwrap(), 0xdead_beef);
+}
+
+#[test]
+pub fn flush_get_id_and_errors() {
+    let mmu = &mut setup_disk();
+
+    // VIRTIO_BLK_T_FLUSH
+    assert_eq!(request(mmu, 0, 4, 0, &[], 0), 0);
+
+    // VIRTIO_BLK_T_GET_ID
+    assert_eq!(request(mmu, 1, 8, 0, &[], 20), 0);
+    assert_eq!(mmu.read32(data(1)).unwrap(), u32::from_le_bytes(*b"rris"));
+
+    // Past the end of the disk
+    assert_eq!(request(mmu, 2, 0, 3, &[], 1024), 1, "VIRTIO_BLK_S_IOERR");
+
+    // Unknown request type
+    assert_eq!(request(mmu, 3, 0x42, 0, &[], 512), 2, "VIRTIO_BLK_S_UNSUPP");
+}
+
+#[test]
+pub fn read_only_disk_reports_write_errors() {
+    let path = temp_path("virtio.img");
+    std::fs::write(&path, disk()).unwrap();
+    let mut mmu = MMU::create();
+    let disk = disk::open(&path, DiskMode::ReadOnly).unwrap();
+    mmu.attach_virtio(Box::new(VirtioBlockDisk::new(disk)));
+    let mmu = &mut setup(mmu, VIRTIO_BASE, 1, 0);
+
+    // VIRTIO_BLK_F_RO
+    assert_eq!(mmu.read32(VIRTIO_BASE + 0x10).unwrap() & (1 << 5), 1 << 5);
+
+    let sector = [0xaa; 512];
+    assert_eq!(request(mmu, 0, 1, 0, &sector, 0), 1, "VIRTIO_BLK_S_IOERR");
+    assert_eq!(request(mmu, 1, 0, 3, &[], 512), 0);
+    assert_eq!(mmu.read32(data(1)).unwrap(), 0x0303_0303);
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+pub fn transports_are_independent() {
+    let mut mmu = MMU::create();
+    let other: Vec<u8> = vec![0x5a; 1024];
+    assert_eq!(
+        mmu.attach_virtio(Box::new(VirtioBlockDisk::new(Box::new(disk())))),
+        Some(0)
+    );
+    assert_eq!(
+        mmu.attach_virtio(Box::new(VirtioBlockDisk::new(Box::new(other)))),
+        Some(1)
+    );
+    let second = VIRTIO_BASE + 0x1000;
+    let mmu = &mut setup(mmu, second, 1, 0);
+
+    assert_eq!(mmu.read32(second + 0x8).unwrap(), 2);
+    assert_eq!(mmu.read32(second + 0x100).unwrap(), 2, "capacity");
+    assert_eq!(
+        mmu.read32(second + 0x1000 + 0x8).unwrap(),
+        0,
+        "free transports have no device"
+    );
+
+    // Route the second transport's source, 2 in the device tree, to the hart
+    mmu.write32(0x0c00_0000 + 4 * 2, 1);
+    mmu.write32(0x0c00_2080, 1 << 2);
+    mmu.write32(0x0c20_1000, 0);
+
+    assert_eq!(request_on(mmu, second, 0, 0, 1, &[], 512), 0);
+    assert_eq!(mmu.read32(data(0)).unwrap(), 0x5a5a_5a5a);
+    assert_eq!(mmu.read32(0x0c20_1004).unwrap(), 2, "claimed source");
+    assert_eq!(
+        mmu.read32(VIRTIO_BASE + INTERRUPT_STATUS).unwrap(),
+        0,
+        "the first disk was not involved"
+    );
+}
+
+/// Bytes `virtio-rng` writes to a `len` byte buffer
+fn entropy(rng: VirtioRng, len: u32) -> Vec<u8> {
+    let mut mmu = MMU::create();
+    mmu.attach_virtio(Box::new(rng));
+    assert_eq!(mmu.read32(VIRTIO_BASE + 0x8).unwrap(), 4);
+    let mmu = &mut setup(mmu, VIRTIO_BASE, 1, 0);
+    give(mmu, VIRTIO_BASE, 0, 0, &[], len);
+    assert_eq!(mmu.read32(VIRTIO_BASE + INTERRUPT_STATUS).unwrap(), 1);
+    assert_eq!(used_len(mmu, 0, 0), len);
+    (0..len as u64)
+        .map(|i| mmu.read8(buffer(0, 0) + i).unwrap())
+        .collect()
+}
+
+#[test]
+pub fn seeded_entropy_is_reproducible() {
+    let bytes = entropy(VirtioRng::seeded(42), 13);
+    assert_eq!(bytes, entropy(VirtioRng::seeded(42), 13));
+    assert_ne!(bytes, vec![0; 13]);
+    assert_ne!(bytes, entropy(VirtioRng::seeded(43), 13));
+    assert_eq!(entropy(VirtioRng::host().unwrap(), 64).len(), 64);
+}
@@ -0,0 +1,241 @@
+mod common;
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use common::{
+    temp_path,
+    virtqueue::{give_chain, part, setup, used_len, VIRTIO_BASE},
+};
+use rriscv::{memory::MemoryOperations, mmu::MMU, virtio::p9::Virtio9p};
+
+const RLERROR: u8 = 7;
+const ENOENT: u32 = 2;
+const EACCES: u32 = 13;
+const ENOTDIR: u32 = 20;
+const ELOOP: u32 = 40;
+
+/// A 9P client talking to the device through its queue
+struct Client {
+    mmu: MMU,
+    requests: u16,
+}
+
+impl Client {
+    fn mount(root: &Path) -> Client {
+        let mut mmu = MMU::create();
+        mmu.attach_virtio(Box::new(Virtio9p::new(root, "share").unwrap()));
+        let mmu = setup(mmu, VIRTIO_BASE, 1, 0);
+        let mut client = Client { mmu, requests: 0 };
+
+        let mut version = 0x2000u32.to_le_bytes().to_vec();
+        put_string(&mut version, "9P2000.L");
+        let (kind, body) = client.call(100, &version);
+        assert_eq!((kind, &body[4..]), (101, &version[4..]));
+        client
+    }
+
+    /// Sends a T-message of type `kind`, returning the type and body of the response
+    fn call(&mut self, kind: u8, body: &[u8]) -> (u8, Vec<u8>) {
+        let mut message = ((7 + body.len()) as u32).to_le_bytes().to_vec();
+        message.push(kind);
+        message.extend_from_slice(&self.requests.to_le_bytes());
+        message.extend_from_slice(body);
+        let mmu = &mut self.mmu;
+        let n = self.requests;
+        give_chain(
+            mmu,
+            VIRTIO_BASE,
+            0,
+            n as u64,
+            &[(&message, 0), (&[], 0x2000)],
+        );
+        self.requests += 1;
+
+        let len = used_len(mmu, 0, n as u64) as u64;
+        let response: Vec<u8> = (0..len)
+            .map(|i| mmu.read8(part(0, n as u64, 1) + i).unwrap())
+            .collect();
+        assert_eq!(response[0..4], (len as u32).to_le_bytes(), "size");
+        assert_eq!(response[5..7], n.to_le_bytes(), "tag");
+        (response[4], response[7..].to_vec())
+    }
+
+    /// Twalk from `fid` to `newfid` through `names`, returning the qids
+    fn walk(&mut self, fid: u32, newfid: u32, names: &[&str]) -> (u8, Vec<u8>) {
+        let mut body = fid.to_le_bytes().to_vec();
+        body.extend_from_slice(&newfid.to_le_bytes());
+        body.extend_from_slice(&(names.len() as u16).to_le_bytes());
+        for name in names {
+            put_string(&mut body, name);
+        }
+        self.call(110, &body)
+    }
+}
+
+fn put_string(body: &mut Vec<u8>, s: &str) {
+    body.extend_from_slice(&(s.len() as u16).to_le_bytes());
+    body.extend_from_slice(s.as_bytes());
+}
+
+fn fields(values: &[u32]) -> Vec<u8> {
+    values.iter().flat_map(|v| v.to_le_bytes()).collect()
+}
+
+/// A shared directory holding `hello.txt`
+fn share(name: &str) -> PathBuf {
+    let root = temp_path(name);
+    let _ = fs::remove_dir_all(&root);
+    fs::create_dir_all(root.join("sub")).unwrap();
+    fs::write(root.join("hello.txt"), "hello from the host\n").unwrap();
+    root
+}
+
+#[test]
+pub fn mount_tag() {
+    let root = share("9p-tag");
+    let mut mmu = MMU::create();
+    mmu.attach_virtio(Box::new(Virtio9p::new(&root, "share").unwrap()));
+    assert_eq!(mmu.read32(VIRTIO_BASE + 0x8).unwrap(), 9);
+    assert_eq!(mmu.read32(VIRTIO_BASE + 0x10).unwrap(), 1);
+    assert_eq!(mmu.read32(VIRTIO_BASE + 0x100).unwrap(), 0x6873_0005);
+    assert_eq!(mmu.read32(VIRTIO_BASE + 0x104).unwrap(), 0x0065_7261);
+    fs::remove_dir_all(root).unwrap();
+}
+
+#[test]
+pub fn read_write_and_list_files() {
+    let root = share("9p-files");
+    let mut client = Client::mount(&root);
+
+    // Tattach fid 0 to the root
+    let mut attach = fields(&[0, u32::MAX]);
+    put_string(&mut attach, "root");
+    put_string(&mut attach, "");
+    attach.extend_from_slice(&0u32.to_le_bytes());
+    let (kind, root_qid) = client.call(104, &attach);
+    assert_eq!((kind, root_qid[0]), (105, 0x80));
+
+    // ".." does not leave the share
+    let (kind, qids) = client.walk(0, 1, &["sub", "..", ".."]);
+    assert_eq!((kind, qids[0..2].to_vec()), (111, vec![3, 0]));
+    assert_eq!(qids[2 + 26..], root_qid[..]);
+    assert_eq!(
+        client.walk(0, 2, &["missing"]),
+        (RLERROR, fields(&[ENOENT]))
+    );
+
+    // Read hello.txt
+    assert_eq!(client.walk(0, 2, &["hello.txt"]).0, 111);
+    assert_eq!(client.call(12, &fields(&[2, 0])).0, 13);
+    let mut read = fields(&[2, 6, 0, 4]);
+    let (kind, body) = client.call(116, &read);
+    assert_eq!((kind, &body[..]), (117, &b"\x04\x00\x00\x00from"[..]));
+    read[4] = 100;
+    assert_eq!(client.call(116, &read).1, fields(&[0]), "past the end");
+
+    // Create and write sub/new.txt
+    assert_eq!(client.walk(0, 3, &["sub"]).0, 111);
+    let mut create = fields(&[3]);
+    put_string(&mut create, "new.txt");
+    create.extend(fields(&[0o2, 0o644, 0]));
+    assert_eq!(client.call(14, &create).0, 15);
+    let mut write = fields(&[3, 0, 0, 5]);
+    write.extend_from_slice(b"guest");
+    assert_eq!(client.call(118, &write), (119, fields(&[5])));
+    assert_eq!(client.call(120, &fields(&[3])).0, 121);
+    assert_eq!(fs::read(root.join("sub/new.txt")).unwrap(), b"guest");
+    assert_eq!(client.call(120, &fields(&[3])), (RLERROR, fields(&[9])));
+
+    // Rgetattr of sub/new.txt
+    assert_eq!(client.walk(0, 4, &["sub", "new.txt"]).0, 111);
+    let (kind, attr) = client.call(24, &fields(&[4, 0x7ff, 0]));
+    assert_eq!(kind, 25);
+    assert_eq!(attr[21..25], (0o100644u32).to_le_bytes(), "mode");
+    assert_eq!(attr[49..57], 5u64.to_le_bytes(), "size");
+
+    // Treaddir of the root lists ".", "..", "hello.txt" and "sub"
+    assert_eq!(client.walk(0, 5, &[]).0, 111);
+    assert_eq!(client.call(12, &fields(&[5, 0])).0, 13);
+    let (kind, entries) = client.call(40, &fields(&[5, 0, 0, 0x1000]));
+    assert_eq!(kind, 41);
+    let mut names = vec![];
+    let mut offset = 4;
+    while offset < entries.len() {
+        let len = u16::from_le_bytes([entries[offset + 22], entries[offset + 23]]) as usize;
+        names.push(String::from_utf8(entries[offset + 24..offset + 24 + len].to_vec()).unwrap());
+        offset += 24 + len;
+    }
+    assert_eq!(names, [".", "..", "hello.txt", "sub"]);
+    let (_, rest) = client.call(40, &fields(&[5, 3, 0, 0x1000]));
+    assert_eq!(rest.len(), 4 + 24 + 3, "continues at the offset of \"sub\"");
+
+    // Tunlinkat takes a single path element, and keeps directories that are not empty
+    let mut unlink = fields(&[0]);
+    put_string(&mut unlink, "../hello.txt");
+    unlink.extend(fields(&[0]));
+    assert_eq!(client.call(76, &unlink), (RLERROR, fields(&[22])));
+    let mut unlink = fields(&[0]);
+    put_string(&mut unlink, "sub");
+    unlink.extend(fields(&[0x200]));
+    assert_eq!(
+        client.call(76, &unlink),
+        (RLERROR, fields(&[39])),
+        "ENOTEMPTY"
+    );
+    assert!(root.join("sub/new.txt").exists());
+    fs::remove_dir_all(root).unwrap();
+}
+
+#[test]
+pub fn symlinks_do_not_leave_the_share() {
+    let root = share("9p-symlinks");
+    let outside = share("9p-outside");
+    let mut client = Client::mount(&root);
+    let mut attach = fields(&[0, u32::MAX]);
+    put_string(&mut attach, "root");
+    put_string(&mut attach, "");
+    attach.extend_from_slice(&0u32.to_le_bytes());
+    assert_eq!(client.call(104, &attach).0, 105);
+
+    // Tsymlink "out" -> the other share, and "hello" -> its hello.txt
+    for (name, target) in [
+        ("out", outside.clone()),
+        ("hello", outside.join("hello.txt")),
+    ] {
+        let mut symlink = fields(&[0]);
+        put_string(&mut symlink, name);
+        put_string(&mut symlink, target.to_str().unwrap());
+        symlink.extend(fields(&[0]));
+        assert_eq!(client.call(16, &symlink).0, 17);
+    }
+
+    // The links themselves can be walked to, but not through
+    let (kind, qids) = client.walk(0, 1, &["out", "hello.txt"]);
+    assert_eq!((kind, &qids[0..2]), (111, &[1, 0][..]), "stops at the link");
+    assert_eq!(client.walk(0, 1, &["out"]).0, 111);
+    assert_eq!(
+        client.walk(1, 2, &["hello.txt"]),
+        (RLERROR, fields(&[EACCES]))
+    );
+    assert_eq!(
+        client.call(40, &fields(&[1, 0, 0, 0x1000])),
+        (RLERROR, fields(&[ENOTDIR]))
+    );
+    let mut create = fields(&[1]);
+    put_string(&mut create, "new.txt");
+    create.extend(fields(&[0o2, 0o644, 0]));
+    assert_eq!(client.call(14, &create), (RLERROR, fields(&[EACCES])));
+    assert!(!outside.join("new.txt").exists());
+
+    // Nor opened
+    assert_eq!(client.walk(0, 3, &["hello"]).0, 111);
+    assert_eq!(
+        client.call(12, &fields(&[3, 0])),
+        (RLERROR, fields(&[ELOOP]))
+    );
+    fs::remove_dir_all(root).unwrap();
+    fs::remove_dir_all(outside).unwrap();
+}